@@ -1,7 +1,7 @@
 use aws_sdk_acm::{types::CertificateStatus, Client};
 use lib_core::{define_cli_error, CliError};
 
-use crate::shared_config::config_from_profile;
+use crate::AwsContext;
 
 define_cli_error!(AcmError, "Error running AWS ACM command.");
 
@@ -15,12 +15,11 @@ define_cli_error!(AcmError, "Error running AWS ACM command.");
 /// Preference is given to an exact match if multiple certificates could match. Otherwise, a
 /// wildcard match is returned if found.
 pub async fn get_acm_certificate_arn_for_domain(
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     domain: &str,
 ) -> Result<Option<String>, CliError> {
     let target = normalize_domain(domain);
-    let client = Client::new(&config_from_profile(profile, region).await);
+    let client = aws.client::<Client>().await;
 
     let mut next_token: Option<String> = None;
     let mut wildcard_candidate: Option<String> = None;
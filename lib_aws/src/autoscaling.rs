@@ -1,14 +1,13 @@
 use aws_sdk_autoscaling::Client;
 use lib_core::{define_cli_error, CliError, Printer};
 
-use crate::shared_config::config_from_profile;
+use crate::AwsContext;
 
 define_cli_error!(Ec2Error, "Error running AWS EC2 command.");
 
 pub async fn set_auto_scaling_group_desired_size(
     pr: &Printer,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     auto_scaling_group_name: &str,
     desired_size: i32,
 ) -> Result<(), CliError> {
@@ -16,7 +15,7 @@ pub async fn set_auto_scaling_group_desired_size(
         "Setting desired size of auto scaling group '{}' to {}...",
         auto_scaling_group_name, desired_size
     ));
-    let client = Client::new(&config_from_profile(profile, region).await);
+    let client = aws.client::<Client>().await;
     client
         .update_auto_scaling_group()
         .auto_scaling_group_name(auto_scaling_group_name)
@@ -0,0 +1,409 @@
+use std::{future::Future, time::Duration};
+
+use lib_core::{define_cli_error, CliError, DeploymentColor, Printer};
+
+use crate::{
+    get_route53_a_record, get_route53_cname_record, get_ssm_parameter_if_exists,
+    set_route53_a_record_alias, set_route53_cname_record, set_ssm_parameter, AliasTargetType,
+    AwsContext,
+};
+
+define_cli_error!(
+    BlueGreenUnknownLiveColor,
+    "Record '{record_name}' points to '{value}', which is neither the blue nor the green endpoint.",
+    { record_name: &str, value: &str }
+);
+define_cli_error!(
+    BlueGreenInvalidColor,
+    "SSM parameter '{name}' has invalid color '{value}' (expected 'blue' or 'green').",
+    { name: &str, value: &str }
+);
+define_cli_error!(
+    BlueGreenHealthCheckFailed,
+    "Health check of the {color} deployment failed ({url}): {details}.",
+    { color: DeploymentColor, url: &str, details: &str }
+);
+define_cli_error!(
+    BlueGreenNothingToRollBack,
+    "No live color found for '{record_name}', so there is no previous color to roll back to.",
+    { record_name: &str }
+);
+
+/// Where a color is deployed.
+#[derive(Debug, Clone)]
+pub struct ColorEndpoint {
+    /// Target of the live record when this color is live (ex. the load
+    /// balancer or CloudFront domain of this color's stack).
+    pub dns_name: String,
+    /// Checked before traffic is sent to this color.
+    pub health_check_url: String,
+}
+
+/// Type of the live record.
+#[derive(Debug, Clone, Copy)]
+pub enum TrafficRecord<'a> {
+    /// A record alias, updated with `set_route53_a_record_alias`.
+    Alias {
+        target_type: AliasTargetType<'a>,
+        evaluate_target_health: bool,
+    },
+    /// CNAME record, updated with `set_route53_cname_record`.
+    Cname { ttl: i64 },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SsmColorParameter<'a> {
+    /// Defaults to the region of the context.
+    pub region: Option<&'a str>,
+    pub name: &'a str,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HealthCheckOptions {
+    pub expected_status: u16,
+    pub attempts: u32,
+    pub interval: Duration,
+    /// Per request.
+    pub timeout: Duration,
+}
+
+impl Default for HealthCheckOptions {
+    fn default() -> Self {
+        HealthCheckOptions {
+            expected_status: 200,
+            attempts: 10,
+            interval: Duration::from_secs(6),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlueGreenConfig<'a> {
+    pub aws: &'a AwsContext,
+    pub hosted_zone_id: &'a str,
+    /// The live domain, which points to the endpoint of the live color.
+    pub record_name: &'a str,
+    pub record: TrafficRecord<'a>,
+    pub blue: ColorEndpoint,
+    pub green: ColorEndpoint,
+    /// If set, the live color is read from (and recorded in) this parameter,
+    /// rather than worked out from the live record. Until the parameter
+    /// exists, the live record is used.
+    pub ssm_parameter: Option<SsmColorParameter<'a>>,
+    pub health_check: HealthCheckOptions,
+}
+
+impl BlueGreenConfig<'_> {
+    fn ssm_context(&self, parameter: &SsmColorParameter<'_>) -> AwsContext {
+        match parameter.region {
+            Some(region) => self.aws.with_region(region),
+            None => self.aws.clone(),
+        }
+    }
+
+    pub fn endpoint(&self, color: DeploymentColor) -> &ColorEndpoint {
+        match color {
+            DeploymentColor::Blue => &self.blue,
+            DeploymentColor::Green => &self.green,
+        }
+    }
+}
+
+/// The color currently serving traffic, or None if nothing has been deployed
+/// yet.
+pub async fn blue_green_live_color(
+    config: &BlueGreenConfig<'_>,
+) -> Result<Option<DeploymentColor>, CliError> {
+    if let Some(parameter) = config.ssm_parameter {
+        let value =
+            get_ssm_parameter_if_exists(&config.ssm_context(&parameter), parameter.name).await?;
+        if let Some(value) = value {
+            return parse_color(&value)
+                .map(Some)
+                .ok_or_else(|| BlueGreenInvalidColor::new(parameter.name, &value));
+        }
+    }
+
+    let value = match config.record {
+        TrafficRecord::Alias { .. } => {
+            get_route53_a_record(config.aws, config.hosted_zone_id, config.record_name).await?
+        }
+        TrafficRecord::Cname { .. } => {
+            get_route53_cname_record(config.aws, config.hosted_zone_id, config.record_name).await?
+        }
+    };
+    match value {
+        Some(value) => color_for_record_value(config, &value)
+            .map(Some)
+            .ok_or_else(|| BlueGreenUnknownLiveColor::new(config.record_name, &value)),
+        None => Ok(None),
+    }
+}
+
+/// Deploys to the idle color with 'deploy', checks its health, and then flips
+/// traffic to it. Traffic is left untouched if the deployment or the health
+/// check fails. On the first deployment, blue is used.
+///
+/// Returns the new live color.
+pub async fn blue_green_deploy<F, Fut>(
+    pr: &Printer,
+    config: &BlueGreenConfig<'_>,
+    deploy: F,
+) -> Result<DeploymentColor, CliError>
+where
+    F: FnOnce(DeploymentColor) -> Fut,
+    Fut: Future<Output = Result<(), CliError>>,
+{
+    let live = blue_green_live_color(config).await?;
+    let idle = live.map_or(DeploymentColor::Blue, DeploymentColor::other);
+    match live {
+        Some(live) => pr.info(&format!("Live color is {}. Deploying to {}...", live, idle)),
+        None => pr.info(&format!("No live color yet. Deploying to {}...", idle)),
+    }
+
+    deploy(idle).await?;
+    blue_green_health_check(pr, config, idle).await?;
+    flip_traffic(pr, config, idle, live).await?;
+    Ok(idle)
+}
+
+/// Sends traffic back to the previous color, after checking it is still
+/// healthy. Returns the new live color.
+pub async fn blue_green_rollback(
+    pr: &Printer,
+    config: &BlueGreenConfig<'_>,
+) -> Result<DeploymentColor, CliError> {
+    let live = blue_green_live_color(config)
+        .await?
+        .ok_or_else(|| BlueGreenNothingToRollBack::new(config.record_name))?;
+    let previous = live.other();
+    pr.info(&format!("Rolling back from {} to {}...", live, previous));
+
+    blue_green_health_check(pr, config, previous).await?;
+    flip_traffic(pr, config, previous, Some(live)).await?;
+    Ok(previous)
+}
+
+/// Retries until the color's health check URL returns the expected status,
+/// or the attempts run out.
+pub async fn blue_green_health_check(
+    pr: &Printer,
+    config: &BlueGreenConfig<'_>,
+    color: DeploymentColor,
+) -> Result<(), CliError> {
+    let options = config.health_check;
+    let url = &config.endpoint(color).health_check_url;
+    pr.info(&format!("Checking health of {} ({})...", color, url));
+
+    let client = reqwest::Client::builder()
+        .timeout(options.timeout)
+        .build()
+        .map_err(|e| BlueGreenHealthCheckFailed::with_debug(color, url, "no HTTP client", &e))?;
+    let mut last_failure = String::new();
+    for attempt in 1..=options.attempts.max(1) {
+        if attempt > 1 {
+            tokio::time::sleep(options.interval).await;
+        }
+        match client.get(url).send().await {
+            Ok(response) if response.status().as_u16() == options.expected_status => {
+                return Ok(());
+            }
+            Ok(response) => {
+                last_failure = format!(
+                    "got status {}, expected {}",
+                    response.status().as_u16(),
+                    options.expected_status
+                )
+            }
+            Err(e) => last_failure = e.to_string(),
+        }
+        pr.info(&format!(
+            "Attempt {}/{}: {}.",
+            attempt, options.attempts, last_failure
+        ));
+    }
+    Err(BlueGreenHealthCheckFailed::new(color, url, &last_failure))
+}
+
+/// 'previous' is the color that was live before, if any.
+async fn flip_traffic(
+    pr: &Printer,
+    config: &BlueGreenConfig<'_>,
+    color: DeploymentColor,
+    previous: Option<DeploymentColor>,
+) -> Result<(), CliError> {
+    let point_record = |color: DeploymentColor| async move {
+        let endpoint = config.endpoint(color);
+        pr.info(&format!(
+            "Pointing '{}' to {} ({})...",
+            config.record_name, color, endpoint.dns_name
+        ));
+        match config.record {
+            TrafficRecord::Alias {
+                target_type,
+                evaluate_target_health,
+            } => {
+                set_route53_a_record_alias(
+                    config.aws,
+                    config.hosted_zone_id,
+                    config.record_name,
+                    target_type,
+                    &endpoint.dns_name,
+                    evaluate_target_health,
+                )
+                .await
+            }
+            TrafficRecord::Cname { ttl } => {
+                set_route53_cname_record(
+                    config.aws,
+                    config.hosted_zone_id,
+                    config.record_name,
+                    &endpoint.dns_name,
+                    ttl,
+                )
+                .await
+            }
+        }
+    };
+    let record_color = |color: DeploymentColor| async move {
+        match config.ssm_parameter {
+            Some(parameter) => {
+                set_ssm_parameter(
+                    &config.ssm_context(&parameter),
+                    parameter.name,
+                    &color.to_string(),
+                )
+                .await
+            }
+            None => Ok(()),
+        }
+    };
+    switch_live_color(pr, color, previous, point_record, record_color).await?;
+    pr.success(&format!("{} is live.", color));
+    Ok(())
+}
+
+/// Points the record to 'color', then records it as the live color. If it
+/// can't be recorded, the record is pointed back to 'previous', so that the
+/// recorded color keeps matching the one that is actually live. Without a
+/// previous color, the record is left as is, which is also where the live
+/// color is read from until it has been recorded once.
+async fn switch_live_color<P, PFut, R, RFut>(
+    pr: &Printer,
+    color: DeploymentColor,
+    previous: Option<DeploymentColor>,
+    point_record: P,
+    record_color: R,
+) -> Result<(), CliError>
+where
+    P: Fn(DeploymentColor) -> PFut,
+    PFut: Future<Output = Result<(), CliError>>,
+    R: FnOnce(DeploymentColor) -> RFut,
+    RFut: Future<Output = Result<(), CliError>>,
+{
+    point_record(color).await?;
+    let Err(error) = record_color(color).await else {
+        return Ok(());
+    };
+    if let Some(previous) = previous {
+        pr.error(&format!(
+            "Failed to record {} as the live color. Reverting to {}...",
+            color, previous
+        ));
+        if let Err(e) = point_record(previous).await {
+            pr.error(&format!("Failed to revert: {}", e.message()));
+        }
+    }
+    Err(error)
+}
+
+fn parse_color(value: &str) -> Option<DeploymentColor> {
+    match value.trim().to_lowercase().as_str() {
+        "blue" => Some(DeploymentColor::Blue),
+        "green" => Some(DeploymentColor::Green),
+        _ => None,
+    }
+}
+
+/// Route 53 returns names in lowercase, with a trailing dot (and alias
+/// targets sometimes with a 'dualstack.' prefix).
+fn color_for_record_value(config: &BlueGreenConfig<'_>, value: &str) -> Option<DeploymentColor> {
+    let normalize = |name: &str| {
+        let name = name.trim_end_matches('.').to_lowercase();
+        name.strip_prefix("dualstack.")
+            .map(String::from)
+            .unwrap_or(name)
+    };
+    let value = normalize(value);
+    [DeploymentColor::Blue, DeploymentColor::Green]
+        .into_iter()
+        .find(|color| normalize(&config.endpoint(*color).dns_name) == value)
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use lib_core::CriticalError;
+
+    use super::*;
+
+    #[test]
+    fn live_color_is_found_from_the_record_target() {
+        let aws = AwsContext::new("prod", "us-east-1");
+        let config = BlueGreenConfig {
+            aws: &aws,
+            hosted_zone_id: "Z123",
+            record_name: "api.example.com",
+            record: TrafficRecord::Cname { ttl: 60 },
+            blue: ColorEndpoint {
+                dns_name: "api-blue-123.us-east-1.elb.amazonaws.com".to_string(),
+                health_check_url: "https://blue.api.example.com/health".to_string(),
+            },
+            green: ColorEndpoint {
+                dns_name: "api-green-456.us-east-1.elb.amazonaws.com".to_string(),
+                health_check_url: "https://green.api.example.com/health".to_string(),
+            },
+            ssm_parameter: None,
+            health_check: HealthCheckOptions::default(),
+        };
+        assert_eq!(
+            color_for_record_value(
+                &config,
+                "dualstack.API-green-456.us-east-1.elb.amazonaws.com."
+            ),
+            Some(DeploymentColor::Green)
+        );
+        assert_eq!(color_for_record_value(&config, "example.com."), None);
+    }
+
+    #[tokio::test]
+    async fn record_is_reverted_if_the_live_color_cannot_be_recorded() {
+        use std::cell::RefCell;
+
+        let calls = RefCell::new(Vec::new());
+        let point_record = |color: DeploymentColor| {
+            calls.borrow_mut().push(format!("record {}", color));
+            async { Ok(()) }
+        };
+        let record_color = |color: DeploymentColor| {
+            calls.borrow_mut().push(format!("ssm {}", color));
+            async { Err(CriticalError::new("ssm is down")) }
+        };
+        let result = switch_live_color(
+            &Printer::new(),
+            DeploymentColor::Green,
+            Some(DeploymentColor::Blue),
+            point_record,
+            record_color,
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(
+            *calls.borrow(),
+            vec!["record green", "ssm green", "record blue"]
+        );
+    }
+}
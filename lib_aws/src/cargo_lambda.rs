@@ -3,14 +3,13 @@ use std::path::Path;
 use lib_core::{CliError, CriticalError, ExecuteOptions, Executor, IOMode, Printer};
 use tempfile::tempdir;
 
-use crate::s3_upload_dir;
+use crate::{s3_upload_dir, AwsContext};
 
 pub async fn cargo_lambda_build_to_s3(
     pr: &Printer,
     ex: &Executor,
     crate_dir: &Path,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     bucket: &str,
     key_prefix: &str,
 ) -> Result<(), CliError> {
@@ -39,6 +38,6 @@ pub async fn cargo_lambda_build_to_s3(
     )
     .await?;
     pr.info("Uploading zip files to S3...");
-    s3_upload_dir(pr, profile, region, bucket, key_prefix, target_dir.path()).await?;
+    s3_upload_dir(pr, aws, bucket, key_prefix, target_dir.path()).await?;
     Ok(())
 }
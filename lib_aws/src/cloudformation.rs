@@ -1,19 +1,22 @@
 use std::{
     collections::{HashMap, HashSet},
+    path::Path,
     time::Duration,
 };
 
 use aws_sdk_cloudformation::{
     client::Waiters,
-    error::SdkError,
-    operation::describe_stacks::DescribeStacksError,
     types::{Capability, ChangeSetStatus, Parameter},
     Client,
 };
 use aws_smithy_runtime_api::client::waiters::error::WaiterError;
 use lib_core::{define_cli_error, CliError, Printer};
 
-use crate::shared_config::config_from_profile;
+use crate::{
+    cloudformation_events::describe_stack, package_template, review_and_execute_change_set,
+    s3_create_bucket_if_not_exists, s3_object_url, s3_unique_bucket_name, stack_events_checkpoint,
+    upload_content_addressed, watch_stack, AwsContext,
+};
 
 define_cli_error!(CloudFormationError, "Error running CloudFormation command.");
 define_cli_error!(
@@ -31,36 +34,56 @@ define_cli_error!(
     "Failed to deploy CloudFormation stack '{stack_name}'.",
     { stack_name: &str }
 );
+define_cli_error!(
+    CloudFormationTemplateInvalid,
+    "The CloudFormation template '{template}' is invalid.",
+    { template: &str }
+);
 define_cli_error!(
     CloudFormationDeploymentFailedWithReason,
     "Failed to deploy CloudFormation stack '{stack_name}': {reason}.",
     { stack_name: &str, reason: &str }
 );
 
-const DEPLOY_WAIT_TIMEOUT: Duration = Duration::from_secs(30 * 60); // 30 minutes
+pub(crate) const DEPLOY_WAIT_TIMEOUT: Duration = Duration::from_secs(30 * 60); // 30 minutes
 
 pub enum StackDeploymentMethod {
     Changeset,
     Direct,
 }
 
-pub async fn stack_exists(profile: &str, region: &str, stack_name: &str) -> Result<bool, CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
-    let response = client.describe_stacks().stack_name(stack_name).send().await;
-    match response {
-        Ok(v) => Ok(!v.stacks.unwrap_or_default().is_empty()),
-        Err(SdkError::<DescribeStacksError>::ServiceError(_)) => Ok(false),
-        Err(e) => Err(CloudFormationError::with_debug(&e)),
+/// Identifies the bucket that packaged templates and artifacts are uploaded
+/// to, which is created if needed (see `s3_unique_bucket_name`).
+#[derive(Debug, Clone, Copy)]
+pub struct ArtifactBucket<'a> {
+    pub sso_session: &'a str,
+    pub account_id: &'a str,
+    pub project_id: &'a str,
+}
+
+impl ArtifactBucket<'_> {
+    pub fn name(&self, region: &str) -> String {
+        s3_unique_bucket_name(
+            self.sso_session,
+            self.account_id,
+            region,
+            "cfn-artifacts",
+            self.project_id,
+        )
     }
 }
 
+pub async fn stack_exists(aws: &AwsContext, stack_name: &str) -> Result<bool, CliError> {
+    let client = aws.client::<Client>().await;
+    Ok(describe_stack(&client, stack_name).await?.is_some())
+}
+
 pub async fn require_stack_outputs(
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     stack_name: &str,
     output_keys: HashSet<String>,
 ) -> Result<HashMap<String, String>, CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+    let client = aws.client::<Client>().await;
 
     let response = client
         .describe_stacks()
@@ -74,7 +97,7 @@ pub async fn require_stack_outputs(
         .unwrap_or_default()
         .into_iter()
         .next()
-        .ok_or_else(|| CloudFormationStackNotFound::new(stack_name, region))?;
+        .ok_or_else(|| CloudFormationStackNotFound::new(stack_name, aws.region()))?;
 
     let outputs = stack.outputs.unwrap_or_default();
     let outputs_map: std::collections::HashMap<String, String> = outputs
@@ -102,41 +125,32 @@ pub async fn require_stack_outputs(
 }
 
 pub async fn require_stack_output(
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     stack_name: &str,
     output_key: &str,
 ) -> Result<String, CliError> {
-    Ok(require_stack_outputs(
-        profile,
-        region,
-        stack_name,
-        HashSet::from([output_key.to_string()]),
+    Ok(
+        require_stack_outputs(aws, stack_name, HashSet::from([output_key.to_string()]))
+            .await?
+            .into_values()
+            .next()
+            .unwrap(),
     )
-    .await?
-    .into_values()
-    .next()
-    .unwrap())
-}
-
-fn derive_template_url(s3_bucket: &str, s3_region: &str, s3_key: &str) -> String {
-    format!("https://{s3_bucket}.s3.{s3_region}.amazonaws.com/{s3_key}")
 }
 
 pub async fn deploy_stack_from_s3(
     pr: &Printer,
-    profile: &str,
+    aws: &AwsContext,
     stack_name: &str,
-    stack_region: &str,
     s3_bucket: &str,
     s3_region: &str,
     s3_key: &str,
     method: StackDeploymentMethod,
     parameters: HashMap<String, String>,
 ) -> Result<(), CliError> {
-    let client = Client::new(&config_from_profile(profile, stack_region).await);
+    let client = aws.client::<Client>().await;
 
-    let s3_url = derive_template_url(s3_bucket, s3_region, s3_key);
+    let s3_url = s3_object_url(s3_bucket, s3_region, s3_key);
     let parameters = parameters
         .into_iter()
         .map(|(key, value)| {
@@ -177,18 +191,18 @@ pub async fn deploy_stack_from_s3(
                 Ok(Ok(final_poll))
                     if final_poll.status() == Some(&ChangeSetStatus::CreateComplete) =>
                 {
-                    pr.important(&format!(
-                        "Changeset '{}' created successfully. Please manually apply it in AWS Console.",
-                        changeset_name
-                    ));
-                    pr.continue_after_enter(None)?;
-                    pr.info("Polling until update complete...");
-                    client
-                        .wait_until_stack_update_complete()
-                        .stack_name(stack_name)
-                        .wait(DEPLOY_WAIT_TIMEOUT)
-                        .await
-                        .map_err(|e| CloudFormationDeploymentFailed::with_debug(stack_name, &e))?;
+                    pr.info(&format!("Changeset '{}' created.", changeset_name));
+                    let since = stack_events_checkpoint(aws, stack_name).await?;
+                    review_and_execute_change_set(
+                        pr,
+                        aws,
+                        stack_name,
+                        &changeset_name,
+                        since,
+                        DEPLOY_WAIT_TIMEOUT,
+                    )
+                    .await?;
+                    pr.info("Deployment succeeded.");
                 }
                 Err(WaiterError::FailureState(failure_state))
                     if failure_state
@@ -218,7 +232,8 @@ pub async fn deploy_stack_from_s3(
             }
         }
         StackDeploymentMethod::Direct => {
-            let stack_exists = stack_exists(profile, stack_region, stack_name).await?;
+            let stack_exists = stack_exists(aws, stack_name).await?;
+            let since = stack_events_checkpoint(aws, stack_name).await?;
             match stack_exists {
                 true => {
                     pr.info(&format!(
@@ -253,28 +268,65 @@ pub async fn deploy_stack_from_s3(
                         .map_err(|e| CloudFormationDeploymentFailed::with_debug(stack_name, &e))?;
                 }
             }
-            pr.info("Deployment initiated. Polling status...");
-            match stack_exists {
-                true => {
-                    client
-                        .wait_until_stack_update_complete()
-                        .stack_name(stack_name)
-                        .wait(DEPLOY_WAIT_TIMEOUT)
-                        .await
-                        .map_err(|e| CloudFormationDeploymentFailed::with_debug(stack_name, &e))?;
-                }
-                false => {
-                    client
-                        .wait_until_stack_create_complete()
-                        .stack_name(stack_name)
-                        .wait(DEPLOY_WAIT_TIMEOUT)
-                        .await
-                        .map_err(|e| CloudFormationDeploymentFailed::with_debug(stack_name, &e))?;
-                }
-            }
+            pr.info("Deployment initiated. Streaming stack events...");
+            watch_stack(pr, aws, stack_name, since, DEPLOY_WAIT_TIMEOUT).await?;
             pr.info("Deployment succeeded.");
         }
     }
 
     Ok(())
 }
+
+/// Packages the template and the local artifacts it references (see
+/// `package_template`), validates it, and deploys it with
+/// `deploy_stack_from_s3`. Everything is uploaded under the stack name in the
+/// artifact bucket (in the stack's region).
+pub async fn deploy_stack_from_local_template(
+    pr: &Printer,
+    aws: &AwsContext,
+    stack_name: &str,
+    template_path: &Path,
+    artifact_bucket: ArtifactBucket<'_>,
+    method: StackDeploymentMethod,
+    parameters: HashMap<String, String>,
+) -> Result<(), CliError> {
+    let bucket = artifact_bucket.name(aws.region());
+    s3_create_bucket_if_not_exists(pr, aws, &bucket).await?;
+
+    pr.info(&format!(
+        "Packaging CloudFormation template '{}'...",
+        template_path.display()
+    ));
+    let template = package_template(pr, aws, template_path, &bucket, stack_name).await?;
+    let key = upload_content_addressed(
+        pr,
+        aws,
+        &bucket,
+        &format!("{}/templates", stack_name),
+        "yaml",
+        template.into_bytes(),
+    )
+    .await?;
+
+    let client = aws.client::<Client>().await;
+    client
+        .validate_template()
+        .template_url(s3_object_url(&bucket, aws.region(), &key))
+        .send()
+        .await
+        .map_err(|e| {
+            CloudFormationTemplateInvalid::with_debug(&template_path.display().to_string(), &e)
+        })?;
+
+    deploy_stack_from_s3(
+        pr,
+        aws,
+        stack_name,
+        &bucket,
+        aws.region(),
+        &key,
+        method,
+        parameters,
+    )
+    .await
+}
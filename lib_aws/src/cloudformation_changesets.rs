@@ -0,0 +1,278 @@
+use std::time::Duration;
+
+use aws_sdk_cloudformation::Client;
+use chrono::{DateTime, Utc};
+use lib_core::{confirm, confirm_by_typing, CliError, Printer};
+
+use crate::{watch_stack, AwsContext, CloudFormationError};
+
+/// Resources holding data, which is lost if they are deleted or replaced.
+const STATEFUL_RESOURCE_TYPES: [&str; 12] = [
+    "AWS::RDS::DBInstance",
+    "AWS::RDS::DBCluster",
+    "AWS::DynamoDB::Table",
+    "AWS::DynamoDB::GlobalTable",
+    "AWS::S3::Bucket",
+    "AWS::EFS::FileSystem",
+    "AWS::EC2::Volume",
+    "AWS::ElastiCache::ReplicationGroup",
+    "AWS::OpenSearchService::Domain",
+    "AWS::Cognito::UserPool",
+    "AWS::KMS::Key",
+    "AWS::SecretsManager::Secret",
+];
+
+/// A resource change of a changeset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackChange {
+    /// For resources of nested stacks, the logical IDs of the nested stacks
+    /// (ex. 'Api/').
+    pub stack_prefix: String,
+    /// 'Add', 'Modify', 'Remove', 'Import' or 'Dynamic'.
+    pub action: String,
+    pub logical_id: String,
+    pub resource_type: String,
+    /// 'True', 'False' or 'Conditional' (depending on values only known during
+    /// the update). Empty for additions and removals.
+    pub replacement: String,
+    /// Ex. 'Properties.BucketName', or 'Tags'. Marked with '!' if the change
+    /// requires the resource to be recreated.
+    pub changed_properties: Vec<String>,
+}
+
+impl StackChange {
+    /// Deletion or replacement of a resource holding data.
+    pub fn is_destructive(&self) -> bool {
+        STATEFUL_RESOURCE_TYPES.contains(&self.resource_type.as_str())
+            && (self.action == "Remove"
+                || matches!(self.replacement.as_str(), "True" | "Conditional"))
+    }
+}
+
+/// Changes of the changeset and of the changesets of its nested stacks (if it
+/// was created with 'include_nested_stacks').
+pub async fn describe_change_set_changes(
+    aws: &AwsContext,
+    stack_name: &str,
+    change_set_name: &str,
+) -> Result<Vec<StackChange>, CliError> {
+    let client = aws.client::<Client>().await;
+    let mut changes = Vec::new();
+    // Nested changesets are identified by their ARN, which doesn't need the
+    // stack name.
+    let mut pending = vec![(
+        Some(stack_name.to_string()),
+        change_set_name.to_string(),
+        String::new(),
+    )];
+
+    while let Some((stack, change_set, prefix)) = pending.pop() {
+        let mut next_token = None;
+        loop {
+            let output = client
+                .describe_change_set()
+                .set_stack_name(stack.clone())
+                .change_set_name(&change_set)
+                .set_next_token(next_token)
+                .send()
+                .await
+                .map_err(|e| CloudFormationError::with_debug(&e))?;
+            for change in output.changes() {
+                let Some(resource) = change.resource_change() else {
+                    continue;
+                };
+                let logical_id = resource.logical_resource_id().unwrap_or_default();
+                if let Some(nested_change_set) = resource.change_set_id() {
+                    pending.push((
+                        None,
+                        nested_change_set.to_string(),
+                        format!("{}{}/", prefix, logical_id),
+                    ));
+                }
+                changes.push(StackChange {
+                    stack_prefix: prefix.clone(),
+                    action: resource.action().map_or("", |a| a.as_str()).to_string(),
+                    logical_id: logical_id.to_string(),
+                    resource_type: resource.resource_type().unwrap_or_default().to_string(),
+                    replacement: resource
+                        .replacement()
+                        .map_or("", |r| r.as_str())
+                        .to_string(),
+                    changed_properties: changed_properties(resource.details()),
+                });
+            }
+            next_token = output.next_token().map(str::to_string);
+            if next_token.is_none() {
+                break;
+            }
+        }
+    }
+
+    changes.sort_by(|a, b| (&a.stack_prefix, &a.logical_id).cmp(&(&b.stack_prefix, &b.logical_id)));
+    Ok(changes)
+}
+
+/// One row per resource change. Additions are green, modifications yellow, and
+/// removals and replacements red.
+pub fn print_change_set(pr: &Printer, changes: &[StackChange]) {
+    if changes.is_empty() {
+        pr.info("No changes.");
+        return;
+    }
+    let header = [
+        "Action".to_string(),
+        "Resource".to_string(),
+        "Type".to_string(),
+        "Replacement".to_string(),
+        "Changed properties".to_string(),
+    ];
+    let rows = changes
+        .iter()
+        .map(|change| {
+            [
+                change.action.clone(),
+                format!("{}{}", change.stack_prefix, change.logical_id),
+                change.resource_type.clone(),
+                change.replacement.clone(),
+                change.changed_properties.join(", "),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let widths = (0..header.len())
+        .map(|i| {
+            rows.iter()
+                .chain([&header])
+                .map(|row| row[i].chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    let format_row = |row: &[String; 5]| {
+        row.iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    pr.important(&format_row(&header));
+    for (change, row) in changes.iter().zip(&rows) {
+        let line = format_row(row);
+        match change.action.as_str() {
+            "Remove" => pr.error(&line),
+            _ if change.replacement == "True" => pr.error(&line),
+            "Add" | "Import" => pr.success(&line),
+            _ => pr.warn(&line),
+        }
+    }
+}
+
+/// Prints the changes and asks to execute the changeset, with a typed
+/// confirmation if it deletes or replaces stateful resources. Then waits for
+/// the stack update, printing its events. If not confirmed, the changeset is
+/// kept (for review in the AWS Console) and `UserCancelled` is returned.
+pub async fn review_and_execute_change_set(
+    pr: &Printer,
+    aws: &AwsContext,
+    stack_name: &str,
+    change_set_name: &str,
+    since: Option<DateTime<Utc>>,
+    timeout: Duration,
+) -> Result<(), CliError> {
+    let changes = describe_change_set_changes(aws, stack_name, change_set_name).await?;
+    print_change_set(pr, &changes);
+
+    let destructive = changes
+        .iter()
+        .filter(|change| change.is_destructive())
+        .map(|change| {
+            format!(
+                "{}{} ({})",
+                change.stack_prefix, change.logical_id, change.resource_type
+            )
+        })
+        .collect::<Vec<_>>();
+    let confirmed = match destructive.is_empty() {
+        true => {
+            pr.important(&format!("Execute changeset '{}'?", change_set_name));
+            confirm()
+        }
+        false => {
+            pr.caution_box(&format!(
+                "The changeset deletes or replaces resources holding data, which may be lost: {}.",
+                destructive.join(", ")
+            ));
+            confirm_by_typing("Executing the changeset.", stack_name)
+        }
+    };
+    if let Err(e) = confirmed {
+        pr.info(&format!(
+            "Changeset '{}' was not executed. It can still be reviewed in the AWS Console.",
+            change_set_name
+        ));
+        return Err(e);
+    }
+
+    let client = aws.client::<Client>().await;
+    client
+        .execute_change_set()
+        .stack_name(stack_name)
+        .change_set_name(change_set_name)
+        .send()
+        .await
+        .map_err(|e| CloudFormationError::with_debug(&e))?;
+    pr.info("Changeset execution initiated. Streaming stack events...");
+    watch_stack(pr, aws, stack_name, since, timeout).await
+}
+
+fn changed_properties(
+    details: &[aws_sdk_cloudformation::types::ResourceChangeDetail],
+) -> Vec<String> {
+    let mut properties = details
+        .iter()
+        .filter_map(|detail| {
+            let target = detail.target()?;
+            let attribute = target.attribute().map_or("", |a| a.as_str());
+            let mut path = match target.name() {
+                Some(name) => format!("{}.{}", attribute, name),
+                None => attribute.to_string(),
+            };
+            if target.requires_recreation().map(|r| r.as_str()) == Some("Always") {
+                path.push('!');
+            }
+            Some(path)
+        })
+        .collect::<Vec<_>>();
+    // The same property is listed once per cause (ex. a parameter and a
+    // resource attribute).
+    properties.sort();
+    properties.dedup();
+    properties
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_deleting_or_replacing_stateful_resources_is_destructive() {
+        let change = |action: &str, resource_type: &str, replacement: &str| StackChange {
+            stack_prefix: String::new(),
+            action: action.to_string(),
+            logical_id: "Resource".to_string(),
+            resource_type: resource_type.to_string(),
+            replacement: replacement.to_string(),
+            changed_properties: Vec::new(),
+        };
+        assert!(change("Remove", "AWS::DynamoDB::Table", "").is_destructive());
+        assert!(change("Modify", "AWS::RDS::DBInstance", "Conditional").is_destructive());
+        assert!(!change("Modify", "AWS::S3::Bucket", "False").is_destructive());
+        assert!(!change("Remove", "AWS::Lambda::Function", "").is_destructive());
+        assert!(!change("Modify", "AWS::Lambda::Function", "True").is_destructive());
+    }
+}
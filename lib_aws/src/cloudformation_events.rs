@@ -0,0 +1,453 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use aws_sdk_cloudformation::{
+    error::{ProvideErrorMetadata, SdkError},
+    types::{Stack, StackEvent},
+    Client,
+};
+use chrono::{DateTime, Utc};
+use lib_core::{define_cli_error, CliError, Printer};
+
+use crate::{AwsContext, CloudFormationError};
+
+define_cli_error!(
+    CloudFormationResourceFailed,
+    "CloudFormation operation on stack '{stack_name}' failed: resource '{resource}' ({resource_type}) is {status}: {reason}",
+    { stack_name: &str, resource: &str, resource_type: &str, status: &str, reason: &str }
+);
+define_cli_error!(
+    CloudFormationOperationFailed,
+    "CloudFormation operation on stack '{stack_name}' failed: {reason}.",
+    { stack_name: &str, reason: &str }
+);
+
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const NESTED_STACK_TYPE: &str = "AWS::CloudFormation::Stack";
+
+/// Statuses the stack ends in when an operation succeeds. Any other final
+/// status (ex. 'UPDATE_ROLLBACK_COMPLETE') means it failed.
+const SUCCESS_STATUSES: [&str; 4] = [
+    "CREATE_COMPLETE",
+    "UPDATE_COMPLETE",
+    "IMPORT_COMPLETE",
+    "DELETE_COMPLETE",
+];
+
+/// Time of the latest event of the stack, to pass to `watch_stack` before
+/// starting an operation, so that older events are skipped. `None` if the
+/// stack doesn't exist (yet).
+pub async fn stack_events_checkpoint(
+    aws: &AwsContext,
+    stack_name: &str,
+) -> Result<Option<DateTime<Utc>>, CliError> {
+    let client = aws.client::<Client>().await;
+    let response = client
+        .describe_stack_events()
+        .stack_name(stack_name)
+        .send()
+        .await;
+    match response {
+        // Events are returned newest first.
+        Ok(output) => Ok(output.stack_events().first().and_then(event_time)),
+        Err(e) if is_stack_not_found(&e) => Ok(None),
+        Err(e) => Err(CloudFormationError::with_debug(&e)),
+    }
+}
+
+/// `None` if the stack doesn't exist.
+pub(crate) async fn describe_stack(
+    client: &Client,
+    stack_name: &str,
+) -> Result<Option<Stack>, CliError> {
+    match client.describe_stacks().stack_name(stack_name).send().await {
+        Ok(output) => Ok(output.stacks().first().cloned()),
+        Err(e) if is_stack_not_found(&e) => Ok(None),
+        Err(e) => Err(CloudFormationError::with_debug(&e)),
+    }
+}
+
+/// CloudFormation has no specific error for missing stacks, only a
+/// 'ValidationError' saying the stack "does not exist". Other errors (ex.
+/// throttling or missing permissions) must not be mistaken for it.
+pub(crate) fn is_stack_not_found<E, R>(e: &SdkError<E, R>) -> bool
+where
+    E: ProvideErrorMetadata,
+{
+    e.as_service_error().is_some_and(|se| {
+        se.code() == Some("ValidationError")
+            && se.message().is_some_and(|m| m.contains("does not exist"))
+    })
+}
+
+/// Prints the events of the stack (and of its nested stacks) after 'since' as
+/// they happen, until the stack reaches a final status. Fails if that status
+/// is not a successful one, with the first resource that failed (if any).
+pub async fn watch_stack(
+    pr: &Printer,
+    aws: &AwsContext,
+    stack_name: &str,
+    since: Option<DateTime<Utc>>,
+    timeout: Duration,
+) -> Result<(), CliError> {
+    watch_stack_until(pr, aws, stack_name, since, timeout, &SUCCESS_STATUSES).await
+}
+
+/// Like `watch_stack`, for operations that succeed with other statuses (ex.
+/// 'UPDATE_ROLLBACK_COMPLETE' when continuing a rollback).
+pub(crate) async fn watch_stack_until(
+    pr: &Printer,
+    aws: &AwsContext,
+    stack_name: &str,
+    since: Option<DateTime<Utc>>,
+    timeout: Duration,
+    success_statuses: &[&str],
+) -> Result<(), CliError> {
+    let client = aws.client::<Client>().await;
+    let mut watcher = StackWatcher {
+        since,
+        seen: HashSet::new(),
+        stacks: BTreeMap::from([(stack_name.to_string(), String::new())]),
+        first_failure: None,
+    };
+    let started = Instant::now();
+
+    loop {
+        watcher.print_new_events(pr, &client).await?;
+        let (status, reason) = stack_status(&client, stack_name).await?;
+        if !status.ends_with("_IN_PROGRESS") {
+            // Events of the last transition may be published after it.
+            watcher.print_new_events(pr, &client).await?;
+            if success_statuses.contains(&status.as_str()) {
+                return Ok(());
+            }
+            return Err(watcher.failure(stack_name, &status, reason.as_deref()));
+        }
+        if started.elapsed() > timeout {
+            return Err(CloudFormationOperationFailed::new(
+                stack_name,
+                &format!("still {} after {} minutes", status, timeout.as_secs() / 60),
+            ));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+struct StackWatcher {
+    since: Option<DateTime<Utc>>,
+    seen: HashSet<String>,
+    /// Stack names or IDs, with the prefix printed before their resources.
+    /// Nested stacks are added when they show up in the events.
+    stacks: BTreeMap<String, String>,
+    first_failure: Option<(String, StackEvent)>,
+}
+
+impl StackWatcher {
+    async fn print_new_events(&mut self, pr: &Printer, client: &Client) -> Result<(), CliError> {
+        let mut events = Vec::new();
+        for (stack, prefix) in &self.stacks {
+            for event in self.new_events(client, stack).await? {
+                events.push((prefix.clone(), event));
+            }
+        }
+        events.sort_by_key(|(_, event)| event_time(event));
+
+        for (prefix, event) in events {
+            self.seen.insert(event.event_id().to_string());
+            print_event(pr, &prefix, &event);
+            self.record_failure(&prefix, &event);
+            if let Some(nested_id) = nested_stack_id(&event) {
+                self.stacks.entry(nested_id.to_string()).or_insert_with(|| {
+                    format!(
+                        "{}{}/",
+                        prefix,
+                        event.logical_resource_id().unwrap_or_default()
+                    )
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Oldest first. Stops at the first already seen event, since events are
+    /// returned newest first.
+    async fn new_events(&self, client: &Client, stack: &str) -> Result<Vec<StackEvent>, CliError> {
+        let mut events = Vec::new();
+        let mut next_token = None;
+        loop {
+            let output = client
+                .describe_stack_events()
+                .stack_name(stack)
+                .set_next_token(next_token)
+                .send()
+                .await
+                .map_err(|e| CloudFormationError::with_debug(&e))?;
+            next_token = output.next_token().map(str::to_string);
+            if !self.take_new_events(output.stack_events(), &mut events) || next_token.is_none() {
+                break;
+            }
+        }
+        events.reverse();
+        Ok(events)
+    }
+
+    /// Adds the events of the page (newest first) until one that is older than
+    /// 'since' or already seen. Returns false if such an event was found, in
+    /// which case the next pages are older.
+    fn take_new_events(&self, page: &[StackEvent], events: &mut Vec<StackEvent>) -> bool {
+        for event in page {
+            let is_old = match (event_time(event), self.since) {
+                (Some(time), Some(since)) => time <= since,
+                _ => false,
+            };
+            if is_old || self.seen.contains(event.event_id()) {
+                return false;
+            }
+            events.push(event.clone());
+        }
+        true
+    }
+
+    /// Nested stacks fail because one of their resources did, so a resource
+    /// that is not a stack is preferred. Resources whose operation was only
+    /// cancelled are not the cause.
+    fn record_failure(&mut self, prefix: &str, event: &StackEvent) {
+        let status = event.resource_status().map_or("", |s| s.as_str());
+        if !status.ends_with("_FAILED") || is_cancellation(event) {
+            return;
+        }
+        let is_stack = event.resource_type() == Some(NESTED_STACK_TYPE);
+        match &self.first_failure {
+            None => self.first_failure = Some((prefix.to_string(), event.clone())),
+            Some((_, first)) if first.resource_type() == Some(NESTED_STACK_TYPE) && !is_stack => {
+                self.first_failure = Some((prefix.to_string(), event.clone()))
+            }
+            _ => {}
+        }
+    }
+
+    fn failure(&self, stack_name: &str, status: &str, reason: Option<&str>) -> CliError {
+        match &self.first_failure {
+            Some((prefix, event)) => CloudFormationResourceFailed::new(
+                stack_name,
+                &format!(
+                    "{}{}",
+                    prefix,
+                    event.logical_resource_id().unwrap_or_default()
+                ),
+                event.resource_type().unwrap_or_default(),
+                event.resource_status().map_or("", |s| s.as_str()),
+                event.resource_status_reason().unwrap_or("no reason given"),
+            ),
+            None => CloudFormationOperationFailed::new(
+                stack_name,
+                &match reason {
+                    Some(reason) => format!("stack is {} ({})", status, reason),
+                    None => format!("stack is {}", status),
+                },
+            ),
+        }
+    }
+}
+
+async fn stack_status(
+    client: &Client,
+    stack_name: &str,
+) -> Result<(String, Option<String>), CliError> {
+    let output = client
+        .describe_stacks()
+        .stack_name(stack_name)
+        .send()
+        .await
+        .map_err(|e| CloudFormationError::with_debug(&e))?;
+    let stack = output
+        .stacks()
+        .first()
+        .ok_or_else(CloudFormationError::new)?;
+    Ok((
+        stack.stack_status().as_str().to_string(),
+        stack.stack_status_reason().map(str::to_string),
+    ))
+}
+
+fn print_event(pr: &Printer, prefix: &str, event: &StackEvent) {
+    let status = event.resource_status().map_or("", |s| s.as_str());
+    let mut line = format!(
+        "{} {:<36} {}{} ({})",
+        event_time(event).map_or_else(String::new, |t| t.format("%H:%M:%S").to_string()),
+        status,
+        prefix,
+        event.logical_resource_id().unwrap_or_default(),
+        event.resource_type().unwrap_or_default()
+    );
+    if let Some(reason) = event.resource_status_reason() {
+        line.push_str(&format!(": {}", reason));
+    }
+
+    if status.ends_with("_FAILED") {
+        pr.error(&line);
+    } else if status.contains("ROLLBACK") || status.starts_with("DELETE") {
+        pr.warn(&line);
+    } else if status.ends_with("_COMPLETE") {
+        pr.success(&line);
+    } else {
+        pr.info(&line);
+    }
+}
+
+/// Resources whose operation was cancelled because another one failed.
+fn is_cancellation(event: &StackEvent) -> bool {
+    event
+        .resource_status_reason()
+        .is_some_and(|reason| reason.contains("cancelled"))
+}
+
+/// The nested stack's ID, if the event is about one (rather than about the
+/// stack it belongs to).
+fn nested_stack_id(event: &StackEvent) -> Option<&str> {
+    let id = event.physical_resource_id()?;
+    (event.resource_type() == Some(NESTED_STACK_TYPE)
+        && id.starts_with("arn:")
+        && id != event.stack_id())
+    .then_some(id)
+}
+
+pub(crate) fn event_time(event: &StackEvent) -> Option<DateTime<Utc>> {
+    let time = event.timestamp();
+    DateTime::from_timestamp(time.secs(), time.subsec_nanos())
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_cloudformation::{
+        primitives::DateTime as AwsDateTime,
+        types::{builders::StackEventBuilder, ResourceStatus},
+    };
+
+    use super::*;
+
+    const STACK_ID: &str = "arn:aws:cloudformation:eu-west-1:123456789012:stack/app/1";
+    const NESTED_ID: &str = "arn:aws:cloudformation:eu-west-1:123456789012:stack/app-Api/2";
+
+    /// An event at 'secs' of the resource, ex. 'Bucket:AWS::S3::Bucket'.
+    fn event(id: &str, secs: i64, resource: &str, status: &str) -> StackEventBuilder {
+        let (logical_id, resource_type) = resource.split_once(':').unwrap();
+        StackEvent::builder()
+            .stack_id(STACK_ID)
+            .stack_name("app")
+            .event_id(id)
+            .timestamp(AwsDateTime::from_secs(secs))
+            .logical_resource_id(logical_id)
+            .resource_type(resource_type)
+            .resource_status(ResourceStatus::from(status))
+    }
+
+    fn watcher(since: Option<i64>) -> StackWatcher {
+        StackWatcher {
+            since: since.and_then(|secs| DateTime::from_timestamp(secs, 0)),
+            seen: HashSet::new(),
+            stacks: BTreeMap::new(),
+            first_failure: None,
+        }
+    }
+
+    #[test]
+    fn the_root_cause_is_recorded_rather_than_cancellations_and_stacks() {
+        let mut watcher = watcher(None);
+        let cancelled = event("1", 10, "Queue:AWS::SQS::Queue", "UPDATE_FAILED")
+            .resource_status_reason("Resource update cancelled")
+            .build()
+            .unwrap();
+        assert!(is_cancellation(&cancelled));
+        watcher.record_failure("", &cancelled);
+        assert!(watcher.first_failure.is_none());
+
+        for (prefix, event) in [
+            (
+                "",
+                event("2", 11, "Api:AWS::CloudFormation::Stack", "UPDATE_FAILED"),
+            ),
+            (
+                "",
+                event("3", 12, "Table:AWS::DynamoDB::Table", "UPDATE_COMPLETE"),
+            ),
+            (
+                "Api/",
+                event("4", 13, "Function:AWS::Lambda::Function", "UPDATE_FAILED"),
+            ),
+            (
+                "",
+                event("5", 14, "Bucket:AWS::S3::Bucket", "UPDATE_FAILED"),
+            ),
+        ] {
+            watcher.record_failure(prefix, &event.build().unwrap());
+        }
+        let (prefix, first) = watcher.first_failure.unwrap();
+        assert_eq!((prefix.as_str(), first.event_id()), ("Api/", "4"));
+    }
+
+    #[test]
+    fn only_events_about_nested_stacks_have_a_nested_stack_id() {
+        let nested = |physical_id: &str| {
+            event(
+                "1",
+                10,
+                "Api:AWS::CloudFormation::Stack",
+                "CREATE_IN_PROGRESS",
+            )
+            .physical_resource_id(physical_id)
+            .build()
+            .unwrap()
+        };
+        assert_eq!(nested_stack_id(&nested(NESTED_ID)), Some(NESTED_ID));
+        // Not created yet, or the event of the stack itself.
+        assert_eq!(nested_stack_id(&nested("")), None);
+        assert_eq!(nested_stack_id(&nested(STACK_ID)), None);
+        let function = event("2", 10, "Function:AWS::Lambda::Function", "CREATE_COMPLETE")
+            .physical_resource_id(NESTED_ID)
+            .build()
+            .unwrap();
+        assert_eq!(nested_stack_id(&function), None);
+    }
+
+    #[test]
+    fn new_events_stop_at_the_cut_off_or_at_seen_events() {
+        let page = |events: [StackEventBuilder; 2]| events.map(|e| e.build().unwrap());
+        let first_page = page([
+            event("4", 40, "Bucket:AWS::S3::Bucket", "CREATE_COMPLETE"),
+            event("3", 30, "Bucket:AWS::S3::Bucket", "CREATE_IN_PROGRESS"),
+        ]);
+        let second_page = page([
+            event(
+                "2",
+                20,
+                "app:AWS::CloudFormation::Stack",
+                "UPDATE_IN_PROGRESS",
+            ),
+            event("1", 10, "app:AWS::CloudFormation::Stack", "CREATE_COMPLETE"),
+        ]);
+        let ids = |events: &[StackEvent]| {
+            events
+                .iter()
+                .map(|e| e.event_id().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let since_10 = watcher(Some(10));
+        let mut events = Vec::new();
+        assert!(since_10.take_new_events(&first_page, &mut events));
+        assert!(!since_10.take_new_events(&second_page, &mut events));
+        assert_eq!(ids(&events), ["4", "3", "2"]);
+
+        let mut seen_3 = watcher(None);
+        seen_3.seen.insert("3".to_string());
+        let mut events = Vec::new();
+        assert!(!seen_3.take_new_events(&first_page, &mut events));
+        assert_eq!(ids(&events), ["4"]);
+    }
+}
@@ -0,0 +1,607 @@
+use std::time::Instant;
+
+use aws_sdk_cloudformation::{
+    types::{
+        StackDriftDetectionStatus, StackEvent, StackResourceDriftStatus, StackResourceSummary,
+    },
+    Client,
+};
+use chrono::{DateTime, Utc};
+use lib_core::{define_cli_error, json_diff, print_json_diff, CliError, Printer};
+use serde_json::Value;
+
+use crate::{
+    cloudformation::DEPLOY_WAIT_TIMEOUT,
+    cloudformation_events::{describe_stack, event_time, watch_stack_until, POLL_INTERVAL},
+    stack_events_checkpoint, AwsContext, CloudFormationError, CloudFormationStackNotFound,
+};
+
+define_cli_error!(
+    CloudFormationTerminationProtected,
+    "The CloudFormation stack '{stack_name}' has termination protection enabled, disable it before deleting the stack.",
+    { stack_name: &str }
+);
+define_cli_error!(
+    CloudFormationInvalidStackStatus,
+    "The CloudFormation stack '{stack_name}' is {status}, expected {expected}.",
+    { stack_name: &str, status: &str, expected: &str }
+);
+define_cli_error!(
+    CloudFormationDriftDetectionFailed,
+    "Failed to detect drift of CloudFormation stack '{stack_name}': {reason}.",
+    { stack_name: &str, reason: &str }
+);
+define_cli_error!(
+    CloudFormationInvalidStackPolicy,
+    "The stack policy of CloudFormation stack '{stack_name}' is not valid JSON.",
+    { stack_name: &str }
+);
+
+/// A resource kept when its stack was deleted, because of its
+/// 'DeletionPolicy' or because it was passed in 'retain_resources'.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetainedResource {
+    pub logical_id: String,
+    pub resource_type: String,
+    pub physical_id: Option<String>,
+}
+
+/// A resource whose actual configuration differs from its template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceDrift {
+    pub logical_id: String,
+    pub resource_type: String,
+    pub physical_id: Option<String>,
+    /// 'MODIFIED' or 'DELETED'.
+    pub status: String,
+    pub differences: Vec<PropertyDrift>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropertyDrift {
+    /// JSON pointer to the property (ex. '/Tags/0/Value').
+    pub path: String,
+    pub expected: String,
+    pub actual: String,
+    /// 'ADD', 'REMOVE' or 'NOT_EQUAL'.
+    pub difference_type: String,
+}
+
+/// Deletes the stack and waits for the deletion, printing its events. Does
+/// nothing if the stack doesn't exist. 'retain_resources' (logical IDs) can
+/// only be given for stacks in 'DELETE_FAILED', to skip the resources that
+/// couldn't be deleted. Returns the resources that were kept, which still
+/// exist (and may still cost money) but are no longer managed by the stack.
+pub async fn delete_stack(
+    pr: &Printer,
+    aws: &AwsContext,
+    stack_name: &str,
+    retain_resources: &[&str],
+) -> Result<Vec<RetainedResource>, CliError> {
+    let client = aws.client::<Client>().await;
+    let Some(stack) = describe_stack(&client, stack_name).await? else {
+        pr.info(&format!(
+            "CloudFormation stack '{}' does not exist.",
+            stack_name
+        ));
+        return Ok(Vec::new());
+    };
+    if stack.enable_termination_protection() == Some(true) {
+        return Err(CloudFormationTerminationProtected::new(stack_name));
+    }
+    // The stack can only be described by its ID once deleted.
+    let stack_id = stack.stack_id().unwrap_or(stack_name).to_string();
+    let since = stack_events_checkpoint(aws, &stack_id).await?;
+
+    client
+        .delete_stack()
+        .stack_name(&stack_id)
+        .set_retain_resources(
+            (!retain_resources.is_empty())
+                .then(|| retain_resources.iter().map(|id| id.to_string()).collect()),
+        )
+        .send()
+        .await
+        .map_err(|e| CloudFormationError::with_debug(&e))?;
+    pr.info(&format!(
+        "Deletion of stack '{}' initiated. Streaming stack events...",
+        stack_name
+    ));
+    watch_stack_until(
+        pr,
+        aws,
+        &stack_id,
+        since,
+        DEPLOY_WAIT_TIMEOUT,
+        &["DELETE_COMPLETE"],
+    )
+    .await?;
+
+    let retained = retained_resources(&client, &stack_id, since).await?;
+    if !retained.is_empty() {
+        pr.warn("The following resources were retained, and must be deleted manually if no longer needed:");
+        for resource in &retained {
+            pr.warn(&format!(
+                "  {} ({}): {}",
+                resource.logical_id,
+                resource.resource_type,
+                resource.physical_id.as_deref().unwrap_or("-")
+            ));
+        }
+    }
+    pr.success(&format!("Deleted stack '{}'.", stack_name));
+    Ok(retained)
+}
+
+/// Recovers a stack in 'UPDATE_ROLLBACK_FAILED', which can't be updated again
+/// until its rollback completes. The rollback usually fails because a resource
+/// can't be restored (ex. it was changed or deleted outside of CloudFormation):
+/// after fixing it, or by skipping it with 'resources_to_skip' (logical IDs,
+/// prefixed by the nested stack's logical ID for resources of nested stacks,
+/// ex. 'Api.Function'). Skipped resources are marked as rolled back, which may
+/// leave the stack inconsistent with the actual resources.
+pub async fn continue_update_rollback(
+    pr: &Printer,
+    aws: &AwsContext,
+    stack_name: &str,
+    resources_to_skip: &[&str],
+) -> Result<(), CliError> {
+    let client = aws.client::<Client>().await;
+    let stack = describe_stack(&client, stack_name)
+        .await?
+        .ok_or_else(|| CloudFormationStackNotFound::new(stack_name, aws.region()))?;
+    let status = stack.stack_status().as_str();
+    if status != "UPDATE_ROLLBACK_FAILED" {
+        return Err(CloudFormationInvalidStackStatus::new(
+            stack_name,
+            status,
+            "UPDATE_ROLLBACK_FAILED",
+        ));
+    }
+
+    let failed = failed_resources(&client, stack_name).await?;
+    if !failed.is_empty() {
+        pr.warn("Resources that failed to roll back:");
+        for (logical_id, resource_type, reason) in &failed {
+            pr.warn(&format!("  {} ({}): {}", logical_id, resource_type, reason));
+        }
+    }
+    if !resources_to_skip.is_empty() {
+        pr.warn(&format!(
+            "Skipping the rollback of: {}.",
+            resources_to_skip.join(", ")
+        ));
+    }
+
+    let since = stack_events_checkpoint(aws, stack_name).await?;
+    client
+        .continue_update_rollback()
+        .stack_name(stack_name)
+        .set_resources_to_skip(
+            (!resources_to_skip.is_empty())
+                .then(|| resources_to_skip.iter().map(|id| id.to_string()).collect()),
+        )
+        .send()
+        .await
+        .map_err(|e| CloudFormationError::with_debug(&e))?;
+    pr.info("Rollback continued. Streaming stack events...");
+    watch_stack_until(
+        pr,
+        aws,
+        stack_name,
+        since,
+        DEPLOY_WAIT_TIMEOUT,
+        &["UPDATE_ROLLBACK_COMPLETE"],
+    )
+    .await?;
+    pr.success(&format!(
+        "Stack '{}' is back to its previous state, and can be updated again.",
+        stack_name
+    ));
+    Ok(())
+}
+
+/// Compares the actual configuration of the stack's resources with its
+/// template, and prints the differences. Resources that don't support drift
+/// detection are ignored.
+pub async fn detect_stack_drift(
+    pr: &Printer,
+    aws: &AwsContext,
+    stack_name: &str,
+) -> Result<Vec<ResourceDrift>, CliError> {
+    let client = aws.client::<Client>().await;
+    let detection_id = client
+        .detect_stack_drift()
+        .stack_name(stack_name)
+        .send()
+        .await
+        .map_err(|e| CloudFormationError::with_debug(&e))?
+        .stack_drift_detection_id()
+        .to_string();
+
+    pr.info(&format!("Detecting drift of stack '{}'...", stack_name));
+    let started = Instant::now();
+    loop {
+        let output = client
+            .describe_stack_drift_detection_status()
+            .stack_drift_detection_id(&detection_id)
+            .send()
+            .await
+            .map_err(|e| CloudFormationError::with_debug(&e))?;
+        match output.detection_status() {
+            StackDriftDetectionStatus::DetectionInProgress => {}
+            // Some resources couldn't be checked, the others are reported.
+            StackDriftDetectionStatus::DetectionFailed => {
+                pr.warn(&format!(
+                    "Drift detection was incomplete: {}",
+                    output
+                        .detection_status_reason()
+                        .unwrap_or("no reason given")
+                ));
+                break;
+            }
+            _ => break,
+        }
+        if started.elapsed() > DEPLOY_WAIT_TIMEOUT {
+            return Err(CloudFormationDriftDetectionFailed::new(
+                stack_name,
+                "timed out",
+            ));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    let mut drifts = Vec::new();
+    let mut next_token = None;
+    loop {
+        let output = client
+            .describe_stack_resource_drifts()
+            .stack_name(stack_name)
+            .stack_resource_drift_status_filters(StackResourceDriftStatus::Modified)
+            .stack_resource_drift_status_filters(StackResourceDriftStatus::Deleted)
+            .set_next_token(next_token)
+            .send()
+            .await
+            .map_err(|e| CloudFormationError::with_debug(&e))?;
+        for drift in output.stack_resource_drifts() {
+            drifts.push(ResourceDrift {
+                logical_id: drift.logical_resource_id().to_string(),
+                resource_type: drift.resource_type().to_string(),
+                physical_id: drift.physical_resource_id().map(str::to_string),
+                status: drift.stack_resource_drift_status().as_str().to_string(),
+                differences: drift
+                    .property_differences()
+                    .iter()
+                    .map(|difference| PropertyDrift {
+                        path: difference.property_path().to_string(),
+                        expected: difference.expected_value().to_string(),
+                        actual: difference.actual_value().to_string(),
+                        difference_type: difference.difference_type().as_str().to_string(),
+                    })
+                    .collect(),
+            });
+        }
+        next_token = output.next_token().map(str::to_string);
+        if next_token.is_none() {
+            break;
+        }
+    }
+    drifts.sort_by(|a, b| a.logical_id.cmp(&b.logical_id));
+
+    print_stack_drift(pr, stack_name, &drifts);
+    Ok(drifts)
+}
+
+pub fn print_stack_drift(pr: &Printer, stack_name: &str, drifts: &[ResourceDrift]) {
+    if drifts.is_empty() {
+        pr.success(&format!("Stack '{}' has not drifted.", stack_name));
+        return;
+    }
+    pr.warn(&format!(
+        "{} resource(s) of stack '{}' drifted:",
+        drifts.len(),
+        stack_name
+    ));
+    for drift in drifts {
+        let line = format!(
+            "{} {} ({})",
+            drift.status, drift.logical_id, drift.resource_type
+        );
+        match drift.status.as_str() {
+            "DELETED" => pr.error(&line),
+            _ => pr.warn(&line),
+        }
+        for difference in &drift.differences {
+            pr.info(&format!("    {}", difference.describe()));
+        }
+    }
+}
+
+impl PropertyDrift {
+    /// Ex. '/Timeout: 30 -> 60'.
+    pub fn describe(&self) -> String {
+        match self.difference_type.as_str() {
+            "ADD" => format!("{}: added {}", self.path, self.actual),
+            "REMOVE" => format!("{}: removed {}", self.path, self.expected),
+            _ => format!("{}: {} -> {}", self.path, self.expected, self.actual),
+        }
+    }
+}
+
+/// Termination protection makes `delete_stack` (and deletions from the AWS
+/// Console) fail until it's disabled.
+pub async fn set_termination_protection(
+    pr: &Printer,
+    aws: &AwsContext,
+    stack_name: &str,
+    enabled: bool,
+) -> Result<(), CliError> {
+    let client = aws.client::<Client>().await;
+    client
+        .update_termination_protection()
+        .stack_name(stack_name)
+        .enable_termination_protection(enabled)
+        .send()
+        .await
+        .map_err(|e| CloudFormationError::with_debug(&e))?;
+    pr.info(&format!(
+        "Termination protection of stack '{}' is {}.",
+        stack_name,
+        match enabled {
+            true => "enabled",
+            false => "disabled",
+        }
+    ));
+    Ok(())
+}
+
+/// Returns `None` if the stack has no policy (all updates are allowed).
+pub async fn get_stack_policy(
+    aws: &AwsContext,
+    stack_name: &str,
+) -> Result<Option<Value>, CliError> {
+    let client = aws.client::<Client>().await;
+    let output = client
+        .get_stack_policy()
+        .stack_name(stack_name)
+        .send()
+        .await
+        .map_err(|e| CloudFormationError::with_debug(&e))?;
+    output
+        .stack_policy_body()
+        .map(|body| {
+            serde_json::from_str(body)
+                .map_err(|e| CloudFormationInvalidStackPolicy::with_debug(stack_name, &e))
+        })
+        .transpose()
+}
+
+/// Sets the policy that protects the stack's resources from updates (ex. to
+/// deny 'Update:Replace' on a database), after printing the differences with
+/// the current one. Returns whether a change was made. A policy can't be
+/// removed once set, only replaced (ex. by one allowing all updates).
+pub async fn set_stack_policy(
+    pr: &Printer,
+    aws: &AwsContext,
+    stack_name: &str,
+    policy: &Value,
+) -> Result<bool, CliError> {
+    let current = get_stack_policy(aws, stack_name)
+        .await?
+        .unwrap_or(Value::Null);
+    let changes = json_diff(&current, policy);
+    if changes.is_empty() {
+        pr.info(&format!(
+            "No changes to the stack policy of stack '{}'.",
+            stack_name
+        ));
+        return Ok(false);
+    }
+    pr.info(&format!(
+        "Updating the stack policy of stack '{}':",
+        stack_name
+    ));
+    print_json_diff(pr, &changes);
+
+    let client = aws.client::<Client>().await;
+    client
+        .set_stack_policy()
+        .stack_name(stack_name)
+        .stack_policy_body(policy.to_string())
+        .send()
+        .await
+        .map_err(|e| CloudFormationError::with_debug(&e))?;
+    Ok(true)
+}
+
+// Helpers.
+// ----------------------------------------------------------------------------
+
+/// Resources skipped by the deletion started after 'since'.
+async fn retained_resources(
+    client: &Client,
+    stack_id: &str,
+    since: Option<DateTime<Utc>>,
+) -> Result<Vec<RetainedResource>, CliError> {
+    let mut retained = Vec::new();
+    let mut next_token = None;
+    loop {
+        let output = client
+            .describe_stack_events()
+            .stack_name(stack_id)
+            .set_next_token(next_token)
+            .send()
+            .await
+            .map_err(|e| CloudFormationError::with_debug(&e))?;
+        next_token = output.next_token().map(str::to_string);
+        if !take_retained_resources(output.stack_events(), since, &mut retained)
+            || next_token.is_none()
+        {
+            break;
+        }
+    }
+    retained.sort_by(|a, b| a.logical_id.cmp(&b.logical_id));
+    Ok(retained)
+}
+
+/// Adds the resources skipped in the page of events (newest first) until the
+/// first event before 'since'. Returns false if it was found, in which case the
+/// next pages are older.
+fn take_retained_resources(
+    page: &[StackEvent],
+    since: Option<DateTime<Utc>>,
+    retained: &mut Vec<RetainedResource>,
+) -> bool {
+    for event in page {
+        if since.is_some() && event_time(event) <= since {
+            return false;
+        }
+        if event.resource_status().map(|s| s.as_str()) == Some("DELETE_SKIPPED") {
+            retained.push(RetainedResource {
+                logical_id: event.logical_resource_id().unwrap_or_default().to_string(),
+                resource_type: event.resource_type().unwrap_or_default().to_string(),
+                physical_id: event
+                    .physical_resource_id()
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_string),
+            });
+        }
+    }
+    true
+}
+
+/// Logical ID, type and reason of the resources in a '*_FAILED' status.
+async fn failed_resources(
+    client: &Client,
+    stack_name: &str,
+) -> Result<Vec<(String, String, String)>, CliError> {
+    let mut failed = Vec::new();
+    let mut next_token = None;
+    loop {
+        let output = client
+            .list_stack_resources()
+            .stack_name(stack_name)
+            .set_next_token(next_token)
+            .send()
+            .await
+            .map_err(|e| CloudFormationError::with_debug(&e))?;
+        failed.extend(failed_in(output.stack_resource_summaries()));
+        next_token = output.next_token().map(str::to_string);
+        if next_token.is_none() {
+            break;
+        }
+    }
+    Ok(failed)
+}
+
+fn failed_in(resources: &[StackResourceSummary]) -> Vec<(String, String, String)> {
+    resources
+        .iter()
+        .filter(|resource| resource.resource_status().as_str().ends_with("_FAILED"))
+        .map(|resource| {
+            (
+                resource.logical_resource_id().to_string(),
+                resource.resource_type().to_string(),
+                resource
+                    .resource_status_reason()
+                    .unwrap_or("no reason given")
+                    .to_string(),
+            )
+        })
+        .collect()
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_cloudformation::{primitives::DateTime as AwsDateTime, types::ResourceStatus};
+
+    use super::*;
+
+    #[test]
+    fn property_drifts_are_described_by_difference_type() {
+        let drift = |difference_type: &str| PropertyDrift {
+            path: "/Timeout".to_string(),
+            expected: "30".to_string(),
+            actual: "60".to_string(),
+            difference_type: difference_type.to_string(),
+        };
+        assert_eq!(drift("NOT_EQUAL").describe(), "/Timeout: 30 -> 60");
+        assert_eq!(drift("ADD").describe(), "/Timeout: added 60");
+        assert_eq!(drift("REMOVE").describe(), "/Timeout: removed 30");
+    }
+
+    #[test]
+    fn retained_resources_are_the_skipped_ones_since_the_deletion() {
+        let event = |id: &str, secs: i64, logical_id: &str, status: &str| {
+            StackEvent::builder()
+                .stack_id("arn:aws:cloudformation:eu-west-1:123456789012:stack/app/1")
+                .stack_name("app")
+                .event_id(id)
+                .timestamp(AwsDateTime::from_secs(secs))
+                .logical_resource_id(logical_id)
+                .resource_type("AWS::S3::Bucket")
+                .physical_resource_id(format!("app-{}", logical_id.to_lowercase()))
+                .resource_status(ResourceStatus::from(status))
+                .build()
+                .unwrap()
+        };
+        let first_page = [
+            event("5", 50, "Logs", "DELETE_SKIPPED"),
+            event("4", 40, "Assets", "DELETE_COMPLETE"),
+        ];
+        let second_page = [
+            event("3", 30, "Data", "DELETE_SKIPPED"),
+            // Skipped by an earlier deletion attempt.
+            event("2", 20, "Backups", "DELETE_SKIPPED"),
+        ];
+        let since = DateTime::from_timestamp(20, 0);
+
+        let mut retained = Vec::new();
+        assert!(take_retained_resources(&first_page, since, &mut retained));
+        assert!(!take_retained_resources(&second_page, since, &mut retained));
+        assert_eq!(
+            retained
+                .iter()
+                .map(|r| (r.logical_id.as_str(), r.physical_id.as_deref()))
+                .collect::<Vec<_>>(),
+            [("Logs", Some("app-logs")), ("Data", Some("app-data"))]
+        );
+    }
+
+    #[test]
+    fn only_failed_resources_are_listed() {
+        let resource = |logical_id: &str, status: &str, reason: Option<&str>| {
+            StackResourceSummary::builder()
+                .logical_resource_id(logical_id)
+                .resource_type("AWS::Lambda::Function")
+                .last_updated_timestamp(AwsDateTime::from_secs(0))
+                .resource_status(ResourceStatus::from(status))
+                .set_resource_status_reason(reason.map(str::to_string))
+                .build()
+                .unwrap()
+        };
+        let failed = failed_in(&[
+            resource("Api", "UPDATE_FAILED", Some("Function not found")),
+            resource("Worker", "UPDATE_COMPLETE", None),
+            resource("Cron", "DELETE_FAILED", None),
+        ]);
+        assert_eq!(
+            failed,
+            [
+                (
+                    "Api".to_string(),
+                    "AWS::Lambda::Function".to_string(),
+                    "Function not found".to_string()
+                ),
+                (
+                    "Cron".to_string(),
+                    "AWS::Lambda::Function".to_string(),
+                    "no reason given".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,322 @@
+use std::{
+    future::Future,
+    io::{Cursor, Write as _},
+    path::{Path, PathBuf},
+    pin::Pin,
+};
+
+use aws_sdk_s3::{primitives::ByteStream, Client};
+use lib_core::{define_cli_error, CliError, IOError, Printer};
+use serde_yaml::{Mapping, Value};
+use sha2::{Digest as _, Sha256};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{s3_head_object, s3_object_url, AwsContext, S3Error};
+
+define_cli_error!(
+    CloudFormationPackageError,
+    "Failed to package '{path}' for CloudFormation: {details}.",
+    { path: &str, details: &str }
+);
+
+/// How a local path referenced by a resource property is packaged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Artifact {
+    /// Zipped (unless already a .zip or .jar file), and replaced by its
+    /// 's3://' URI.
+    ZipUri,
+    /// Zipped, and replaced by '{ S3Bucket, S3Key }'.
+    ZipBucketKey,
+    /// Packaged itself, and replaced by its HTTPS URL.
+    Template,
+}
+
+/// Resource properties that may reference local paths.
+fn packaged_property(resource_type: &str) -> Option<(&'static str, Artifact)> {
+    match resource_type {
+        "AWS::Serverless::Function" => Some(("CodeUri", Artifact::ZipUri)),
+        "AWS::Serverless::LayerVersion" => Some(("ContentUri", Artifact::ZipUri)),
+        "AWS::Lambda::Function" => Some(("Code", Artifact::ZipBucketKey)),
+        "AWS::Lambda::LayerVersion" => Some(("Content", Artifact::ZipBucketKey)),
+        "AWS::CloudFormation::Stack" => Some(("TemplateURL", Artifact::Template)),
+        "AWS::Serverless::Application" => Some(("Location", Artifact::Template)),
+        _ => None,
+    }
+}
+
+/// Similar to 'aws cloudformation package': uploads the local artifacts
+/// referenced by the template (ex. a function's 'CodeUri', or a nested stack's
+/// 'TemplateURL') under 'key_prefix', and returns the template (as YAML) with
+/// these references replaced by their S3 locations. Relative paths are
+/// relative to the template's directory.
+///
+/// Artifacts are named after the hash of their content (zips are built
+/// deterministically), so unchanged artifacts are not uploaded again, and
+/// don't cause updates of the resources referencing them.
+pub async fn package_template(
+    pr: &Printer,
+    aws: &AwsContext,
+    template_path: &Path,
+    bucket: &str,
+    key_prefix: &str,
+) -> Result<String, CliError> {
+    let template = package_template_value(pr, aws, template_path, bucket, key_prefix).await?;
+    serde_yaml::to_string(&template).map_err(|e| {
+        CloudFormationPackageError::with_debug(
+            &template_path.display().to_string(),
+            "failed to serialize template",
+            &e,
+        )
+    })
+}
+
+/// Uploads 'data' to '<key_prefix>/<hash>.<extension>', unless it already
+/// exists. Returns the key.
+pub(crate) async fn upload_content_addressed(
+    pr: &Printer,
+    aws: &AwsContext,
+    bucket: &str,
+    key_prefix: &str,
+    extension: &str,
+    data: Vec<u8>,
+) -> Result<String, CliError> {
+    let hash = hex::encode(Sha256::digest(&data));
+    let key = format!("{}/{}.{}", key_prefix, &hash[..32], extension);
+    if s3_head_object(aws, bucket, &key).await?.is_some() {
+        pr.debug(&format!("'s3://{}/{}' is already uploaded.", bucket, key));
+        return Ok(key);
+    }
+    let client = aws.client::<Client>().await;
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(&key)
+        .body(ByteStream::from(data))
+        .send()
+        .await
+        .map_err(|e| S3Error::with_debug(&e))?;
+    pr.info(&format!("Uploaded 's3://{}/{}'.", bucket, key));
+    Ok(key)
+}
+
+fn package_template_value<'a>(
+    pr: &'a Printer,
+    aws: &'a AwsContext,
+    template_path: &'a Path,
+    bucket: &'a str,
+    key_prefix: &'a str,
+) -> Pin<Box<dyn Future<Output = Result<Value, CliError>> + 'a>> {
+    Box::pin(async move {
+        let path_str = template_path.display().to_string();
+        let content = std::fs::read_to_string(template_path)
+            .map_err(|e| CloudFormationPackageError::with_debug(&path_str, "failed to read", &e))?;
+        // Also parses JSON templates. Short-form intrinsic functions (ex.
+        // '!Ref') are kept as tagged values.
+        let mut template = serde_yaml::from_str::<Value>(&content).map_err(|e| {
+            CloudFormationPackageError::with_debug(&path_str, "invalid template", &e)
+        })?;
+        let base_dir = template_path.parent().unwrap_or(Path::new("."));
+
+        let Some(resources) = template
+            .get_mut("Resources")
+            .and_then(Value::as_mapping_mut)
+        else {
+            return Ok(template);
+        };
+        for (_, resource) in resources.iter_mut() {
+            let Some((property, artifact)) = resource
+                .get("Type")
+                .and_then(Value::as_str)
+                .and_then(packaged_property)
+            else {
+                continue;
+            };
+            let Some(value) = resource
+                .get_mut("Properties")
+                .and_then(|properties| properties.get_mut(property))
+            else {
+                continue;
+            };
+            let Some(local_path) = local_artifact_path(value, base_dir) else {
+                continue;
+            };
+            let local_str = local_path.display().to_string();
+            if !local_path.exists() {
+                return Err(CloudFormationPackageError::new(
+                    &local_str,
+                    &format!("'{}' does not exist", property),
+                ));
+            }
+
+            *value = match artifact {
+                Artifact::ZipUri | Artifact::ZipBucketKey => {
+                    let (data, extension) = match local_path.extension().and_then(|e| e.to_str()) {
+                        Some(extension @ ("zip" | "jar")) if local_path.is_file() => (
+                            std::fs::read(&local_path).map_err(|e| IOError::with_debug(&e))?,
+                            extension,
+                        ),
+                        _ => (zip_path(&local_path)?, "zip"),
+                    };
+                    let key = upload_content_addressed(
+                        pr,
+                        aws,
+                        bucket,
+                        &format!("{}/artifacts", key_prefix),
+                        extension,
+                        data,
+                    )
+                    .await?;
+                    match artifact {
+                        Artifact::ZipUri => Value::String(format!("s3://{}/{}", bucket, key)),
+                        _ => Value::Mapping(Mapping::from_iter([
+                            ("S3Bucket".into(), bucket.into()),
+                            ("S3Key".into(), key.into()),
+                        ])),
+                    }
+                }
+                Artifact::Template => {
+                    let nested =
+                        package_template_value(pr, aws, &local_path, bucket, key_prefix).await?;
+                    let body = serde_yaml::to_string(&nested).map_err(|e| {
+                        CloudFormationPackageError::with_debug(
+                            &local_str,
+                            "failed to serialize template",
+                            &e,
+                        )
+                    })?;
+                    let key = upload_content_addressed(
+                        pr,
+                        aws,
+                        bucket,
+                        &format!("{}/templates", key_prefix),
+                        "yaml",
+                        body.into_bytes(),
+                    )
+                    .await?;
+                    Value::String(s3_object_url(bucket, aws.region(), &key))
+                }
+            };
+        }
+        Ok(template)
+    })
+}
+
+/// `None` if the value is not a plain string, or is already a remote location.
+fn local_artifact_path(value: &Value, base_dir: &Path) -> Option<PathBuf> {
+    let path = value.as_str()?;
+    if ["s3://", "https://", "http://"]
+        .iter()
+        .any(|scheme| path.starts_with(scheme))
+    {
+        return None;
+    }
+    Some(base_dir.join(path))
+}
+
+/// Zips a directory's content (or a single file). Entries are sorted and
+/// timestamps fixed, so that the same content always gives the same zip.
+/// Unix permissions are kept (ex. for a Lambda 'bootstrap' executable).
+fn zip_path(path: &Path) -> Result<Vec<u8>, CliError> {
+    let path_str = path.display().to_string();
+    let zip_error = |e: &dyn std::fmt::Debug| {
+        CloudFormationPackageError::with_debug(&path_str, "failed to zip", &e)
+    };
+    let root = match path.is_dir() {
+        true => path,
+        false => path.parent().unwrap_or(Path::new(".")),
+    };
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for entry in walkdir::WalkDir::new(path)
+        .follow_links(true)
+        .sort_by_file_name()
+    {
+        let entry = entry.map_err(|e| IOError::with_debug(&e))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let name = entry
+            .path()
+            .strip_prefix(root)
+            .unwrap_or(entry.path())
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(zip::DateTime::default())
+            .unix_permissions(file_mode(entry.path()));
+        zip.start_file(name, options).map_err(|e| zip_error(&e))?;
+        let data = std::fs::read(entry.path()).map_err(|e| IOError::with_debug(&e))?;
+        zip.write_all(&data).map_err(|e| zip_error(&e))?;
+    }
+    Ok(zip.finish().map_err(|e| zip_error(&e))?.into_inner())
+}
+
+#[cfg(unix)]
+fn file_mode(path: &Path) -> u32 {
+    use std::os::unix::fs::PermissionsExt as _;
+    std::fs::metadata(path).map_or(0o644, |m| m.permissions().mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_path: &Path) -> u32 {
+    0o644
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zips_are_deterministic() {
+        let dir = tempfile::tempdir().unwrap();
+        let function_dir = dir.path().join("function");
+        std::fs::create_dir_all(function_dir.join("lib")).unwrap();
+        std::fs::write(function_dir.join("bootstrap"), b"binary").unwrap();
+        std::fs::write(function_dir.join("lib/data.json"), b"{}").unwrap();
+
+        let first = zip_path(&function_dir).unwrap();
+        // Far from the current time, beyond the 2-second resolution of zip
+        // timestamps.
+        std::fs::File::options()
+            .write(true)
+            .open(function_dir.join("bootstrap"))
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000))
+            .unwrap();
+        assert_eq!(zip_path(&function_dir).unwrap(), first);
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(first)).unwrap();
+        assert_eq!(
+            archive.file_names().collect::<Vec<_>>(),
+            vec!["bootstrap", "lib/data.json"]
+        );
+        // Rather than the time of zipping, which would change across runs.
+        for i in 0..archive.len() {
+            assert_eq!(
+                archive.by_index(i).unwrap().last_modified(),
+                Some(zip::DateTime::default())
+            );
+        }
+    }
+
+    #[test]
+    fn only_local_paths_are_packaged() {
+        let dir = tempfile::tempdir().unwrap();
+        let function_dir = dir.path().join("function");
+        std::fs::create_dir_all(&function_dir).unwrap();
+
+        let local = |value: &str| local_artifact_path(&Value::from(value), dir.path());
+        assert_eq!(local("function"), Some(function_dir));
+        assert_eq!(local("s3://bucket/code.zip"), None);
+        assert_eq!(
+            local_artifact_path(&Value::Mapping(Mapping::new()), dir.path()),
+            None
+        );
+    }
+}
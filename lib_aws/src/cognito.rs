@@ -6,17 +6,16 @@ use aws_sdk_cognitoidentityprovider::{
 };
 use lib_core::{define_cli_error, CliError, Printer};
 
-use crate::shared_config::config_from_profile;
+use crate::AwsContext;
 
 define_cli_error!(CognitoError, "Error running AWS Cognito command.");
 
 pub async fn user_exists(
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     user_pool_id: &str,
     username: &str,
 ) -> Result<bool, CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+    let client = aws.client::<Client>().await;
     let response = client
         .admin_get_user()
         .user_pool_id(user_pool_id)
@@ -37,19 +36,18 @@ pub async fn user_exists(
 /// Returns true if new user was created.
 pub async fn create_user_if_not_exists(
     printer: &Printer,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     user_pool_id: &str,
     username: &str,
     email: &str,
     password: &str,
 ) -> Result<bool, CliError> {
-    if user_exists(profile, region, user_pool_id, username).await? {
+    if user_exists(aws, user_pool_id, username).await? {
         printer.info(&format!("User '{}' already exists.", username));
         Ok(false)
     } else {
         printer.info(&format!("Creating user '{}'...", username));
-        let client = Client::new(&config_from_profile(profile, region).await);
+        let client = aws.client::<Client>().await;
         client
             .admin_create_user()
             .user_pool_id(user_pool_id)
@@ -89,8 +87,7 @@ pub async fn create_user_if_not_exists(
 
 pub async fn add_user_to_group(
     pr: &Printer,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     user_pool_id: &str,
     username: &str,
     group: &str,
@@ -99,7 +96,7 @@ pub async fn add_user_to_group(
         "Adding user '{}' to group '{}'...",
         username, group
     ));
-    let client = Client::new(&config_from_profile(profile, region).await);
+    let client = aws.client::<Client>().await;
     client
         .admin_add_user_to_group()
         .user_pool_id(user_pool_id)
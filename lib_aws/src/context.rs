@@ -0,0 +1,410 @@
+use std::{
+    any::{Any, TypeId},
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use aws_config::SdkConfig;
+use aws_sdk_sts::config::ProvideCredentials as _;
+use lib_core::{define_cli_error, CliError};
+use tokio::sync::OnceCell;
+
+use crate::shared_config::load_sdk_config;
+
+define_cli_error!(
+    AwsCredentialsError,
+    "Failed to resolve AWS credentials for profile '{profile}'.",
+    { profile: &str }
+);
+
+/// Profile and region (plus optional role, credentials and endpoints) used to
+/// call AWS.
+///
+/// The SDK config and clients are created on first use, and shared by all
+/// clones of the context, as well as by contexts derived from it (ex. with
+/// `with_region`). Create one per profile at the start of the script and pass
+/// it to the helpers, so credentials are only resolved once:
+///
+/// ```ignore
+/// let aws = AwsContext::new("company-staging", "us-east-1");
+/// s3_download_objects(pr, &aws, bucket, objects, 8).await?;
+/// let secret = get_secret(&aws.with_region("eu-west-1"), "api-key").await?;
+/// ```
+///
+/// For tests, `AwsContext::local` targets a local stand-in of AWS instead
+/// (LocalStack, MinIO, moto...).
+#[derive(Clone)]
+pub struct AwsContext {
+    key: ContextKey,
+    cache: Arc<Mutex<HashMap<ContextKey, Arc<CachedConfig>>>>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ContextKey {
+    pub(crate) profile: String,
+    pub(crate) region: String,
+    pub(crate) role_arn: Option<String>,
+    pub(crate) endpoint_url: Option<String>,
+    pub(crate) service_endpoint_urls: BTreeMap<String, String>,
+    pub(crate) static_credentials: Option<StaticCredentials>,
+    pub(crate) s3_force_path_style: bool,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(crate) struct StaticCredentials {
+    pub(crate) access_key_id: String,
+    pub(crate) secret_access_key: String,
+}
+
+#[derive(Default)]
+struct CachedConfig {
+    config: OnceCell<SdkConfig>,
+    clients: Mutex<HashMap<TypeId, Box<dyn Any + Send + Sync>>>,
+}
+
+impl AwsContext {
+    pub fn new(profile: &str, region: &str) -> Self {
+        AwsContext {
+            key: ContextKey {
+                profile: profile.to_string(),
+                region: region.to_string(),
+                role_arn: None,
+                endpoint_url: None,
+                service_endpoint_urls: BTreeMap::new(),
+                static_credentials: None,
+                s3_force_path_style: false,
+            },
+            cache: Default::default(),
+        }
+    }
+
+    /// Context for a local stand-in of AWS: all services at 'endpoint_url'
+    /// (ex. 'http://localhost:4566' for LocalStack), with test credentials and
+    /// path-style S3 addressing.
+    pub fn local(endpoint_url: &str) -> Self {
+        AwsContext::new("local", "us-east-1")
+            .with_endpoint_url(endpoint_url)
+            .with_static_credentials("test", "test")
+            .with_s3_force_path_style(true)
+    }
+
+    /// Same context in another region.
+    pub fn with_region(&self, region: &str) -> Self {
+        self.derive(|key| key.region = region.to_string())
+    }
+
+    /// Assumes 'role_arn' with the credentials of the profile.
+    pub fn with_role(&self, role_arn: &str) -> Self {
+        self.derive(|key| key.role_arn = Some(role_arn.to_string()))
+    }
+
+    /// Sends all requests to 'endpoint_url' rather than to AWS.
+    pub fn with_endpoint_url(&self, endpoint_url: &str) -> Self {
+        self.derive(|key| key.endpoint_url = Some(endpoint_url.to_string()))
+    }
+
+    /// Sends requests of one service to 'endpoint_url', overriding
+    /// `with_endpoint_url` (ex. MinIO for S3 only). The service is named as in
+    /// its SDK crate (ex. 's3', 'secretsmanager', 'cloudformation').
+    pub fn with_service_endpoint_url(&self, service: &str, endpoint_url: &str) -> Self {
+        self.derive(|key| {
+            key.service_endpoint_urls
+                .insert(service.to_string(), endpoint_url.to_string());
+        })
+    }
+
+    /// Uses fixed credentials rather than those of the profile. Meant for
+    /// stand-ins, which usually accept any credentials.
+    pub fn with_static_credentials(&self, access_key_id: &str, secret_access_key: &str) -> Self {
+        self.derive(|key| {
+            key.static_credentials = Some(StaticCredentials {
+                access_key_id: access_key_id.to_string(),
+                secret_access_key: secret_access_key.to_string(),
+            })
+        })
+    }
+
+    /// Addresses buckets as '<endpoint>/<bucket>' rather than
+    /// '<bucket>.<endpoint>', which most stand-ins require.
+    pub fn with_s3_force_path_style(&self, enabled: bool) -> Self {
+        self.derive(|key| key.s3_force_path_style = enabled)
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.key.profile
+    }
+
+    pub fn region(&self) -> &str {
+        &self.key.region
+    }
+
+    pub fn role_arn(&self) -> Option<&str> {
+        self.key.role_arn.as_deref()
+    }
+
+    pub fn endpoint_url(&self) -> Option<&str> {
+        self.key.endpoint_url.as_deref()
+    }
+
+    /// Endpoint used for 'service', if not the default one.
+    pub fn service_endpoint_url(&self, service: &str) -> Option<&str> {
+        self.key
+            .service_endpoint_urls
+            .get(service)
+            .map(String::as_str)
+            .or(self.endpoint_url())
+    }
+
+    /// True if nothing but the profile and region is needed to call AWS (no
+    /// role, static credentials or custom endpoints), so CLIs can be given
+    /// '--profile' and '--region'. Otherwise, see `credentials_env`.
+    pub fn is_plain_profile(&self) -> bool {
+        self.key.role_arn.is_none()
+            && self.key.static_credentials.is_none()
+            && self.key.endpoint_url.is_none()
+            && self.key.service_endpoint_urls.is_empty()
+    }
+
+    /// Loaded on first use.
+    pub async fn sdk_config(&self) -> SdkConfig {
+        self.load(&self.cached()).await.clone()
+    }
+
+    /// Client of any AWS service, created on first use.
+    pub async fn client<C: SdkClient>(&self) -> C {
+        let cached = self.cached();
+        let config = self.load(&cached).await;
+        let client = cached
+            .clients
+            .lock()
+            .unwrap()
+            .entry(TypeId::of::<C>())
+            .or_insert_with(|| Box::new(C::from_context(config, self)))
+            .downcast_ref::<C>()
+            .expect("clients should be cached by type")
+            .clone();
+        client
+    }
+
+    /// Resolved credentials (and endpoints, if any) as environment variables,
+    /// for CLIs which can't use the context directly (ex. when a role is
+    /// assumed). S3 path-style addressing can't be set through the
+    /// environment, so CLIs use virtual-hosted-style with the endpoint.
+    pub async fn credentials_env(&self) -> Result<Vec<(String, String)>, CliError> {
+        let config = self.sdk_config().await;
+        let credentials = config
+            .credentials_provider()
+            .ok_or_else(|| AwsCredentialsError::new(self.profile()))?
+            .provide_credentials()
+            .await
+            .map_err(|e| AwsCredentialsError::with_debug(self.profile(), &e))?;
+        let mut env = vec![
+            (
+                "AWS_ACCESS_KEY_ID".to_string(),
+                credentials.access_key_id().to_string(),
+            ),
+            (
+                "AWS_SECRET_ACCESS_KEY".to_string(),
+                credentials.secret_access_key().to_string(),
+            ),
+            ("AWS_REGION".to_string(), self.region().to_string()),
+        ];
+        if let Some(token) = credentials.session_token() {
+            env.push(("AWS_SESSION_TOKEN".to_string(), token.to_string()));
+        }
+        env.extend(self.endpoints_env());
+        Ok(env)
+    }
+
+    /// 'AWS_ENDPOINT_URL', and 'AWS_ENDPOINT_URL_<SERVICE>' for each service
+    /// endpoint, as read by the AWS CLI and SDKs.
+    fn endpoints_env(&self) -> Vec<(String, String)> {
+        let mut env = Vec::new();
+        if let Some(endpoint_url) = self.endpoint_url() {
+            env.push(("AWS_ENDPOINT_URL".to_string(), endpoint_url.to_string()));
+        }
+        for (service, endpoint_url) in &self.key.service_endpoint_urls {
+            env.push((
+                format!("AWS_ENDPOINT_URL_{}", service_env_id(service)),
+                endpoint_url.clone(),
+            ));
+        }
+        env
+    }
+
+    async fn load<'a>(&self, cached: &'a CachedConfig) -> &'a SdkConfig {
+        cached
+            .config
+            .get_or_init(|| load_sdk_config(&self.key))
+            .await
+    }
+
+    fn derive(&self, f: impl FnOnce(&mut ContextKey)) -> Self {
+        let mut key = self.key.clone();
+        f(&mut key);
+        AwsContext {
+            key,
+            cache: self.cache.clone(),
+        }
+    }
+
+    fn cached(&self) -> Arc<CachedConfig> {
+        self.cache
+            .lock()
+            .unwrap()
+            .entry(self.key.clone())
+            .or_default()
+            .clone()
+    }
+}
+
+impl fmt::Debug for AwsContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AwsContext")
+            .field("profile", &self.key.profile)
+            .field("region", &self.key.region)
+            .field("role_arn", &self.key.role_arn)
+            .field("endpoint_url", &self.key.endpoint_url)
+            .field("service_endpoint_urls", &self.key.service_endpoint_urls)
+            .field("static_credentials", &self.key.static_credentials.is_some())
+            .field("s3_force_path_style", &self.key.s3_force_path_style)
+            .finish()
+    }
+}
+
+/// An SDK client which can be cached by `AwsContext`.
+pub trait SdkClient: Clone + Send + Sync + 'static {
+    /// As in the SDK crate name (ex. 's3' for 'aws-sdk-s3').
+    const SERVICE: &'static str;
+
+    /// 'config' is the context's SDK config, to which service-specific
+    /// options of the context are applied.
+    fn from_context(config: &SdkConfig, aws: &AwsContext) -> Self;
+}
+
+macro_rules! impl_sdk_client {
+    ($($sdk:ident => $service:literal),* $(,)?) => {
+        $(
+            impl SdkClient for $sdk::Client {
+                const SERVICE: &'static str = $service;
+
+                fn from_context(config: &SdkConfig, aws: &AwsContext) -> Self {
+                    match aws.key.service_endpoint_urls.get(Self::SERVICE) {
+                        Some(endpoint_url) => $sdk::Client::from_conf(
+                            $sdk::config::Builder::from(config)
+                                .endpoint_url(endpoint_url)
+                                .build(),
+                        ),
+                        None => $sdk::Client::new(config),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_sdk_client!(
+    aws_sdk_acm => "acm",
+    aws_sdk_autoscaling => "autoscaling",
+    aws_sdk_cloudformation => "cloudformation",
+    aws_sdk_cloudwatchlogs => "cloudwatchlogs",
+    aws_sdk_cognitoidentityprovider => "cognitoidentityprovider",
+    aws_sdk_ecr => "ecr",
+    aws_sdk_ecs => "ecs",
+    aws_sdk_iam => "iam",
+    aws_sdk_route53 => "route53",
+    aws_sdk_route53domains => "route53domains",
+    aws_sdk_secretsmanager => "secretsmanager",
+    aws_sdk_ses => "ses",
+    aws_sdk_ssm => "ssm",
+    aws_sdk_sts => "sts",
+);
+
+impl SdkClient for aws_sdk_s3::Client {
+    const SERVICE: &'static str = "s3";
+
+    fn from_context(config: &SdkConfig, aws: &AwsContext) -> Self {
+        let mut builder =
+            aws_sdk_s3::config::Builder::from(config).force_path_style(aws.key.s3_force_path_style);
+        if let Some(endpoint_url) = aws.key.service_endpoint_urls.get(Self::SERVICE) {
+            builder = builder.endpoint_url(endpoint_url);
+        }
+        aws_sdk_s3::Client::from_conf(builder.build())
+    }
+}
+
+/// The service ID used in endpoint environment variables (its name with
+/// spaces replaced by underscores, in upper case), from its SDK crate name.
+fn service_env_id(service: &str) -> String {
+    match service {
+        "autoscaling" => "AUTO_SCALING",
+        "cloudwatchlogs" => "CLOUDWATCH_LOGS",
+        "cognitoidentityprovider" => "COGNITO_IDENTITY_PROVIDER",
+        "route53" => "ROUTE_53",
+        "route53domains" => "ROUTE_53_DOMAINS",
+        "secretsmanager" => "SECRETS_MANAGER",
+        _ => return service.to_uppercase(),
+    }
+    .to_string()
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derived_contexts_share_the_cache() {
+        let aws = AwsContext::new("prod", "us-east-1");
+        let eu = aws.with_region("eu-west-1");
+        assert!(Arc::ptr_eq(
+            &aws.cached(),
+            &eu.with_region("us-east-1").cached()
+        ));
+        assert!(!Arc::ptr_eq(&aws.cached(), &eu.cached()));
+        assert!(!Arc::ptr_eq(
+            &aws.cached(),
+            &aws.with_role("arn:aws:iam::123456789012:role/Deployer")
+                .cached()
+        ));
+    }
+
+    #[test]
+    fn service_endpoints_override_the_global_one() {
+        let aws = AwsContext::local("http://localhost:4566")
+            .with_service_endpoint_url("s3", "http://localhost:9000");
+        assert_eq!(
+            aws.service_endpoint_url("s3"),
+            Some("http://localhost:9000")
+        );
+        assert_eq!(
+            aws.service_endpoint_url("secretsmanager"),
+            Some("http://localhost:4566")
+        );
+        assert!(!aws.is_plain_profile());
+        assert!(AwsContext::new("prod", "us-east-1").is_plain_profile());
+    }
+
+    #[test]
+    fn service_endpoints_are_passed_to_clis() {
+        let aws = AwsContext::new("dev", "us-east-1")
+            .with_service_endpoint_url("s3", "http://localhost:9000")
+            .with_service_endpoint_url("secretsmanager", "http://localhost:4566");
+        assert_eq!(
+            aws.endpoints_env(),
+            [
+                (
+                    "AWS_ENDPOINT_URL_S3".to_string(),
+                    "http://localhost:9000".to_string()
+                ),
+                (
+                    "AWS_ENDPOINT_URL_SECRETS_MANAGER".to_string(),
+                    "http://localhost:4566".to_string()
+                ),
+            ]
+        );
+    }
+}
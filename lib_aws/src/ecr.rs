@@ -2,7 +2,7 @@ use aws_sdk_ecr::Client;
 use base64::{prelude::BASE64_STANDARD, Engine};
 use lib_core::{define_cli_error, CliError, InvalidUTF8};
 
-use crate::shared_config::config_from_profile;
+use crate::AwsContext;
 
 define_cli_error!(EcrError, "Error running AWS ECR command.");
 define_cli_error!(EcrCredentialsError, "Error decoding ECR credentials: {details}.", { details: &str });
@@ -14,8 +14,8 @@ pub struct EcrCredentials {
     pub proxy_endpoint: String,
 }
 
-pub async fn get_ecr_credentials(profile: &str, region: &str) -> Result<EcrCredentials, CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+pub async fn get_ecr_credentials(aws: &AwsContext) -> Result<EcrCredentials, CliError> {
+    let client = aws.client::<Client>().await;
 
     let auth_response = client
         .get_authorization_token()
@@ -5,8 +5,7 @@ use aws_sdk_ecs::{
 use lib_core::{define_cli_error, CliError, Printer};
 
 use crate::{
-    get_auto_scaling_group_name_from_arn, set_auto_scaling_group_desired_size,
-    shared_config::config_from_profile,
+    get_auto_scaling_group_name_from_arn, set_auto_scaling_group_desired_size, AwsContext,
 };
 
 define_cli_error!(EcsError, "Error running AWS ECS command.");
@@ -36,12 +35,11 @@ impl Into<LaunchType> for EcsTaskLaunchType {
 }
 
 pub async fn cluster_has_running_task_for_family(
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     cluster: &str,
     task_definition_family: &str,
 ) -> Result<bool, CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+    let client = aws.client::<Client>().await;
     let response = client
         .list_tasks()
         .cluster(cluster)
@@ -54,8 +52,7 @@ pub async fn cluster_has_running_task_for_family(
 
 pub async fn run_task(
     pr: &Printer,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     cluster: &str,
     task_definition: &str,
     launch_type: Option<EcsTaskLaunchType>,
@@ -63,9 +60,11 @@ pub async fn run_task(
 ) -> Result<(), CliError> {
     pr.info(&format!(
         "Running task '{}' on cluster '{}' ({})...",
-        task_definition, cluster, region
+        task_definition,
+        cluster,
+        aws.region()
     ));
-    let client = Client::new(&config_from_profile(profile, region).await);
+    let client = aws.client::<Client>().await;
     client
         .run_task()
         .cluster(cluster)
@@ -105,18 +104,16 @@ pub async fn run_task(
 /// Returns true if a new task was started.
 pub async fn run_task_if_not_running(
     pr: &Printer,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     cluster: &str,
     task_definition: &str,
     launch_type: Option<EcsTaskLaunchType>,
     network_configuration: Option<EcsTaskNetworkConfiguration>,
 ) -> Result<bool, CliError> {
-    if !cluster_has_running_task_for_family(profile, region, cluster, task_definition).await? {
+    if !cluster_has_running_task_for_family(aws, cluster, task_definition).await? {
         run_task(
             pr,
-            profile,
-            region,
+            aws,
             cluster,
             task_definition,
             launch_type,
@@ -133,12 +130,11 @@ pub async fn run_task_if_not_running(
 /// given capacity provider.
 pub async fn set_capacity_provider_desired_size(
     pr: &Printer,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     capacity_provider: &str,
     desired_size: i32,
 ) -> Result<(), CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+    let client = aws.client::<Client>().await;
     let auto_scaling_group_arn = client
         .describe_capacity_providers()
         .send()
@@ -155,21 +151,16 @@ pub async fn set_capacity_provider_desired_size(
         .auto_scaling_group_arn
         .clone();
     let auto_scaling_group_name = get_auto_scaling_group_name_from_arn(&auto_scaling_group_arn);
-    set_auto_scaling_group_desired_size(pr, profile, region, &auto_scaling_group_name, desired_size)
-        .await
+    set_auto_scaling_group_desired_size(pr, aws, &auto_scaling_group_name, desired_size).await
 }
 
-pub async fn stop_all_tasks(
-    pr: &Printer,
-    profile: &str,
-    region: &str,
-    cluster: &str,
-) -> Result<(), CliError> {
+pub async fn stop_all_tasks(pr: &Printer, aws: &AwsContext, cluster: &str) -> Result<(), CliError> {
     pr.info(&format!(
         "Stopping all tasks on cluster '{}' ({})...",
-        cluster, region
+        cluster,
+        aws.region()
     ));
-    let client = Client::new(&config_from_profile(profile, region).await);
+    let client = aws.client::<Client>().await;
     let tasks = client
         .list_tasks()
         .cluster(cluster)
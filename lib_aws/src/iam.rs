@@ -4,7 +4,7 @@ use aws_sdk_iam::{
 };
 use lib_core::{define_cli_error, CliError, Printer};
 
-use crate::shared_config::config_from_profile;
+use crate::AwsContext;
 
 define_cli_error!(IamError, "Error running AWS IAM command.");
 define_cli_error!(
@@ -41,12 +41,11 @@ pub enum KeyRotation {
 /// rotation of existing keys should be handled.
 pub async fn create_access_key_for_user(
     printer: &Printer,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     username: &str,
     rotation: KeyRotation,
 ) -> Result<IamAccessKeyCredentials, CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+    let client = aws.client::<Client>().await;
     let mut existing_keys = list_access_keys(&client, username).await?;
 
     // Clean up any inactive keys.
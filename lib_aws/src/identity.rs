@@ -2,7 +2,7 @@ use aws_sdk_sts::{error::ProvideErrorMetadata, Client};
 use aws_smithy_runtime_api::client::result::SdkError;
 use lib_core::{define_cli_error, CliError};
 
-use crate::shared_config::config_from_profile;
+use crate::AwsContext;
 
 const TEST_REGION: &str = "us-west-1";
 
@@ -24,7 +24,9 @@ pub async fn require_aws_profile(
     cli_role: &str,
 ) -> Result<String, CliError> {
     let profile = format!("{}-{}", cli_role, account_id);
-    let client = Client::new(&config_from_profile(&profile, TEST_REGION).await);
+    let client = AwsContext::new(&profile, TEST_REGION)
+        .client::<Client>()
+        .await;
     client.get_caller_identity().send().await.map_err(|e| {
         if is_aws_profile_required_error(&e) {
             AwsProfileRequired::with_debug(&profile, cli_role, account_id, sso_session, &e)
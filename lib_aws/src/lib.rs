@@ -1,17 +1,28 @@
 mod account;
 mod acm;
 mod autoscaling;
+mod blue_green;
 mod cargo_lambda;
 mod cloudformation;
+mod cloudformation_changesets;
+mod cloudformation_events;
+mod cloudformation_lifecycle;
+mod cloudformation_package;
 mod cognito;
+mod context;
 mod ecr;
 mod ecs;
 mod iam;
 mod identity;
+mod local_aws;
 mod logs;
 mod route53;
 mod route53domains;
 mod s3;
+mod s3_bucket;
+mod s3_objects;
+mod s3_sync;
+mod s3_transfer;
 mod sam;
 mod secrets;
 mod ses;
@@ -21,17 +32,28 @@ mod ssm;
 pub use account::*;
 pub use acm::*;
 pub use autoscaling::*;
+pub use blue_green::*;
 pub use cargo_lambda::*;
 pub use cloudformation::*;
+pub use cloudformation_changesets::*;
+pub use cloudformation_events::*;
+pub use cloudformation_lifecycle::*;
+pub use cloudformation_package::*;
 pub use cognito::*;
+pub use context::*;
 pub use ecr::*;
 pub use ecs::*;
 pub use iam::*;
 pub use identity::*;
+pub use local_aws::*;
 pub use logs::*;
 pub use route53::*;
 pub use route53domains::*;
 pub use s3::*;
+pub use s3_bucket::*;
+pub use s3_objects::*;
+pub use s3_sync::*;
+pub use s3_transfer::*;
 pub use sam::*;
 pub use secrets::*;
 pub use ses::*;
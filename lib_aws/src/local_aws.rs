@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use lib_core::{define_cli_error, CliError, Executor, IOMode};
+
+use crate::AwsContext;
+
+define_cli_error!(
+    LocalAwsStartFailed,
+    "Failed to start local AWS stand-in: {details}.",
+    { details: &str }
+);
+
+/// If set, `LocalAws::start` uses the stand-in at this endpoint (ex. a shared
+/// LocalStack, or MinIO for S3-only tests) instead of starting one.
+pub const LOCAL_AWS_ENDPOINT_URL_ENV: &str = "LIB_AWS_TEST_ENDPOINT_URL";
+
+const LOCALSTACK_IMAGE: &str = "localstack/localstack:3";
+const LOCALSTACK_PORT: &str = "4566/tcp";
+const READY_ATTEMPTS: u32 = 60;
+const READY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A local stand-in of AWS, to run the lib_aws helpers against in tests:
+///
+/// ```ignore
+/// let ex = Executor::new();
+/// let local = LocalAws::start(&ex).await?;
+/// s3_create_bucket_if_not_exists(&pr, &local.context(), "artifacts").await?;
+/// ...
+/// local.stop(&ex).await?;
+/// ```
+#[derive(Debug)]
+pub struct LocalAws {
+    endpoint_url: String,
+    /// Only set if the container was started by `start`.
+    container_id: Option<String>,
+}
+
+impl LocalAws {
+    /// Uses the endpoint in `LOCAL_AWS_ENDPOINT_URL_ENV` if set, or otherwise
+    /// starts a LocalStack container with Docker (on a random local port) and
+    /// waits until it's ready. The container is removed if it never becomes
+    /// ready.
+    pub async fn start(ex: &Executor) -> Result<Self, CliError> {
+        if let Ok(endpoint_url) = std::env::var(LOCAL_AWS_ENDPOINT_URL_ENV) {
+            return Ok(LocalAws {
+                endpoint_url,
+                container_id: None,
+            });
+        }
+
+        ex.require_command("docker").await?;
+        let container_id = ex
+            .execute(
+                "docker",
+                &[
+                    "run",
+                    "-d",
+                    "--rm",
+                    "-p",
+                    "127.0.0.1::4566",
+                    LOCALSTACK_IMAGE,
+                ],
+                IOMode::Mute,
+            )
+            .await?
+            .trim()
+            .to_string();
+        match Self::connect(ex, &container_id).await {
+            Ok(endpoint_url) => Ok(LocalAws {
+                endpoint_url,
+                container_id: Some(container_id),
+            }),
+            Err(e) => {
+                ex.execute("docker", &["stop", &container_id], IOMode::Mute)
+                    .await?;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn endpoint_url(&self) -> &str {
+        &self.endpoint_url
+    }
+
+    /// See `AwsContext::local`.
+    pub fn context(&self) -> AwsContext {
+        AwsContext::local(&self.endpoint_url)
+    }
+
+    /// Removes the container, if started by `start`.
+    pub async fn stop(self, ex: &Executor) -> Result<(), CliError> {
+        if let Some(container_id) = &self.container_id {
+            ex.execute("docker", &["stop", container_id], IOMode::Mute)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn connect(ex: &Executor, container_id: &str) -> Result<String, CliError> {
+        // Ex. '127.0.0.1:49153'.
+        let output = ex
+            .execute(
+                "docker",
+                &["port", container_id, LOCALSTACK_PORT],
+                IOMode::Mute,
+            )
+            .await?;
+        let endpoint_url = output
+            .lines()
+            .next()
+            .map(|address| format!("http://{}", address.trim()))
+            .ok_or_else(|| LocalAwsStartFailed::new("LocalStack port is not published"))?;
+
+        let health_url = format!("{}/_localstack/health", endpoint_url);
+        let client = reqwest::Client::new();
+        for _ in 0..READY_ATTEMPTS {
+            if let Ok(response) = client.get(&health_url).send().await {
+                if response.status().is_success() {
+                    return Ok(endpoint_url);
+                }
+            }
+            tokio::time::sleep(READY_INTERVAL).await;
+        }
+        Err(LocalAwsStartFailed::new(&format!(
+            "LocalStack at '{}' was not ready after {} seconds",
+            endpoint_url,
+            READY_ATTEMPTS as u64 * READY_INTERVAL.as_secs()
+        )))
+    }
+}
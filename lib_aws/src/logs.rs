@@ -1,7 +1,7 @@
 use aws_sdk_cloudwatchlogs::Client;
 use lib_core::{define_cli_error, CliError};
 
-use crate::shared_config::config_from_profile;
+use crate::AwsContext;
 
 define_cli_error!(
     CloudWatchLogsError,
@@ -13,13 +13,10 @@ define_cli_error!(InvalidRetentionValue, "Invalid retention value: {value}.", {
 /// Sets the retention policy in days for all CloudWatch log groups whose names
 /// match the supplied wildcard pattern (supports `*` and `?`).
 ///
-/// - `profile`: AWS named profile to use
-/// - `region`: AWS region (e.g., "us-east-1")
 /// - `log_group_wildcard`: pattern like "app-prod-*" or "service-?-logs"
 /// - `retention_in_days`: number of days for retention policy
 pub async fn set_log_retention_by_wildcard(
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     log_group_wildcard: &str,
     retention_in_days: u32,
 ) -> Result<(), CliError> {
@@ -27,8 +24,7 @@ pub async fn set_log_retention_by_wildcard(
         .try_into()
         .map_err(|e| InvalidRetentionValue::with_debug(retention_in_days, &e))?;
 
-    let config = config_from_profile(profile, region).await;
-    let client = Client::new(&config);
+    let client = aws.client::<Client>().await;
 
     let matcher = WildcardMatcher::new(log_group_wildcard)
         .map_err(|e| InvalidWildcardPattern::with_debug(log_group_wildcard, &e))?;
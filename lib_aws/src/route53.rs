@@ -6,7 +6,7 @@ use aws_sdk_route53::{
 };
 use lib_core::{define_cli_error, CliError};
 
-use crate::shared_config::config_from_profile;
+use crate::AwsContext;
 
 define_cli_error!(Route53Error, "Error running AWS Route53 command.");
 define_cli_error!(InvalidDnsValue, "Invalid IP address: {ip_address}.", { ip_address: &str });
@@ -16,6 +16,7 @@ const DEFAULT_REGION: &'static str = "us-east-1";
 /// CloudFront's alias hosted zone ID is global and constant.
 const CLOUDFRONT_ALIAS_HOSTED_ZONE_ID: &'static str = "Z2FDTNDATAQYW2";
 
+#[derive(Debug, Clone, Copy)]
 pub enum AliasTargetType<'a> {
     /// Automatically uses the global CloudFront hosted zone ID.
     CloudFront,
@@ -25,43 +26,27 @@ pub enum AliasTargetType<'a> {
 }
 
 pub async fn set_route53_a_record(
-    profile: &str,
+    aws: &AwsContext,
     hosted_zone_id: &str,
     record_name: &str,
     ip_address: &str,
     ttl: i64,
 ) -> Result<(), CliError> {
-    record_set_helper(
-        profile,
-        hosted_zone_id,
-        RrType::A,
-        record_name,
-        ip_address,
-        ttl,
-    )
-    .await
+    record_set_helper(aws, hosted_zone_id, RrType::A, record_name, ip_address, ttl).await
 }
 
 pub async fn set_route53_cname_record(
-    profile: &str,
+    aws: &AwsContext,
     hosted_zone_id: &str,
     record_name: &str,
     value: &str,
     ttl: i64,
 ) -> Result<(), CliError> {
-    record_set_helper(
-        profile,
-        hosted_zone_id,
-        RrType::Cname,
-        record_name,
-        value,
-        ttl,
-    )
-    .await
+    record_set_helper(aws, hosted_zone_id, RrType::Cname, record_name, value, ttl).await
 }
 
 pub async fn set_route53_a_record_alias<'a>(
-    profile: &str,
+    aws: &AwsContext,
     hosted_zone_id: &str,
     record_name: &str,
     target_type: AliasTargetType<'a>,
@@ -69,7 +54,7 @@ pub async fn set_route53_a_record_alias<'a>(
     evaluate_target_health: bool,
 ) -> Result<(), CliError> {
     alias_record_set_helper(
-        profile,
+        aws,
         hosted_zone_id,
         RrType::A,
         record_name,
@@ -81,7 +66,7 @@ pub async fn set_route53_a_record_alias<'a>(
 }
 
 pub async fn set_route53_cname_record_alias<'a>(
-    profile: &str,
+    aws: &AwsContext,
     hosted_zone_id: &str,
     record_name: &str,
     target_type: AliasTargetType<'a>,
@@ -89,7 +74,7 @@ pub async fn set_route53_cname_record_alias<'a>(
     evaluate_target_health: bool,
 ) -> Result<(), CliError> {
     alias_record_set_helper(
-        profile,
+        aws,
         hosted_zone_id,
         RrType::Cname,
         record_name,
@@ -101,20 +86,20 @@ pub async fn set_route53_cname_record_alias<'a>(
 }
 
 pub async fn get_route53_a_record(
-    profile: &str,
+    aws: &AwsContext,
     hosted_zone_id: &str,
     record_name: &str,
 ) -> Result<Option<String>, CliError> {
-    let record = record_get_helper(profile, hosted_zone_id, RrType::A, record_name).await?;
+    let record = record_get_helper(aws, hosted_zone_id, RrType::A, record_name).await?;
     Ok(record.and_then(record_value_string))
 }
 
 pub async fn get_route53_cname_record(
-    profile: &str,
+    aws: &AwsContext,
     hosted_zone_id: &str,
     record_name: &str,
 ) -> Result<Option<String>, CliError> {
-    let record = record_get_helper(profile, hosted_zone_id, RrType::Cname, record_name).await?;
+    let record = record_get_helper(aws, hosted_zone_id, RrType::Cname, record_name).await?;
     Ok(record.and_then(record_value_string))
 }
 
@@ -123,14 +108,14 @@ pub async fn get_route53_cname_record(
 // ---------------------------------------------------------------------------
 
 async fn record_set_helper(
-    profile: &str,
+    aws: &AwsContext,
     hosted_zone_id: &str,
     rtype: RrType,
     name: &str,
     value: &str,
     ttl: i64,
 ) -> Result<(), CliError> {
-    let client = Client::new(&config_from_profile(profile, DEFAULT_REGION).await);
+    let client = aws.with_region(DEFAULT_REGION).client::<Client>().await;
 
     let record_set = ResourceRecordSet::builder()
         .name(name)
@@ -168,7 +153,7 @@ async fn record_set_helper(
 }
 
 async fn alias_record_set_helper<'a>(
-    profile: &str,
+    aws: &AwsContext,
     hosted_zone_id: &str,
     rtype: RrType,
     name: &str,
@@ -176,7 +161,7 @@ async fn alias_record_set_helper<'a>(
     dns_name: &str,
     evaluate_target_health: bool,
 ) -> Result<(), CliError> {
-    let client = Client::new(&config_from_profile(profile, DEFAULT_REGION).await);
+    let client = aws.with_region(DEFAULT_REGION).client::<Client>().await;
 
     let hosted_zone_id_for_target = match target_type {
         AliasTargetType::CloudFront => CLOUDFRONT_ALIAS_HOSTED_ZONE_ID,
@@ -220,12 +205,12 @@ async fn alias_record_set_helper<'a>(
 }
 
 async fn record_get_helper(
-    profile: &str,
+    aws: &AwsContext,
     hosted_zone_id: &str,
     rtype: RrType,
     record_name: &str,
 ) -> Result<Option<ResourceRecordSet>, CliError> {
-    let client = Client::new(&config_from_profile(profile, DEFAULT_REGION).await);
+    let client = aws.with_region(DEFAULT_REGION).client::<Client>().await;
 
     let response = client
         .list_resource_record_sets()
@@ -4,7 +4,7 @@ use aws_sdk_route53domains::{
 };
 use lib_core::{define_cli_error, CliError};
 
-use crate::shared_config::config_from_profile;
+use crate::AwsContext;
 
 define_cli_error!(
     Route53DomainError,
@@ -20,11 +20,11 @@ define_cli_error!(
 const DEFAULT_REGION: &'static str = "us-east-1";
 
 pub async fn set_route53_domain_nameservers(
-    profile: &str,
+    aws: &AwsContext,
     domain: &str,
     nameservers: Vec<&str>,
 ) -> Result<(), CliError> {
-    let client = Client::new(&config_from_profile(profile, DEFAULT_REGION).await);
+    let client = aws.with_region(DEFAULT_REGION).client::<Client>().await;
 
     client
         .update_domain_nameservers()
@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 use aws_sdk_cloudformation::error::SdkError;
 use aws_sdk_s3::{
@@ -9,21 +9,30 @@ use futures_util::stream::{self, StreamExt as _, TryStreamExt as _};
 use lib_core::{define_cli_error, CliError, IOError, Printer};
 use sha2::{Digest as _, Sha256};
 
-use crate::shared_config::config_from_profile;
+use crate::{
+    s3_download_object_with_options, s3_upload_file_with_options, AwsContext, S3TransferOptions,
+};
 
 define_cli_error!(S3Error, "Error running S3 command.");
 define_cli_error!(S3InvalidUpload, "Invalid S3 upload request: {details}.", { details: &str });
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct S3ObjectMetadata {
     pub key: String,
     pub size: i64,
     pub etag: Option<String>,
     pub last_modified: Option<DateTime<Utc>>,
+    /// Ex. 'STANDARD' or 'GLACIER'.
+    pub storage_class: Option<String>,
+    /// Only returned by `s3_head_object`, not when listing.
+    pub content_type: Option<String>,
+    /// User-defined ('x-amz-meta-*') metadata. Only returned by
+    /// `s3_head_object`, not when listing.
+    pub metadata: HashMap<String, String>,
 }
 
-pub async fn s3_bucket_exists(profile: &str, region: &str, bucket: &str) -> Result<bool, CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+pub async fn s3_bucket_exists(aws: &AwsContext, bucket: &str) -> Result<bool, CliError> {
+    let client = aws.client::<Client>().await;
     let response = client.head_bucket().bucket(bucket).send().await;
     match response {
         Ok(_) => Ok(true),
@@ -35,21 +44,22 @@ pub async fn s3_bucket_exists(profile: &str, region: &str, bucket: &str) -> Resu
 /// Returns true if new bucket was created.
 pub async fn s3_create_bucket_if_not_exists(
     pr: &Printer,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     bucket: &str,
 ) -> Result<bool, CliError> {
-    if !s3_bucket_exists(profile, region, bucket).await? {
+    if !s3_bucket_exists(aws, bucket).await? {
         pr.info(&format!("Creating S3 bucket '{}'...", bucket));
-        let client = Client::new(&config_from_profile(profile, region).await);
+        let client = aws.client::<Client>().await;
         client
             .create_bucket()
             .bucket(bucket)
-            .create_bucket_configuration(
+            // us-east-1 is the default location, and is rejected if given
+            // explicitly.
+            .set_create_bucket_configuration((aws.region() != "us-east-1").then(|| {
                 CreateBucketConfiguration::builder()
-                    .location_constraint(region.into())
-                    .build(),
-            )
+                    .location_constraint(aws.region().into())
+                    .build()
+            }))
             .send()
             .await
             .map_err(|e| S3Error::with_debug(&e))?;
@@ -61,6 +71,12 @@ pub async fn s3_create_bucket_if_not_exists(
     }
 }
 
+/// Virtual-hosted-style HTTPS URL of the object (ex. for a CloudFormation
+/// 'TemplateURL').
+pub fn s3_object_url(bucket: &str, region: &str, key: &str) -> String {
+    format!("https://{bucket}.s3.{region}.amazonaws.com/{key}")
+}
+
 /// Deterministically derives a globally unique yet obsure bucket name. Since
 /// it's deterministic it can be used to re-use the same bucket between
 /// independent runs.
@@ -81,8 +97,7 @@ pub fn s3_unique_bucket_name(
 
 pub async fn s3_upload_file<P>(
     pr: &Printer,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     bucket: &str,
     key: &str,
     file_path: P,
@@ -90,36 +105,14 @@ pub async fn s3_upload_file<P>(
 where
     P: AsRef<Path>,
 {
-    if !file_path.as_ref().exists() {
-        return Err(S3InvalidUpload::new("file does not exist"));
-    }
-    if !file_path.as_ref().is_file() {
-        return Err(S3InvalidUpload::new("path is not a file"));
-    }
-
-    let client = Client::new(&config_from_profile(profile, region).await);
-
-    let body = aws_sdk_s3::primitives::ByteStream::from_path(file_path)
-        .await
-        .map_err(|e| IOError::with_debug(&e))?;
-    client
-        .put_object()
-        .bucket(bucket)
-        .key(key)
-        .body(body)
-        .send()
-        .await
-        .map_err(|e| S3Error::with_debug(&e))?;
-
-    pr.info(&format!("Uploaded file to 's3://{}/{}'.", bucket, key));
-    Ok(())
+    s3_upload_file_with_options(pr, aws, bucket, key, file_path, Default::default()).await
 }
 
-/// Returns the number of files uploaded.
+/// Returns the number of files uploaded. Uploads every file, see `s3_sync_dir`
+/// to only upload changes.
 pub async fn s3_upload_dir<P>(
     pr: &Printer,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     bucket: &str,
     key_prefix: &str,
     dir_path: P,
@@ -134,7 +127,7 @@ where
         return Err(S3InvalidUpload::new("path is not a directory"));
     }
 
-    let client = Client::new(&config_from_profile(profile, region).await);
+    let client = aws.client::<Client>().await;
 
     let mut count = 0;
     for entry in walkdir::WalkDir::new(&dir_path) {
@@ -172,12 +165,11 @@ where
 }
 
 pub async fn s3_list(
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     bucket: &str,
     prefix: &str,
 ) -> Result<Vec<S3ObjectMetadata>, CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+    let client = aws.client::<Client>().await;
 
     let objects = client
         .list_objects_v2()
@@ -200,6 +192,8 @@ pub async fn s3_list(
                     .last_modified
                     .and_then(|dt| chrono::DateTime::from_timestamp(dt.secs(), 0))
                     .map(|dt| dt.with_timezone(&Utc)),
+                storage_class: obj.storage_class.map(|c| c.as_str().to_string()),
+                ..Default::default()
             })
         })
         .collect();
@@ -208,8 +202,7 @@ pub async fn s3_list(
 }
 
 pub async fn s3_download_object<P>(
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     bucket: &str,
     key: &str,
     local_path: P,
@@ -217,34 +210,16 @@ pub async fn s3_download_object<P>(
 where
     P: AsRef<Path>,
 {
-    let client = Client::new(&config_from_profile(profile, region).await);
-
-    let response = client
-        .get_object()
-        .bucket(bucket)
-        .key(key)
-        .send()
-        .await
-        .map_err(|e| S3Error::with_debug(&e))?;
-    let bytes = response
-        .body
-        .collect()
-        .await
-        .map_err(|e| IOError::with_debug(&e))?
-        .into_bytes();
-
-    if let Some(parent) = local_path.as_ref().parent() {
-        std::fs::create_dir_all(parent).map_err(|e| IOError::with_debug(&e))?;
-    }
-    std::fs::write(local_path, bytes).map_err(|e| IOError::with_debug(&e))?;
-
-    Ok(())
+    let options = S3TransferOptions {
+        progress: false,
+        ..Default::default()
+    };
+    s3_download_object_with_options(&Printer::new(), aws, bucket, key, local_path, options).await
 }
 
 pub async fn s3_download_objects(
     pr: &Printer,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     bucket: &str,
     objects: Vec<(String, std::path::PathBuf)>,
     max_concurrency: usize,
@@ -261,7 +236,7 @@ pub async fn s3_download_objects(
     ));
 
     stream::iter(objects.into_iter().map(|(key, local_path)| async move {
-        s3_download_object(profile, region, bucket, &key, &local_path).await
+        s3_download_object(aws, bucket, &key, &local_path).await
     }))
     .buffer_unordered(max_concurrency.max(1))
     .try_collect::<Vec<_>>()
@@ -270,13 +245,8 @@ pub async fn s3_download_objects(
     Ok(())
 }
 
-pub async fn s3_delete_object(
-    profile: &str,
-    region: &str,
-    bucket: &str,
-    key: &str,
-) -> Result<(), CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+pub async fn s3_delete_object(aws: &AwsContext, bucket: &str, key: &str) -> Result<(), CliError> {
+    let client = aws.client::<Client>().await;
 
     client
         .delete_object()
@@ -291,8 +261,7 @@ pub async fn s3_delete_object(
 
 pub async fn s3_delete_objects(
     pr: &Printer,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     bucket: &str,
     keys: Vec<String>,
     max_concurrency: usize,
@@ -310,7 +279,7 @@ pub async fn s3_delete_objects(
 
     stream::iter(
         keys.into_iter()
-            .map(|key| async move { s3_delete_object(profile, region, bucket, &key).await }),
+            .map(|key| async move { s3_delete_object(aws, bucket, &key).await }),
     )
     .buffer_unordered(max_concurrency.max(1))
     .try_collect::<Vec<_>>()
@@ -321,12 +290,11 @@ pub async fn s3_delete_objects(
 
 pub async fn s3_create_folder_placeholder(
     pr: &Printer,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     bucket: &str,
     key: &str,
 ) -> Result<(), CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+    let client = aws.client::<Client>().await;
 
     client
         .put_object()
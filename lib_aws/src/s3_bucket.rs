@@ -0,0 +1,682 @@
+use std::{fmt::Debug, future::Future};
+
+use aws_sdk_s3::{
+    error::ProvideErrorMetadata,
+    types::{
+        AbortIncompleteMultipartUpload, BucketLifecycleConfiguration, BucketVersioningStatus,
+        CorsConfiguration, CorsRule, ErrorDocument, ExpirationStatus, IndexDocument,
+        LifecycleExpiration, LifecycleRule, LifecycleRuleFilter, NoncurrentVersionExpiration,
+        PublicAccessBlockConfiguration, ServerSideEncryption, ServerSideEncryptionByDefault,
+        ServerSideEncryptionConfiguration, ServerSideEncryptionRule, VersioningConfiguration,
+        WebsiteConfiguration,
+    },
+    Client,
+};
+use lib_core::{json_diff, print_json_diff, CliError, Printer};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{AwsContext, S3Error};
+
+// The 'ensure' functions below read the current configuration of the bucket,
+// and only apply the desired one (after printing the differences) if it
+// differs. They return whether a change was made.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct S3PublicAccessBlock {
+    pub block_public_acls: bool,
+    pub ignore_public_acls: bool,
+    pub block_public_policy: bool,
+    pub restrict_public_buckets: bool,
+}
+
+impl Default for S3PublicAccessBlock {
+    /// Blocks all public access.
+    fn default() -> Self {
+        S3PublicAccessBlock {
+            block_public_acls: true,
+            ignore_public_acls: true,
+            block_public_policy: true,
+            restrict_public_buckets: true,
+        }
+    }
+}
+
+impl S3PublicAccessBlock {
+    /// Needed for buckets with a public policy (ex. static websites).
+    pub fn allow_public_policy() -> Self {
+        S3PublicAccessBlock {
+            block_public_policy: false,
+            restrict_public_buckets: false,
+            ..Default::default()
+        }
+    }
+}
+
+/// Default encryption of new objects.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum S3Encryption {
+    /// SSE-S3.
+    S3Managed,
+    /// SSE-KMS. Without a key, the AWS managed key ('aws/s3') is used. Give
+    /// the key's ARN, since that's what S3 returns when comparing.
+    Kms {
+        key_arn: Option<String>,
+        bucket_key: bool,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct S3LifecycleRule {
+    pub id: String,
+    /// Empty for the whole bucket.
+    pub prefix: String,
+    pub expiration_days: Option<i32>,
+    /// For versioned buckets: how long old versions are kept.
+    pub noncurrent_expiration_days: Option<i32>,
+    pub abort_incomplete_multipart_days: Option<i32>,
+    /// Kept in the configuration, but not applied.
+    pub disabled: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct S3CorsRule {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub expose_headers: Vec<String>,
+    pub max_age_seconds: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct S3Website {
+    /// Ex. 'index.html'.
+    pub index_document: String,
+    pub error_document: Option<String>,
+}
+
+/// 'policy' is the policy document. Removes the policy if 'None'.
+pub async fn s3_ensure_bucket_policy(
+    pr: &Printer,
+    aws: &AwsContext,
+    bucket: &str,
+    policy: Option<&Value>,
+) -> Result<bool, CliError> {
+    let client = aws.client::<Client>().await;
+    let current = optional(
+        client.get_bucket_policy().bucket(bucket).send().await,
+        "NoSuchBucketPolicy",
+    )?
+    .and_then(|output| output.policy)
+    .map(|policy| serde_json::from_str::<Value>(&policy).map_err(|e| S3Error::with_debug(&e)))
+    .transpose()?;
+
+    let desired = policy.cloned().unwrap_or(Value::Null);
+    apply_if_changed(
+        pr,
+        "policy",
+        bucket,
+        to_value(&current),
+        desired,
+        || async {
+            match policy {
+                Some(policy) => client
+                    .put_bucket_policy()
+                    .bucket(bucket)
+                    .policy(policy.to_string())
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| S3Error::with_debug(&e)),
+                None => client
+                    .delete_bucket_policy()
+                    .bucket(bucket)
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| S3Error::with_debug(&e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn s3_ensure_public_access_block(
+    pr: &Printer,
+    aws: &AwsContext,
+    bucket: &str,
+    block: S3PublicAccessBlock,
+) -> Result<bool, CliError> {
+    let client = aws.client::<Client>().await;
+    // Everything is allowed if the bucket has no configuration.
+    let current = optional(
+        client.get_public_access_block().bucket(bucket).send().await,
+        "NoSuchPublicAccessBlockConfiguration",
+    )?
+    .and_then(|output| output.public_access_block_configuration)
+    .map(|config| S3PublicAccessBlock {
+        block_public_acls: config.block_public_acls.unwrap_or_default(),
+        ignore_public_acls: config.ignore_public_acls.unwrap_or_default(),
+        block_public_policy: config.block_public_policy.unwrap_or_default(),
+        restrict_public_buckets: config.restrict_public_buckets.unwrap_or_default(),
+    })
+    .unwrap_or(S3PublicAccessBlock {
+        block_public_acls: false,
+        ignore_public_acls: false,
+        block_public_policy: false,
+        restrict_public_buckets: false,
+    });
+
+    let desired = to_value(&block);
+    apply_if_changed(
+        pr,
+        "public access block",
+        bucket,
+        to_value(&current),
+        desired,
+        || async {
+            client
+                .put_public_access_block()
+                .bucket(bucket)
+                .public_access_block_configuration(
+                    PublicAccessBlockConfiguration::builder()
+                        .block_public_acls(block.block_public_acls)
+                        .ignore_public_acls(block.ignore_public_acls)
+                        .block_public_policy(block.block_public_policy)
+                        .restrict_public_buckets(block.restrict_public_buckets)
+                        .build(),
+                )
+                .send()
+                .await
+                .map_err(|e| S3Error::with_debug(&e))?;
+            Ok(())
+        },
+    )
+    .await
+}
+
+pub async fn s3_ensure_encryption(
+    pr: &Printer,
+    aws: &AwsContext,
+    bucket: &str,
+    encryption: S3Encryption,
+) -> Result<bool, CliError> {
+    let client = aws.client::<Client>().await;
+    // Buckets are encrypted with SSE-S3 unless configured otherwise.
+    let current = optional(
+        client.get_bucket_encryption().bucket(bucket).send().await,
+        "ServerSideEncryptionConfigurationNotFoundError",
+    )?
+    .and_then(|output| output.server_side_encryption_configuration)
+    .and_then(|config| config.rules.into_iter().next())
+    .and_then(|rule| {
+        let default = rule.apply_server_side_encryption_by_default?;
+        Some(match default.sse_algorithm {
+            ServerSideEncryption::AwsKms | ServerSideEncryption::AwsKmsDsse => S3Encryption::Kms {
+                key_arn: default.kms_master_key_id,
+                bucket_key: rule.bucket_key_enabled.unwrap_or_default(),
+            },
+            _ => S3Encryption::S3Managed,
+        })
+    })
+    .unwrap_or(S3Encryption::S3Managed);
+
+    let desired = to_value(&encryption);
+    apply_if_changed(
+        pr,
+        "default encryption",
+        bucket,
+        to_value(&current),
+        desired,
+        || async {
+            let (algorithm, key_arn, bucket_key) = match &encryption {
+                S3Encryption::S3Managed => (ServerSideEncryption::Aes256, None, None),
+                S3Encryption::Kms {
+                    key_arn,
+                    bucket_key,
+                } => (
+                    ServerSideEncryption::AwsKms,
+                    key_arn.clone(),
+                    Some(*bucket_key),
+                ),
+            };
+            let rule = ServerSideEncryptionRule::builder()
+                .apply_server_side_encryption_by_default(
+                    ServerSideEncryptionByDefault::builder()
+                        .sse_algorithm(algorithm)
+                        .set_kms_master_key_id(key_arn)
+                        .build()
+                        .map_err(|e| S3Error::with_debug(&e))?,
+                )
+                .set_bucket_key_enabled(bucket_key)
+                .build();
+            client
+                .put_bucket_encryption()
+                .bucket(bucket)
+                .server_side_encryption_configuration(
+                    ServerSideEncryptionConfiguration::builder()
+                        .rules(rule)
+                        .build()
+                        .map_err(|e| S3Error::with_debug(&e))?,
+                )
+                .send()
+                .await
+                .map_err(|e| S3Error::with_debug(&e))?;
+            Ok(())
+        },
+    )
+    .await
+}
+
+/// Versioning can't be turned off once enabled, only suspended (existing
+/// versions are kept).
+pub async fn s3_ensure_versioning(
+    pr: &Printer,
+    aws: &AwsContext,
+    bucket: &str,
+    enabled: bool,
+) -> Result<bool, CliError> {
+    let client = aws.client::<Client>().await;
+    let current = client
+        .get_bucket_versioning()
+        .bucket(bucket)
+        .send()
+        .await
+        .map_err(|e| S3Error::with_debug(&e))?
+        .status
+        == Some(BucketVersioningStatus::Enabled);
+
+    apply_if_changed(
+        pr,
+        "versioning",
+        bucket,
+        json!(current),
+        json!(enabled),
+        || async {
+            let status = match enabled {
+                true => BucketVersioningStatus::Enabled,
+                false => BucketVersioningStatus::Suspended,
+            };
+            client
+                .put_bucket_versioning()
+                .bucket(bucket)
+                .versioning_configuration(VersioningConfiguration::builder().status(status).build())
+                .send()
+                .await
+                .map_err(|e| S3Error::with_debug(&e))?;
+            Ok(())
+        },
+    )
+    .await
+}
+
+/// Replaces all lifecycle rules of the bucket (including disabled ones, which
+/// are deleted unless given too). Rules not created by this function (ex. with
+/// tag filters, or transitions) show up as changed.
+pub async fn s3_ensure_lifecycle_rules(
+    pr: &Printer,
+    aws: &AwsContext,
+    bucket: &str,
+    rules: &[S3LifecycleRule],
+) -> Result<bool, CliError> {
+    let client = aws.client::<Client>().await;
+    let current = optional(
+        client
+            .get_bucket_lifecycle_configuration()
+            .bucket(bucket)
+            .send()
+            .await,
+        "NoSuchLifecycleConfiguration",
+    )?
+    .map(|output| output.rules.unwrap_or_default())
+    .unwrap_or_default()
+    .into_iter()
+    .map(lifecycle_rule_from)
+    .collect::<Vec<_>>();
+
+    let desired = to_value(&rules);
+    apply_if_changed(
+        pr,
+        "lifecycle rules",
+        bucket,
+        to_value(&current),
+        desired,
+        || async {
+            if rules.is_empty() {
+                client
+                    .delete_bucket_lifecycle()
+                    .bucket(bucket)
+                    .send()
+                    .await
+                    .map_err(|e| S3Error::with_debug(&e))?;
+                return Ok(());
+            }
+            let rules = rules
+                .iter()
+                .map(|rule| {
+                    LifecycleRule::builder()
+                        .id(&rule.id)
+                        .status(match rule.disabled {
+                            true => ExpirationStatus::Disabled,
+                            false => ExpirationStatus::Enabled,
+                        })
+                        .filter(LifecycleRuleFilter::builder().prefix(&rule.prefix).build())
+                        .set_expiration(
+                            rule.expiration_days
+                                .map(|days| LifecycleExpiration::builder().days(days).build()),
+                        )
+                        .set_noncurrent_version_expiration(rule.noncurrent_expiration_days.map(
+                            |days| {
+                                NoncurrentVersionExpiration::builder()
+                                    .noncurrent_days(days)
+                                    .build()
+                            },
+                        ))
+                        .set_abort_incomplete_multipart_upload(
+                            rule.abort_incomplete_multipart_days.map(|days| {
+                                AbortIncompleteMultipartUpload::builder()
+                                    .days_after_initiation(days)
+                                    .build()
+                            }),
+                        )
+                        .build()
+                        .map_err(|e| S3Error::with_debug(&e))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            client
+                .put_bucket_lifecycle_configuration()
+                .bucket(bucket)
+                .lifecycle_configuration(
+                    BucketLifecycleConfiguration::builder()
+                        .set_rules(Some(rules))
+                        .build()
+                        .map_err(|e| S3Error::with_debug(&e))?,
+                )
+                .send()
+                .await
+                .map_err(|e| S3Error::with_debug(&e))?;
+            Ok(())
+        },
+    )
+    .await
+}
+
+/// Replaces all CORS rules of the bucket. Removes them if 'rules' is empty.
+pub async fn s3_ensure_cors(
+    pr: &Printer,
+    aws: &AwsContext,
+    bucket: &str,
+    rules: &[S3CorsRule],
+) -> Result<bool, CliError> {
+    let client = aws.client::<Client>().await;
+    let current = optional(
+        client.get_bucket_cors().bucket(bucket).send().await,
+        "NoSuchCORSConfiguration",
+    )?
+    .and_then(|output| output.cors_rules)
+    .unwrap_or_default()
+    .into_iter()
+    .map(|rule| S3CorsRule {
+        allowed_origins: rule.allowed_origins,
+        allowed_methods: rule.allowed_methods,
+        allowed_headers: rule.allowed_headers.unwrap_or_default(),
+        expose_headers: rule.expose_headers.unwrap_or_default(),
+        max_age_seconds: rule.max_age_seconds,
+    })
+    .collect::<Vec<_>>();
+
+    let desired = to_value(&rules);
+    apply_if_changed(
+        pr,
+        "CORS rules",
+        bucket,
+        to_value(&current),
+        desired,
+        || async {
+            if rules.is_empty() {
+                client
+                    .delete_bucket_cors()
+                    .bucket(bucket)
+                    .send()
+                    .await
+                    .map_err(|e| S3Error::with_debug(&e))?;
+                return Ok(());
+            }
+            let rules = rules
+                .iter()
+                .map(|rule| {
+                    CorsRule::builder()
+                        .set_allowed_origins(Some(rule.allowed_origins.clone()))
+                        .set_allowed_methods(Some(rule.allowed_methods.clone()))
+                        .set_allowed_headers(Some(rule.allowed_headers.clone()))
+                        .set_expose_headers(Some(rule.expose_headers.clone()))
+                        .set_max_age_seconds(rule.max_age_seconds)
+                        .build()
+                        .map_err(|e| S3Error::with_debug(&e))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            client
+                .put_bucket_cors()
+                .bucket(bucket)
+                .cors_configuration(
+                    CorsConfiguration::builder()
+                        .set_cors_rules(Some(rules))
+                        .build()
+                        .map_err(|e| S3Error::with_debug(&e))?,
+                )
+                .send()
+                .await
+                .map_err(|e| S3Error::with_debug(&e))?;
+            Ok(())
+        },
+    )
+    .await
+}
+
+/// Static website hosting. Disables it if 'None'. The objects must also be
+/// made public (see `s3_ensure_bucket_policy` and
+/// `S3PublicAccessBlock::allow_public_policy`).
+pub async fn s3_ensure_website(
+    pr: &Printer,
+    aws: &AwsContext,
+    bucket: &str,
+    website: Option<S3Website>,
+) -> Result<bool, CliError> {
+    let client = aws.client::<Client>().await;
+    let current = optional(
+        client.get_bucket_website().bucket(bucket).send().await,
+        "NoSuchWebsiteConfiguration",
+    )?
+    .and_then(|output| {
+        Some(S3Website {
+            index_document: output.index_document?.suffix,
+            error_document: output.error_document.map(|e| e.key),
+        })
+    });
+
+    let desired = to_value(&website);
+    apply_if_changed(
+        pr,
+        "website hosting",
+        bucket,
+        to_value(&current),
+        desired,
+        || async {
+            let Some(website) = &website else {
+                client
+                    .delete_bucket_website()
+                    .bucket(bucket)
+                    .send()
+                    .await
+                    .map_err(|e| S3Error::with_debug(&e))?;
+                return Ok(());
+            };
+            let error_document = match &website.error_document {
+                Some(key) => Some(
+                    ErrorDocument::builder()
+                        .key(key)
+                        .build()
+                        .map_err(|e| S3Error::with_debug(&e))?,
+                ),
+                None => None,
+            };
+            client
+                .put_bucket_website()
+                .bucket(bucket)
+                .website_configuration(
+                    WebsiteConfiguration::builder()
+                        .index_document(
+                            IndexDocument::builder()
+                                .suffix(&website.index_document)
+                                .build()
+                                .map_err(|e| S3Error::with_debug(&e))?,
+                        )
+                        .set_error_document(error_document)
+                        .build(),
+                )
+                .send()
+                .await
+                .map_err(|e| S3Error::with_debug(&e))?;
+            Ok(())
+        },
+    )
+    .await
+}
+
+// Helpers.
+// ----------------------------------------------------------------------------
+
+async fn apply_if_changed<F, Fut>(
+    pr: &Printer,
+    what: &str,
+    bucket: &str,
+    current: Value,
+    desired: Value,
+    apply: F,
+) -> Result<bool, CliError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<(), CliError>>,
+{
+    let changes = json_diff(&current, &desired);
+    if changes.is_empty() {
+        pr.info(&format!(
+            "No changes to the {} of bucket '{}'.",
+            what, bucket
+        ));
+        return Ok(false);
+    }
+    pr.info(&format!("Updating the {} of bucket '{}':", what, bucket));
+    print_json_diff(pr, &changes);
+    apply().await?;
+    Ok(true)
+}
+
+fn lifecycle_rule_from(rule: LifecycleRule) -> S3LifecycleRule {
+    // Older rules set the prefix outside of the filter.
+    #[allow(deprecated)]
+    let legacy_prefix = rule.prefix;
+    S3LifecycleRule {
+        id: rule.id.unwrap_or_default(),
+        prefix: rule
+            .filter
+            .and_then(|filter| filter.prefix)
+            .or(legacy_prefix)
+            .unwrap_or_default(),
+        expiration_days: rule.expiration.and_then(|e| e.days),
+        noncurrent_expiration_days: rule
+            .noncurrent_version_expiration
+            .and_then(|e| e.noncurrent_days),
+        abort_incomplete_multipart_days: rule
+            .abort_incomplete_multipart_upload
+            .and_then(|a| a.days_after_initiation),
+        disabled: rule.status == ExpirationStatus::Disabled,
+    }
+}
+
+/// 'None' if the bucket has no such configuration (S3 returns an error code
+/// specific to each configuration).
+fn optional<T, E>(result: Result<T, E>, missing_code: &str) -> Result<Option<T>, CliError>
+where
+    E: ProvideErrorMetadata + Debug,
+{
+    match result {
+        Ok(output) => Ok(Some(output)),
+        Err(e) if e.code() == Some(missing_code) => Ok(None),
+        Err(e) => Err(S3Error::with_debug(&e)),
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or_default()
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn changes_are_only_applied_if_the_configuration_differs() {
+        let pr = Printer::new();
+        let current = to_value(&S3PublicAccessBlock::default());
+        let mut applied = false;
+        let changed = apply_if_changed(
+            &pr,
+            "public access block",
+            "b",
+            current.clone(),
+            current.clone(),
+            || async {
+                applied = true;
+                Ok(())
+            },
+        )
+        .await
+        .unwrap();
+        assert!(!changed && !applied);
+
+        let desired = to_value(&S3PublicAccessBlock::allow_public_policy());
+        let changed = apply_if_changed(
+            &pr,
+            "public access block",
+            "b",
+            current,
+            desired,
+            || async {
+                applied = true;
+                Ok(())
+            },
+        )
+        .await
+        .unwrap();
+        assert!(changed && applied);
+    }
+
+    #[test]
+    fn disabled_lifecycle_rules_are_part_of_the_current_configuration() {
+        let rule = LifecycleRule::builder()
+            .id("old-logs")
+            .status(ExpirationStatus::Disabled)
+            .filter(LifecycleRuleFilter::builder().prefix("logs/").build())
+            .expiration(LifecycleExpiration::builder().days(30).build())
+            .build()
+            .unwrap();
+        assert_eq!(
+            lifecycle_rule_from(rule),
+            S3LifecycleRule {
+                id: "old-logs".to_string(),
+                prefix: "logs/".to_string(),
+                expiration_days: Some(30),
+                disabled: true,
+                ..Default::default()
+            }
+        );
+    }
+}
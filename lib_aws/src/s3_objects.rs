@@ -0,0 +1,495 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use aws_sdk_s3::{
+    operation::{
+        create_multipart_upload::{
+            builders::CreateMultipartUploadInputBuilder, CreateMultipartUploadInput,
+        },
+        head_object::HeadObjectOutput,
+    },
+    presigning::PresigningConfig,
+    types::{CompletedMultipartUpload, CompletedPart, StorageClass, Tag, Tagging},
+    Client,
+};
+use futures_util::stream::{self, StreamExt as _, TryStreamExt as _};
+use lib_core::{define_cli_error, CliError, Printer};
+
+use crate::{s3_delete_object, AwsContext, S3Error, S3ObjectMetadata};
+
+define_cli_error!(
+    S3InvalidPresignExpiry,
+    "Invalid presigned URL expiry of {seconds}s (at most 7 days is allowed).",
+    { seconds: u64 }
+);
+define_cli_error!(
+    S3ObjectNotFound,
+    "Object 's3://{bucket}/{key}' not found.",
+    { bucket: &str, key: &str }
+);
+
+/// Larger objects can't be copied in a single request.
+const MAX_COPY_SIZE: i64 = 5 * 1024 * 1024 * 1024;
+const COPY_PART_SIZE: i64 = 512 * 1024 * 1024;
+const COPY_CONCURRENCY: usize = 4;
+
+/// Lets anyone with the URL download the object until it expires, without
+/// credentials. URLs signed with temporary credentials (ex. SSO or an assumed
+/// role) stop working when the credentials expire, even if that's earlier.
+pub async fn s3_presigned_get_url(
+    aws: &AwsContext,
+    bucket: &str,
+    key: &str,
+    expires_in: Duration,
+) -> Result<String, CliError> {
+    let client = aws.client::<Client>().await;
+    let request = client
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .presigned(presigning_config(expires_in)?)
+        .await
+        .map_err(|e| S3Error::with_debug(&e))?;
+    Ok(request.uri().to_string())
+}
+
+/// Lets anyone with the URL upload the object with an HTTP PUT request (ex.
+/// `curl --upload-file artifact.zip '<url>'`), until it expires. See
+/// `s3_presigned_get_url` about temporary credentials.
+pub async fn s3_presigned_put_url(
+    aws: &AwsContext,
+    bucket: &str,
+    key: &str,
+    expires_in: Duration,
+) -> Result<String, CliError> {
+    let client = aws.client::<Client>().await;
+    let request = client
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .presigned(presigning_config(expires_in)?)
+        .await
+        .map_err(|e| S3Error::with_debug(&e))?;
+    Ok(request.uri().to_string())
+}
+
+/// Returns `None` if the object doesn't exist.
+pub async fn s3_head_object(
+    aws: &AwsContext,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<S3ObjectMetadata>, CliError> {
+    let client = aws.client::<Client>().await;
+    let Some(output) = head_object(&client, bucket, key).await? else {
+        return Ok(None);
+    };
+
+    Ok(Some(S3ObjectMetadata {
+        key: key.to_string(),
+        size: output.content_length.unwrap_or_default(),
+        etag: output.e_tag.map(|e| e.trim_matches('"').to_string()),
+        last_modified: output
+            .last_modified
+            .and_then(|dt| chrono::DateTime::from_timestamp(dt.secs(), 0)),
+        // Omitted for the default storage class.
+        storage_class: Some(
+            output
+                .storage_class
+                .unwrap_or(StorageClass::Standard)
+                .as_str()
+                .to_string(),
+        ),
+        content_type: output.content_type,
+        metadata: output.metadata.unwrap_or_default(),
+    }))
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct S3CopyOptions<'a> {
+    /// Region of the source bucket, if it differs from the region of the
+    /// context (which must be the destination bucket's region).
+    pub source_region: Option<&'a str>,
+}
+
+/// Server-side copy, keeping the object's headers (ex. 'Cache-Control'), user
+/// metadata, tags, storage class and encryption settings. Both buckets must be
+/// in the region of 'aws' (see `s3_copy_object_with_options` otherwise).
+pub async fn s3_copy_object(
+    pr: &Printer,
+    aws: &AwsContext,
+    src_bucket: &str,
+    src_key: &str,
+    dst_bucket: &str,
+    dst_key: &str,
+) -> Result<(), CliError> {
+    s3_copy_object_with_options(
+        pr,
+        aws,
+        src_bucket,
+        src_key,
+        dst_bucket,
+        dst_key,
+        S3CopyOptions::default(),
+    )
+    .await
+}
+
+/// 'aws' must be in the destination bucket's region.
+pub async fn s3_copy_object_with_options(
+    pr: &Printer,
+    aws: &AwsContext,
+    src_bucket: &str,
+    src_key: &str,
+    dst_bucket: &str,
+    dst_key: &str,
+    options: S3CopyOptions<'_>,
+) -> Result<(), CliError> {
+    let client = aws.client::<Client>().await;
+    // The object is read from its own region, but copied by the destination.
+    let src_aws = source_context(aws, &options);
+    let source = head_object(&src_aws.client::<Client>().await, src_bucket, src_key)
+        .await?
+        .ok_or_else(|| S3ObjectNotFound::new(src_bucket, src_key))?;
+    let size = source.content_length().unwrap_or_default();
+    let copy_source = copy_source(src_bucket, src_key);
+
+    if size <= MAX_COPY_SIZE {
+        // The headers, metadata and tags are copied by default, but not these.
+        client
+            .copy_object()
+            .copy_source(&copy_source)
+            .bucket(dst_bucket)
+            .key(dst_key)
+            .set_storage_class(source.storage_class().cloned())
+            .set_server_side_encryption(source.server_side_encryption().cloned())
+            .set_ssekms_key_id(source.ssekms_key_id().map(str::to_string))
+            .set_bucket_key_enabled(source.bucket_key_enabled())
+            .send()
+            .await
+            .map_err(|e| S3Error::with_debug(&e))?;
+    } else {
+        let tags = s3_get_object_tags(&src_aws, src_bucket, src_key).await?;
+        let upload = multipart_copy_input(&source, &tags, dst_bucket, dst_key);
+        copy_object_in_parts(&client, upload, size, &copy_source, dst_bucket, dst_key).await?;
+    }
+
+    pr.info(&format!(
+        "Copied 's3://{}/{}' to 's3://{}/{}'.",
+        src_bucket, src_key, dst_bucket, dst_key
+    ));
+    Ok(())
+}
+
+/// Copies the object, then deletes the source. Both buckets must be in the
+/// region of 'aws' (see `s3_move_object_with_options` otherwise).
+pub async fn s3_move_object(
+    pr: &Printer,
+    aws: &AwsContext,
+    src_bucket: &str,
+    src_key: &str,
+    dst_bucket: &str,
+    dst_key: &str,
+) -> Result<(), CliError> {
+    s3_move_object_with_options(
+        pr,
+        aws,
+        src_bucket,
+        src_key,
+        dst_bucket,
+        dst_key,
+        S3CopyOptions::default(),
+    )
+    .await
+}
+
+/// 'aws' must be in the destination bucket's region.
+pub async fn s3_move_object_with_options(
+    pr: &Printer,
+    aws: &AwsContext,
+    src_bucket: &str,
+    src_key: &str,
+    dst_bucket: &str,
+    dst_key: &str,
+    options: S3CopyOptions<'_>,
+) -> Result<(), CliError> {
+    s3_copy_object_with_options(pr, aws, src_bucket, src_key, dst_bucket, dst_key, options).await?;
+    s3_delete_object(&source_context(aws, &options), src_bucket, src_key).await
+}
+
+fn source_context(aws: &AwsContext, options: &S3CopyOptions<'_>) -> AwsContext {
+    match options.source_region {
+        Some(region) => aws.with_region(region),
+        None => aws.clone(),
+    }
+}
+
+pub async fn s3_get_object_tags(
+    aws: &AwsContext,
+    bucket: &str,
+    key: &str,
+) -> Result<BTreeMap<String, String>, CliError> {
+    let client = aws.client::<Client>().await;
+    let output = client
+        .get_object_tagging()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| S3Error::with_debug(&e))?;
+    Ok(output
+        .tag_set
+        .into_iter()
+        .map(|tag| (tag.key, tag.value))
+        .collect())
+}
+
+/// Replaces all tags of the object.
+pub async fn s3_put_object_tags(
+    aws: &AwsContext,
+    bucket: &str,
+    key: &str,
+    tags: &BTreeMap<String, String>,
+) -> Result<(), CliError> {
+    let tag_set = tags
+        .iter()
+        .map(|(key, value)| {
+            Tag::builder()
+                .key(key)
+                .value(value)
+                .build()
+                .map_err(|e| S3Error::with_debug(&e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let tagging = Tagging::builder()
+        .set_tag_set(Some(tag_set))
+        .build()
+        .map_err(|e| S3Error::with_debug(&e))?;
+
+    let client = aws.client::<Client>().await;
+    client
+        .put_object_tagging()
+        .bucket(bucket)
+        .key(key)
+        .tagging(tagging)
+        .send()
+        .await
+        .map_err(|e| S3Error::with_debug(&e))?;
+    Ok(())
+}
+
+// Helpers.
+// ----------------------------------------------------------------------------
+
+fn presigning_config(expires_in: Duration) -> Result<PresigningConfig, CliError> {
+    PresigningConfig::expires_in(expires_in)
+        .map_err(|e| S3InvalidPresignExpiry::with_debug(expires_in.as_secs(), &e))
+}
+
+async fn head_object(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<HeadObjectOutput>, CliError> {
+    match client.head_object().bucket(bucket).key(key).send().await {
+        Ok(output) => Ok(Some(output)),
+        Err(e) if e.as_service_error().is_some_and(|se| se.is_not_found()) => Ok(None),
+        Err(e) => Err(S3Error::with_debug(&e)),
+    }
+}
+
+/// Nothing but the data is copied with the parts, so everything else is set
+/// when starting the upload. Objects encrypted with customer-provided keys
+/// can't be copied without the key.
+fn multipart_copy_input(
+    source: &HeadObjectOutput,
+    tags: &BTreeMap<String, String>,
+    dst_bucket: &str,
+    dst_key: &str,
+) -> CreateMultipartUploadInputBuilder {
+    #[allow(deprecated)]
+    let expires = source.expires().cloned();
+    CreateMultipartUploadInput::builder()
+        .bucket(dst_bucket)
+        .key(dst_key)
+        .set_cache_control(source.cache_control().map(str::to_string))
+        .set_content_disposition(source.content_disposition().map(str::to_string))
+        .set_content_encoding(source.content_encoding().map(str::to_string))
+        .set_content_language(source.content_language().map(str::to_string))
+        .set_content_type(source.content_type().map(str::to_string))
+        .set_expires(expires)
+        .set_website_redirect_location(source.website_redirect_location().map(str::to_string))
+        .set_metadata(source.metadata().cloned())
+        .set_storage_class(source.storage_class().cloned())
+        .set_server_side_encryption(source.server_side_encryption().cloned())
+        .set_ssekms_key_id(source.ssekms_key_id().map(str::to_string))
+        .set_bucket_key_enabled(source.bucket_key_enabled())
+        .set_tagging((!tags.is_empty()).then(|| {
+            tags.iter()
+                .map(|(key, value)| {
+                    format!(
+                        "{}={}",
+                        percent_encode(key, false),
+                        percent_encode(value, false)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("&")
+        }))
+}
+
+async fn copy_object_in_parts(
+    client: &Client,
+    upload: CreateMultipartUploadInputBuilder,
+    size: i64,
+    copy_source: &str,
+    dst_bucket: &str,
+    dst_key: &str,
+) -> Result<(), CliError> {
+    let upload_id = upload
+        .send_with(client)
+        .await
+        .map_err(|e| S3Error::with_debug(&e))?
+        .upload_id
+        .ok_or_else(S3Error::new)?;
+
+    let part_count = (size + COPY_PART_SIZE - 1) / COPY_PART_SIZE;
+    let parts = stream::iter((0..part_count).map(|i| {
+        let upload_id = &upload_id;
+        async move {
+            let start = i * COPY_PART_SIZE;
+            let end = (start + COPY_PART_SIZE).min(size) - 1;
+            let output = client
+                .upload_part_copy()
+                .bucket(dst_bucket)
+                .key(dst_key)
+                .upload_id(upload_id)
+                .part_number(i as i32 + 1)
+                .copy_source(copy_source)
+                .copy_source_range(format!("bytes={}-{}", start, end))
+                .send()
+                .await
+                .map_err(|e| S3Error::with_debug(&e))?;
+            Ok::<_, CliError>(
+                CompletedPart::builder()
+                    .part_number(i as i32 + 1)
+                    .set_e_tag(output.copy_part_result.and_then(|r| r.e_tag))
+                    .build(),
+            )
+        }
+    }))
+    .buffered(COPY_CONCURRENCY)
+    .try_collect::<Vec<_>>()
+    .await;
+    let parts = match parts {
+        Ok(parts) => parts,
+        Err(e) => {
+            // Best effort, since the copy already failed.
+            let _ = client
+                .abort_multipart_upload()
+                .bucket(dst_bucket)
+                .key(dst_key)
+                .upload_id(&upload_id)
+                .send()
+                .await;
+            return Err(e);
+        }
+    };
+
+    client
+        .complete_multipart_upload()
+        .bucket(dst_bucket)
+        .key(dst_key)
+        .upload_id(&upload_id)
+        .multipart_upload(
+            CompletedMultipartUpload::builder()
+                .set_parts(Some(parts))
+                .build(),
+        )
+        .send()
+        .await
+        .map_err(|e| S3Error::with_debug(&e))?;
+    Ok(())
+}
+
+/// '<bucket>/<key>', with the key URL-encoded (except for slashes).
+fn copy_source(bucket: &str, key: &str) -> String {
+    format!("{}/{}", bucket, percent_encode(key, true))
+}
+
+fn percent_encode(value: &str, keep_slashes: bool) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if keep_slashes => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_s3::types::ServerSideEncryption;
+
+    use super::*;
+
+    #[test]
+    fn copy_source_encodes_the_key() {
+        assert_eq!(
+            copy_source("artifacts", "builds/v1.2/app release+1 é.zip"),
+            "artifacts/builds/v1.2/app%20release%2B1%20%C3%A9.zip"
+        );
+    }
+
+    #[test]
+    fn multipart_copies_keep_headers_encryption_and_tags() {
+        let source = HeadObjectOutput::builder()
+            .cache_control("max-age=3600")
+            .content_disposition("attachment")
+            .content_encoding("gzip")
+            .content_type("application/json")
+            .metadata("build", "42")
+            .storage_class(StorageClass::StandardIa)
+            .server_side_encryption(ServerSideEncryption::AwsKms)
+            .ssekms_key_id("arn:aws:kms:us-east-1:123456789012:key/1")
+            .bucket_key_enabled(true)
+            .build();
+        let tags = BTreeMap::from([
+            ("env".to_string(), "prod".to_string()),
+            ("owner".to_string(), "data team".to_string()),
+        ]);
+        let input = multipart_copy_input(&source, &tags, "backups", "dump.json.gz");
+
+        assert_eq!(input.get_bucket().as_deref(), Some("backups"));
+        assert_eq!(input.get_cache_control().as_deref(), Some("max-age=3600"));
+        assert_eq!(
+            input.get_content_disposition().as_deref(),
+            Some("attachment")
+        );
+        assert_eq!(input.get_content_encoding().as_deref(), Some("gzip"));
+        assert_eq!(
+            input.get_content_type().as_deref(),
+            Some("application/json")
+        );
+        assert_eq!(input.get_metadata().as_ref().unwrap()["build"], "42");
+        assert_eq!(input.get_storage_class(), &Some(StorageClass::StandardIa));
+        assert_eq!(
+            input.get_server_side_encryption(),
+            &Some(ServerSideEncryption::AwsKms)
+        );
+        assert_eq!(
+            input.get_ssekms_key_id().as_deref(),
+            Some("arn:aws:kms:us-east-1:123456789012:key/1")
+        );
+        assert_eq!(input.get_bucket_key_enabled(), &Some(true));
+        assert_eq!(
+            input.get_tagging().as_deref(),
+            Some("env=prod&owner=data%20team")
+        );
+    }
+}
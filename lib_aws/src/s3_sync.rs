@@ -0,0 +1,535 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{self, BufReader, Write},
+    path::Path,
+};
+
+use aws_sdk_s3::{primitives::ByteStream, Client};
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use futures_util::stream::{self, StreamExt as _, TryStreamExt as _};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use lib_core::{define_cli_error, CliError, IOError, Printer};
+use md5::{Digest as _, Md5};
+
+use crate::{
+    s3_delete_objects, s3_list, s3_upload_file_with_options, AwsContext, S3Error, S3InvalidUpload,
+    S3ObjectMetadata, S3TransferOptions,
+};
+
+define_cli_error!(
+    S3InvalidSyncPattern,
+    "Invalid S3 sync pattern '{pattern}'.",
+    { pattern: &str }
+);
+
+/// Files up to this size (after compression) are kept in memory between
+/// diffing and uploading, and uploaded in a single request. Larger files are
+/// read again, and uploaded in parts if needed.
+const MAX_IN_MEMORY_BODY: u64 = 8 * 1024 * 1024;
+
+/// Applied to text-based files (HTML, CSS, JS, JSON, SVG, WASM, ...), which
+/// are then served with the matching 'Content-Encoding'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum S3Compression {
+    Gzip,
+    Brotli,
+}
+
+impl S3Compression {
+    fn content_encoding(self) -> &'static str {
+        match self {
+            S3Compression::Gzip => "gzip",
+            S3Compression::Brotli => "br",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct S3SyncOptions<'a> {
+    /// Delete objects under the prefix that don't exist locally.
+    pub delete_extra: bool,
+    /// Glob patterns, matched against paths relative to the synced directory.
+    /// Patterns without a '/' match file names at any depth (ex. ".DS_Store").
+    /// Excluded paths are neither uploaded nor deleted.
+    pub exclude: Vec<&'a str>,
+    /// '(pattern, value)' pairs, where the first matching pattern sets the
+    /// 'Cache-Control' header (ex. `("*.html", "no-cache")`).
+    pub cache_control: Vec<(&'a str, &'a str)>,
+    pub compression: Option<S3Compression>,
+    /// Only compute (and print) the changes, without applying them.
+    pub dry_run: bool,
+    /// Files uploaded (or objects deleted) in parallel.
+    pub concurrency: usize,
+}
+
+impl Default for S3SyncOptions<'_> {
+    fn default() -> Self {
+        S3SyncOptions {
+            delete_extra: false,
+            exclude: Vec::new(),
+            cache_control: Vec::new(),
+            compression: None,
+            dry_run: false,
+            concurrency: 8,
+        }
+    }
+}
+
+/// Keys, sorted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct S3SyncDiff {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: usize,
+}
+
+impl S3SyncDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    pub fn print(&self, pr: &Printer) {
+        for key in &self.added {
+            pr.success(&format!("+ {}", key));
+        }
+        for key in &self.changed {
+            pr.warn(&format!("~ {}", key));
+        }
+        for key in &self.removed {
+            pr.error(&format!("- {}", key));
+        }
+        if self.is_empty() {
+            pr.info("No differences.");
+        }
+    }
+}
+
+/// File as it would be uploaded.
+#[derive(Debug, Clone)]
+struct LocalObject {
+    key: String,
+    len: u64,
+    md5: [u8; 16],
+    /// The content as uploaded, if small enough to keep.
+    body: Option<Vec<u8>>,
+}
+
+/// Makes the objects under 'key_prefix' match 'dir_path'. Only files whose
+/// content differs are uploaded, compared by MD5 with the object's ETag, or by
+/// size for objects uploaded in parts. Changes to headers alone (ex. a new
+/// 'Cache-Control' rule) are not detected. Returns the changes that were made
+/// (or would be made, if 'dry_run'), where 'removed' is only filled if
+/// 'delete_extra' is set.
+pub async fn s3_sync_dir<P>(
+    pr: &Printer,
+    aws: &AwsContext,
+    bucket: &str,
+    key_prefix: &str,
+    dir_path: P,
+    options: S3SyncOptions<'_>,
+) -> Result<S3SyncDiff, CliError>
+where
+    P: AsRef<Path>,
+{
+    let dir_path = dir_path.as_ref();
+    if !dir_path.is_dir() {
+        return Err(S3InvalidUpload::new("path is not a directory"));
+    }
+    let key_prefix = key_prefix.trim_matches('/');
+    let exclude = build_glob_set(&options.exclude)?;
+    let cache_control = options
+        .cache_control
+        .iter()
+        .map(|(pattern, value)| Ok((build_glob_set(&[pattern])?, *value)))
+        .collect::<Result<Vec<_>, CliError>>()?;
+
+    let mut local = Vec::new();
+    for entry in walkdir::WalkDir::new(dir_path).sort_by_file_name() {
+        let entry = entry.map_err(|e| IOError::with_debug(&e))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel = relative_key(dir_path, entry.path());
+        if exclude.is_match(&rel) {
+            continue;
+        }
+        let headers = content_headers(entry.path(), options.compression);
+        let digest = encode_file(entry.path(), headers.compression, DigestWriter::default())?;
+        local.push(LocalObject {
+            key: object_key(key_prefix, &rel),
+            len: digest.len,
+            md5: digest.md5.finalize().into(),
+            body: digest.data,
+        });
+    }
+    let list_prefix = match key_prefix.is_empty() {
+        true => String::new(),
+        false => format!("{}/", key_prefix),
+    };
+    let remote = s3_list(aws, bucket, &list_prefix)
+        .await?
+        .into_iter()
+        .filter(|object| {
+            let rel = &object.key[list_prefix.len()..];
+            // Folder placeholders are left alone.
+            !object.key.ends_with('/') && !exclude.is_match(rel)
+        })
+        .collect::<Vec<_>>();
+    let mut diff = diff_objects(&local, &remote);
+    if !options.delete_extra {
+        diff.removed.clear();
+    }
+
+    let location = format!("s3://{}/{}", bucket, list_prefix);
+    if options.dry_run {
+        diff.print(pr);
+        pr.info(&format!("Dry run: no changes made to '{}'.", location));
+        return Ok(diff);
+    }
+
+    let client = aws.client::<Client>().await;
+    let mut local = local
+        .into_iter()
+        .map(|object| (object.key.clone(), object))
+        .collect::<BTreeMap<_, _>>();
+    let to_upload = diff
+        .added
+        .iter()
+        .chain(&diff.changed)
+        .filter_map(|key| local.remove(key))
+        .collect::<Vec<_>>();
+    stream::iter(to_upload.into_iter().map(|object| {
+        let rel = object.key[list_prefix.len()..].to_string();
+        let path = dir_path.join(&rel);
+        let cache_control = cache_control
+            .iter()
+            .find(|(patterns, _)| patterns.is_match(&rel))
+            .map(|(_, value)| value.to_string());
+        let client = &client;
+        let compression = options.compression;
+        async move {
+            let headers = content_headers(&path, compression);
+            let Some(body) = object.body else {
+                return upload_large_file(
+                    pr,
+                    aws,
+                    bucket,
+                    &object.key,
+                    &path,
+                    headers,
+                    cache_control,
+                )
+                .await;
+            };
+            client
+                .put_object()
+                .bucket(bucket)
+                .key(&object.key)
+                .set_content_encoding(headers.content_encoding().map(str::to_string))
+                .content_type(headers.content_type)
+                .set_cache_control(cache_control)
+                .content_md5(BASE64_STANDARD.encode(object.md5))
+                .body(ByteStream::from(body))
+                .send()
+                .await
+                .map_err(|e| S3Error::with_debug(&e))?;
+            Ok::<_, CliError>(())
+        }
+    }))
+    .buffer_unordered(options.concurrency.max(1))
+    .try_collect::<Vec<_>>()
+    .await?;
+    s3_delete_objects(pr, aws, bucket, diff.removed.clone(), options.concurrency).await?;
+
+    pr.info(&format!(
+        "Synced '{}': {} uploaded, {} deleted, {} unchanged.",
+        location,
+        diff.added.len() + diff.changed.len(),
+        diff.removed.len(),
+        diff.unchanged
+    ));
+    Ok(diff)
+}
+
+fn diff_objects(local: &[LocalObject], remote: &[S3ObjectMetadata]) -> S3SyncDiff {
+    let remote = remote
+        .iter()
+        .map(|object| (object.key.as_str(), object))
+        .collect::<BTreeMap<_, _>>();
+    let local_keys = local
+        .iter()
+        .map(|object| object.key.as_str())
+        .collect::<BTreeSet<_>>();
+
+    let mut diff = S3SyncDiff::default();
+    for object in local {
+        match remote.get(object.key.as_str()) {
+            None => diff.added.push(object.key.clone()),
+            Some(existing) if is_unchanged(object, existing) => diff.unchanged += 1,
+            Some(_) => diff.changed.push(object.key.clone()),
+        }
+    }
+    diff.removed = remote
+        .keys()
+        .filter(|key| !local_keys.contains(*key))
+        .map(|key| key.to_string())
+        .collect();
+    diff.added.sort();
+    diff.changed.sort();
+    diff
+}
+
+/// The ETag of an object uploaded in parts is not the MD5 of its content
+/// (it ends with '-<part count>'), so only the size can be compared.
+fn is_unchanged(local: &LocalObject, remote: &S3ObjectMetadata) -> bool {
+    if remote.size < 0 || remote.size as u64 != local.len {
+        return false;
+    }
+    match remote.etag.as_deref() {
+        Some(etag) if etag.contains('-') => true,
+        Some(etag) => etag.eq_ignore_ascii_case(&hex::encode(local.md5)),
+        None => false,
+    }
+}
+
+/// Files that don't fit in memory are uploaded from disk (compressed to a
+/// temporary file first, if needed), in parts if they are large.
+async fn upload_large_file(
+    pr: &Printer,
+    aws: &AwsContext,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    headers: ContentHeaders,
+    cache_control: Option<String>,
+) -> Result<(), CliError> {
+    let options = S3TransferOptions {
+        progress: false,
+        content_type: Some(headers.content_type.clone()),
+        content_encoding: headers.content_encoding().map(str::to_string),
+        cache_control,
+        ..Default::default()
+    };
+    match headers.compression {
+        None => s3_upload_file_with_options(pr, aws, bucket, key, path, options).await,
+        Some(compression) => {
+            let compressed = tempfile::NamedTempFile::new().map_err(|e| IOError::with_debug(&e))?;
+            encode_file(path, Some(compression), compressed.as_file())?;
+            s3_upload_file_with_options(pr, aws, bucket, key, compressed.path(), options).await
+        }
+    }
+}
+
+struct ContentHeaders {
+    content_type: String,
+    /// Only set if the file type is compressible.
+    compression: Option<S3Compression>,
+}
+
+impl ContentHeaders {
+    fn content_encoding(&self) -> Option<&'static str> {
+        self.compression.map(S3Compression::content_encoding)
+    }
+}
+
+fn content_headers(path: &Path, compression: Option<S3Compression>) -> ContentHeaders {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    let content_type =
+        match mime.type_() == mime_guess::mime::TEXT && mime.get_param("charset").is_none() {
+            true => format!("{}; charset=utf-8", mime),
+            false => mime.to_string(),
+        };
+    ContentHeaders {
+        content_type,
+        compression: compression.filter(|_| is_compressible(&mime)),
+    }
+}
+
+/// Writes the file to 'writer' as it would be uploaded, without reading it
+/// all into memory. Compression is deterministic, so unchanged files keep the
+/// same MD5.
+fn encode_file<W: Write>(
+    path: &Path,
+    compression: Option<S3Compression>,
+    mut writer: W,
+) -> Result<W, CliError> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| IOError::with_debug(&e))?);
+    match compression {
+        None => {
+            io::copy(&mut reader, &mut writer).map_err(|e| IOError::with_debug(&e))?;
+            Ok(writer)
+        }
+        Some(S3Compression::Gzip) => {
+            let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::best());
+            io::copy(&mut reader, &mut encoder).map_err(|e| IOError::with_debug(&e))?;
+            encoder.finish().map_err(|e| IOError::with_debug(&e))
+        }
+        Some(S3Compression::Brotli) => {
+            let mut encoder = brotli::CompressorWriter::new(writer, 4096, 11, 22);
+            io::copy(&mut reader, &mut encoder).map_err(|e| IOError::with_debug(&e))?;
+            encoder.flush().map_err(|e| IOError::with_debug(&e))?;
+            Ok(encoder.into_inner())
+        }
+    }
+}
+
+/// Hashes and counts what is written, keeping a copy while it is small enough
+/// (see `MAX_IN_MEMORY_BODY`).
+struct DigestWriter {
+    md5: Md5,
+    len: u64,
+    data: Option<Vec<u8>>,
+}
+
+impl Default for DigestWriter {
+    fn default() -> Self {
+        DigestWriter {
+            md5: Md5::new(),
+            len: 0,
+            data: Some(Vec::new()),
+        }
+    }
+}
+
+impl Write for DigestWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.md5.update(buf);
+        self.len += buf.len() as u64;
+        if self.len > MAX_IN_MEMORY_BODY {
+            self.data = None;
+        }
+        if let Some(data) = &mut self.data {
+            data.extend_from_slice(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn is_compressible(mime: &mime_guess::Mime) -> bool {
+    mime.type_() == mime_guess::mime::TEXT
+        || mime.suffix() == Some(mime_guess::mime::JSON)
+        || mime.suffix() == Some(mime_guess::mime::XML)
+        || matches!(
+            mime.essence_str(),
+            "application/javascript"
+                | "application/json"
+                | "application/wasm"
+                | "application/xml"
+                | "image/svg+xml"
+        )
+}
+
+/// Always uses '/', whatever the platform.
+fn relative_key(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn object_key(key_prefix: &str, rel: &str) -> String {
+    match key_prefix.is_empty() {
+        true => rel.to_string(),
+        false => format!("{}/{}", key_prefix, rel),
+    }
+}
+
+fn build_glob_set(patterns: &[&str]) -> Result<GlobSet, CliError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        // Patterns without a separator apply at any depth.
+        let normalized = match pattern.contains('/') {
+            true => pattern.trim_start_matches('/').to_string(),
+            false => format!("**/{}", pattern),
+        };
+        builder.add(
+            Glob::new(&normalized).map_err(|e| S3InvalidSyncPattern::with_debug(pattern, &e))?,
+        );
+    }
+    builder
+        .build()
+        .map_err(|e| S3InvalidSyncPattern::with_debug(&patterns.join(", "), &e))
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(key: &str, content: &[u8]) -> LocalObject {
+        LocalObject {
+            key: key.to_string(),
+            len: content.len() as u64,
+            md5: Md5::digest(content).into(),
+            body: Some(content.to_vec()),
+        }
+    }
+
+    fn remote(key: &str, size: i64, etag: &str) -> S3ObjectMetadata {
+        S3ObjectMetadata {
+            key: key.to_string(),
+            size,
+            etag: Some(etag.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn diff_compares_etags_and_falls_back_to_size_for_multipart_objects() {
+        let local = [
+            local("web/index.html", b"<html>v2</html>"),
+            local("web/main.dart.js", b"main()"),
+            local("web/assets/big.bin", b"0123456789"),
+            local("web/new.css", b"body {}"),
+        ];
+        let remote = [
+            remote(
+                "web/index.html",
+                15,
+                &hex::encode(Md5::digest(b"<html>v1</html>")),
+            ),
+            remote("web/main.dart.js", 6, &hex::encode(Md5::digest(b"main()"))),
+            remote("web/assets/big.bin", 10, "0a1b2c-2"),
+            remote("web/old.js", 3, "abc"),
+        ];
+        let diff = diff_objects(&local, &remote);
+        assert_eq!(diff.added, vec!["web/new.css"]);
+        assert_eq!(diff.changed, vec!["web/index.html"]);
+        assert_eq!(diff.removed, vec!["web/old.js"]);
+        assert_eq!(diff.unchanged, 2);
+    }
+
+    #[test]
+    fn only_small_bodies_are_kept_in_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let small = dir.path().join("index.html");
+        std::fs::write(&small, "<html></html>").unwrap();
+        let large = dir.path().join("video.bin");
+        let content = vec![7; MAX_IN_MEMORY_BODY as usize + 1];
+        std::fs::write(&large, &content).unwrap();
+
+        let digest =
+            encode_file(&small, Some(S3Compression::Gzip), DigestWriter::default()).unwrap();
+        let compressed = encode_file(&small, Some(S3Compression::Gzip), Vec::new()).unwrap();
+        assert_eq!(digest.data.as_ref(), Some(&compressed));
+        assert_eq!(digest.len, compressed.len() as u64);
+
+        let digest = encode_file(&large, None, DigestWriter::default()).unwrap();
+        assert_eq!(digest.data, None);
+        assert_eq!(digest.len, content.len() as u64);
+        assert_eq!(
+            <[u8; 16]>::from(digest.md5.finalize()),
+            <[u8; 16]>::from(Md5::digest(&content))
+        );
+    }
+}
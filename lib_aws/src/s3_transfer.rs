@@ -0,0 +1,879 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use aws_sdk_s3::{
+    primitives::ByteStream,
+    types::{ChecksumAlgorithm, ChecksumMode, CompletedMultipartUpload, CompletedPart},
+    Client,
+};
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use futures_util::stream::{self, StreamExt as _, TryStreamExt as _};
+use lib_core::{define_cli_error, CliError, IOError, Printer};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt as _, AsyncSeekExt as _, AsyncWriteExt as _},
+    select,
+};
+
+use crate::{AwsContext, S3Error, S3InvalidUpload};
+
+define_cli_error!(
+    S3ChecksumMismatch,
+    "Checksum mismatch for 's3://{bucket}/{key}': expected {expected}, got {actual}.",
+    { bucket: &str, key: &str, expected: &str, actual: &str }
+);
+
+/// S3 rejects smaller parts (except the last one).
+const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+const MAX_PARTS: u64 = 10_000;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum S3ChecksumAlgorithm {
+    Sha256,
+    Crc32c,
+}
+
+#[derive(Debug, Clone)]
+pub struct S3TransferOptions {
+    /// Files larger than this are uploaded in parts of this size. Raised to 5
+    /// MiB, or as needed to stay within 10,000 parts.
+    pub part_size: u64,
+    /// Parts uploaded in parallel.
+    pub concurrency: usize,
+    /// Verified by S3 when uploading, and locally when downloading.
+    pub checksum: Option<S3ChecksumAlgorithm>,
+    /// Keep interrupted transfers so that they can be continued, rather than
+    /// cleaning them up. Downloads are written to a '.part' file next to the
+    /// destination until complete. Incomplete multipart uploads are billed
+    /// until completed or aborted, so the bucket should have a lifecycle rule
+    /// aborting them (see `S3LifecycleRule::abort_incomplete_multipart_days`)
+    /// in case they are never resumed.
+    pub resume: bool,
+    /// Show the bytes transferred in a status bar.
+    pub progress: bool,
+    /// Headers of uploaded objects. Ignored by downloads.
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub cache_control: Option<String>,
+}
+
+impl Default for S3TransferOptions {
+    fn default() -> Self {
+        S3TransferOptions {
+            part_size: 16 * 1024 * 1024,
+            concurrency: 4,
+            checksum: None,
+            resume: false,
+            progress: true,
+            content_type: None,
+            content_encoding: None,
+            cache_control: None,
+        }
+    }
+}
+
+/// Uploads in parts if the file is larger than the part size. With 'resume',
+/// an interrupted upload is continued from the last uploaded part on the next
+/// call (unless the file was modified in between). Otherwise, it's aborted.
+pub async fn s3_upload_file_with_options<P>(
+    pr: &Printer,
+    aws: &AwsContext,
+    bucket: &str,
+    key: &str,
+    file_path: P,
+    options: S3TransferOptions,
+) -> Result<(), CliError>
+where
+    P: AsRef<Path>,
+{
+    let file_path = file_path.as_ref();
+    if !file_path.exists() {
+        return Err(S3InvalidUpload::new("file does not exist"));
+    }
+    if !file_path.is_file() {
+        return Err(S3InvalidUpload::new("path is not a file"));
+    }
+    let metadata = std::fs::metadata(file_path).map_err(|e| IOError::with_debug(&e))?;
+    let file_len = metadata.len();
+    let part_size = options
+        .part_size
+        .max(MIN_PART_SIZE)
+        .max(file_len.div_ceil(MAX_PARTS));
+    let client = aws.client::<Client>().await;
+
+    if file_len <= part_size {
+        let checksum = match options.checksum {
+            Some(algorithm) => Some((algorithm, file_checksum(file_path, algorithm).await?)),
+            None => None,
+        };
+        let body = ByteStream::from_path(file_path)
+            .await
+            .map_err(|e| IOError::with_debug(&e))?;
+        client
+            .put_object()
+            .bucket(bucket)
+            .key(key)
+            .body(body)
+            .set_content_type(options.content_type.clone())
+            .set_content_encoding(options.content_encoding.clone())
+            .set_cache_control(options.cache_control.clone())
+            .set_checksum_sha256(checksum_for(&checksum, S3ChecksumAlgorithm::Sha256))
+            .set_checksum_crc32_c(checksum_for(&checksum, S3ChecksumAlgorithm::Crc32c))
+            .send()
+            .await
+            .map_err(|e| S3Error::with_debug(&e))?;
+    } else {
+        let upload = MultipartUpload {
+            client: &client,
+            bucket,
+            key,
+            file_path,
+            file_len,
+            modified: modified_secs(&metadata),
+            part_size,
+            options: &options,
+        };
+        let progress = TransferProgress::new(&format!("Uploading '{}'", key), file_len);
+        with_progress(pr, options.progress, &progress, upload.run(pr, &progress)).await?;
+    }
+
+    pr.info(&format!("Uploaded file to 's3://{}/{}'.", bucket, key));
+    Ok(())
+}
+
+/// Streams the object to disk. With 'resume', an interrupted download is
+/// continued from the partial file on the next call (unless the object changed
+/// in between). Otherwise, the partial file is removed.
+pub async fn s3_download_object_with_options<P>(
+    pr: &Printer,
+    aws: &AwsContext,
+    bucket: &str,
+    key: &str,
+    local_path: P,
+    options: S3TransferOptions,
+) -> Result<(), CliError>
+where
+    P: AsRef<Path>,
+{
+    let local_path = local_path.as_ref();
+    let client = aws.client::<Client>().await;
+    let head = client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .set_checksum_mode(options.checksum.map(|_| ChecksumMode::Enabled))
+        .send()
+        .await
+        .map_err(|e| S3Error::with_debug(&e))?;
+    let size = head.content_length().unwrap_or_default().max(0) as u64;
+    let etag = head
+        .e_tag()
+        .unwrap_or_default()
+        .trim_matches('"')
+        .to_string();
+
+    if let Some(parent) = local_path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .map_err(|e| IOError::with_debug(&e))?;
+    }
+    let partial_path = partial_download_path(local_path, &etag);
+    // Left by interrupted downloads of other versions of the object, which
+    // can't be resumed.
+    for stale in stale_partial_downloads(local_path, &partial_path).await {
+        let _ = fs::remove_file(stale).await;
+    }
+    let offset = match fs::metadata(&partial_path).await {
+        Ok(metadata) if options.resume && metadata.len() <= size => metadata.len(),
+        _ => 0,
+    };
+    if offset > 0 {
+        pr.info(&format!(
+            "Resuming download of '{}' at {}...",
+            key,
+            format_bytes(offset)
+        ));
+    }
+
+    let progress = TransferProgress::new(&format!("Downloading '{}'", key), size);
+    progress.add(offset);
+    let download = async {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(offset > 0)
+            .truncate(offset == 0)
+            .open(&partial_path)
+            .await
+            .map_err(|e| IOError::with_debug(&e))?;
+        if offset < size {
+            // 'if_match' fails the request if the object changed since the
+            // partial file was started.
+            let mut body = client
+                .get_object()
+                .bucket(bucket)
+                .key(key)
+                .if_match(&etag)
+                .set_range((offset > 0).then(|| format!("bytes={}-", offset)))
+                .send()
+                .await
+                .map_err(|e| S3Error::with_debug(&e))?
+                .body;
+            while let Some(bytes) = body.try_next().await.map_err(|e| IOError::with_debug(&e))? {
+                file.write_all(&bytes)
+                    .await
+                    .map_err(|e| IOError::with_debug(&e))?;
+                progress.add(bytes.len() as u64);
+            }
+        }
+        file.flush().await.map_err(|e| IOError::with_debug(&e))?;
+        Ok(())
+    };
+    if let Err(e) = with_progress(pr, options.progress, &progress, download).await {
+        if !options.resume {
+            let _ = fs::remove_file(&partial_path).await;
+        }
+        return Err(e);
+    }
+
+    if let Some(algorithm) = options.checksum {
+        let expected = match algorithm {
+            S3ChecksumAlgorithm::Sha256 => head.checksum_sha256(),
+            S3ChecksumAlgorithm::Crc32c => head.checksum_crc32_c(),
+        };
+        match expected {
+            Some(expected) => {
+                let actual = match composite_part_count(expected) {
+                    Some(_) => {
+                        let part_size = first_part_size(&client, bucket, key).await?;
+                        composite_checksum(&partial_path, algorithm, part_size).await?
+                    }
+                    None => file_checksum(&partial_path, algorithm).await?,
+                };
+                if actual != expected {
+                    fs::remove_file(&partial_path)
+                        .await
+                        .map_err(|e| IOError::with_debug(&e))?;
+                    return Err(S3ChecksumMismatch::new(bucket, key, expected, &actual));
+                }
+            }
+            None => pr.warn(&format!(
+                "'s3://{}/{}' has no {:?} checksum, so it could not be verified.",
+                bucket, key, algorithm
+            )),
+        }
+    }
+
+    fs::rename(&partial_path, local_path)
+        .await
+        .map_err(|e| IOError::with_debug(&e))?;
+    Ok(())
+}
+
+// Multipart uploads.
+// ----------------------------------------------------------------------------
+
+struct MultipartUpload<'a> {
+    client: &'a Client,
+    bucket: &'a str,
+    key: &'a str,
+    file_path: &'a Path,
+    file_len: u64,
+    modified: u64,
+    part_size: u64,
+    options: &'a S3TransferOptions,
+}
+
+/// Saved while a multipart upload is in progress, so it can be resumed.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UploadState {
+    upload_id: String,
+    file_len: u64,
+    modified: u64,
+    part_size: u64,
+    checksum: Option<S3ChecksumAlgorithm>,
+}
+
+impl MultipartUpload<'_> {
+    async fn run(&self, pr: &Printer, progress: &TransferProgress) -> Result<(), CliError> {
+        let part_count = self.file_len.div_ceil(self.part_size);
+        let state_path = self.state_path();
+        let (upload_id, mut completed, resumable) = match self.resume(pr, &state_path).await {
+            Some((upload_id, completed)) => (upload_id, completed, true),
+            None => {
+                let (upload_id, resumable) = self.start(&state_path).await?;
+                (upload_id, HashMap::new(), resumable)
+            }
+        };
+        for part in completed.values() {
+            progress.add(self.part_len(part.part_number().unwrap_or_default()));
+        }
+
+        let remaining = (1..=part_count as i32)
+            .filter(|n| !completed.contains_key(n))
+            .collect::<Vec<_>>();
+        let uploaded = stream::iter(remaining.into_iter().map(|part_number| {
+            let upload_id = &upload_id;
+            async move {
+                let part = self.upload_part(upload_id, part_number).await?;
+                progress.add(self.part_len(part_number));
+                Ok::<_, CliError>(part)
+            }
+        }))
+        .buffer_unordered(self.options.concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await;
+        let uploaded = match uploaded {
+            Ok(uploaded) => uploaded,
+            Err(e) => {
+                // Without a saved state, nothing could ever resume the upload.
+                if !resumable {
+                    self.abort(&upload_id, &state_path).await;
+                }
+                return Err(e);
+            }
+        };
+        completed.extend(
+            uploaded
+                .into_iter()
+                .map(|part| (part.part_number().unwrap_or_default(), part)),
+        );
+
+        let mut parts = completed.into_values().collect::<Vec<_>>();
+        parts.sort_by_key(|part| part.part_number());
+        self.client
+            .complete_multipart_upload()
+            .bucket(self.bucket)
+            .key(self.key)
+            .upload_id(&upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| S3Error::with_debug(&e))?;
+        let _ = fs::remove_file(&state_path).await;
+        Ok(())
+    }
+
+    /// The upload ID and already uploaded parts of an interrupted upload of the
+    /// same file, if any.
+    async fn resume(
+        &self,
+        pr: &Printer,
+        state_path: &Path,
+    ) -> Option<(String, HashMap<i32, CompletedPart>)> {
+        if !self.options.resume {
+            return None;
+        }
+        let state = load_upload_state(state_path).await?;
+        if state != self.state(&state.upload_id) {
+            return None;
+        }
+        // Fails if the upload was completed or aborted since.
+        let pages = self
+            .client
+            .list_parts()
+            .bucket(self.bucket)
+            .key(self.key)
+            .upload_id(&state.upload_id)
+            .into_paginator()
+            .send()
+            .try_collect()
+            .await
+            .ok()?;
+        let completed = pages
+            .into_iter()
+            .flat_map(|page| page.parts.unwrap_or_default())
+            .filter_map(|part| {
+                let part_number = part.part_number?;
+                (part.size? as u64 == self.part_len(part_number)).then(|| {
+                    let completed = CompletedPart::builder()
+                        .part_number(part_number)
+                        .set_e_tag(part.e_tag)
+                        .set_checksum_sha256(part.checksum_sha256)
+                        .set_checksum_crc32_c(part.checksum_crc32_c)
+                        .build();
+                    (part_number, completed)
+                })
+            })
+            .collect::<HashMap<_, _>>();
+        pr.info(&format!(
+            "Resuming upload of '{}' ({}/{} parts already uploaded)...",
+            self.key,
+            completed.len(),
+            self.file_len.div_ceil(self.part_size)
+        ));
+        Some((state.upload_id, completed))
+    }
+
+    /// Returns the upload ID, and whether the upload can be resumed (i.e. its
+    /// state was saved).
+    async fn start(&self, state_path: &Path) -> Result<(String, bool), CliError> {
+        let upload_id = self
+            .client
+            .create_multipart_upload()
+            .bucket(self.bucket)
+            .key(self.key)
+            .set_content_type(self.options.content_type.clone())
+            .set_content_encoding(self.options.content_encoding.clone())
+            .set_cache_control(self.options.cache_control.clone())
+            .set_checksum_algorithm(self.options.checksum.map(|algorithm| match algorithm {
+                S3ChecksumAlgorithm::Sha256 => ChecksumAlgorithm::Sha256,
+                S3ChecksumAlgorithm::Crc32c => ChecksumAlgorithm::Crc32C,
+            }))
+            .send()
+            .await
+            .map_err(|e| S3Error::with_debug(&e))?
+            .upload_id
+            .ok_or_else(|| S3Error::new())?;
+        let resumable =
+            self.options.resume && save_upload_state(state_path, &self.state(&upload_id)).await;
+        Ok((upload_id, resumable))
+    }
+
+    fn state(&self, upload_id: &str) -> UploadState {
+        UploadState {
+            upload_id: upload_id.to_string(),
+            file_len: self.file_len,
+            modified: self.modified,
+            part_size: self.part_size,
+            checksum: self.options.checksum,
+        }
+    }
+
+    async fn upload_part(
+        &self,
+        upload_id: &str,
+        part_number: i32,
+    ) -> Result<CompletedPart, CliError> {
+        let len = self.part_len(part_number);
+        let mut data = vec![0; len as usize];
+        let mut file = File::open(self.file_path)
+            .await
+            .map_err(|e| IOError::with_debug(&e))?;
+        file.seek(std::io::SeekFrom::Start(
+            (part_number as u64 - 1) * self.part_size,
+        ))
+        .await
+        .map_err(|e| IOError::with_debug(&e))?;
+        file.read_exact(&mut data)
+            .await
+            .map_err(|e| IOError::with_debug(&e))?;
+
+        let checksum = self
+            .options
+            .checksum
+            .map(|algorithm| (algorithm, encode_checksum(&algorithm.digest(&data))));
+        let output = self
+            .client
+            .upload_part()
+            .bucket(self.bucket)
+            .key(self.key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .content_length(len as i64)
+            .body(ByteStream::from(data))
+            .set_checksum_sha256(checksum_for(&checksum, S3ChecksumAlgorithm::Sha256))
+            .set_checksum_crc32_c(checksum_for(&checksum, S3ChecksumAlgorithm::Crc32c))
+            .send()
+            .await
+            .map_err(|e| S3Error::with_debug(&e))?;
+        Ok(CompletedPart::builder()
+            .part_number(part_number)
+            .set_e_tag(output.e_tag)
+            .set_checksum_sha256(output.checksum_sha256)
+            .set_checksum_crc32_c(output.checksum_crc32_c)
+            .build())
+    }
+
+    /// Best effort, since the upload already failed.
+    async fn abort(&self, upload_id: &str, state_path: &Path) {
+        let _ = self
+            .client
+            .abort_multipart_upload()
+            .bucket(self.bucket)
+            .key(self.key)
+            .upload_id(upload_id)
+            .send()
+            .await;
+        let _ = fs::remove_file(state_path).await;
+    }
+
+    fn part_len(&self, part_number: i32) -> u64 {
+        let start = (part_number as u64 - 1) * self.part_size;
+        self.part_size.min(self.file_len.saturating_sub(start))
+    }
+
+    /// In the temp dir, named after the destination and the source file.
+    fn state_path(&self) -> PathBuf {
+        let source =
+            std::fs::canonicalize(self.file_path).unwrap_or_else(|_| self.file_path.to_path_buf());
+        let id = hex::encode(Sha256::digest(
+            format!("{}\n{}\n{}", self.bucket, self.key, source.display()).as_bytes(),
+        ));
+        std::env::temp_dir()
+            .join("lib_aws_uploads")
+            .join(format!("{}.json", &id[..32]))
+    }
+}
+
+/// Returns false if the state couldn't be saved.
+async fn save_upload_state(state_path: &Path, state: &UploadState) -> bool {
+    if let Some(parent) = state_path.parent() {
+        if fs::create_dir_all(parent).await.is_err() {
+            return false;
+        }
+    }
+    match serde_json::to_string(state) {
+        Ok(state) => fs::write(state_path, state).await.is_ok(),
+        Err(_) => false,
+    }
+}
+
+async fn load_upload_state(state_path: &Path) -> Option<UploadState> {
+    let state = fs::read_to_string(state_path).await.ok()?;
+    serde_json::from_str(&state).ok()
+}
+
+// Checksums.
+// ----------------------------------------------------------------------------
+
+impl S3ChecksumAlgorithm {
+    fn digest(self, data: &[u8]) -> Vec<u8> {
+        let mut hasher = Hasher::new(self);
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Crc32c(u32),
+}
+
+impl Hasher {
+    fn new(algorithm: S3ChecksumAlgorithm) -> Self {
+        match algorithm {
+            S3ChecksumAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            S3ChecksumAlgorithm::Crc32c => Hasher::Crc32c(0),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Crc32c(crc) => crc.to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// S3 checksums are base64-encoded.
+fn encode_checksum(digest: &[u8]) -> String {
+    BASE64_STANDARD.encode(digest)
+}
+
+fn checksum_for(
+    checksum: &Option<(S3ChecksumAlgorithm, String)>,
+    algorithm: S3ChecksumAlgorithm,
+) -> Option<String> {
+    checksum
+        .as_ref()
+        .filter(|(a, _)| *a == algorithm)
+        .map(|(_, value)| value.clone())
+}
+
+async fn file_checksum(path: &Path, algorithm: S3ChecksumAlgorithm) -> Result<String, CliError> {
+    let mut file = File::open(path)
+        .await
+        .map_err(|e| IOError::with_debug(&e))?;
+    let mut hasher = Hasher::new(algorithm);
+    let mut buffer = vec![0; HASH_BUFFER_SIZE];
+    loop {
+        let n = file
+            .read(&mut buffer)
+            .await
+            .map_err(|e| IOError::with_debug(&e))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(encode_checksum(&hasher.finalize()))
+}
+
+/// Checksum of a multipart object, as computed by S3: the checksum of the
+/// concatenated part checksums, followed by '-<part count>'.
+async fn composite_checksum(
+    path: &Path,
+    algorithm: S3ChecksumAlgorithm,
+    part_size: u64,
+) -> Result<String, CliError> {
+    let mut file = File::open(path)
+        .await
+        .map_err(|e| IOError::with_debug(&e))?;
+    let mut part_digests = Hasher::new(algorithm);
+    let mut part_count = 0;
+    let mut buffer = vec![0; part_size as usize];
+    loop {
+        let mut len = 0;
+        while len < buffer.len() {
+            let n = file
+                .read(&mut buffer[len..])
+                .await
+                .map_err(|e| IOError::with_debug(&e))?;
+            if n == 0 {
+                break;
+            }
+            len += n;
+        }
+        if len == 0 {
+            break;
+        }
+        part_digests.update(&algorithm.digest(&buffer[..len]));
+        part_count += 1;
+    }
+    Ok(format!(
+        "{}-{}",
+        encode_checksum(&part_digests.finalize()),
+        part_count
+    ))
+}
+
+fn composite_part_count(checksum: &str) -> Option<u64> {
+    checksum.rsplit_once('-')?.1.parse().ok()
+}
+
+/// All parts but the last have the same size.
+async fn first_part_size(client: &Client, bucket: &str, key: &str) -> Result<u64, CliError> {
+    let head = client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .part_number(1)
+        .send()
+        .await
+        .map_err(|e| S3Error::with_debug(&e))?;
+    Ok(head.content_length().unwrap_or_default().max(1) as u64)
+}
+
+// Progress.
+// ----------------------------------------------------------------------------
+
+struct TransferProgress {
+    label: String,
+    total: u64,
+    done: AtomicU64,
+}
+
+impl TransferProgress {
+    fn new(label: &str, total: u64) -> Self {
+        TransferProgress {
+            label: label.to_string(),
+            total,
+            done: AtomicU64::new(0),
+        }
+    }
+
+    fn add(&self, bytes: u64) {
+        self.done.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn status(&self) -> String {
+        let done = self.done.load(Ordering::Relaxed).min(self.total);
+        format!(
+            "{}: {} / {} ({}%)",
+            self.label,
+            format_bytes(done),
+            format_bytes(self.total),
+            (done * 100).checked_div(self.total).unwrap_or(100)
+        )
+    }
+}
+
+/// Runs 'transfer', showing its progress in a status bar if 'enabled'.
+async fn with_progress<T>(
+    pr: &Printer,
+    enabled: bool,
+    progress: &TransferProgress,
+    transfer: impl Future<Output = Result<T, CliError>>,
+) -> Result<T, CliError> {
+    if !enabled {
+        return transfer.await;
+    }
+    let mut pr = pr.clone();
+    pr.with_status_bar(|mut status_bar| async move {
+        tokio::pin!(transfer);
+        let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+        loop {
+            select! {
+                result = &mut transfer => {
+                    status_bar.info(&progress.status());
+                    break result;
+                }
+                _ = interval.tick() => status_bar.info(&progress.status()),
+            }
+        }
+    })
+    .await
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+// Helpers.
+// ----------------------------------------------------------------------------
+
+fn modified_secs(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Next to the destination, and named after the object's ETag, so a partial
+/// file of another version of the object is never resumed.
+fn partial_download_path(local_path: &Path, etag: &str) -> PathBuf {
+    let version = etag
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .take(8)
+        .collect::<String>();
+    let file_name = local_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    local_path.with_file_name(format!("{}.{}.part", file_name, version))
+}
+
+/// Partial files of the destination, other than 'current'.
+async fn stale_partial_downloads(local_path: &Path, current: &Path) -> Vec<PathBuf> {
+    let (Some(dir), Some(file_name)) = (local_path.parent(), local_path.file_name()) else {
+        return Vec::new();
+    };
+    let dir = match dir.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => dir,
+    };
+    let prefix = format!("{}.", file_name.to_string_lossy());
+    let Ok(mut entries) = fs::read_dir(dir).await else {
+        return Vec::new();
+    };
+    let mut stale = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        if name.starts_with(&prefix) && name.ends_with(".part") && path != current {
+            stale.push(path);
+        }
+    }
+    stale.sort();
+    stale
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn composite_checksum_matches_s3() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("artifact.zip");
+        std::fs::write(&path, b"abcdefghij").unwrap();
+
+        // Parts 'abcd', 'efgh' and 'ij'.
+        let parts = [&b"abcd"[..], b"efgh", b"ij"]
+            .iter()
+            .flat_map(|part| crc32c::crc32c(part).to_be_bytes())
+            .collect::<Vec<_>>();
+        let expected = format!(
+            "{}-3",
+            BASE64_STANDARD.encode(crc32c::crc32c(&parts).to_be_bytes())
+        );
+        let actual = composite_checksum(&path, S3ChecksumAlgorithm::Crc32c, 4)
+            .await
+            .unwrap();
+        assert_eq!(actual, expected);
+        assert_eq!(composite_part_count(&actual), Some(3));
+        assert_eq!(
+            file_checksum(&path, S3ChecksumAlgorithm::Crc32c)
+                .await
+                .unwrap(),
+            BASE64_STANDARD.encode(crc32c::crc32c(b"abcdefghij").to_be_bytes())
+        );
+    }
+
+    #[tokio::test]
+    async fn upload_state_is_only_resumable_once_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = UploadState {
+            upload_id: "upload-1".to_string(),
+            file_len: 100 * 1024 * 1024,
+            modified: 1_700_000_000,
+            part_size: 16 * 1024 * 1024,
+            checksum: Some(S3ChecksumAlgorithm::Crc32c),
+        };
+        let state_path = dir.path().join("uploads/state.json");
+        assert!(save_upload_state(&state_path, &state).await);
+        assert_eq!(load_upload_state(&state_path).await, Some(state));
+
+        // The state dir can't be created under a file, so the upload must be
+        // aborted on failure.
+        let blocked = dir.path().join("uploads/state.json/nested.json");
+        assert!(!save_upload_state(&blocked, &load_upload_state(&state_path).await.unwrap()).await);
+        assert_eq!(load_upload_state(&blocked).await, None);
+    }
+
+    #[tokio::test]
+    async fn partial_downloads_of_other_versions_are_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let local_path = dir.path().join("data.bin");
+        let current = partial_download_path(&local_path, "\"abc123\"");
+        let old = partial_download_path(&local_path, "\"def456\"");
+        for path in [
+            &current,
+            &old,
+            &dir.path().join("data.bin.txt"),
+            &dir.path().join("other.bin.x.part"),
+        ] {
+            std::fs::write(path, b"").unwrap();
+        }
+        assert_eq!(
+            stale_partial_downloads(&local_path, &current).await,
+            vec![old]
+        );
+    }
+}
@@ -1,6 +1,10 @@
 use std::{collections::HashMap, path::Path};
 
-use lib_core::{ln_s, mkdir_p, rm_rf, CliError, ExecuteOptions, Executor, IOMode, Printer};
+use lib_core::{
+    ln_s, mkdir_p, rm_rf, CliError, ExecuteOptions, Executor, IOMode, Printer, ResourceLimit,
+};
+
+use crate::AwsContext;
 
 pub async fn sam_build(
     pr: &Printer,
@@ -43,14 +47,14 @@ pub async fn sam_build(
 
     // Call sam build with raised ulimit to avoid ProcessFdQuotaExceeded
     // exception (common on M-type macs).
-    let shell_command = format!("ulimit -n 8192; exec sam build");
     ex.execute_with_options(
-        "sh",
-        &["-c", &shell_command],
+        "sam",
+        &["build"],
         IOMode::Attach,
         ExecuteOptions {
             dir: Some(project_dir),
             env: Some(env),
+            rlimits: vec![ResourceLimit::OpenFiles(8192)],
             ..Default::default()
         },
     )
@@ -58,12 +62,13 @@ pub async fn sam_build(
     Ok(())
 }
 
+/// Unless the context is a plain profile, the SAM CLI is given resolved
+/// credentials (ex. of the assumed role) rather than the profile.
 pub async fn sam_deploy(
     pr: &Printer,
     ex: &Executor,
     project_dir: &Path,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     stack_name: &str,
     parameter_overrides: HashMap<String, String>,
 ) -> Result<(), CliError> {
@@ -71,21 +76,24 @@ pub async fn sam_deploy(
         .into_iter()
         .map(|(k, v)| format!("{}={}", k, v))
         .collect::<Vec<_>>();
-    let args = vec![
-        "deploy",
-        "--profile",
-        profile,
-        "--region",
-        region,
-        "--stack-name",
-        stack_name,
-        "--no-fail-on-empty-changeset",
-        "--parameter-overrides",
-    ]
-    .into_iter()
-    .map(String::from)
-    .chain(param_strs.into_iter())
-    .collect::<Vec<_>>();
+    let (profile_args, env) = match aws.is_plain_profile() {
+        true => (vec!["--profile", aws.profile()], None),
+        false => (vec![], Some(aws.credentials_env().await?)),
+    };
+    let args = ["deploy"]
+        .into_iter()
+        .chain(profile_args)
+        .chain([
+            "--region",
+            aws.region(),
+            "--stack-name",
+            stack_name,
+            "--no-fail-on-empty-changeset",
+            "--parameter-overrides",
+        ])
+        .map(String::from)
+        .chain(param_strs.into_iter())
+        .collect::<Vec<_>>();
 
     pr.info("Deploying with SAM...");
     ex.execute_with_options(
@@ -94,9 +102,57 @@ pub async fn sam_deploy(
         IOMode::Attach,
         ExecuteOptions {
             dir: Some(project_dir),
+            env,
             ..Default::default()
         },
     )
     .await?;
     Ok(())
 }
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use lib_core::{Expectation, ScriptedRunner};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn deploy_passes_parameter_overrides() {
+        let runner = ScriptedRunner::new();
+        runner.expect(Expectation::new(
+            "sam",
+            &[
+                "deploy",
+                "--profile",
+                "prod",
+                "--region",
+                "us-east-1",
+                "--stack-name",
+                "api-production",
+                "--no-fail-on-empty-changeset",
+                "--parameter-overrides",
+                "Env=production",
+            ],
+        ));
+        let ex = Executor::with_runner(runner.clone());
+
+        sam_deploy(
+            &Printer::new(),
+            &ex,
+            Path::new("."),
+            &AwsContext::new("prod", "us-east-1"),
+            "api-production",
+            HashMap::from([("Env".to_string(), "production".to_string())]),
+        )
+        .await
+        .unwrap();
+        runner.assert_done();
+        assert_eq!(
+            runner.calls()[0].dir,
+            Some(std::fs::canonicalize(".").unwrap())
+        );
+    }
+}
@@ -7,7 +7,7 @@ use aws_sdk_secretsmanager::{
 use lib_core::{define_cli_error, CliError, Printer};
 use serde::de::DeserializeOwned;
 
-use crate::shared_config::config_from_profile;
+use crate::AwsContext;
 
 define_cli_error!(
     FailedToFetchAwsSecret,
@@ -35,8 +35,8 @@ define_cli_error!(
 // Public functions.
 // ----------------------------------------------------------------------------
 
-pub async fn secret_exists(profile: &str, region: &str, secret_id: &str) -> Result<bool, CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+pub async fn secret_exists(aws: &AwsContext, secret_id: &str) -> Result<bool, CliError> {
+    let client = aws.client::<Client>().await;
     match client.describe_secret().secret_id(secret_id).send().await {
         Ok(_) => Ok(true),
         Err(SdkError::<DescribeSecretError>::ServiceError(se))
@@ -48,8 +48,8 @@ pub async fn secret_exists(profile: &str, region: &str, secret_id: &str) -> Resu
     }
 }
 
-pub async fn get_secret(profile: &str, region: &str, secret_id: &str) -> Result<String, CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+pub async fn get_secret(aws: &AwsContext, secret_id: &str) -> Result<String, CliError> {
+    let client = aws.client::<Client>().await;
     client
         .get_secret_value()
         .secret_id(secret_id)
@@ -59,46 +59,38 @@ pub async fn get_secret(profile: &str, region: &str, secret_id: &str) -> Result<
         .secret_string()
         .map(str::to_owned)
         .ok_or_else(|| {
-            FailedToFetchAwsSecret::new(secret_id, region, "could not parse secret value")
+            FailedToFetchAwsSecret::new(secret_id, aws.region(), "could not parse secret value")
         })
 }
 
 pub async fn get_secret_subkeys<T: DeserializeOwned>(
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     secret_id: &str,
     subkeys: HashSet<String>,
 ) -> Result<HashMap<String, T>, CliError> {
     parse_secret_subkeys(
-        &get_secret(profile, region, secret_id).await?,
+        &get_secret(aws, secret_id).await?,
         secret_id,
-        region,
+        aws.region(),
         &subkeys,
     )
 }
 
 pub async fn get_secret_subkey<T: DeserializeOwned>(
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     secret_id: &str,
     subkey: &str,
 ) -> Result<T, CliError> {
-    get_secret_subkeys(
-        profile,
-        region,
-        secret_id,
-        HashSet::from([subkey.to_string()]),
-    )
-    .await
-    .map(|subkeys| subkeys.into_values().next().unwrap())
+    get_secret_subkeys(aws, secret_id, HashSet::from([subkey.to_string()]))
+        .await
+        .map(|subkeys| subkeys.into_values().next().unwrap())
 }
 
 pub async fn secret_replica_regions(
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     secret_id: &str,
 ) -> Result<HashSet<String>, CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+    let client = aws.client::<Client>().await;
     Ok(client
         .describe_secret()
         .secret_id(secret_id)
@@ -115,14 +107,13 @@ pub async fn secret_replica_regions(
 /// Returns true if new secret was created.
 pub async fn update_or_create_secret(
     pr: &Printer,
-    profile: &str,
-    region: &str,
+    aws: &AwsContext,
     secret_id: &str,
     value: &str,
     replica_regions: Option<&HashSet<String>>,
 ) -> Result<bool, CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
-    match secret_exists(profile, region, secret_id).await? {
+    let client = aws.client::<Client>().await;
+    match secret_exists(aws, secret_id).await? {
         true => {
             pr.info(&format!("Updating secret '{secret_id}'..."));
             client
@@ -1,7 +1,7 @@
 use aws_sdk_ses::{types::VerificationStatus, Client};
 use lib_core::{define_cli_error, CliError};
 
-use crate::shared_config::config_from_profile;
+use crate::AwsContext;
 
 define_cli_error!(
     SesIdentityValidationError,
@@ -14,19 +14,15 @@ define_cli_error!(
     { identity: &str, region: &str }
 );
 
-pub async fn require_ses_identity(
-    profile: &str,
-    region: &str,
-    identity: &str,
-) -> Result<(), CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+pub async fn require_ses_identity(aws: &AwsContext, identity: &str) -> Result<(), CliError> {
+    let client = aws.client::<Client>().await;
 
     let result = client
         .get_identity_verification_attributes()
         .identities(identity)
         .send()
         .await
-        .map_err(|e| SesIdentityValidationError::with_debug(identity, region, &e))?;
+        .map_err(|e| SesIdentityValidationError::with_debug(identity, aws.region(), &e))?;
 
     result
         .verification_attributes
@@ -38,7 +34,7 @@ pub async fn require_ses_identity(
                 None
             }
         })
-        .ok_or_else(|| SesIdentityNotVerified::new(identity, region))?;
+        .ok_or_else(|| SesIdentityNotVerified::new(identity, aws.region()))?;
 
     Ok(())
 }
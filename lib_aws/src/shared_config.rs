@@ -1,14 +1,62 @@
 use std::time::Duration;
 
 use aws_config::{
-    profile::ProfileFileCredentialsProvider, timeout::TimeoutConfig, BehaviorVersion, Region,
-    SdkConfig,
+    profile::ProfileFileCredentialsProvider, sts::AssumeRoleProvider, timeout::TimeoutConfig,
+    BehaviorVersion, ConfigLoader, Region, SdkConfig,
 };
+use aws_sdk_sts::config::Credentials;
 
-pub(crate) async fn config_from_profile(
-    profile: impl Into<String>,
-    region: impl Into<String>,
-) -> SdkConfig {
+use crate::context::ContextKey;
+
+const ASSUMED_ROLE_SESSION_NAME: &str = "lib-aws";
+const STATIC_CREDENTIALS_PROVIDER_NAME: &str = "lib-aws-static";
+
+pub(crate) async fn load_sdk_config(key: &ContextKey) -> SdkConfig {
+    let region = &key.region;
+    let endpoint_url = key.endpoint_url.as_deref();
+    let config = match &key.static_credentials {
+        Some(credentials) => {
+            loader(region, endpoint_url)
+                .credentials_provider(Credentials::new(
+                    &credentials.access_key_id,
+                    &credentials.secret_access_key,
+                    None,
+                    None,
+                    STATIC_CREDENTIALS_PROVIDER_NAME,
+                ))
+                .load()
+                .await
+        }
+        None => {
+            loader(region, endpoint_url)
+                .credentials_provider(
+                    ProfileFileCredentialsProvider::builder()
+                        .profile_name(&key.profile)
+                        .build(),
+                )
+                .load()
+                .await
+        }
+    };
+    match key.role_arn.as_deref() {
+        // The profile's credentials are used to assume the role, and the
+        // provider refreshes the role's credentials as needed.
+        Some(role_arn) => {
+            let provider = AssumeRoleProvider::builder(role_arn)
+                .session_name(ASSUMED_ROLE_SESSION_NAME)
+                .configure(&config)
+                .build()
+                .await;
+            loader(region, endpoint_url)
+                .credentials_provider(provider)
+                .load()
+                .await
+        }
+        None => config,
+    }
+}
+
+fn loader(region: &str, endpoint_url: Option<&str>) -> ConfigLoader {
     // Important to raise the default timeouts to support large operations
     // (e.g., large S3 file transfers). In particular, the default 5s connect
     // timeout can easily trigger on slower connections or with multiple
@@ -19,14 +67,11 @@ pub(crate) async fn config_from_profile(
         .operation_timeout(Duration::from_secs(3600))
         .build();
 
-    aws_config::defaults(BehaviorVersion::latest())
-        .region(Region::new(region.into()))
-        .timeout_config(timeout_config)
-        .credentials_provider(
-            ProfileFileCredentialsProvider::builder()
-                .profile_name(profile)
-                .build(),
-        )
-        .load()
-        .await
+    let loader = aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new(region.to_string()))
+        .timeout_config(timeout_config);
+    match endpoint_url {
+        Some(endpoint_url) => loader.endpoint_url(endpoint_url),
+        None => loader,
+    }
 }
@@ -1,17 +1,13 @@
-use aws_sdk_ssm::Client;
+use aws_sdk_ssm::{types::ParameterType, Client};
 use lib_core::{define_cli_error, CliError};
 
-use crate::shared_config::config_from_profile;
+use crate::AwsContext;
 
 define_cli_error!(SsmError, "Error running AWS SSM command.");
 define_cli_error!(SsmParameterNotFound, "No SSM parameter found with name: {name}.", { name: &str });
 
-pub async fn get_ssm_parameter(
-    profile: &str,
-    region: &str,
-    name: &str,
-) -> Result<String, CliError> {
-    let client = Client::new(&config_from_profile(profile, region).await);
+pub async fn get_ssm_parameter(aws: &AwsContext, name: &str) -> Result<String, CliError> {
+    let client = aws.client::<Client>().await;
     Ok(client
         .get_parameter()
         .name(name)
@@ -25,3 +21,35 @@ pub async fn get_ssm_parameter(
         .ok_or_else(|| SsmParameterNotFound::new(name))?
         .clone())
 }
+
+/// Like `get_ssm_parameter`, but returns None if the parameter doesn't exist.
+pub async fn get_ssm_parameter_if_exists(
+    aws: &AwsContext,
+    name: &str,
+) -> Result<Option<String>, CliError> {
+    let client = aws.client::<Client>().await;
+    match client.get_parameter().name(name).send().await {
+        Ok(output) => Ok(output.parameter.and_then(|p| p.value)),
+        Err(e)
+            if e.as_service_error()
+                .is_some_and(|se| se.is_parameter_not_found()) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(SsmError::with_debug(&e)),
+    }
+}
+
+pub async fn set_ssm_parameter(aws: &AwsContext, name: &str, value: &str) -> Result<(), CliError> {
+    let client = aws.client::<Client>().await;
+    client
+        .put_parameter()
+        .name(name)
+        .value(value)
+        .r#type(ParameterType::String)
+        .overwrite(true)
+        .send()
+        .await
+        .map_err(|e| SsmError::with_debug(&e))?;
+    Ok(())
+}
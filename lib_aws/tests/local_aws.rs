@@ -0,0 +1,101 @@
+//! Runs the lib_aws helpers against a local stand-in of AWS. Requires Docker
+//! (or LIB_AWS_TEST_ENDPOINT_URL), so ignored by default:
+//!
+//! $ cargo test -p lib_aws --test local_aws -- --ignored
+
+use std::panic::AssertUnwindSafe;
+
+use futures_util::FutureExt as _;
+use lib_aws::{
+    get_secret_subkey, get_ssm_parameter_if_exists, s3_create_bucket_if_not_exists,
+    s3_delete_object, s3_download_object, s3_list, s3_upload_file, set_ssm_parameter, stack_exists,
+    update_or_create_secret, AwsContext, LocalAws,
+};
+use lib_core::{Executor, Printer};
+
+#[tokio::test]
+#[ignore = "requires Docker or LIB_AWS_TEST_ENDPOINT_URL"]
+async fn helpers_work_against_a_local_stand_in() {
+    let ex = Executor::new();
+    let local = LocalAws::start(&ex).await.unwrap();
+    let aws = local.context();
+
+    // The stand-in is stopped even if an assertion fails.
+    let result = AssertUnwindSafe(async {
+        s3_round_trip(&aws).await;
+        secrets_and_parameters(&aws).await;
+        assert!(!stack_exists(&aws, "missing-stack").await.unwrap());
+    })
+    .catch_unwind()
+    .await;
+    local.stop(&ex).await.unwrap();
+    if let Err(panic) = result {
+        std::panic::resume_unwind(panic);
+    }
+}
+
+async fn s3_round_trip(aws: &AwsContext) {
+    let pr = Printer::new();
+    let dir = tempfile::tempdir().unwrap();
+    let upload = dir.path().join("upload.txt");
+    let download = dir.path().join("nested/download.txt");
+    std::fs::write(&upload, "hello").unwrap();
+
+    assert!(s3_create_bucket_if_not_exists(&pr, aws, "artifacts")
+        .await
+        .unwrap());
+    assert!(!s3_create_bucket_if_not_exists(&pr, aws, "artifacts")
+        .await
+        .unwrap());
+    s3_upload_file(&pr, aws, "artifacts", "builds/upload.txt", &upload)
+        .await
+        .unwrap();
+
+    let objects = s3_list(aws, "artifacts", "builds/").await.unwrap();
+    assert_eq!(objects.len(), 1);
+    assert_eq!(objects[0].key, "builds/upload.txt");
+    assert_eq!(objects[0].size, 5);
+
+    s3_download_object(aws, "artifacts", "builds/upload.txt", &download)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&download).unwrap(), "hello");
+
+    s3_delete_object(aws, "artifacts", "builds/upload.txt")
+        .await
+        .unwrap();
+    assert!(s3_list(aws, "artifacts", "").await.unwrap().is_empty());
+}
+
+async fn secrets_and_parameters(aws: &AwsContext) {
+    let pr = Printer::new();
+    assert!(
+        update_or_create_secret(&pr, aws, "api", r#"{"token": "abc"}"#, None)
+            .await
+            .unwrap()
+    );
+    assert!(
+        !update_or_create_secret(&pr, aws, "api", r#"{"token": "def"}"#, None)
+            .await
+            .unwrap()
+    );
+    let token: String = get_secret_subkey(aws, "api", "token").await.unwrap();
+    assert_eq!(token, "def");
+
+    assert_eq!(
+        get_ssm_parameter_if_exists(aws, "/api/live-color")
+            .await
+            .unwrap(),
+        None
+    );
+    set_ssm_parameter(aws, "/api/live-color", "blue")
+        .await
+        .unwrap();
+    assert_eq!(
+        get_ssm_parameter_if_exists(aws, "/api/live-color")
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("blue")
+    );
+}
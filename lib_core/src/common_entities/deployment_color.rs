@@ -1,8 +1,19 @@
+use std::str::FromStr;
+
 use clap::ValueEnum;
 use fractic_core::impl_deterministic_display_from_serde;
 use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator as _;
 use strum_macros::EnumIter;
 
+use crate::{define_cli_error, CliError};
+
+define_cli_error!(
+    InvalidDeploymentColor,
+    "Invalid deployment color '{value}'.",
+    { value: &str }
+);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, EnumIter, ValueEnum)]
 #[serde(rename_all = "snake_case")]
 #[clap(rename_all = "snake_case")]
@@ -11,4 +22,24 @@ pub enum DeploymentColor {
     Green,
 }
 
+impl DeploymentColor {
+    /// The opposite color (ex. the idle color, given the live one).
+    pub fn other(self) -> Self {
+        match self {
+            DeploymentColor::Blue => DeploymentColor::Green,
+            DeploymentColor::Green => DeploymentColor::Blue,
+        }
+    }
+}
+
 impl_deterministic_display_from_serde!(DeploymentColor);
+
+impl FromStr for DeploymentColor {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DeploymentColor::iter()
+            .find(|v| v.to_string() == s)
+            .ok_or_else(|| InvalidDeploymentColor::new(s))
+    }
+}
@@ -1,8 +1,19 @@
+use std::str::FromStr;
+
 use clap::ValueEnum;
 use fractic_core::impl_deterministic_display_from_serde;
 use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator as _;
 use strum_macros::EnumIter;
 
+use crate::{define_cli_error, CliError};
+
+define_cli_error!(
+    InvalidDeploymentEnv,
+    "Invalid deployment environment '{value}'.",
+    { value: &str }
+);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, EnumIter, ValueEnum)]
 #[serde(rename_all = "snake_case")]
 #[clap(rename_all = "snake_case")]
@@ -13,3 +24,13 @@ pub enum DeploymentEnv {
 }
 
 impl_deterministic_display_from_serde!(DeploymentEnv);
+
+impl FromStr for DeploymentEnv {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DeploymentEnv::iter()
+            .find(|v| v.to_string() == s)
+            .ok_or_else(|| InvalidDeploymentEnv::new(s))
+    }
+}
@@ -0,0 +1,369 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    confirm_by_typing, define_cli_error, define_parameterized_string, CliError, DeploymentColor,
+    DeploymentEnv, ParameterizedTemplate, Printer,
+};
+
+define_cli_error!(
+    EnvironmentsConfigReadError,
+    "Failed to read environments config '{path}'.",
+    { path: &str }
+);
+define_cli_error!(
+    EnvironmentsConfigInvalid,
+    "Invalid environments config '{path}':\n{problems}",
+    { path: &str, problems: &str }
+);
+define_cli_error!(
+    EnvironmentNotConfigured,
+    "Environment '{env}' is not configured in '{path}'.",
+    { env: DeploymentEnv, path: &str }
+);
+
+define_parameterized_string!(StackNameTemplate, { env: DeploymentEnv, color: DeploymentColor }, parse);
+define_parameterized_string!(ColorDomainTemplate, { color: DeploymentColor });
+
+/// Value of an environment parameter. Unlike the names, it may use either,
+/// both or none of the '{env}' and '{color}' placeholders.
+#[derive(Debug, Clone)]
+pub struct ParameterTemplate {
+    value: String,
+    template: ParameterizedTemplate,
+}
+
+impl ParameterTemplate {
+    pub fn new(value: String) -> Result<Self, CliError> {
+        let template = ParameterizedTemplate::new(&value, &[("env", true), ("color", true)])?;
+        Ok(ParameterTemplate { value, template })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.value
+    }
+
+    pub fn render(&self, env: DeploymentEnv, color: DeploymentColor) -> Result<String, CliError> {
+        self.template
+            .render(&[("env", env.to_string()), ("color", color.to_string())])
+    }
+}
+
+impl<'de> Deserialize<'de> for ParameterTemplate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        ParameterTemplate::new(s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Per-project description of each deployment environment, usually checked in
+/// as a YAML file:
+///
+/// ```yaml
+/// project: api
+/// stack_name: "api-{env}-{color}"
+/// environments:
+///   staging:
+///     account_id: "111111111111"
+///     profile: company-staging
+///     regions: [us-east-1]
+///     parameters:
+///       LogLevel: debug
+///   production:
+///     account_id: "222222222222"
+///     profile: company-production
+///     role: arn:aws:iam::222222222222:role/Deployer
+///     regions: [us-east-1, eu-west-1]
+///     domain: api.example.com
+///     color_domain: "{color}.api.example.com"
+///     parameters:
+///       LogLevel: warn
+///       BucketName: "api-{env}-{color}-assets"
+///     colors:
+///       green:
+///         parameters:
+///           LogLevel: info
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnvironmentsConfig {
+    pub project: String,
+    pub stack_name: StackNameTemplate,
+    pub environments: HashMap<DeploymentEnv, EnvironmentConfig>,
+    #[serde(skip)]
+    path: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnvironmentConfig {
+    #[serde(deserialize_with = "deserialize_account_id")]
+    pub account_id: String,
+    /// AWS profile used to deploy to this environment.
+    pub profile: String,
+    /// Role assumed with the profile, if any.
+    #[serde(default)]
+    pub role: Option<String>,
+    /// The first region is the primary one.
+    pub regions: Vec<String>,
+    /// Domain serving the live color.
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// Domain of each color, regardless of which one is live.
+    #[serde(default)]
+    pub color_domain: Option<ColorDomainTemplate>,
+    /// Values may contain '{env}' and '{color}' placeholders ('{{' and '}}'
+    /// for literal braces).
+    #[serde(default)]
+    pub parameters: BTreeMap<String, ParameterTemplate>,
+    #[serde(default)]
+    pub colors: HashMap<DeploymentColor, ColorConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColorConfig {
+    /// Overrides the environment's parameters.
+    #[serde(default)]
+    pub parameters: BTreeMap<String, ParameterTemplate>,
+}
+
+/// Everything needed to deploy to one environment (and color).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeploymentTarget {
+    pub project: String,
+    pub env: DeploymentEnv,
+    pub color: DeploymentColor,
+    pub account_id: String,
+    pub profile: String,
+    pub role: Option<String>,
+    pub regions: Vec<String>,
+    pub stack_name: String,
+    pub domain: Option<String>,
+    pub color_domain: Option<String>,
+    /// Environment parameters, with the color's overrides applied, rendered
+    /// for the environment and color.
+    pub parameters: BTreeMap<String, String>,
+}
+
+impl DeploymentTarget {
+    pub fn primary_region(&self) -> &str {
+        &self.regions[0]
+    }
+
+    /// Caution box and typed confirmation before anything targets
+    /// Production. Does nothing for other environments.
+    fn confirm(&self, pr: &Printer) -> Result<(), CliError> {
+        if self.env != DeploymentEnv::Production {
+            return Ok(());
+        }
+        pr.caution_box(&format!(
+            "This targets PRODUCTION: stack '{}' in account {} ({}).",
+            self.stack_name,
+            self.account_id,
+            self.regions.join(", ")
+        ));
+        confirm_by_typing("Deploying to production.", &self.stack_name)
+    }
+}
+
+impl EnvironmentsConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CliError> {
+        let path_str = path.as_ref().display().to_string();
+        let content = fs::read_to_string(path.as_ref())
+            .map_err(|e| EnvironmentsConfigReadError::with_debug(&path_str, &e))?;
+        Self::parse(&content, &path_str)
+    }
+
+    /// 'source' is only used in error messages.
+    pub fn parse(content: &str, source: &str) -> Result<Self, CliError> {
+        let mut config: EnvironmentsConfig = serde_yaml::from_str(content)
+            .map_err(|e| EnvironmentsConfigInvalid::new(source, &format!("  {}", e)))?;
+        config.path = source.to_string();
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), CliError> {
+        let mut problems = Vec::new();
+        if self.environments.is_empty() {
+            problems.push("no environments are configured".to_string());
+        }
+        let mut envs = self.environments.iter().collect::<Vec<_>>();
+        envs.sort_by_key(|(env, _)| env.to_string());
+        for (env, config) in envs {
+            if config.account_id.len() != 12
+                || !config.account_id.chars().all(|c| c.is_ascii_digit())
+            {
+                problems.push(format!(
+                    "{}: account_id '{}' is not a 12-digit AWS account ID",
+                    env, config.account_id
+                ));
+            }
+            if config.profile.trim().is_empty() {
+                problems.push(format!("{}: profile is empty", env));
+            }
+            if config.regions.is_empty() {
+                problems.push(format!("{}: at least one region is required", env));
+            }
+            if let Some(role) = &config.role {
+                if !role.starts_with("arn:") {
+                    problems.push(format!("{}: role '{}' is not an ARN", env, role));
+                }
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            let problems = problems
+                .iter()
+                .map(|p| format!("  {}", p))
+                .collect::<Vec<_>>();
+            Err(EnvironmentsConfigInvalid::new(
+                &self.path,
+                &problems.join("\n"),
+            ))
+        }
+    }
+
+    pub fn environment(&self, env: DeploymentEnv) -> Result<&EnvironmentConfig, CliError> {
+        self.environments
+            .get(&env)
+            .ok_or_else(|| EnvironmentNotConfigured::new(env, &self.path))
+    }
+
+    /// Asks for a typed confirmation if the target is Production, so that
+    /// nothing can target it by accident.
+    pub fn target(
+        &self,
+        pr: &Printer,
+        env: DeploymentEnv,
+        color: DeploymentColor,
+    ) -> Result<DeploymentTarget, CliError> {
+        let target = self.resolve_target(env, color)?;
+        target.confirm(pr)?;
+        Ok(target)
+    }
+
+    fn resolve_target(
+        &self,
+        env: DeploymentEnv,
+        color: DeploymentColor,
+    ) -> Result<DeploymentTarget, CliError> {
+        let config = self.environment(env)?;
+        let mut parameters = BTreeMap::new();
+        let overrides = config.colors.get(&color).map(|c| &c.parameters);
+        for (name, value) in config
+            .parameters
+            .iter()
+            .chain(overrides.into_iter().flatten())
+        {
+            parameters.insert(name.clone(), value.render(env, color)?);
+        }
+        Ok(DeploymentTarget {
+            project: self.project.clone(),
+            env,
+            color,
+            account_id: config.account_id.clone(),
+            profile: config.profile.clone(),
+            role: config.role.clone(),
+            regions: config.regions.clone(),
+            stack_name: self.stack_name.get(env, color),
+            domain: config.domain.clone(),
+            color_domain: config.color_domain.as_ref().map(|d| d.get(color)),
+            parameters,
+        })
+    }
+}
+
+/// Accepts account IDs written as numbers too, which is easy to do by
+/// accident in YAML.
+fn deserialize_account_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum AccountId {
+        Number(u64),
+        String(String),
+    }
+
+    Ok(match AccountId::deserialize(deserializer)? {
+        // Leading zeros are lost, so this is reported by validation.
+        AccountId::Number(n) => n.to_string(),
+        AccountId::String(s) => s,
+    })
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+project: api
+stack_name: "api-{env}-{color}"
+environments:
+  production:
+    account_id: 222222222222
+    profile: company-production
+    regions: [us-east-1, eu-west-1]
+    domain: api.example.com
+    color_domain: "{color}.api.example.com"
+    parameters:
+      LogLevel: warn
+      Replicas: "3"
+      BucketName: "api-{env}-{color}-assets"
+    colors:
+      green:
+        parameters:
+          LogLevel: info
+"#;
+
+    #[test]
+    fn target_merges_color_overrides() {
+        let config = EnvironmentsConfig::parse(CONFIG, "envs.yaml").unwrap();
+        let target = config
+            .resolve_target(DeploymentEnv::Production, DeploymentColor::Green)
+            .unwrap();
+        assert_eq!(target.stack_name, "api-production-green");
+        assert_eq!(target.account_id, "222222222222");
+        assert_eq!(target.primary_region(), "us-east-1");
+        assert_eq!(
+            target.color_domain.as_deref(),
+            Some("green.api.example.com")
+        );
+        assert_eq!(target.parameters["LogLevel"], "info");
+        assert_eq!(target.parameters["Replicas"], "3");
+        assert_eq!(
+            target.parameters["BucketName"],
+            "api-production-green-assets"
+        );
+
+        let e = config
+            .resolve_target(DeploymentEnv::Staging, DeploymentColor::Blue)
+            .unwrap_err();
+        assert_eq!(
+            e.message(),
+            "Environment 'staging' is not configured in 'envs.yaml'."
+        );
+    }
+
+    #[test]
+    fn problems_are_reported_together() {
+        let config = CONFIG
+            .replace("222222222222", "2222")
+            .replace("[us-east-1, eu-west-1]", "[]");
+        let e = EnvironmentsConfig::parse(&config, "envs.yaml").unwrap_err();
+        assert_eq!(
+            e.message(),
+            "Invalid environments config 'envs.yaml':\n  production: account_id '2222' is not a \
+             12-digit AWS account ID\n  production: at least one region is required"
+        );
+    }
+}
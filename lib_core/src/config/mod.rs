@@ -1,3 +1,5 @@
+mod environments;
 mod parameterized_string;
 
+pub use environments::*;
 pub use parameterized_string::*;
@@ -1,4 +1,8 @@
-use crate::define_cli_error;
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use crate::{define_cli_error, CliError};
 
 define_cli_error!(
     ParameterizedStringMissingPlaceholder,
@@ -10,56 +14,134 @@ define_cli_error!(
     "Invalid placeholders '{found:?}' in parameterized string. Expected: {expected:?}",
     { found: Vec<String>, expected: Vec<String> }
 );
+define_cli_error!(
+    ParameterizedStringSyntaxError,
+    "Invalid parameterized string '{value}': {details}.",
+    { value: &str, details: &str }
+);
+define_cli_error!(
+    ParameterizedStringFormatError,
+    "Failed to format parameter '{param}' with value '{value}': {details}.",
+    { param: &str, value: &str, details: &str }
+);
+define_cli_error!(
+    ParameterizedStringParseError,
+    "'{value}' does not match parameterized string '{template}': {details}.",
+    { value: &str, template: &str, details: &str }
+);
 
+/// Defines a string type with named placeholders, which is validated when
+/// created (or deserialized), and rendered with typed parameters:
+///
+/// ```ignore
+/// define_parameterized_string!(ServiceUrl, {
+///     env: DeploymentEnv,
+///     port: u16 = 443,
+/// });
+///
+/// let url = ServiceUrl::new("https://{env}.example.com:{port:05}/{{id}}".into())?;
+/// url.get(DeploymentEnv::Staging, None); // "https://staging.example.com:00443/{id}"
+/// ```
+///
+/// Adding 'parse' after the parameters also generates `parse`, which extracts
+/// the parameters from a rendered string. It requires all parameter types to
+/// implement `FromStr`:
+///
+/// ```ignore
+/// define_parameterized_string!(StackName, { env: DeploymentEnv }, parse);
+///
+/// let name = StackName::new("api-{env}".into())?;
+/// name.parse("api-production")?; // (Production,)
+/// ```
+///
+/// Template syntax:
+/// - '{param}' is replaced by the parameter, and can appear more than once.
+/// - '{param:spec}' formats it like `format!` would, with a subset of the
+///   spec: '[[fill]align][+][0][width][.precision]', where align is '<', '^'
+///   or '>'. '+', '0' and precision require a numeric value.
+/// - '{{' and '}}' are literal braces.
+///
+/// Parameters without a default must appear in the template. Parameters with
+/// a default are optional, and are passed to `get` as an `Option`.
 #[macro_export]
 macro_rules! define_parameterized_string {
     (
-        $name:ident, { $($param:ident : $param_type:ty),* }
+        $name:ident, { $($param:ident : $param_type:ty $(= $default:expr)?),* $(,)? } $(,)?
+    ) => {
+        $crate::define_parameterized_string!(
+            @define $name, { $($param : $param_type $(= $default)?),* }
+        );
+    };
+
+    (
+        $name:ident, { $($param:ident : $param_type:ty $(= $default:expr)?),* $(,)? }, parse $(,)?
+    ) => {
+        $crate::define_parameterized_string!(
+            @define $name, { $($param : $param_type $(= $default)?),* }
+        );
+
+        impl $name {
+            /// Extracts the parameters from a string rendered from this
+            /// template. Optional parameters not in the template are set to
+            /// their default.
+            pub fn parse(&self, rendered: &str) -> Result<($($param_type,)*), $crate::CliError> {
+                let values = self.template.extract(rendered)?;
+                Ok(($(
+                    $crate::define_parameterized_string!(
+                        @parse self, rendered, values, $param, $param_type; $($default)?
+                    ),
+                )*))
+            }
+        }
+    };
+
+    (
+        @define $name:ident, { $($param:ident : $param_type:ty $(= $default:expr)?),* }
     ) => {
-        #[derive(Debug)]
+        #[derive(Debug, Clone)]
         pub struct $name {
             value: String,
+            template: $crate::ParameterizedTemplate,
         }
 
         impl $name {
             pub fn new(value: String) -> Result<Self, $crate::CliError> {
-                // Use regex to extract placeholders
-                let re = regex::Regex::new(r"\{([^\}]+)\}").expect("Hard-coded regex should be valid.");
-                let placeholders: Vec<String> = re.captures_iter(&value)
-                    .map(|cap| cap[1].to_string())
-                    .collect();
-
-                // Expected placeholders
-                let expected_placeholders = vec![$(stringify!($param).to_string()),*];
-
-                // Check if each expected placeholder is in the string and no extra placeholders exist
-                for expected in &expected_placeholders {
-                    if !placeholders.contains(expected) {
-                        return Err($crate::ParameterizedStringMissingPlaceholder::new(
-                            expected,
-                        ));
-                    }
-                }
-                if placeholders.len() != expected_placeholders.len() {
-                    return Err($crate::ParameterizedStringInvalidPlaceholder::new(
-                        placeholders,
-                        expected_placeholders,
-                    ));
-                }
+                let template = $crate::ParameterizedTemplate::new(
+                    &value,
+                    &[$((
+                        stringify!($param),
+                        $crate::define_parameterized_string!(@is_optional $($default)?),
+                    )),*],
+                )?;
+                Ok($name { value, template })
+            }
 
-                Ok($name { value })
+            pub fn as_str(&self) -> &str {
+                &self.value
             }
 
-            pub fn get(&self, $($param: $param_type),*) -> String {
-                let mut result = self.value.clone();
-                $(
-                    result = result.replace(concat!("{", stringify!($param), "}"), $param.to_string().as_str());
-                )*
-                // Panic if any placeholders are still left after replacement
-                if result.contains('{') || result.contains('}') {
-                    panic!("Unreplaced placeholders found in final string.");
-                }
-                result
+            /// Panics if a parameter can not be formatted with its format
+            /// spec (see `try_get`).
+            #[allow(clippy::too_many_arguments)]
+            pub fn get(
+                &self,
+                $($param: $crate::define_parameterized_string!(@arg_type $param_type; $($default)?)),*
+            ) -> String {
+                self.try_get($($param),*)
+                    .unwrap_or_else(|e| panic!("{}", e.message()))
+            }
+
+            #[allow(clippy::too_many_arguments)]
+            pub fn try_get(
+                &self,
+                $($param: $crate::define_parameterized_string!(@arg_type $param_type; $($default)?)),*
+            ) -> Result<String, $crate::CliError> {
+                self.template.render(&[$((
+                    stringify!($param),
+                    $crate::define_parameterized_string!(
+                        @value $param, $param_type; $($default)?
+                    ),
+                )),*])
             }
         }
 
@@ -71,4 +153,413 @@ macro_rules! define_parameterized_string {
             }
         }
     };
+
+    (@is_optional) => { false };
+    (@is_optional $default:expr) => { true };
+
+    (@arg_type $param_type:ty;) => { $param_type };
+    (@arg_type $param_type:ty; $default:expr) => { Option<$param_type> };
+
+    (@value $param:ident, $param_type:ty;) => { $param.to_string() };
+    (@value $param:ident, $param_type:ty; $default:expr) => {
+        match $param {
+            Some(value) => value.to_string(),
+            None => {
+                let default: $param_type = $default;
+                default.to_string()
+            }
+        }
+    };
+
+    (@parse $self:ident, $rendered:ident, $values:ident, $param:ident, $param_type:ty;) => {
+        $self.template.parse_value::<$param_type>($rendered, &$values, stringify!($param))?
+    };
+    (@parse $self:ident, $rendered:ident, $values:ident, $param:ident, $param_type:ty; $default:expr) => {
+        match $values.contains_key(stringify!($param)) {
+            true => $self
+                .template
+                .parse_value::<$param_type>($rendered, &$values, stringify!($param))?,
+            false => $default,
+        }
+    };
+}
+
+/// Parsed template of a `define_parameterized_string!` type.
+#[derive(Debug, Clone)]
+pub struct ParameterizedTemplate {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Param { name: String, spec: FormatSpec },
+}
+
+#[derive(Debug, Clone, Default)]
+struct FormatSpec {
+    fill: Option<char>,
+    align: Option<char>,
+    plus: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl ParameterizedTemplate {
+    /// 'params' are the allowed parameter names, and whether each one is
+    /// optional.
+    pub fn new(value: &str, params: &[(&str, bool)]) -> Result<Self, CliError> {
+        let segments = parse_segments(value)
+            .map_err(|details| ParameterizedStringSyntaxError::new(value, &details))?;
+        let found = segments
+            .iter()
+            .filter_map(|s| match s {
+                Segment::Param { name, .. } => Some(name.clone()),
+                Segment::Literal(_) => None,
+            })
+            .collect::<Vec<_>>();
+
+        for (param, optional) in params {
+            if !optional && !found.iter().any(|f| f == param) {
+                return Err(ParameterizedStringMissingPlaceholder::new(param));
+            }
+        }
+        if found.iter().any(|f| !params.iter().any(|(p, _)| p == f)) {
+            return Err(ParameterizedStringInvalidPlaceholder::new(
+                found,
+                params.iter().map(|(p, _)| p.to_string()).collect(),
+            ));
+        }
+
+        Ok(ParameterizedTemplate {
+            source: value.to_string(),
+            segments,
+        })
+    }
+
+    pub fn render(&self, values: &[(&str, String)]) -> Result<String, CliError> {
+        let mut result = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => result.push_str(literal),
+                Segment::Param { name, spec } => {
+                    let value = values
+                        .iter()
+                        .find(|(n, _)| n == name)
+                        .map(|(_, v)| v.as_str())
+                        .unwrap_or_default();
+                    let formatted = spec.apply(value).map_err(|details| {
+                        ParameterizedStringFormatError::new(name, value, &details)
+                    })?;
+                    result.push_str(&formatted);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Raw values of the parameters in 'rendered', by name.
+    pub fn extract(&self, rendered: &str) -> Result<HashMap<String, String>, CliError> {
+        let parse_error =
+            |details: &str| ParameterizedStringParseError::new(rendered, &self.source, details);
+
+        let mut pattern = String::from("^");
+        let mut groups = Vec::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => pattern.push_str(&regex::escape(literal)),
+                Segment::Param { name, spec } => {
+                    pattern.push_str("(.*?)");
+                    groups.push((name, spec));
+                }
+            }
+        }
+        pattern.push('$');
+        let captures = Regex::new(&pattern)
+            .expect("escaped template should be a valid regex")
+            .captures(rendered)
+            .ok_or_else(|| parse_error("the text around the parameters doesn't match"))?;
+
+        let mut values = HashMap::<String, String>::new();
+        for (i, (name, spec)) in groups.into_iter().enumerate() {
+            let value = spec.strip(&captures[i + 1]);
+            match values.get(name.as_str()) {
+                Some(previous) if *previous != value => {
+                    return Err(parse_error(&format!(
+                        "'{}' has different values ('{}' and '{}')",
+                        name, previous, value
+                    )));
+                }
+                _ => {
+                    values.insert(name.clone(), value);
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    pub fn parse_value<T: std::str::FromStr>(
+        &self,
+        rendered: &str,
+        values: &HashMap<String, String>,
+        name: &str,
+    ) -> Result<T, CliError> {
+        let value = values.get(name).map(String::as_str).unwrap_or_default();
+        value.parse().map_err(|_| {
+            ParameterizedStringParseError::new(
+                rendered,
+                &self.source,
+                &format!("invalid value '{}' for '{}'", value, name),
+            )
+        })
+    }
+}
+
+fn parse_segments(value: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '}' => return Err("unmatched '}' (use '}}' for a literal brace)".to_string()),
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => {
+                            return Err("unclosed '{' (use '{{' for a literal brace)".to_string())
+                        }
+                    }
+                }
+                let (name, spec) = match placeholder.split_once(':') {
+                    Some((name, spec)) => (name, FormatSpec::parse(spec)?),
+                    None => (placeholder.as_str(), FormatSpec::default()),
+                };
+                let name = name.trim();
+                if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    return Err(format!("invalid placeholder '{{{}}}'", placeholder));
+                }
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(Segment::Param {
+                    name: name.to_string(),
+                    spec,
+                });
+            }
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+impl FormatSpec {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid format spec '{}'", spec);
+        let mut result = FormatSpec::default();
+        let chars = spec.chars().collect::<Vec<_>>();
+        let is_align = |c: &char| matches!(c, '<' | '^' | '>');
+        let mut i = 0;
+        if chars.len() >= 2 && is_align(&chars[1]) {
+            result.fill = Some(chars[0]);
+            result.align = Some(chars[1]);
+            i = 2;
+        } else if chars.first().is_some_and(is_align) {
+            result.align = Some(chars[0]);
+            i = 1;
+        }
+        if chars.get(i) == Some(&'+') {
+            result.plus = true;
+            i += 1;
+        }
+        if chars.get(i) == Some(&'0') {
+            result.zero = true;
+            i += 1;
+        }
+        let rest = chars[i..].iter().collect::<String>();
+        let (width, precision) = match rest.split_once('.') {
+            Some((width, precision)) => (width, Some(precision)),
+            None => (rest.as_str(), None),
+        };
+        if !width.is_empty() {
+            result.width = width.parse().map_err(|_| invalid())?;
+        }
+        if let Some(precision) = precision {
+            result.precision = Some(precision.parse().map_err(|_| invalid())?);
+        }
+        Ok(result)
+    }
+
+    fn apply(&self, value: &str) -> Result<String, String> {
+        let number = parse_number(value);
+        let needs_number = self.plus || self.zero || self.precision.is_some();
+        let mut value = match (number, needs_number) {
+            (None, true) => return Err("'+', '0' and precision require a number".to_string()),
+            (Some(n), _) => match self.precision {
+                Some(precision) => format!("{:.*}", precision, n),
+                None => value.trim().to_string(),
+            },
+            (None, false) => value.to_string(),
+        };
+        if self.plus && !value.starts_with('-') {
+            value.insert(0, '+');
+        }
+
+        let len = value.chars().count();
+        if len >= self.width {
+            return Ok(value);
+        }
+        let padding = self.width - len;
+        if self.zero && self.align.is_none() {
+            // Zeros go after the sign.
+            let sign_len = if value.starts_with(['+', '-']) { 1 } else { 0 };
+            value.insert_str(sign_len, &"0".repeat(padding));
+            return Ok(value);
+        }
+        let fill = self.fill.unwrap_or(' ').to_string();
+        // Like `format!`, numbers are right-aligned by default.
+        let align = self
+            .align
+            .unwrap_or(if number.is_some() { '>' } else { '<' });
+        Ok(match align {
+            '>' => format!("{}{}", fill.repeat(padding), value),
+            '^' => format!(
+                "{}{}{}",
+                fill.repeat(padding / 2),
+                value,
+                fill.repeat(padding - padding / 2)
+            ),
+            _ => format!("{}{}", value, fill.repeat(padding)),
+        })
+    }
+
+    /// Undoes padding, so the value can be parsed back.
+    fn strip(&self, value: &str) -> String {
+        if self.width == 0 {
+            return value.to_string();
+        }
+        if self.zero && self.align.is_none() {
+            let (sign, digits) = match value.strip_prefix(['+', '-']) {
+                Some(digits) => (&value[..1], digits),
+                None => ("", value),
+            };
+            let digits = digits.trim_start_matches('0');
+            let digits = if digits.is_empty() || digits.starts_with('.') {
+                format!("0{}", digits)
+            } else {
+                digits.to_string()
+            };
+            return format!("{}{}", sign.trim_start_matches('+'), digits);
+        }
+        value
+            .trim_matches(self.fill.unwrap_or(' '))
+            .trim_start_matches('+')
+            .to_string()
+    }
+}
+
+/// Only plain decimal numbers count, so that values like "nan" or "inf" (which
+/// `f64` would accept) are formatted as text.
+fn parse_number(value: &str) -> Option<f64> {
+    let number = Regex::new(r"^[+-]?(\d+(\.\d*)?|\.\d+)([eE][+-]?\d+)?$")
+        .expect("hardcoded regex should be valid");
+    let value = value.trim();
+    match number.is_match(value) {
+        true => value.parse().ok(),
+        false => None,
+    }
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::{DeploymentColor, DeploymentEnv};
+
+    define_parameterized_string!(StackName, { env: DeploymentEnv, color: DeploymentColor }, parse);
+    define_parameterized_string!(ServiceUrl, { env: DeploymentEnv, port: u16 = 443 }, parse);
+    define_parameterized_string!(Label, { name: Name });
+
+    /// No `FromStr`, so only usable without 'parse'.
+    pub struct Name(&'static str);
+
+    impl std::fmt::Display for Name {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    #[test]
+    fn templates_support_escapes_specs_and_defaults() {
+        let url = ServiceUrl::new(
+            r#"{{"url": "https://{env}.example.com:{port:05}", "env": "{env}"}}"#.into(),
+        )
+        .unwrap();
+        assert_eq!(
+            url.get(DeploymentEnv::Staging, None),
+            r#"{"url": "https://staging.example.com:00443", "env": "staging"}"#
+        );
+        assert_eq!(
+            url.parse(r#"{"url": "https://production.example.com:08080", "env": "production"}"#)
+                .unwrap(),
+            (DeploymentEnv::Production, 8080)
+        );
+        assert!(url
+            .parse(r#"{"url": "https://production.example.com:08080", "env": "staging"}"#)
+            .is_err());
+
+        let url = ServiceUrl::new("https://{env}.example.com".into()).unwrap();
+        assert_eq!(url.as_str(), "https://{env}.example.com");
+        assert_eq!(
+            url.parse("https://sandbox.example.com").unwrap(),
+            (DeploymentEnv::Sandbox, 443)
+        );
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        assert!(StackName::new("api-{env}".into()).is_err());
+        assert!(StackName::new("api-{env}-{color}-{region}".into()).is_err());
+        assert!(StackName::new("api-{env}-{color".into()).is_err());
+        let name = StackName::new("api-{env}-{color}".into()).unwrap();
+        assert_eq!(name.as_str(), "api-{env}-{color}");
+        assert_eq!(
+            name.parse(&name.get(DeploymentEnv::Staging, DeploymentColor::Blue))
+                .unwrap(),
+            (DeploymentEnv::Staging, DeploymentColor::Blue)
+        );
+        let name = StackName::new("api-{env:+}-{color}".into()).unwrap();
+        assert!(name
+            .try_get(DeploymentEnv::Staging, DeploymentColor::Blue)
+            .is_err());
+    }
+
+    #[test]
+    fn only_decimal_values_are_formatted_as_numbers() {
+        let label = Label::new("[{name:05}]".into()).unwrap();
+        assert_eq!(label.as_str(), "[{name:05}]");
+        assert_eq!(label.get(Name("-12")), "[-0012]");
+        assert!(label.try_get(Name("nan")).is_err());
+        assert!(label.try_get(Name("inf")).is_err());
+        let label = Label::new("[{name:6}]".into()).unwrap();
+        assert_eq!(label.get(Name("1.5e3")), "[ 1.5e3]");
+        assert_eq!(label.get(Name("nan")), "[nan   ]");
+        assert_eq!(label.get(Name("infinity")), "[infinity]");
+    }
 }
@@ -0,0 +1,628 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read as _, Seek as _, SeekFrom, Write as _},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{define_cli_error, umount_sync, CliError, Printer};
+
+use super::{mkdir_p, mv, rm_rf};
+
+define_cli_error!(
+    CleanupFailed,
+    "Failed to run {count} cleanup action(s). They will be offered for recovery on the next run.",
+    { count: usize }
+);
+
+/// Process-wide registry used by the temporary file helpers. Set by `Tty`.
+static CURRENT_REGISTRY: OnceLock<CleanupRegistry> = OnceLock::new();
+
+/// Lists every backup registered for restore, across runs, so backups can
+/// still be found if their journal is lost (ex. it couldn't be written, or
+/// the process was killed mid-rename). Kept next to the journals.
+const BACKUP_INDEX: &str = "backups.index";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum CleanupAction {
+    /// Delete a file, symlink or directory, if it exists.
+    Remove { path: PathBuf },
+    /// Move 'backup' back to 'original', overwriting it. Skipped if the backup
+    /// no longer exists (i.e. it was already restored).
+    Restore { backup: PathBuf, original: PathBuf },
+    /// Unmount 'path', if still mounted. See `track_mount`.
+    Unmount { path: PathBuf, sudo: bool },
+}
+
+impl CleanupAction {
+    fn run(&self) -> Result<(), CliError> {
+        match self {
+            CleanupAction::Remove { path } => rm_rf(path),
+            CleanupAction::Restore { backup, original } => {
+                if backup.exists() {
+                    mv(backup, original)?;
+                }
+                Ok(())
+            }
+            CleanupAction::Unmount { path, sudo } => umount_sync(path, *sudo),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            CleanupAction::Remove { path } => format!("Remove '{}'.", path.display()),
+            CleanupAction::Restore { backup, original } => format!(
+                "Restore '{}' from '{}'.",
+                original.display(),
+                backup.display()
+            ),
+            CleanupAction::Unmount { path, .. } => format!("Unmount '{}'.", path.display()),
+        }
+    }
+}
+
+/// Keeps track of pending cleanup actions (ex. restoring a backed up file), so
+/// they can still be run if the script exits early.
+///
+/// Pending actions are also written to a journal file, which stays locked for
+/// as long as the registry is alive. If the process is killed without running
+/// cleanup, the next run finds the unlocked journal and offers to recover.
+/// Backups are additionally recorded in an index, so stray backups that no
+/// journal accounts for are offered for restore too.
+///
+/// Once the registry holds entries, Ctrl-C runs the pending actions before
+/// exiting, unless a section that handles Ctrl-C itself is active (see
+/// `defer_interrupts`).
+#[derive(Debug, Clone)]
+pub struct CleanupRegistry {
+    inner: Arc<Mutex<RegistryState>>,
+}
+
+#[derive(Debug)]
+struct RegistryState {
+    next_id: u64,
+    pending: Vec<(u64, CleanupAction)>,
+    journal_dir: Option<PathBuf>,
+    journal: Option<(PathBuf, File)>,
+    interrupt_printer: Option<Printer>,
+    interrupt_handler_installed: bool,
+    interrupts_deferred: usize,
+}
+
+/// Returned when registering a cleanup action. Call `dismiss` once the
+/// operation has cleaned up after itself. Dropping the guard without
+/// dismissing leaves the action pending.
+#[must_use]
+#[derive(Debug)]
+pub struct CleanupGuard {
+    registry: Option<CleanupRegistry>,
+    id: u64,
+}
+
+impl CleanupGuard {
+    pub fn dismiss(self) {
+        if let Some(registry) = &self.registry {
+            registry.remove(self.id);
+        }
+    }
+}
+
+/// Returned by `defer_interrupts`. Ctrl-C is left to the caller until dropped.
+#[must_use]
+#[derive(Debug)]
+pub(crate) struct InterruptDeferral {
+    registry: CleanupRegistry,
+}
+
+impl Drop for InterruptDeferral {
+    fn drop(&mut self) {
+        self.registry.inner.lock().unwrap().interrupts_deferred -= 1;
+    }
+}
+
+impl CleanupRegistry {
+    /// If 'journal_dir' is None, pending actions are only kept in memory, so
+    /// they can't be recovered after a hard crash.
+    pub fn new(journal_dir: Option<PathBuf>) -> Self {
+        CleanupRegistry {
+            inner: Arc::new(Mutex::new(RegistryState {
+                next_id: 0,
+                pending: Vec::new(),
+                journal_dir,
+                journal: None,
+                interrupt_printer: None,
+                interrupt_handler_installed: false,
+                interrupts_deferred: 0,
+            })),
+        }
+    }
+
+    /// The registry installed by `Tty`, if any.
+    pub fn current() -> Option<&'static CleanupRegistry> {
+        CURRENT_REGISTRY.get()
+    }
+
+    pub(crate) fn install_as_current(&self) {
+        let _ = CURRENT_REGISTRY.set(self.clone());
+    }
+
+    pub fn register(&self, action: CleanupAction) -> CleanupGuard {
+        let mut state = self.inner.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        if let (CleanupAction::Restore { .. }, Some(dir)) = (&action, &state.journal_dir) {
+            record_backup(dir, &action);
+        }
+        state.pending.push((id, action));
+        state.write_journal();
+        if !state.interrupt_handler_installed {
+            if let Some(printer) = state.interrupt_printer.clone() {
+                state.interrupt_handler_installed = self.spawn_interrupt_handler(printer);
+            }
+        }
+        CleanupGuard {
+            registry: Some(self.clone()),
+            id,
+        }
+    }
+
+    fn remove(&self, id: u64) {
+        let mut state = self.inner.lock().unwrap();
+        state.pending.retain(|(i, _)| *i != id);
+        state.write_journal();
+    }
+
+    /// Drops pending actions equal to 'action', for when the cleanup was
+    /// already done through another path (ex. an explicit unmount).
+    pub(crate) fn forget(&self, action: &CleanupAction) {
+        let mut state = self.inner.lock().unwrap();
+        let before = state.pending.len();
+        state.pending.retain(|(_, a)| a != action);
+        if state.pending.len() != before {
+            state.write_journal();
+        }
+    }
+
+    /// Runs all pending actions, most recently registered first. Actions that
+    /// fail are kept in the journal, so they are offered for recovery again
+    /// on the next run.
+    pub fn run_all(&self, printer: &Printer) -> Result<(), CliError> {
+        self.run_matching(printer, |_| true)
+    }
+
+    /// Like `run_all`, but only runs the pending unmounts (see `track_mount`),
+    /// for when filesystems have to be unmounted before the processes serving
+    /// them are stopped, while other actions still have to wait for them.
+    pub fn run_unmounts(&self, printer: &Printer) -> Result<(), CliError> {
+        self.run_matching(printer, |a| matches!(a, CleanupAction::Unmount { .. }))
+    }
+
+    fn run_matching(
+        &self,
+        printer: &Printer,
+        filter: impl Fn(&CleanupAction) -> bool,
+    ) -> Result<(), CliError> {
+        let mut state = self.inner.lock().unwrap();
+        let mut kept = Vec::new();
+        let mut failed = 0;
+        while let Some((id, action)) = state.pending.pop() {
+            if !filter(&action) {
+                kept.push((id, action));
+                continue;
+            }
+            printer.info(&format!("Cleanup: {}", action.describe()));
+            if let Err(e) = action.run() {
+                printer.error(&format!("Cleanup failed: {}", e.message()));
+                kept.push((id, action));
+                failed += 1;
+            }
+        }
+        kept.reverse();
+        state.pending = kept;
+        state.write_journal();
+        match failed {
+            0 => Ok(()),
+            count => Err(CleanupFailed::new(count)),
+        }
+    }
+
+    /// Looks for journals left behind by previous runs that did not exit
+    /// cleanly, and offers to run their pending actions.
+    pub fn recover_abandoned(&self, printer: &Printer) -> Result<(), CliError> {
+        let (journal_dir, own_journal) = {
+            let state = self.inner.lock().unwrap();
+            (
+                state.journal_dir.clone(),
+                state.journal.as_ref().map(|(path, _)| path.clone()),
+            )
+        };
+        let Some(journal_dir) = journal_dir else {
+            return Ok(());
+        };
+        let Ok(entries) = std::fs::read_dir(&journal_dir) else {
+            return Ok(());
+        };
+        // Actions still accounted for by a journal, so their backups aren't
+        // offered again as strays.
+        let mut journaled = self
+            .inner
+            .lock()
+            .unwrap()
+            .pending
+            .iter()
+            .map(|(_, a)| a.clone())
+            .collect::<Vec<_>>();
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if Some(&path) == own_journal.as_ref()
+                || path.file_name() == Some(BACKUP_INDEX.as_ref())
+            {
+                continue;
+            }
+            let Some((mut file, actions)) = read_abandoned_journal(&path) else {
+                // Still owned by a running process.
+                journaled.extend(read_journal(&path).unwrap_or_default());
+                continue;
+            };
+            if actions.is_empty() {
+                let _ = std::fs::remove_file(&path);
+                continue;
+            }
+            printer.caution_box(&format!(
+                "A previous run exited without cleaning up. Pending actions:\n{}",
+                actions
+                    .iter()
+                    .map(|a| format!("  - {}", a.describe()))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ));
+            if !printer.yes_no("Run these cleanup actions now?")? {
+                printer.info("Skipped. You will be asked again on the next run.");
+                journaled.extend(actions);
+                continue;
+            }
+            let mut remaining = Vec::new();
+            for action in actions.into_iter().rev() {
+                if let Err(e) = action.run() {
+                    printer.error(&format!("Cleanup failed: {}", e.message()));
+                    remaining.push(action);
+                }
+            }
+            if remaining.is_empty() {
+                let _ = std::fs::remove_file(&path);
+            } else {
+                remaining.reverse();
+                let _ = write_actions(&mut file, &remaining);
+                journaled.extend(remaining);
+            }
+        }
+        recover_stray_backups(printer, &journal_dir, &journaled)
+    }
+
+    /// Leaves Ctrl-C to the caller (ex. `Tty::in_exec_section`, which ends up
+    /// running cleanup through `Tty::close`) for as long as the returned guard
+    /// is alive.
+    pub(crate) fn defer_interrupts(&self) -> InterruptDeferral {
+        self.inner.lock().unwrap().interrupts_deferred += 1;
+        InterruptDeferral {
+            registry: self.clone(),
+        }
+    }
+
+    /// Runs pending actions before exiting on SIGTERM or SIGHUP. Ctrl-C is
+    /// only intercepted once the registry holds entries, so that until then it
+    /// keeps its default behaviour.
+    #[cfg(unix)]
+    pub(crate) fn spawn_signal_handler(&self, printer: Printer) {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        {
+            let mut state = self.inner.lock().unwrap();
+            if !state.pending.is_empty() && !state.interrupt_handler_installed {
+                state.interrupt_handler_installed = self.spawn_interrupt_handler(printer.clone());
+            }
+            state.interrupt_printer = Some(printer.clone());
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let registry = self.clone();
+        runtime.spawn(async move {
+            let (Ok(mut term), Ok(mut hup)) = (
+                signal(SignalKind::terminate()),
+                signal(SignalKind::hangup()),
+            ) else {
+                return;
+            };
+            let exit_code = tokio::select! {
+                _ = term.recv() => 143,
+                _ = hup.recv() => 129,
+            };
+            printer.error("Terminated. Running cleanup...");
+            let _ = registry.run_all(&printer);
+            std::process::exit(exit_code);
+        });
+    }
+
+    /// Returns false if there is no runtime to run the handler on yet, in
+    /// which case installing is retried on the next registration.
+    fn spawn_interrupt_handler(&self, printer: Printer) -> bool {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return false;
+        };
+        let registry = self.clone();
+        runtime.spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                let pending = {
+                    let state = registry.inner.lock().unwrap();
+                    if state.interrupts_deferred > 0 {
+                        continue;
+                    }
+                    !state.pending.is_empty()
+                };
+                if pending {
+                    printer.error("Interrupted. Running cleanup...");
+                    let _ = registry.run_all(&printer);
+                }
+                std::process::exit(130);
+            }
+        });
+        true
+    }
+}
+
+impl RegistryState {
+    /// Best effort. If the journal can't be written, cleanup still runs on
+    /// normal exit, it just can't be recovered after a hard crash.
+    fn write_journal(&mut self) {
+        if self.journal.is_none() {
+            if self.pending.is_empty() {
+                return;
+            }
+            self.journal = self.journal_dir.as_deref().and_then(create_journal);
+        }
+        let Some((path, file)) = &mut self.journal else {
+            return;
+        };
+        if self.pending.is_empty() {
+            // Unlocked automatically when the file is closed.
+            let _ = std::fs::remove_file(path);
+            self.journal = None;
+            return;
+        }
+        let actions = self
+            .pending
+            .iter()
+            .map(|(_, a)| a.clone())
+            .collect::<Vec<_>>();
+        let _ = write_actions(file, &actions);
+    }
+}
+
+fn write_actions(file: &mut File, actions: &[CleanupAction]) -> std::io::Result<()> {
+    let json = serde_json::to_vec(actions)?;
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&json)?;
+    file.sync_data()
+}
+
+fn create_journal(dir: &Path) -> Option<(PathBuf, File)> {
+    mkdir_p(dir).ok()?;
+    let path = dir.join(format!("{}.json", uuid::Uuid::new_v4()));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .ok()?;
+    file.lock().ok()?;
+    Some((path, file))
+}
+
+/// Reads a journal without taking its lock (ex. one owned by a running
+/// process).
+fn read_journal(path: &Path) -> Option<Vec<CleanupAction>> {
+    let content = std::fs::read_to_string(path).ok()?;
+    if content.trim().is_empty() {
+        return Some(Vec::new());
+    }
+    serde_json::from_str(&content).ok()
+}
+
+/// Adds 'action' to the backup index. Best effort, like the journal.
+fn record_backup(journal_dir: &Path, action: &CleanupAction) {
+    let Some((mut file, mut backups)) = open_backup_index(journal_dir) else {
+        return;
+    };
+    if !backups.contains(action) {
+        backups.push(action.clone());
+        let _ = write_actions(&mut file, &backups);
+    }
+}
+
+/// The returned file holds the (blocking) lock on the index until dropped.
+fn open_backup_index(journal_dir: &Path) -> Option<(File, Vec<CleanupAction>)> {
+    mkdir_p(journal_dir).ok()?;
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(journal_dir.join(BACKUP_INDEX))
+        .ok()?;
+    file.lock().ok()?;
+    let mut content = String::new();
+    file.read_to_string(&mut content).ok()?;
+    if content.trim().is_empty() {
+        return Some((file, Vec::new()));
+    }
+    Some((file, serde_json::from_str(&content).unwrap_or_default()))
+}
+
+/// Offers to restore indexed backups that still exist, but that no journal
+/// accounts for. Entries whose backup is gone, and that no journal still
+/// needs, are dropped from the index.
+fn recover_stray_backups(
+    printer: &Printer,
+    journal_dir: &Path,
+    journaled: &[CleanupAction],
+) -> Result<(), CliError> {
+    let Some((mut file, backups)) = open_backup_index(journal_dir) else {
+        return Ok(());
+    };
+    let (stray, mut kept) = split_stray_backups(backups, journaled);
+    if !stray.is_empty() {
+        printer.caution_box(&format!(
+            "Found backups left behind by a previous run:\n{}",
+            stray
+                .iter()
+                .map(|a| format!("  - {}", a.describe()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ));
+        if printer.yes_no("Restore these backups now?")? {
+            for action in stray {
+                if let Err(e) = action.run() {
+                    printer.error(&format!("Cleanup failed: {}", e.message()));
+                    kept.push(action);
+                }
+            }
+        } else {
+            printer.info("Skipped. You will be asked again on the next run.");
+            kept.extend(stray);
+        }
+    }
+    let _ = write_actions(&mut file, &kept);
+    Ok(())
+}
+
+/// Returns (stray, kept): backups that exist but aren't journaled, and
+/// journaled entries that should stay in the index.
+fn split_stray_backups(
+    backups: Vec<CleanupAction>,
+    journaled: &[CleanupAction],
+) -> (Vec<CleanupAction>, Vec<CleanupAction>) {
+    let mut stray = Vec::new();
+    let mut kept = Vec::new();
+    for action in backups {
+        let CleanupAction::Restore { backup, .. } = &action else {
+            continue;
+        };
+        if journaled.contains(&action) {
+            kept.push(action);
+        } else if backup.exists() {
+            stray.push(action);
+        }
+    }
+    (stray, kept)
+}
+
+/// Returns None if the journal is still locked by a running process, or can't
+/// be read. Otherwise, the returned file holds the lock until dropped.
+fn read_abandoned_journal(path: &Path) -> Option<(File, Vec<CleanupAction>)> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path).ok()?;
+    file.try_lock().ok()?;
+    let mut content = String::new();
+    file.read_to_string(&mut content).ok()?;
+    if content.trim().is_empty() {
+        return Some((file, Vec::new()));
+    }
+    let actions = serde_json::from_str(&content).ok()?;
+    Some((file, actions))
+}
+
+/// Registers 'action' with the registry installed by `Tty`. If there is none
+/// (ex. when used outside of a `Tty`), the returned guard does nothing.
+pub fn register_cleanup(action: CleanupAction) -> CleanupGuard {
+    match CleanupRegistry::current() {
+        Some(registry) => registry.register(action),
+        None => CleanupGuard {
+            registry: None,
+            id: 0,
+        },
+    }
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal_is_locked_while_alive_and_recoverable_after_crash() {
+        let dir = tempfile::tempdir().unwrap();
+        let action = CleanupAction::Restore {
+            backup: dir.path().join("file.txt.bak"),
+            original: dir.path().join("file.txt"),
+        };
+
+        let registry = CleanupRegistry::new(Some(dir.path().to_path_buf()));
+        let guard = registry.register(action.clone());
+        let journal = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|p| p.extension() == Some("json".as_ref()))
+            .unwrap();
+        assert!(read_abandoned_journal(&journal).is_none());
+
+        // Simulate a crash: the registry goes away without running cleanup.
+        drop(registry);
+        drop(guard);
+        let (_, actions) = read_abandoned_journal(&journal).unwrap();
+        assert_eq!(actions, vec![action]);
+    }
+
+    #[test]
+    fn backups_without_a_journal_are_found_as_strays() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal_dir = dir.path().join("cleanup");
+        let restore = |name: &str| CleanupAction::Restore {
+            backup: dir.path().join(format!("{name}.bak")),
+            original: dir.path().join(name),
+        };
+
+        let registry = CleanupRegistry::new(Some(journal_dir.clone()));
+        let _lost = registry.register(restore("lost.txt"));
+        let _live = registry.register(restore("live.txt"));
+        let _restored = registry.register(restore("restored.txt"));
+        std::fs::write(dir.path().join("lost.txt.bak"), "").unwrap();
+        std::fs::write(dir.path().join("live.txt.bak"), "").unwrap();
+
+        // Only 'live.txt' is still accounted for by a journal.
+        let (_, backups) = open_backup_index(&journal_dir).unwrap();
+        let (stray, kept) = split_stray_backups(backups, &[restore("live.txt")]);
+        assert_eq!(stray, vec![restore("lost.txt")]);
+        assert_eq!(kept, vec![restore("live.txt")]);
+    }
+
+    #[test]
+    fn unmounts_run_without_the_other_actions() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("scratch.txt");
+        std::fs::write(&file, "").unwrap();
+        let registry = CleanupRegistry::new(None);
+        let _guard = registry.register(CleanupAction::Remove { path: file.clone() });
+
+        registry.run_unmounts(&Printer::new()).unwrap();
+        assert!(file.exists());
+        registry.run_all(&Printer::new()).unwrap();
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn journal_is_removed_once_nothing_is_pending() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = CleanupRegistry::new(Some(dir.path().to_path_buf()));
+        let guard = registry.register(CleanupAction::Remove {
+            path: dir.path().join("symlink"),
+        });
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        guard.dismiss();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -1,9 +1,47 @@
 use std::{
+    fs::File,
     os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
 };
 
-use crate::{CliError, IOError};
+use crate::{define_cli_error, diff_dirs, CliError, CompareBy, IOError};
+
+define_cli_error!(
+    FileAlreadyExists,
+    "File already exists at '{path}', and overwrite is disabled.",
+    { path: &str }
+);
+define_cli_error!(
+    DirectoryMoveVerificationFailed,
+    "Copy of '{src}' at '{dst}' does not match the original, so the original was left in place.",
+    { src: &str, dst: &str }
+);
+
+#[derive(Debug, Clone, Copy)]
+pub struct CopyOptions {
+    /// Replace existing files at the destination. If false, an existing file
+    /// is an error.
+    pub overwrite: bool,
+    /// Copy the permission bits of files and directories. If false, new files
+    /// get the default permissions.
+    pub preserve_permissions: bool,
+    /// Recreate symlinks as symlinks (pointing to the same target). If false,
+    /// symlinks are followed and their target is copied.
+    pub preserve_symlinks: bool,
+    /// Copy the modification time of files.
+    pub preserve_mtime: bool,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        CopyOptions {
+            overwrite: true,
+            preserve_permissions: true,
+            preserve_symlinks: false,
+            preserve_mtime: false,
+        }
+    }
+}
 
 #[track_caller]
 pub fn cp<S, D>(src: S, dst: D) -> Result<(), CliError>
@@ -15,21 +53,46 @@ where
     Ok(())
 }
 
+#[track_caller]
+pub fn cp_with_options<S, D>(src: S, dst: D, options: CopyOptions) -> Result<(), CliError>
+where
+    S: AsRef<Path>,
+    D: AsRef<Path>,
+{
+    copy_entry(src.as_ref(), dst.as_ref(), &options)?;
+    copy_dir_permissions(src.as_ref(), dst.as_ref(), &options)
+}
+
 #[track_caller]
 pub fn mv<S, D>(src: S, dst: D) -> Result<(), CliError>
 where
     S: AsRef<Path>,
     D: AsRef<Path>,
 {
-    // Use fs_extra to support moving between different filesystems (not
-    // supported by std::fs::rename).
     if src.as_ref().is_dir() {
-        // Unfortunately, this doesn't seem to work:
-        // fs_extra::dir::move_dir(src, dst, &fs_extra::dir::CopyOptions::new().overwrite(true))
-        //     .map_err(|e| IOError::with_debug(&e))?;
-        //
-        // So we can't support moving directories between different filesystems.
-        std::fs::rename(src, dst).map_err(|e| IOError::with_debug(&e))?;
+        // std::fs::rename doesn't support moving between different
+        // filesystems. In that case, fall back to copying the whole tree,
+        // verifying the copy, and only then deleting the original.
+        match std::fs::rename(&src, &dst) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                let options = CopyOptions {
+                    overwrite: true,
+                    preserve_permissions: true,
+                    preserve_symlinks: true,
+                    preserve_mtime: true,
+                };
+                copy_tree(src.as_ref(), dst.as_ref(), &options)?;
+                if !diff_dirs(&src, &dst, CompareBy::Checksum)?.is_empty() {
+                    return Err(DirectoryMoveVerificationFailed::new(
+                        &src.as_ref().to_string_lossy(),
+                        &dst.as_ref().to_string_lossy(),
+                    ));
+                }
+                rm_rf(src)?;
+            }
+            Err(e) => return Err(IOError::with_debug(&e)),
+        }
     } else {
         fs_extra::file::move_file(
             src,
@@ -132,3 +195,137 @@ where
     .map_err(|e| IOError::with_debug(&e))?;
     Ok(())
 }
+
+/// Similar to `cp_r`, 'src' is copied into the 'dst' directory (i.e. to
+/// 'dst/<src file name>').
+#[track_caller]
+pub fn cp_r_with_options<S, D>(src: S, dst: D, options: CopyOptions) -> Result<(), CliError>
+where
+    S: AsRef<Path>,
+    D: AsRef<Path>,
+{
+    let file_name = src.as_ref().file_name().ok_or_else(IOError::new)?;
+    copy_tree(src.as_ref(), &dst.as_ref().join(file_name), &options)
+}
+
+/// Copies 'src' to 'dst', recursing into directories.
+pub(crate) fn copy_tree(src: &Path, dst: &Path, options: &CopyOptions) -> Result<(), CliError> {
+    copy_entry(src, dst, options)?;
+    let is_dir = match options.preserve_symlinks {
+        true => std::fs::symlink_metadata(src)
+            .map_err(|e| IOError::with_debug(&e))?
+            .is_dir(),
+        false => src.is_dir(),
+    };
+    if is_dir {
+        for child in ls(src)? {
+            let file_name = child.file_name().ok_or_else(IOError::new)?;
+            copy_tree(&child, &dst.join(file_name), options)?;
+        }
+        copy_dir_permissions(src, dst, options)?;
+    }
+    Ok(())
+}
+
+/// Copies a single file or symlink, or creates an (empty) directory. The
+/// permissions of a directory are only applied by `copy_dir_permissions`,
+/// once its children are copied, since they may not allow writing to it.
+pub(crate) fn copy_entry(src: &Path, dst: &Path, options: &CopyOptions) -> Result<(), CliError> {
+    let metadata = match options.preserve_symlinks {
+        true => std::fs::symlink_metadata(src),
+        false => std::fs::metadata(src),
+    }
+    .map_err(|e| IOError::with_debug(&e))?;
+
+    // Anything other than a directory-on-directory or file-on-file copy
+    // requires removing what's at the destination first.
+    if let Ok(existing) = std::fs::symlink_metadata(dst) {
+        let same_kind =
+            (existing.is_dir() && metadata.is_dir()) || (existing.is_file() && metadata.is_file());
+        if !same_kind || !metadata.is_dir() {
+            if !options.overwrite {
+                return Err(FileAlreadyExists::new(&dst.to_string_lossy()));
+            }
+            if !same_kind {
+                rm_rf(dst)?;
+            }
+        }
+    }
+
+    if metadata.is_symlink() {
+        let target = std::fs::read_link(src).map_err(|e| IOError::with_debug(&e))?;
+        ln_s(target, dst)?;
+        return Ok(());
+    }
+    if metadata.is_dir() {
+        return mkdir_p(dst);
+    }
+    let mut reader = File::open(src).map_err(|e| IOError::with_debug(&e))?;
+    let mut writer = File::create(dst).map_err(|e| IOError::with_debug(&e))?;
+    std::io::copy(&mut reader, &mut writer).map_err(|e| IOError::with_debug(&e))?;
+    if options.preserve_mtime {
+        let mtime = metadata.modified().map_err(|e| IOError::with_debug(&e))?;
+        writer
+            .set_modified(mtime)
+            .map_err(|e| IOError::with_debug(&e))?;
+    }
+    if options.preserve_permissions {
+        std::fs::set_permissions(dst, metadata.permissions())
+            .map_err(|e| IOError::with_debug(&e))?;
+    }
+    Ok(())
+}
+
+/// Applies the permissions of 'src' to 'dst', if 'src' is a directory (see
+/// `copy_entry`).
+pub(crate) fn copy_dir_permissions(
+    src: &Path,
+    dst: &Path,
+    options: &CopyOptions,
+) -> Result<(), CliError> {
+    let metadata = match options.preserve_symlinks {
+        true => std::fs::symlink_metadata(src),
+        false => std::fs::metadata(src),
+    }
+    .map_err(|e| IOError::with_debug(&e))?;
+    if options.preserve_permissions && metadata.is_dir() {
+        std::fs::set_permissions(dst, metadata.permissions())
+            .map_err(|e| IOError::with_debug(&e))?;
+    }
+    Ok(())
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_only_directories_are_copied_with_their_children() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        mkdir_p(src.join("sub")).unwrap();
+        std::fs::write(src.join("sub/file.txt"), "content").unwrap();
+        chmod(src.join("sub"), 0o555).unwrap();
+        chmod(&src, 0o555).unwrap();
+
+        let dst = dir.path().join("dst");
+        mkdir_p(&dst).unwrap();
+        let result = cp_r_with_options(&src, &dst, CopyOptions::default());
+
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let copied = dst.join("src");
+        let copied_mode = copied.exists().then(|| mode(&copied));
+        let copied_content = std::fs::read_to_string(copied.join("sub/file.txt")).ok();
+        // Make everything writable again, so the temp dir can be removed.
+        for path in [&src, &src.join("sub"), &copied, &copied.join("sub")] {
+            let _ = chmod(path, 0o755);
+        }
+
+        result.unwrap();
+        assert_eq!(copied_mode, Some(0o555));
+        assert_eq!(copied_content.as_deref(), Some("content"));
+    }
+}
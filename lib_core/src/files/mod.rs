@@ -1,11 +1,15 @@
+mod cleanup;
 mod encryption;
 mod management;
 mod placeholders;
+mod sync;
 mod tar;
 mod temporary;
 
+pub use cleanup::*;
 pub use encryption::*;
 pub use management::*;
 pub use placeholders::*;
+pub use sync::*;
 pub use tar::*;
 pub use temporary::*;
@@ -0,0 +1,357 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::{define_cli_error, sha256_file, CliError, IOError, Printer};
+
+use super::{copy_dir_permissions, copy_entry, rm_rf, CopyOptions};
+
+define_cli_error!(
+    InvalidExcludePattern,
+    "Invalid exclude pattern '{pattern}'.",
+    { pattern: &str }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareBy {
+    /// Compare file contents. Slower, but exact.
+    Checksum,
+    /// Consider files equal if they have the same size and modification time
+    /// (to the second, since precision differs between filesystems).
+    SizeAndMtime,
+}
+
+/// Paths are relative to the compared directories, and sorted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirDiff {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub changed: Vec<PathBuf>,
+}
+
+impl DirDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn print(&self, pr: &Printer) {
+        for path in &self.added {
+            pr.success(&format!("+ {}", path.display()));
+        }
+        for path in &self.changed {
+            pr.warn(&format!("~ {}", path.display()));
+        }
+        for path in &self.removed {
+            pr.error(&format!("- {}", path.display()));
+        }
+        if self.is_empty() {
+            pr.info("No differences.");
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncOptions<'a> {
+    pub compare: CompareBy,
+    /// Delete files in the destination that don't exist in the source.
+    pub delete_extra: bool,
+    /// Glob patterns, matched against paths relative to the synced
+    /// directories. Patterns without a '/' match file names at any depth (ex.
+    /// ".DS_Store" or "*.tmp"). Excluded paths are neither copied nor deleted.
+    pub exclude: Vec<&'a str>,
+    /// Only compute (and return) the changes, without applying them.
+    pub dry_run: bool,
+    pub copy: CopyOptions,
+}
+
+impl Default for SyncOptions<'_> {
+    fn default() -> Self {
+        SyncOptions {
+            compare: CompareBy::SizeAndMtime,
+            delete_extra: false,
+            exclude: Vec::new(),
+            dry_run: false,
+            copy: CopyOptions {
+                // Needed for SizeAndMtime comparisons on the next sync.
+                preserve_mtime: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    File { len: u64, mtime: u64 },
+    Dir,
+    Symlink(PathBuf),
+}
+
+/// Lists the differences going from 'old' to 'new'. Symlinks are compared by
+/// their target, without following them.
+pub fn diff_dirs<O, N>(old: O, new: N, compare: CompareBy) -> Result<DirDiff, CliError>
+where
+    O: AsRef<Path>,
+    N: AsRef<Path>,
+{
+    let empty = GlobSet::empty();
+    let old_entries = list_tree(old.as_ref(), &empty, false)?;
+    let new_entries = list_tree(new.as_ref(), &empty, false)?;
+    diff_entries(
+        old.as_ref(),
+        &old_entries,
+        new.as_ref(),
+        &new_entries,
+        compare,
+    )
+}
+
+/// Makes 'dst' match 'src', similar to 'rsync -a'. Only files that differ are
+/// copied. Returns the changes that were made (or would be made, if
+/// 'dry_run'), where 'removed' is only filled if 'delete_extra' is set.
+pub fn sync_dir<S, D>(src: S, dst: D, options: SyncOptions<'_>) -> Result<DirDiff, CliError>
+where
+    S: AsRef<Path>,
+    D: AsRef<Path>,
+{
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    let exclude = build_glob_set(&options.exclude)?;
+    let follow_symlinks = !options.copy.preserve_symlinks;
+    let src_entries = list_tree(src, &exclude, follow_symlinks)?;
+    let dst_entries = match dst.exists() {
+        true => list_tree(dst, &exclude, follow_symlinks)?,
+        false => BTreeMap::new(),
+    };
+    let mut diff = diff_entries(dst, &dst_entries, src, &src_entries, options.compare)?;
+    // Diffing from 'dst' to 'src' gives exactly the changes to apply: added
+    // and changed entries are copied, removed entries are deleted.
+    if !options.delete_extra {
+        diff.removed.clear();
+    }
+
+    if !options.dry_run {
+        // Children are sorted after their parents, so delete in reverse order
+        // and copy in order.
+        for rel in diff.removed.iter().rev() {
+            match dst_entries.get(rel) {
+                // Left in place if it still contains excluded files.
+                Some(Entry::Dir) => {
+                    let _ = std::fs::remove_dir(dst.join(rel));
+                }
+                _ => rm_rf(dst.join(rel))?,
+            }
+        }
+        let copy_options = CopyOptions {
+            overwrite: true,
+            ..options.copy
+        };
+        let mut to_copy = diff.added.iter().chain(&diff.changed).collect::<Vec<_>>();
+        to_copy.sort();
+        if !to_copy.is_empty() {
+            super::mkdir_p(dst)?;
+        }
+        for rel in &to_copy {
+            copy_entry(&src.join(rel), &dst.join(rel), &copy_options)?;
+        }
+        // Once their children are copied, so read-only directories can be
+        // filled first.
+        for rel in to_copy.iter().rev() {
+            copy_dir_permissions(&src.join(rel), &dst.join(rel), &copy_options)?;
+        }
+    }
+
+    Ok(diff)
+}
+
+fn build_glob_set(patterns: &[&str]) -> Result<GlobSet, CliError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        // Patterns without a separator apply at any depth.
+        let normalized = match pattern.contains('/') {
+            true => pattern.trim_start_matches('/').to_string(),
+            false => format!("**/{}", pattern),
+        };
+        builder.add(
+            Glob::new(&normalized).map_err(|e| InvalidExcludePattern::with_debug(pattern, &e))?,
+        );
+    }
+    builder
+        .build()
+        .map_err(|e| InvalidExcludePattern::with_debug(&patterns.join(", "), &e))
+}
+
+fn list_tree(
+    root: &Path,
+    exclude: &GlobSet,
+    follow_symlinks: bool,
+) -> Result<BTreeMap<PathBuf, Entry>, CliError> {
+    let mut entries = BTreeMap::new();
+    list_tree_recursive(root, Path::new(""), exclude, follow_symlinks, &mut entries)?;
+    Ok(entries)
+}
+
+fn list_tree_recursive(
+    root: &Path,
+    rel: &Path,
+    exclude: &GlobSet,
+    follow_symlinks: bool,
+    entries: &mut BTreeMap<PathBuf, Entry>,
+) -> Result<(), CliError> {
+    for dir_entry in std::fs::read_dir(root.join(rel)).map_err(|e| IOError::with_debug(&e))? {
+        let dir_entry = dir_entry.map_err(|e| IOError::with_debug(&e))?;
+        let rel_child = rel.join(dir_entry.file_name());
+        if exclude.is_match(&rel_child) {
+            continue;
+        }
+        let path = root.join(&rel_child);
+        let metadata = match follow_symlinks {
+            true => std::fs::metadata(&path),
+            false => std::fs::symlink_metadata(&path),
+        }
+        .map_err(|e| IOError::with_debug(&e))?;
+        let entry = if metadata.is_symlink() {
+            Entry::Symlink(std::fs::read_link(&path).map_err(|e| IOError::with_debug(&e))?)
+        } else if metadata.is_dir() {
+            list_tree_recursive(root, &rel_child, exclude, follow_symlinks, entries)?;
+            Entry::Dir
+        } else {
+            let mtime = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            Entry::File {
+                len: metadata.len(),
+                mtime,
+            }
+        };
+        entries.insert(rel_child, entry);
+    }
+    Ok(())
+}
+
+fn diff_entries(
+    old_root: &Path,
+    old: &BTreeMap<PathBuf, Entry>,
+    new_root: &Path,
+    new: &BTreeMap<PathBuf, Entry>,
+    compare: CompareBy,
+) -> Result<DirDiff, CliError> {
+    let mut diff = DirDiff::default();
+    for (rel, new_entry) in new {
+        match old.get(rel) {
+            None => diff.added.push(rel.clone()),
+            Some(old_entry) => {
+                if entry_changed(
+                    &old_root.join(rel),
+                    old_entry,
+                    &new_root.join(rel),
+                    new_entry,
+                    compare,
+                )? {
+                    diff.changed.push(rel.clone());
+                }
+            }
+        }
+    }
+    diff.removed = old
+        .keys()
+        .filter(|rel| !new.contains_key(*rel))
+        .cloned()
+        .collect();
+    Ok(diff)
+}
+
+fn entry_changed(
+    old_path: &Path,
+    old: &Entry,
+    new_path: &Path,
+    new: &Entry,
+    compare: CompareBy,
+) -> Result<bool, CliError> {
+    Ok(match (old, new) {
+        (Entry::Dir, Entry::Dir) => false,
+        (Entry::Symlink(old_target), Entry::Symlink(new_target)) => old_target != new_target,
+        (
+            Entry::File {
+                len: old_len,
+                mtime: old_mtime,
+            },
+            Entry::File {
+                len: new_len,
+                mtime: new_mtime,
+            },
+        ) => match compare {
+            _ if old_len != new_len => true,
+            CompareBy::SizeAndMtime => old_mtime != new_mtime,
+            CompareBy::Checksum => sha256_file(old_path)? != sha256_file(new_path)?,
+        },
+        _ => true,
+    })
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, rel: &str, content: &str) {
+        let path = root.join(rel);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn sync_copies_changes_deletes_extras_and_honours_excludes() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        write(src.path(), "same.txt", "same");
+        write(src.path(), "nested/new.txt", "new");
+        write(src.path(), "changed.txt", "after");
+        write(src.path(), "nested/.DS_Store", "ignored");
+        write(dst.path(), "same.txt", "same");
+        write(dst.path(), "changed.txt", "before");
+        write(dst.path(), "stale.txt", "stale");
+        write(dst.path(), ".DS_Store", "ignored");
+
+        let options = SyncOptions {
+            compare: CompareBy::Checksum,
+            delete_extra: true,
+            exclude: vec![".DS_Store"],
+            ..Default::default()
+        };
+        let dry_run = sync_dir(
+            src.path(),
+            dst.path(),
+            SyncOptions {
+                dry_run: true,
+                ..options.clone()
+            },
+        )
+        .unwrap();
+        assert!(dst.path().join("stale.txt").exists());
+
+        let applied = sync_dir(src.path(), dst.path(), options).unwrap();
+        assert_eq!(dry_run, applied);
+        assert_eq!(
+            applied.added,
+            vec![PathBuf::from("nested"), PathBuf::from("nested/new.txt")]
+        );
+        assert_eq!(applied.changed, vec![PathBuf::from("changed.txt")]);
+        assert_eq!(applied.removed, vec![PathBuf::from("stale.txt")]);
+        assert!(!dst.path().join("stale.txt").exists());
+        assert!(!dst.path().join("nested/.DS_Store").exists());
+        assert!(dst.path().join(".DS_Store").exists());
+        assert!(diff_dirs(src.path(), dst.path(), CompareBy::Checksum)
+            .unwrap()
+            .changed
+            .is_empty());
+    }
+}
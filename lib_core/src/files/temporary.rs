@@ -1,10 +1,10 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use tempfile::NamedTempFile;
 
 use crate::{define_cli_error, CliError, Printer};
 
-use super::{ln_s, mv, rm};
+use super::{ln_s, mv, register_cleanup, rm, CleanupAction};
 
 define_cli_error!(TemporaryFileError, "Temporary file error: {details}.", { details: &str });
 
@@ -29,6 +29,27 @@ where
     result
 }
 
+/// Similar to `with_written_to_tmp_file`, but the temporary file name ends in
+/// 'suffix' (ex. ".yaml"), which some tools use to pick a file type.
+pub fn with_written_to_tmp_file_with_suffix<F, R, C: AsRef<[u8]>>(
+    content: C,
+    suffix: &str,
+    op: F,
+) -> Result<R, CliError>
+where
+    F: FnOnce(&Path) -> Result<R, CliError>,
+{
+    let temp_file = tempfile::Builder::new()
+        .suffix(suffix)
+        .tempfile()
+        .map_err(|e| TemporaryFileError::with_debug("failed to create NamedTempFile", &e))?;
+    std::fs::write(temp_file.path(), content)
+        .map_err(|e| TemporaryFileError::with_debug("failed to write content", &e))?;
+    let result = op(temp_file.path());
+    drop(temp_file);
+    result
+}
+
 /// Similar to `with_written_to_tmp_file`, but creates an empty file.
 pub fn with_tmp_file<F, R>(op: F) -> Result<R, CliError>
 where
@@ -70,8 +91,12 @@ where
         .map_err(|e| TemporaryFileError::with_debug("failed to write content", &e))?;
     ln_s(&temp_file.path(), path)
         .map_err(|e| TemporaryFileError::with_debug("failed to create symlink", &e))?;
+    let cleanup = register_cleanup(CleanupAction::Remove {
+        path: absolute(path),
+    });
     let result = op();
     rm(path).map_err(|e| TemporaryFileError::with_debug("failed to remove symlink", &e))?;
+    cleanup.dismiss();
     drop(temp_file);
     result
 }
@@ -82,7 +107,8 @@ where
 /// "file.txt" -> "file.txt.bak"). After `op` completes (whether it succeeds or
 /// fails), the original file is restored from the backup. If writing the edited
 /// contents fails, the function attempts to restore the original immediately and
-/// returns an error.
+/// returns an error. If the process exits before the restore, the pending
+/// restore is run (or offered on the next run) by the `CleanupRegistry`.
 pub fn with_tmp_edits_to_file<F, R, E>(
     pr: &Printer,
     path: &Path,
@@ -113,13 +139,22 @@ where
     let backup_name = format!("{}.bak", file_name.to_string_lossy());
     let backup_path = path.with_file_name(backup_name);
 
+    // Register the restore before moving, so there is no window in which the
+    // backup exists but isn't tracked.
+    let cleanup = register_cleanup(CleanupAction::Restore {
+        backup: absolute(&backup_path),
+        original: absolute(path),
+    });
+
     // Move original to backup.
     mv(path, &backup_path)
         .map_err(|e| TemporaryFileError::with_debug("failed to back up original file", &e))?;
 
     // Write edited contents; on failure, attempt immediate restore.
     if let Err(e) = std::fs::write(path, edited) {
-        let _ = mv(&backup_path, path);
+        if mv(&backup_path, path).is_ok() {
+            cleanup.dismiss();
+        }
         return Err(TemporaryFileError::with_debug(
             "failed to write edited file",
             &e,
@@ -136,6 +171,13 @@ where
             &e,
         ));
     }
+    cleanup.dismiss();
 
     result
 }
+
+/// Cleanup actions may run from a different working directory (ex. when
+/// recovered on the next run), so paths are stored as absolute.
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
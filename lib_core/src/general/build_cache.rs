@@ -0,0 +1,116 @@
+use std::{collections::BTreeMap, future::Future, path::PathBuf};
+
+use crate::{define_cli_error, mkdir_p, CliError, IOError, Printer};
+
+define_cli_error!(
+    InvalidBuildCacheFile,
+    "Invalid build cache file at '{path}'.",
+    { path: &str }
+);
+
+/// Remembers the inputs hash of each build step the last time it succeeded,
+/// so that unchanged steps can be skipped.
+///
+/// Hashes are typically computed with `InputsHasher`:
+///
+/// ```ignore
+/// let hash = InputsHasher::new()
+///     .dir("lib", &HashDirOptions::default())?
+///     .file("pubspec.yaml")?
+///     .str(&flavor)
+///     .finish();
+/// cache.run_if_changed(pr, "flutter_build_web", &hash, || flutter_build(...))?;
+/// ```
+#[derive(Debug)]
+pub struct BuildCache {
+    path: PathBuf,
+    steps: BTreeMap<String, String>,
+}
+
+impl BuildCache {
+    /// Loads the cache from 'path'. A missing file is an empty cache.
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, CliError> {
+        let path = path.into();
+        let steps = match path.exists() {
+            true => {
+                let content =
+                    std::fs::read_to_string(&path).map_err(|e| IOError::with_debug(&e))?;
+                serde_yaml::from_str(&content)
+                    .map_err(|e| InvalidBuildCacheFile::with_debug(&path.to_string_lossy(), &e))?
+            }
+            false => BTreeMap::new(),
+        };
+        Ok(BuildCache { path, steps })
+    }
+
+    pub fn is_up_to_date(&self, step: &str, inputs_hash: &str) -> bool {
+        self.steps.get(step).is_some_and(|h| h == inputs_hash)
+    }
+
+    pub fn record(&mut self, step: &str, inputs_hash: &str) -> Result<(), CliError> {
+        self.steps.insert(step.to_string(), inputs_hash.to_string());
+        self.save()
+    }
+
+    pub fn invalidate(&mut self, step: &str) -> Result<(), CliError> {
+        if self.steps.remove(step).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Runs 'op' unless 'step' last succeeded with the same inputs hash.
+    /// Returns None if the step was skipped.
+    pub fn run_if_changed<F, R>(
+        &mut self,
+        pr: &Printer,
+        step: &str,
+        inputs_hash: &str,
+        op: F,
+    ) -> Result<Option<R>, CliError>
+    where
+        F: FnOnce() -> Result<R, CliError>,
+    {
+        if self.is_up_to_date(step, inputs_hash) {
+            pr.info(&format!("Skipping '{}' (inputs unchanged).", step));
+            return Ok(None);
+        }
+        let result = op()?;
+        self.record(step, inputs_hash)?;
+        Ok(Some(result))
+    }
+
+    /// Async version of `run_if_changed`.
+    pub async fn run_if_changed_async<F, Fut, R>(
+        &mut self,
+        pr: &Printer,
+        step: &str,
+        inputs_hash: &str,
+        op: F,
+    ) -> Result<Option<R>, CliError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<R, CliError>>,
+    {
+        if self.is_up_to_date(step, inputs_hash) {
+            pr.info(&format!("Skipping '{}' (inputs unchanged).", step));
+            return Ok(None);
+        }
+        let result = op().await?;
+        self.record(step, inputs_hash)?;
+        Ok(Some(result))
+    }
+
+    fn save(&self) -> Result<(), CliError> {
+        if let Some(parent) = self.path.parent() {
+            mkdir_p(parent)?;
+        }
+        let yaml = serde_yaml::to_string(&self.steps).map_err(|e| IOError::with_debug(&e))?;
+        // Write to a sibling file first, so an interrupted write can't leave
+        // a corrupt cache behind.
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, yaml).map_err(|e| IOError::with_debug(&e))?;
+        std::fs::rename(&tmp_path, &self.path).map_err(|e| IOError::with_debug(&e))?;
+        Ok(())
+    }
+}
@@ -1,10 +1,238 @@
-use std::hash::{DefaultHasher, Hash as _, Hasher as _};
+use std::{
+    fs::File,
+    io::Read as _,
+    path::{Path, PathBuf},
+};
 
+use ignore::{overrides::OverrideBuilder, WalkBuilder};
+use sha2::{Digest as _, Sha256};
+
+use crate::{define_cli_error, CliError, IOError};
+
+define_cli_error!(
+    HashDirError,
+    "Failed to hash directory '{path}': {details}.",
+    { path: &str, details: &str }
+);
+
+/// Returns a number in the range [min, max] derived from 'input'.
+///
+/// Uses SHA-256 rather than std's DefaultHasher, whose output is not
+/// guaranteed to be the same across Rust versions, so the result is stable
+/// (ex. for deriving ports).
 pub fn deterministic_number_from_string(input: &str, min: u32, max: u32) -> u32 {
-    let mut hasher = DefaultHasher::new();
-    input.hash(&mut hasher);
-    let hash_value = hasher.finish();
+    let digest = Sha256::digest(input.as_bytes());
+    let hash_value = u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"));
 
     // Scale the hash value to the range [min, max]
     min + (hash_value % (max - min + 1) as u64) as u32
 }
+
+pub fn sha256_hex<C: AsRef<[u8]>>(content: C) -> String {
+    to_hex(&Sha256::digest(content.as_ref()))
+}
+
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> Result<String, CliError> {
+    Ok(to_hex(&sha256_file_bytes(path.as_ref())?))
+}
+
+#[derive(Debug, Clone)]
+pub struct HashDirOptions<'a> {
+    /// Skip files ignored by .gitignore / .ignore files (also outside of a git
+    /// repository).
+    pub respect_ignore_files: bool,
+    /// Additional gitignore-style patterns to skip (ex. "build/" or "*.log").
+    pub exclude: Vec<&'a str>,
+}
+
+impl Default for HashDirOptions<'_> {
+    fn default() -> Self {
+        HashDirOptions {
+            respect_ignore_files: true,
+            exclude: Vec::new(),
+        }
+    }
+}
+
+/// Hashes the contents and relative paths of all files in a directory tree.
+/// The result only changes if a file is added, removed, renamed or modified.
+/// Symlinks are hashed by their target, without following them.
+pub fn sha256_dir<P: AsRef<Path>>(
+    path: P,
+    options: &HashDirOptions<'_>,
+) -> Result<String, CliError> {
+    Ok(to_hex(&sha256_dir_bytes(path.as_ref(), options)?))
+}
+
+/// Combines several inputs (files, directories, strings) into a single hash,
+/// for use with `BuildCache`. The order in which inputs are added matters.
+#[derive(Debug, Clone, Default)]
+pub struct InputsHasher {
+    hasher: Sha256,
+}
+
+impl InputsHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn str(mut self, value: &str) -> Self {
+        self.update(b"str", value.as_bytes());
+        self
+    }
+
+    pub fn file<P: AsRef<Path>>(mut self, path: P) -> Result<Self, CliError> {
+        let digest = sha256_file_bytes(path.as_ref())?;
+        self.update(b"file", &digest);
+        Ok(self)
+    }
+
+    pub fn dir<P: AsRef<Path>>(
+        mut self,
+        path: P,
+        options: &HashDirOptions<'_>,
+    ) -> Result<Self, CliError> {
+        let digest = sha256_dir_bytes(path.as_ref(), options)?;
+        self.update(b"dir", &digest);
+        Ok(self)
+    }
+
+    pub fn finish(self) -> String {
+        to_hex(&self.hasher.finalize())
+    }
+
+    /// Length-prefixed, so that ex. str("ab").str("c") differs from
+    /// str("a").str("bc").
+    fn update(&mut self, kind: &[u8], bytes: &[u8]) {
+        self.hasher.update(kind);
+        self.hasher.update((bytes.len() as u64).to_be_bytes());
+        self.hasher.update(bytes);
+    }
+}
+
+fn sha256_file_bytes(path: &Path) -> Result<[u8; 32], CliError> {
+    let mut file = File::open(path).map_err(|e| IOError::with_debug(&e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        match file
+            .read(&mut buffer)
+            .map_err(|e| IOError::with_debug(&e))?
+        {
+            0 => break,
+            n => hasher.update(&buffer[..n]),
+        }
+    }
+    Ok(hasher.finalize().into())
+}
+
+fn sha256_dir_bytes(root: &Path, options: &HashDirOptions<'_>) -> Result<[u8; 32], CliError> {
+    let root_str = root.to_string_lossy();
+    let mut overrides = OverrideBuilder::new(root);
+    for pattern in &options.exclude {
+        // In overrides, plain patterns are whitelists, and '!' patterns are
+        // ignores.
+        overrides
+            .add(&format!("!{}", pattern))
+            .map_err(|e| HashDirError::with_debug(&root_str, "invalid exclude pattern", &e))?;
+    }
+    let overrides = overrides
+        .build()
+        .map_err(|e| HashDirError::with_debug(&root_str, "invalid exclude pattern", &e))?;
+
+    let mut entries: Vec<(PathBuf, bool)> = Vec::new();
+    for entry in WalkBuilder::new(root)
+        .standard_filters(false)
+        .git_ignore(options.respect_ignore_files)
+        .git_exclude(options.respect_ignore_files)
+        .ignore(options.respect_ignore_files)
+        .require_git(false)
+        .overrides(overrides)
+        .build()
+    {
+        let entry =
+            entry.map_err(|e| HashDirError::with_debug(&root_str, "failed to walk tree", &e))?;
+        let Some(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            continue;
+        }
+        let rel = entry
+            .path()
+            .strip_prefix(root)
+            .map_err(|e| HashDirError::with_debug(&root_str, "unexpected path", &e))?
+            .to_path_buf();
+        entries.push((rel, file_type.is_symlink()));
+    }
+    // Walk order is not guaranteed, so sort for a stable result.
+    entries.sort();
+
+    let mut hasher = Sha256::new();
+    for (rel, is_symlink) in entries {
+        let path = root.join(&rel);
+        // Use '/' regardless of platform, so the hash is the same everywhere.
+        let rel = rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        hasher.update(rel.as_bytes());
+        hasher.update([0]);
+        if is_symlink {
+            let target = std::fs::read_link(&path).map_err(|e| IOError::with_debug(&e))?;
+            hasher.update(b"symlink:");
+            hasher.update(target.to_string_lossy().as_bytes());
+        } else {
+            hasher.update(sha256_file_bytes(&path)?);
+        }
+        hasher.update([0]);
+    }
+    Ok(hasher.finalize().into())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic_number_is_stable_and_in_range() {
+        let port = deterministic_number_from_string("pixel_7_api_34", 5600, 5800);
+        assert!((5600..=5800).contains(&port));
+        assert_eq!(
+            port,
+            deterministic_number_from_string("pixel_7_api_34", 5600, 5800)
+        );
+        assert_eq!(
+            sha256_hex("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn dir_hash_honours_ignore_files_and_excludes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(".gitignore"), "build/\n").unwrap();
+        std::fs::write(dir.path().join("main.dart"), "void main() {}").unwrap();
+        let options = HashDirOptions {
+            exclude: vec!["*.log"],
+            ..Default::default()
+        };
+        let before = sha256_dir(dir.path(), &options).unwrap();
+
+        std::fs::create_dir(dir.path().join("build")).unwrap();
+        std::fs::write(dir.path().join("build/app.apk"), "binary").unwrap();
+        std::fs::write(dir.path().join("debug.log"), "noise").unwrap();
+        assert_eq!(sha256_dir(dir.path(), &options).unwrap(), before);
+
+        std::fs::write(dir.path().join("main.dart"), "void main() { run(); }").unwrap();
+        assert_ne!(sha256_dir(dir.path(), &options).unwrap(), before);
+    }
+}
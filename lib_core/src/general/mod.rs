@@ -1,5 +1,7 @@
+mod build_cache;
 mod hash;
 mod mount;
 
+pub use build_cache::*;
 pub use hash::*;
 pub use mount::*;
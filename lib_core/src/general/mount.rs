@@ -1,26 +1,370 @@
-use crate::{CliError, Executor, IOMode};
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-pub async fn umount(ex: &Executor, path: &str, sudo_fallback: bool) -> Result<(), CliError> {
-    match ex.execute("umount", &[path], IOMode::StreamOutput).await {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            let msg = e.message().to_lowercase();
-
-            // If the target was not mounted, consider the operation a success.
-            if msg.contains("not mounted") || msg.contains("not currently mounted") {
-                return Ok(());
+use crate::{
+    define_cli_error, register_cleanup, CleanupAction, CleanupRegistry, CliError, Executor,
+    IOError, IOMode,
+};
+
+define_cli_error!(
+    MountTimeout,
+    "Mount at '{path}' did not become ready within {timeout_sec}s.",
+    { path: &str, timeout_sec: u64 }
+);
+define_cli_error!(
+    UmountFailed,
+    "Failed to unmount '{path}': {details}.",
+    { path: &str, details: &str }
+);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountInfo {
+    pub mount_point: PathBuf,
+    /// Device or remote, ex. "/dev/sda1" or "user@host:/path".
+    pub source: String,
+    pub fs_type: String,
+    pub options: Vec<String>,
+}
+
+impl MountInfo {
+    pub fn is_fuse(&self) -> bool {
+        // ex. "fuse.sshfs" on Linux, "macfuse" or "osxfuse" on macOS.
+        self.fs_type.contains("fuse")
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UmountOptions {
+    /// If unmounting fails because of permissions, retry with sudo.
+    pub sudo_fallback: bool,
+    /// If the mount is busy, detach it anyway ('umount -l' on Linux, 'umount
+    /// -f' on macOS).
+    pub lazy_if_busy: bool,
+}
+
+/// Lists current mounts, from /proc/self/mountinfo on Linux, or the output of
+/// 'mount' elsewhere.
+pub fn list_mounts() -> Result<Vec<MountInfo>, CliError> {
+    if cfg!(target_os = "linux") {
+        let content =
+            std::fs::read_to_string("/proc/self/mountinfo").map_err(|e| IOError::with_debug(&e))?;
+        Ok(parse_mountinfo(&content))
+    } else {
+        let output = std::process::Command::new("mount")
+            .output()
+            .map_err(|e| IOError::with_debug(&e))?;
+        Ok(parse_mount_output(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+/// Returns what is mounted at 'path', if anything. If several mounts are
+/// stacked on the same path, returns the top one.
+pub fn mount_at<P: AsRef<Path>>(path: P) -> Result<Option<MountInfo>, CliError> {
+    let path = normalize(path.as_ref());
+    Ok(list_mounts()?
+        .into_iter()
+        .rev()
+        .find(|m| m.mount_point == path))
+}
+
+/// True if 'path' is a mount whose backing process or connection is gone,
+/// which shows up as "transport endpoint is not connected" (typically a FUSE
+/// mount such as sshfs after the connection dropped).
+pub fn is_stale_mount<P: AsRef<Path>>(path: P) -> bool {
+    matches!(
+        std::fs::metadata(path.as_ref()),
+        Err(e) if e.kind() == ErrorKind::NotConnected
+    )
+}
+
+/// Waits until something is mounted at 'path' and the mount responds.
+pub async fn wait_for_mount<P: AsRef<Path>>(
+    path: P,
+    timeout: Duration,
+) -> Result<MountInfo, CliError> {
+    let start = Instant::now();
+    loop {
+        if let Some(mount) = mount_at(path.as_ref())? {
+            if std::fs::read_dir(path.as_ref()).is_ok() {
+                return Ok(mount);
             }
+        }
+        if start.elapsed() > timeout {
+            return Err(MountTimeout::new(
+                &path.as_ref().to_string_lossy(),
+                timeout.as_secs(),
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+/// Unmounts 'path' when `Tty` closes (or when the script is terminated), if
+/// it is still mounted by then. Set 'sudo' if the mount was created with sudo.
+/// Unmounting earlier with `umount` stops tracking it.
+pub fn track_mount<P: AsRef<Path>>(path: P, sudo: bool) {
+    // Never dismissed, since unmounting a path that is no longer mounted is a
+    // no-op.
+    let _ = register_cleanup(CleanupAction::Unmount {
+        path: normalize(path.as_ref()),
+        sudo,
+    });
+}
+
+pub async fn umount(ex: &Executor, path: &str, sudo_fallback: bool) -> Result<(), CliError> {
+    umount_with_options(
+        ex,
+        path,
+        UmountOptions {
+            sudo_fallback,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+pub async fn umount_with_options(
+    ex: &Executor,
+    path: &str,
+    options: UmountOptions,
+) -> Result<(), CliError> {
+    let mount = mount_at(path)?;
+    if mount.is_none() && !is_stale_mount(path) {
+        // Not mounted, so consider the operation a success.
+        return Ok(());
+    }
+
+    let mut attempt = umount_command(mount.as_ref(), path, false);
+    let mut tried_lazy = false;
+    let mut tried_sudo = false;
+    loop {
+        let (program, args) = &attempt;
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        let result = ex.execute(program, &args, IOMode::StreamOutput).await;
+        let Err(e) = result else {
+            break;
+        };
+        let msg = e.message().to_lowercase();
+        if options.lazy_if_busy && !tried_lazy && msg.contains("busy") {
+            tried_lazy = true;
+            attempt = umount_command(mount.as_ref(), path, true);
+        } else if options.sudo_fallback
+            && !tried_sudo
+            && (msg.contains("permission denied") || msg.contains("operation not permitted"))
+        {
+            tried_sudo = true;
+            attempt = with_sudo(umount_command(None, path, tried_lazy), false);
+        } else if mount_at(path)?.is_none() && !is_stale_mount(path) {
+            // Raced with something else unmounting it.
+            break;
+        } else {
+            return Err(e);
+        }
+    }
+
+    if let Some(registry) = CleanupRegistry::current() {
+        registry.forget(&CleanupAction::Unmount {
+            path: normalize(Path::new(path)),
+            sudo: false,
+        });
+        registry.forget(&CleanupAction::Unmount {
+            path: normalize(Path::new(path)),
+            sudo: true,
+        });
+    }
+    Ok(())
+}
 
-            // When permission is denied and the caller opted-in, retry with sudo.
-            if sudo_fallback
-                && (msg.contains("permission denied") || msg.contains("operation not permitted"))
-            {
-                ex.execute("sudo", &["umount", path], IOMode::StreamOutput)
-                    .await?;
-                return Ok(());
+/// Blocking version used by the cleanup registry, which can't rely on the
+/// async runtime still running. Never prompts for a password.
+pub(crate) fn umount_sync(path: &Path, sudo_fallback: bool) -> Result<(), CliError> {
+    let path_str = path.to_string_lossy();
+    let mount = mount_at(path)?;
+    if mount.is_none() && !is_stale_mount(path) {
+        return Ok(());
+    }
+    let mut attempts = vec![
+        umount_command(mount.as_ref(), &path_str, false),
+        umount_command(mount.as_ref(), &path_str, true),
+    ];
+    if sudo_fallback {
+        attempts.push(with_sudo(umount_command(None, &path_str, true), true));
+    }
+    let mut last_error = String::new();
+    for (program, args) in attempts {
+        match std::process::Command::new(&program).args(&args).output() {
+            Ok(output) if output.status.success() => return Ok(()),
+            Ok(output) => last_error = String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    Err(UmountFailed::new(&path_str, &last_error))
+}
+
+/// FUSE mounts created by the current user need 'fusermount' on Linux, since
+/// 'umount' requires root there.
+fn umount_command(mount: Option<&MountInfo>, path: &str, lazy: bool) -> (String, Vec<String>) {
+    let mut args = Vec::new();
+    let program = if cfg!(target_os = "linux") {
+        if mount.is_some_and(|m| m.is_fuse()) {
+            args.push(if lazy { "-uz" } else { "-u" }.to_string());
+            "fusermount"
+        } else {
+            if lazy {
+                args.push("-l".to_string());
             }
+            "umount"
+        }
+    } else {
+        if lazy {
+            args.push("-f".to_string());
+        }
+        "umount"
+    };
+    args.push(path.to_string());
+    (program.to_string(), args)
+}
+
+fn with_sudo(
+    (program, args): (String, Vec<String>),
+    non_interactive: bool,
+) -> (String, Vec<String>) {
+    let mut sudo_args = Vec::new();
+    if non_interactive {
+        sudo_args.push("-n".to_string());
+    }
+    sudo_args.push(program);
+    sudo_args.extend(args);
+    ("sudo".to_string(), sudo_args)
+}
+
+/// Mount points are listed as absolute paths with symlinks resolved. Stale
+/// mounts can't be canonicalized themselves, so resolve the parent instead.
+fn normalize(path: &Path) -> PathBuf {
+    if let Ok(canonical) = std::fs::canonicalize(path) {
+        return canonical;
+    }
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    match (absolute.parent(), absolute.file_name()) {
+        (Some(parent), Some(name)) => std::fs::canonicalize(parent)
+            .map(|p| p.join(name))
+            .unwrap_or(absolute),
+        _ => absolute,
+    }
+}
 
-            Err(e)
+/// Format: "36 35 98:0 /root /mnt/point rw,noatime master:1 - ext3 /dev/sda1
+/// rw,errors=continue", where the number of fields before '-' varies.
+fn parse_mountinfo(content: &str) -> Vec<MountInfo> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (pre, post) = line.split_once(" - ")?;
+            let pre = pre.split(' ').collect::<Vec<_>>();
+            let mut post = post.split(' ');
+            Some(MountInfo {
+                mount_point: PathBuf::from(unescape_octal(pre.get(4)?)),
+                options: pre.get(5)?.split(',').map(String::from).collect(),
+                fs_type: post.next()?.to_string(),
+                source: unescape_octal(post.next()?),
+            })
+        })
+        .collect()
+}
+
+/// Format: "user@host:/path on /mnt/point (macfuse, nodev, nosuid)".
+fn parse_mount_output(content: &str) -> Vec<MountInfo> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (source, rest) = line.split_once(" on ")?;
+            let (mount_point, options) = rest.rsplit_once(" (")?;
+            let mut options = options.trim_end_matches(')').split(", ").map(String::from);
+            Some(MountInfo {
+                mount_point: PathBuf::from(mount_point),
+                source: source.to_string(),
+                fs_type: options.next()?,
+                options: options.collect(),
+            })
+        })
+        .collect()
+}
+
+/// mountinfo escapes spaces, tabs, newlines and backslashes as '\ooo'.
+fn unescape_octal(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let digits = chars.clone().take(3).collect::<String>();
+            if digits.len() == 3 {
+                if let Ok(code) = u8::from_str_radix(&digits, 8) {
+                    result.push(code as char);
+                    chars.nth(2);
+                    continue;
+                }
+            }
         }
+        result.push(c);
+    }
+    result
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::{Expectation, ScriptedRunner};
+
+    use super::*;
+
+    #[test]
+    fn mountinfo_lines_are_parsed_with_optional_fields_and_escapes() {
+        let mounts = parse_mountinfo(
+            "22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n\
+             98 22 0:50 / /home/me/remote\\040dir rw,nosuid,nodev - fuse.sshfs me@host:/srv rw\n",
+        );
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[1].mount_point, PathBuf::from("/home/me/remote dir"));
+        assert_eq!(mounts[1].source, "me@host:/srv");
+        assert_eq!(mounts[1].fs_type, "fuse.sshfs");
+        assert!(mounts[1].is_fuse());
+        assert!(!mounts[0].is_fuse());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn busy_mount_is_retried_lazily() {
+        let runner = ScriptedRunner::new();
+        runner
+            .expect(
+                Expectation::new("umount", &["/"])
+                    .output("umount: /: target is busy.")
+                    .exit_code(32),
+            )
+            .expect(Expectation::new("umount", &["-l", "/"]));
+        let ex = Executor::with_runner(runner.clone());
+
+        let options = UmountOptions {
+            lazy_if_busy: true,
+            ..Default::default()
+        };
+        umount_with_options(&ex, "/", options).await.unwrap();
+        runner.assert_done();
+    }
+
+    #[test]
+    fn mount_output_is_parsed() {
+        let mounts = parse_mount_output(
+            "/dev/disk3s1s1 on / (apfs, sealed, local, read-only, journaled)\n\
+             me@host:/srv on /Users/me/remote (macfuse, nodev, nosuid, mounted by me)\n",
+        );
+        assert_eq!(mounts[1].mount_point, PathBuf::from("/Users/me/remote"));
+        assert_eq!(mounts[1].fs_type, "macfuse");
+        assert_eq!(mounts[0].options[0], "sealed");
     }
 }
@@ -1,5 +1,452 @@
-use serde_json::Value;
+use std::path::Path;
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{define_cli_error, CliError, IOError, Printer};
+
+define_cli_error!(
+    JsonPathError,
+    "Invalid JSON path '{path}': {details}.",
+    { path: &str, details: &str }
+);
+define_cli_error!(JsonPatchError, "Failed to apply JSON patch.");
+define_cli_error!(
+    JsonFileError,
+    "Failed to process JSON file '{path}'.",
+    { path: &str }
+);
 
 pub fn is_valid_json(input: &str) -> bool {
     serde_json::from_str::<Value>(input).is_ok()
 }
+
+pub fn read_json_file<T, P>(path: P) -> Result<T, CliError>
+where
+    T: DeserializeOwned,
+    P: AsRef<Path>,
+{
+    let content = std::fs::read_to_string(path.as_ref()).map_err(|e| IOError::with_debug(&e))?;
+    serde_json::from_str(&content)
+        .map_err(|e| JsonFileError::with_debug(&path.as_ref().to_string_lossy(), &e))
+}
+
+/// Pretty-printed, with a trailing newline.
+pub fn write_json_file<T, P>(path: P, value: &T) -> Result<(), CliError>
+where
+    T: Serialize,
+    P: AsRef<Path>,
+{
+    let mut content = serde_json::to_string_pretty(value)
+        .map_err(|e| JsonFileError::with_debug(&path.as_ref().to_string_lossy(), &e))?;
+    content.push('\n');
+    std::fs::write(path.as_ref(), content).map_err(|e| IOError::with_debug(&e))
+}
+
+// Paths.
+// --------------------------------------------------
+
+/// Paths can either be a JSON Pointer (RFC 6901, ex. "/Parameters/0/Value"),
+/// or dotted (ex. "Parameters[0].Value"). The empty string is the root.
+pub fn json_get<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    parse_path(path)
+        .iter()
+        .try_fold(value, |current, token| match current {
+            Value::Object(map) => map.get(token),
+            Value::Array(items) => token.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        })
+}
+
+/// Sets the value at 'path', creating missing objects along the way. For
+/// arrays, an index equal to the length (or "-") appends.
+pub fn json_set(value: &mut Value, path: &str, new_value: Value) -> Result<(), CliError> {
+    let tokens = parse_path(path);
+    let Some((last, parents)) = tokens.split_last() else {
+        *value = new_value;
+        return Ok(());
+    };
+    let mut current = value;
+    for token in parents {
+        if current.is_null() {
+            *current = Value::Object(Map::new());
+        }
+        current = match current {
+            Value::Object(map) => map
+                .entry(token.clone())
+                .or_insert_with(|| Value::Object(Map::new())),
+            Value::Array(items) => {
+                let index = array_index(path, token, items.len(), false)?;
+                &mut items[index]
+            }
+            _ => {
+                return Err(JsonPathError::new(
+                    path,
+                    &format!("'{}' is not inside an object or array", token),
+                ))
+            }
+        };
+    }
+    if current.is_null() {
+        *current = Value::Object(Map::new());
+    }
+    match current {
+        Value::Object(map) => {
+            map.insert(last.clone(), new_value);
+        }
+        Value::Array(items) => {
+            let index = array_index(path, last, items.len(), true)?;
+            if index == items.len() {
+                items.push(new_value);
+            } else {
+                items[index] = new_value;
+            }
+        }
+        _ => {
+            return Err(JsonPathError::new(
+                path,
+                &format!("'{}' is not inside an object or array", last),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Removes and returns the value at 'path', if it exists.
+pub fn json_remove(value: &mut Value, path: &str) -> Option<Value> {
+    let tokens = parse_path(path);
+    let (last, parents) = tokens.split_last()?;
+    let parent = parents
+        .iter()
+        .try_fold(value, |current, token| match current {
+            Value::Object(map) => map.get_mut(token),
+            Value::Array(items) => token
+                .parse::<usize>()
+                .ok()
+                .and_then(move |i| items.get_mut(i)),
+            _ => None,
+        })?;
+    match parent {
+        Value::Object(map) => map.remove(last),
+        Value::Array(items) => match last.parse::<usize>() {
+            Ok(i) if i < items.len() => Some(items.remove(i)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn array_index(path: &str, token: &str, len: usize, allow_append: bool) -> Result<usize, CliError> {
+    let index = match token {
+        "-" if allow_append => len,
+        _ => token
+            .parse::<usize>()
+            .map_err(|_| JsonPathError::new(path, &format!("'{}' is not an array index", token)))?,
+    };
+    let max = if allow_append {
+        len
+    } else {
+        len.saturating_sub(1)
+    };
+    if index > max || (!allow_append && len == 0) {
+        return Err(JsonPathError::new(
+            path,
+            &format!("index {} is out of bounds (length {})", index, len),
+        ));
+    }
+    Ok(index)
+}
+
+pub(crate) fn parse_path(path: &str) -> Vec<String> {
+    if path.is_empty() {
+        return Vec::new();
+    }
+    if let Some(pointer) = path.strip_prefix('/') {
+        return pointer
+            .split('/')
+            .map(|t| t.replace("~1", "/").replace("~0", "~"))
+            .collect();
+    }
+    let mut tokens = Vec::new();
+    for part in path.split('.') {
+        let mut rest = part;
+        if let Some(bracket) = rest.find('[') {
+            if bracket > 0 {
+                tokens.push(rest[..bracket].to_string());
+            }
+            rest = &rest[bracket..];
+            while let Some(inner) = rest.strip_prefix('[') {
+                let Some(end) = inner.find(']') else {
+                    break;
+                };
+                tokens.push(inner[..end].to_string());
+                rest = &inner[end + 1..];
+            }
+        } else {
+            tokens.push(rest.to_string());
+        }
+    }
+    tokens
+}
+
+fn to_pointer(tokens: &[String]) -> String {
+    tokens
+        .iter()
+        .map(|t| format!("/{}", t.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+// Merging and patching.
+// --------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrayMergeStrategy {
+    /// The overlay array replaces the base array.
+    Replace,
+    /// Overlay items are appended to the base array.
+    Append,
+    /// Like Append, but skips items already in the base array.
+    Union,
+    /// Items at the same index are deep merged.
+    MergeByIndex,
+}
+
+/// Recursively merges 'overlay' into 'base'. Objects are merged key by key,
+/// arrays according to 'arrays', and anything else is replaced. Unlike
+/// `apply_merge_patch`, null values in the overlay are set rather than
+/// removing keys.
+pub fn deep_merge(base: &mut Value, overlay: Value, arrays: ArrayMergeStrategy) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => deep_merge(existing, value, arrays),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(overlay)) => match arrays {
+            ArrayMergeStrategy::Replace => *base = overlay,
+            ArrayMergeStrategy::Append => base.extend(overlay),
+            ArrayMergeStrategy::Union => {
+                for item in overlay {
+                    if !base.contains(&item) {
+                        base.push(item);
+                    }
+                }
+            }
+            ArrayMergeStrategy::MergeByIndex => {
+                for (i, item) in overlay.into_iter().enumerate() {
+                    match base.get_mut(i) {
+                        Some(existing) => deep_merge(existing, item, arrays),
+                        None => base.push(item),
+                    }
+                }
+            }
+        },
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Applies a JSON Patch (RFC 6902), given as an array of operations. The
+/// value is left unchanged if any operation fails.
+pub fn apply_json_patch(value: &mut Value, patch: &Value) -> Result<(), CliError> {
+    let patch: json_patch::Patch =
+        serde_json::from_value(patch.clone()).map_err(|e| JsonPatchError::with_debug(&e))?;
+    json_patch::patch(value, &patch).map_err(|e| JsonPatchError::with_debug(&e))
+}
+
+/// Applies a JSON Merge Patch (RFC 7386): objects are merged recursively,
+/// null removes a key, and anything else (including arrays) is replaced.
+pub fn apply_merge_patch(value: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *value = patch.clone();
+        return;
+    };
+    if !value.is_object() {
+        *value = Value::Object(Map::new());
+    }
+    let Value::Object(map) = value else {
+        unreachable!("value was just made an object");
+    };
+    for (key, patch_value) in patch {
+        if patch_value.is_null() {
+            map.remove(key);
+        } else {
+            apply_merge_patch(map.entry(key.clone()).or_insert(Value::Null), patch_value);
+        }
+    }
+}
+
+// Diffing.
+// --------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonChange {
+    Added {
+        path: String,
+        value: Value,
+    },
+    Removed {
+        path: String,
+        value: Value,
+    },
+    Changed {
+        path: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+/// Structural diff from 'old' to 'new'. Paths are JSON Pointers. Objects are
+/// compared key by key and arrays index by index.
+pub fn json_diff(old: &Value, new: &Value) -> Vec<JsonChange> {
+    let mut changes = Vec::new();
+    diff_recursive(&mut Vec::new(), old, new, &mut changes);
+    changes
+}
+
+fn diff_recursive(path: &mut Vec<String>, old: &Value, new: &Value, out: &mut Vec<JsonChange>) {
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) => {
+            for (key, old_value) in old_map {
+                path.push(key.clone());
+                match new_map.get(key) {
+                    Some(new_value) => diff_recursive(path, old_value, new_value, out),
+                    None => out.push(JsonChange::Removed {
+                        path: to_pointer(path),
+                        value: old_value.clone(),
+                    }),
+                }
+                path.pop();
+            }
+            for (key, new_value) in new_map {
+                if !old_map.contains_key(key) {
+                    path.push(key.clone());
+                    out.push(JsonChange::Added {
+                        path: to_pointer(path),
+                        value: new_value.clone(),
+                    });
+                    path.pop();
+                }
+            }
+        }
+        (Value::Array(old_items), Value::Array(new_items)) => {
+            for i in 0..old_items.len().max(new_items.len()) {
+                path.push(i.to_string());
+                match (old_items.get(i), new_items.get(i)) {
+                    (Some(o), Some(n)) => diff_recursive(path, o, n, out),
+                    (Some(o), None) => out.push(JsonChange::Removed {
+                        path: to_pointer(path),
+                        value: o.clone(),
+                    }),
+                    (None, Some(n)) => out.push(JsonChange::Added {
+                        path: to_pointer(path),
+                        value: n.clone(),
+                    }),
+                    (None, None) => {}
+                }
+                path.pop();
+            }
+        }
+        (old, new) if old != new => out.push(JsonChange::Changed {
+            path: to_pointer(path),
+            old: old.clone(),
+            new: new.clone(),
+        }),
+        _ => {}
+    }
+}
+
+pub fn print_json_diff(pr: &Printer, changes: &[JsonChange]) {
+    if changes.is_empty() {
+        pr.info("No differences.");
+    }
+    for change in changes {
+        match change {
+            JsonChange::Added { path, value } => pr.success(&format!("+ {}: {}", path, value)),
+            JsonChange::Removed { path, value } => pr.error(&format!("- {}: {}", path, value)),
+            JsonChange::Changed { path, old, new } => {
+                pr.warn(&format!("~ {}: {} -> {}", path, old, new))
+            }
+        }
+    }
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn pointer_and_dotted_paths_get_and_set() {
+        let mut value = json!({"Parameters": [{"Key": "Env", "Value": "staging"}]});
+        assert_eq!(
+            json_get(&value, "/Parameters/0/Value"),
+            Some(&json!("staging"))
+        );
+        json_set(&mut value, "Parameters[0].Value", json!("production")).unwrap();
+        json_set(&mut value, "Tags.team", json!("infra")).unwrap();
+        json_set(&mut value, "/Parameters/-", json!({"Key": "Color"})).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "Parameters": [{"Key": "Env", "Value": "production"}, {"Key": "Color"}],
+                "Tags": {"team": "infra"}
+            })
+        );
+        assert!(json_set(&mut value, "Parameters[5].Key", json!("x")).is_err());
+    }
+
+    #[test]
+    fn merge_patch_removes_nulls_while_deep_merge_keeps_them() {
+        let mut patched = json!({"a": {"b": 1, "c": 2}, "list": [1, 2]});
+        let mut merged = patched.clone();
+        let overlay = json!({"a": {"c": null, "d": 3}, "list": [2, 3]});
+
+        apply_merge_patch(&mut patched, &overlay);
+        assert_eq!(patched, json!({"a": {"b": 1, "d": 3}, "list": [2, 3]}));
+
+        deep_merge(&mut merged, overlay, ArrayMergeStrategy::Union);
+        assert_eq!(
+            merged,
+            json!({"a": {"b": 1, "c": null, "d": 3}, "list": [1, 2, 3]})
+        );
+    }
+
+    #[test]
+    fn diff_reports_pointer_paths() {
+        let changes = json_diff(
+            &json!({"a": 1, "b": {"c": [1, 2]}}),
+            &json!({"b": {"c": [1, 3, 4]}, "d/e": true}),
+        );
+        assert_eq!(
+            changes,
+            vec![
+                JsonChange::Removed {
+                    path: "/a".to_string(),
+                    value: json!(1)
+                },
+                JsonChange::Changed {
+                    path: "/b/c/1".to_string(),
+                    old: json!(2),
+                    new: json!(3)
+                },
+                JsonChange::Added {
+                    path: "/b/c/2".to_string(),
+                    value: json!(4)
+                },
+                JsonChange::Added {
+                    path: "/d~1e".to_string(),
+                    value: json!(true)
+                },
+            ]
+        );
+    }
+}
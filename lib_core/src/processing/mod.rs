@@ -1,3 +1,5 @@
 mod json;
+mod yaml;
 
 pub use json::*;
+pub use yaml::*;
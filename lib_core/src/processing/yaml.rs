@@ -0,0 +1,417 @@
+use std::{fmt, path::Path};
+
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+
+use crate::{define_cli_error, CliError, IOError};
+
+use super::json::parse_path;
+
+define_cli_error!(YamlParseError, "Failed to parse YAML.");
+define_cli_error!(
+    YamlEditError,
+    "Failed to set '{path}' in YAML: {details}.",
+    { path: &str, details: &str }
+);
+
+/// Edits YAML text in place, keeping comments, key order and formatting of
+/// everything that isn't changed (unlike deserializing and serializing with
+/// serde_yaml, which drops comments).
+///
+/// Paths use the same syntax as `json_get` (ex. "dependencies.http" or
+/// "/dependencies/http"). Only block-style mappings can be navigated, which
+/// covers typical config files such as pubspec.yaml. Every edit is verified by
+/// re-parsing the result, so unsupported constructs fail instead of corrupting
+/// the file.
+#[derive(Debug, Clone)]
+pub struct YamlEditor {
+    lines: Vec<String>,
+    trailing_newline: bool,
+}
+
+impl YamlEditor {
+    pub fn parse(content: &str) -> Result<Self, CliError> {
+        serde_yaml::from_str::<Value>(content).map_err(|e| YamlParseError::with_debug(&e))?;
+        Ok(YamlEditor {
+            lines: content.lines().map(String::from).collect(),
+            trailing_newline: content.is_empty() || content.ends_with('\n'),
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, CliError> {
+        let content = std::fs::read_to_string(path).map_err(|e| IOError::with_debug(&e))?;
+        Self::parse(&content)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), CliError> {
+        std::fs::write(path, self.to_string()).map_err(|e| IOError::with_debug(&e))
+    }
+
+    pub fn value(&self) -> Value {
+        serde_yaml::from_str(&self.to_string()).unwrap_or(Value::Null)
+    }
+
+    pub fn get(&self, path: &str) -> Option<Value> {
+        parse_path(path)
+            .iter()
+            .try_fold(self.value(), |current, token| match current {
+                Value::Mapping(mut map) => map.remove(token.as_str()),
+                Value::Sequence(mut items) => token
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|i| *i < items.len())
+                    .map(|i| items.swap_remove(i)),
+                _ => None,
+            })
+    }
+
+    /// Replaces the value at 'path', or adds it (and any missing parent
+    /// mappings) at the end of its parent mapping. Comments on the edited
+    /// line are kept.
+    pub fn set<T: Serialize>(&mut self, path: &str, value: &T) -> Result<(), CliError> {
+        let value = serde_yaml::to_value(value)
+            .map_err(|e| YamlEditError::with_debug(path, "unsupported value", &e))?;
+        let tokens = parse_path(path);
+        if tokens.is_empty() {
+            return Err(YamlEditError::new(path, "the path is empty"));
+        }
+        let backup = self.lines.clone();
+        if let Err(details) = self.set_lines(&tokens, &value) {
+            self.lines = backup;
+            return Err(YamlEditError::new(path, &details));
+        }
+        if self.get(path).as_ref() != Some(&value) {
+            self.lines = backup;
+            return Err(YamlEditError::new(
+                path,
+                "the edit would change the document structure",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Removes the key at 'path' along with its value. Returns false if it
+    /// didn't exist.
+    pub fn remove(&mut self, path: &str) -> Result<bool, CliError> {
+        let tokens = parse_path(path);
+        let mut range = (0, self.lines.len());
+        let mut parent_indent = None;
+        for (depth, token) in tokens.iter().enumerate() {
+            let child_indent = self.child_indent(range.0, range.1, parent_indent);
+            let Some(line) = self
+                .find_key(range.0, range.1, child_indent, token)
+                .map_err(|details| YamlEditError::new(path, &details))?
+            else {
+                return Ok(false);
+            };
+            let indent = leading_spaces(&self.lines[line]);
+            let block_end = self.block_end(line + 1, range.1, indent);
+            if depth == tokens.len() - 1 {
+                self.lines.drain(line..block_end);
+                return Ok(true);
+            }
+            range = (line + 1, block_end);
+            parent_indent = Some(indent);
+        }
+        Ok(false)
+    }
+
+    fn set_lines(&mut self, tokens: &[String], value: &Value) -> Result<(), String> {
+        let (mut start, mut end) = (0, self.lines.len());
+        let mut parent_indent = None;
+        for (depth, token) in tokens.iter().enumerate() {
+            let child_indent = self.child_indent(start, end, parent_indent);
+            let Some(line) = self.find_key(start, end, child_indent, token)? else {
+                let at = self.last_content_line(start, end).map_or(start, |i| i + 1);
+                let indent = child_indent.unwrap_or(parent_indent.map_or(0, |i| i + 2));
+                let nested = tokens[depth..]
+                    .iter()
+                    .rev()
+                    .fold(value.clone(), |inner, key| {
+                        let mut map = Mapping::new();
+                        map.insert(Value::String(key.clone()), inner);
+                        Value::Mapping(map)
+                    });
+                let rendered = render(&nested)?;
+                self.lines
+                    .splice(at..at, rendered.lines().map(|l| indent_line(l, indent)));
+                return Ok(());
+            };
+
+            let indent = leading_spaces(&self.lines[line]);
+            let block_end = self.block_end(line + 1, end, indent);
+            let (_, head, inline, comment) =
+                split_entry(&self.lines[line]).ok_or("unexpected line format")?;
+            let (head, comment) = (head.to_string(), comment.to_string());
+            if depth == tokens.len() - 1 {
+                let is_block = match value {
+                    Value::Mapping(m) => !m.is_empty(),
+                    Value::Sequence(s) => !s.is_empty(),
+                    _ => false,
+                };
+                let mut new_lines = Vec::new();
+                let rendered = render(value)?;
+                if is_block {
+                    // Keep the existing indentation of the value, if any.
+                    let child_indent = (line + 1..block_end)
+                        .find(|i| is_content(&self.lines[*i]))
+                        .map_or(indent + 2, |i| leading_spaces(&self.lines[i]));
+                    new_lines.push(format!("{}{}", head, comment));
+                    new_lines.extend(rendered.lines().map(|l| indent_line(l, child_indent)));
+                } else {
+                    // Multi-line scalars are rendered as block literals, whose
+                    // content is already indented by 2.
+                    let mut rendered = rendered.lines();
+                    let first = rendered.next().unwrap_or_default();
+                    new_lines.push(format!("{} {}{}", head, first, comment));
+                    new_lines.extend(rendered.map(|l| indent_line(l, indent)));
+                }
+                self.lines.splice(line..block_end, new_lines);
+                return Ok(());
+            }
+
+            // Allow adding keys under a key that is explicitly empty.
+            match inline.trim() {
+                "" => {}
+                "~" | "null" | "{}" => self.lines[line] = format!("{}{}", head, comment),
+                _ => return Err(format!("'{}' is not a mapping", token)),
+            }
+            start = line + 1;
+            end = block_end;
+            parent_indent = Some(indent);
+        }
+        Ok(())
+    }
+
+    /// Indentation of the first content line in the range, if it is nested
+    /// deeper than the parent.
+    fn child_indent(&self, start: usize, end: usize, parent: Option<usize>) -> Option<usize> {
+        let first = (start..end).find(|i| is_content(&self.lines[*i]))?;
+        let indent = leading_spaces(&self.lines[first]);
+        match parent {
+            Some(p) if indent <= p => None,
+            _ => Some(indent),
+        }
+    }
+
+    fn find_key(
+        &self,
+        start: usize,
+        end: usize,
+        child_indent: Option<usize>,
+        key: &str,
+    ) -> Result<Option<usize>, String> {
+        let Some(child_indent) = child_indent else {
+            return Ok(None);
+        };
+        let mut first = true;
+        for i in (start..end).filter(|i| is_content(&self.lines[*i])) {
+            let line = &self.lines[i];
+            if leading_spaces(line) != child_indent {
+                first = false;
+                continue;
+            }
+            if is_sequence_item(line) {
+                if first {
+                    return Err(format!("'{}' is inside a sequence", key));
+                }
+                // Part of a sequence that isn't indented under its key.
+                continue;
+            }
+            first = false;
+            if split_entry(line).is_some_and(|(k, ..)| k == key) {
+                return Ok(Some(i));
+            }
+        }
+        Ok(None)
+    }
+
+    /// End (exclusive) of the value of a key at 'indent', starting after the
+    /// key's line. Trailing comments and blank lines are not included, since
+    /// they usually belong to the next key.
+    fn block_end(&self, from: usize, end: usize, indent: usize) -> usize {
+        let mut block_end = from;
+        for i in from..end {
+            let line = &self.lines[i];
+            if !is_content(line) {
+                continue;
+            }
+            let line_indent = leading_spaces(line);
+            if line_indent < indent || (line_indent == indent && !is_sequence_item(line)) {
+                break;
+            }
+            block_end = i + 1;
+        }
+        block_end
+    }
+
+    fn last_content_line(&self, start: usize, end: usize) -> Option<usize> {
+        (start..end).rev().find(|i| is_content(&self.lines[*i]))
+    }
+}
+
+impl fmt::Display for YamlEditor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.lines.join("\n"))?;
+        if self.trailing_newline && !self.lines.is_empty() {
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn render(value: &Value) -> Result<String, String> {
+    serde_yaml::to_string(value).map_err(|e| e.to_string())
+}
+
+fn indent_line(line: &str, indent: usize) -> String {
+    match line.is_empty() {
+        true => String::new(),
+        false => format!("{}{}", " ".repeat(indent), line),
+    }
+}
+
+fn leading_spaces(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+fn is_content(line: &str) -> bool {
+    let trimmed = line.trim();
+    !trimmed.is_empty() && !trimmed.starts_with('#') && trimmed != "---" && trimmed != "..."
+}
+
+fn is_sequence_item(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed == "-" || trimmed.starts_with("- ")
+}
+
+/// Splits "  key: value  # comment" into the unquoted key, the line up to and
+/// including the colon, the value, and the comment (with its leading
+/// whitespace).
+fn split_entry(line: &str) -> Option<(String, &str, &str, &str)> {
+    let trimmed = line.trim_start();
+    let offset = line.len() - trimmed.len();
+    let (key, colon) = match trimmed.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let close = trimmed[1..].find(quote)? + 1;
+            let rest = &trimmed[close + 1..];
+            let colon = close + 1 + (rest.len() - rest.trim_start().len());
+            (trimmed[1..close].to_string(), colon)
+        }
+        _ => {
+            let colon = trimmed.char_indices().find_map(|(i, c)| {
+                let next = trimmed[i + 1..].chars().next();
+                (c == ':' && matches!(next, None | Some(' ' | '\t'))).then_some(i)
+            })?;
+            (trimmed[..colon].trim_end().to_string(), colon)
+        }
+    };
+    if !trimmed[colon..].starts_with(':') {
+        return None;
+    }
+    let (head, after) = line.split_at(offset + colon + 1);
+    if !(after.is_empty() || after.starts_with([' ', '\t'])) {
+        return None;
+    }
+    let comment_start = find_comment(after).unwrap_or(after.len());
+    let (value, comment) = after.split_at(comment_start);
+    Some((key, head, value, comment))
+}
+
+fn find_comment(value: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut prev_is_space = true;
+    for (i, c) in value.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if (c == '"' || c == '\'') && prev_is_space => quote = Some(c),
+            None if c == '#' && prev_is_space => return Some(value[..i].trim_end().len()),
+            None => {}
+        }
+        prev_is_space = c.is_whitespace();
+    }
+    None
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBSPEC: &str = "\
+name: app # Must match the bundle ID.
+# Bumped by CI.
+version: 1.0.0+1
+
+environment:
+  sdk: \">=3.0.0 <4.0.0\"
+
+dependencies:
+  flutter:
+    sdk: flutter
+  http: ^1.2.0
+
+flutter:
+  assets:
+  - assets/
+";
+
+    #[test]
+    fn edits_keep_comments_order_and_formatting() {
+        let mut editor = YamlEditor::parse(PUBSPEC).unwrap();
+        editor.set("version", &"1.0.1+2").unwrap();
+        editor.set("name", &"my_app").unwrap();
+        editor.set("environment.flutter", &">=3.22.0").unwrap();
+        editor.set("dependencies.flutter", &"any").unwrap();
+        editor.set("dev_dependencies.lints", &"^4.0.0").unwrap();
+        editor
+            .set("flutter.assets", &vec!["assets/", "fonts/"])
+            .unwrap();
+        assert_eq!(
+            editor.to_string(),
+            "\
+name: my_app # Must match the bundle ID.
+# Bumped by CI.
+version: 1.0.1+2
+
+environment:
+  sdk: \">=3.0.0 <4.0.0\"
+  flutter: '>=3.22.0'
+
+dependencies:
+  flutter: any
+  http: ^1.2.0
+
+flutter:
+  assets:
+  - assets/
+  - fonts/
+dev_dependencies:
+  lints: ^4.0.0
+"
+        );
+        assert_eq!(
+            editor.get("dependencies.http"),
+            Some(Value::String("^1.2.0".to_string()))
+        );
+    }
+
+    #[test]
+    fn remove_and_invalid_edits_leave_document_intact() {
+        let mut editor = YamlEditor::parse(PUBSPEC).unwrap();
+        assert!(editor.remove("dependencies.flutter").unwrap());
+        assert!(!editor.remove("dependencies.missing").unwrap());
+        assert!(editor.set("version.major", &1).is_err());
+        assert!(editor.set("flutter.assets.path", &"x").is_err());
+        assert!(editor
+            .to_string()
+            .contains("dependencies:\n  http: ^1.2.0\n"));
+        assert_eq!(
+            editor.get("version"),
+            Some(Value::String("1.0.0+1".to_string()))
+        );
+    }
+}
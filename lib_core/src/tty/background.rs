@@ -0,0 +1,527 @@
+use std::{
+    fs::File,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt as _},
+    sync::{watch, Notify},
+};
+
+use crate::{
+    define_cli_error, mkdir_p, register_cleanup, CleanupAction, CleanupRegistry, CliError, IOError,
+};
+
+use super::Printer;
+
+define_cli_error!(
+    BackgroundProcessExited,
+    "[{exit_status}] Background process '{name}' exited unexpectedly. Last output ({log_path}):\n{output}",
+    { exit_status: ExitStatus, name: &str, log_path: &str, output: &str }
+);
+define_cli_error!(
+    BackgroundProcessLost,
+    "Lost track of background process '{name}': {details}.",
+    { name: &str, details: &str }
+);
+define_cli_error!(
+    BackgroundProcessNotReady,
+    "Background process '{name}' was not ready after {timeout_sec}s. Last output ({log_path}):\n{output}",
+    { name: &str, timeout_sec: u64, log_path: &str, output: &str }
+);
+
+/// Number of log lines included in errors.
+const ERROR_LOG_LINES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EarlyExitPolicy {
+    /// Don't report the failure.
+    Ignore,
+    /// Print a warning as soon as the process fails, but don't fail the
+    /// script.
+    Warn,
+    /// Fail the script when `Tty` closes (or earlier, when calling
+    /// `Executor::check_background_processes`).
+    #[default]
+    Fail,
+    /// Print the error, run cleanup actions and exit immediately.
+    Abort,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnClose {
+    /// Wait for the process to exit by itself.
+    #[default]
+    Wait,
+    /// Kill the process if it is still running.
+    Kill,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackgroundOptions<'a> {
+    /// Used in messages and for `Executor::background_process`. Defaults to
+    /// the name of the program.
+    pub name: Option<&'a str>,
+    /// Where stdout and stderr are written. Defaults to a new file in the
+    /// temporary directory, which is removed when `Tty` closes, unless the
+    /// process failed (so it can be inspected). A given file is left as is.
+    pub log_file: Option<PathBuf>,
+    /// Also print stderr to the terminal.
+    pub stream_errors: bool,
+    /// What to do if the process fails (exits with an error or is killed by a
+    /// signal not sent through `BackgroundProcess::kill`) before `Tty` closes.
+    pub on_early_exit: EarlyExitPolicy,
+    pub on_close: OnClose,
+}
+
+impl Default for BackgroundOptions<'_> {
+    fn default() -> Self {
+        BackgroundOptions {
+            name: None,
+            log_file: None,
+            stream_errors: true,
+            on_early_exit: EarlyExitPolicy::default(),
+            on_close: OnClose::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ReadinessProbe {
+    /// A line containing this text appears in the log.
+    LogContains(String),
+    /// A line matching this pattern appears in the log.
+    LogMatches(Regex),
+    /// Something accepts TCP connections on this port on localhost.
+    Port(u16),
+    /// This file (or directory) exists.
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackgroundStatus {
+    Running,
+    Exited(ExitStatus),
+    /// Waiting for the process failed, so its status is unknown.
+    Lost,
+}
+
+#[derive(Debug, Clone)]
+enum State {
+    Running,
+    Exited(ExitStatus),
+    Lost(String),
+}
+
+/// Handle to a process started with `Executor::execute_background`. Clones
+/// refer to the same process.
+#[derive(Debug, Clone)]
+pub struct BackgroundProcess {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    name: String,
+    pid: Option<u32>,
+    log_path: PathBuf,
+    /// The log is a default one, rather than given in the options.
+    owns_log: bool,
+    on_early_exit: EarlyExitPolicy,
+    on_close: OnClose,
+    state: watch::Receiver<State>,
+    kill_requested: Notify,
+    killed: AtomicBool,
+    closing: AtomicBool,
+}
+
+impl BackgroundProcess {
+    pub(crate) fn spawn(
+        mut command: tokio::process::Command,
+        program: &str,
+        options: BackgroundOptions<'_>,
+    ) -> Result<Self, CliError> {
+        let (name, log_path) = name_and_log_path(program, &options)?;
+        let log = Arc::new(Mutex::new(
+            File::create(&log_path).map_err(|e| IOError::with_debug(&e))?,
+        ));
+
+        let mut child = command
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .map_err(|e| super::TtyExecuteError::with_debug(&e))?;
+        let mut pumps = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            pumps.push(tokio::spawn(pump_output(stdout, log.clone(), false)));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            pumps.push(tokio::spawn(pump_output(
+                stderr,
+                log,
+                options.stream_errors,
+            )));
+        }
+
+        let (state_tx, state_rx) = watch::channel(State::Running);
+        let process = BackgroundProcess {
+            inner: Arc::new(Inner {
+                name,
+                pid: child.id(),
+                owns_log: options.log_file.is_none(),
+                log_path,
+                on_early_exit: options.on_early_exit,
+                on_close: options.on_close,
+                state: state_rx,
+                kill_requested: Notify::new(),
+                killed: AtomicBool::new(false),
+                closing: AtomicBool::new(false),
+            }),
+        };
+
+        let monitor = process.clone();
+        tokio::spawn(async move {
+            let result = tokio::select! {
+                result = child.wait() => result,
+                _ = monitor.inner.kill_requested.notified() => {
+                    let _ = child.start_kill();
+                    child.wait().await
+                }
+            };
+            // Let the last output reach the log, but don't wait on pipes that
+            // were inherited by daemonized children.
+            let _ = tokio::time::timeout(Duration::from_secs(1), async {
+                for pump in pumps {
+                    let _ = pump.await;
+                }
+            })
+            .await;
+            let state = match result {
+                Ok(status) => State::Exited(status),
+                Err(e) => State::Lost(e.to_string()),
+            };
+            let _ = state_tx.send(state);
+            monitor.handle_early_exit();
+        });
+
+        Ok(process)
+    }
+
+    /// A process that has already exited with 'output', used by
+    /// `ScriptedRunner`.
+    pub(crate) fn exited(
+        program: &str,
+        options: BackgroundOptions<'_>,
+        output: &str,
+        status: ExitStatus,
+    ) -> Result<Self, CliError> {
+        let (name, log_path) = name_and_log_path(program, &options)?;
+        std::fs::write(&log_path, output).map_err(|e| IOError::with_debug(&e))?;
+        let (_, state_rx) = watch::channel(State::Exited(status));
+        Ok(BackgroundProcess {
+            inner: Arc::new(Inner {
+                name,
+                pid: None,
+                owns_log: options.log_file.is_none(),
+                log_path,
+                on_early_exit: options.on_early_exit,
+                on_close: options.on_close,
+                state: state_rx,
+                kill_requested: Notify::new(),
+                killed: AtomicBool::new(false),
+                closing: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        self.inner.pid
+    }
+
+    pub fn log_path(&self) -> &Path {
+        &self.inner.log_path
+    }
+
+    pub fn status(&self) -> BackgroundStatus {
+        match &*self.inner.state.borrow() {
+            State::Running => BackgroundStatus::Running,
+            State::Exited(status) => BackgroundStatus::Exited(*status),
+            State::Lost(_) => BackgroundStatus::Lost,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.status() == BackgroundStatus::Running
+    }
+
+    /// Waits for the process to exit, and returns its exit status (whether or
+    /// not it succeeded).
+    pub async fn wait(&self) -> Result<ExitStatus, CliError> {
+        let mut state = self.inner.state.clone();
+        let result = state
+            .wait_for(|s| !matches!(s, State::Running))
+            .await
+            .map(|s| s.clone());
+        match result {
+            Ok(State::Exited(status)) => Ok(status),
+            Ok(State::Lost(details)) => Err(BackgroundProcessLost::new(&self.inner.name, &details)),
+            _ => Err(BackgroundProcessLost::new(
+                &self.inner.name,
+                "monitor stopped",
+            )),
+        }
+    }
+
+    /// Kills the process (SIGKILL on Unix), and waits for it to exit. Exiting
+    /// this way is never reported as a failure.
+    pub async fn kill(&self) -> Result<ExitStatus, CliError> {
+        if self.is_running() {
+            self.inner.killed.store(true, Ordering::SeqCst);
+            self.inner.kill_requested.notify_one();
+        }
+        self.wait().await
+    }
+
+    /// Returns the last 'lines' lines of output (stdout and stderr).
+    pub fn tail_log(&self, lines: usize) -> Result<String, CliError> {
+        let content = std::fs::read(&self.inner.log_path).map_err(|e| IOError::with_debug(&e))?;
+        let content = String::from_utf8_lossy(&content);
+        let mut tail = content.lines().rev().take(lines).collect::<Vec<_>>();
+        tail.reverse();
+        Ok(tail.join("\n"))
+    }
+
+    /// Polls 'probe' until it succeeds. Fails early if the process exits with
+    /// an error. Processes that exit successfully keep being probed, since
+    /// some daemonize (ex. sshfs).
+    pub async fn wait_ready(
+        &self,
+        probe: &ReadinessProbe,
+        timeout: Duration,
+    ) -> Result<(), CliError> {
+        let start = Instant::now();
+        loop {
+            if self.probe(probe).await {
+                return Ok(());
+            }
+            if let Some(e) = self.failure() {
+                return Err(e);
+            }
+            if start.elapsed() > timeout {
+                return Err(BackgroundProcessNotReady::new(
+                    &self.inner.name,
+                    timeout.as_secs(),
+                    &self.inner.log_path.to_string_lossy(),
+                    &self.tail_log(ERROR_LOG_LINES).unwrap_or_default(),
+                ));
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    }
+
+    async fn probe(&self, probe: &ReadinessProbe) -> bool {
+        match probe {
+            ReadinessProbe::LogContains(text) => self.log_has_line(|l| l.contains(text.as_str())),
+            ReadinessProbe::LogMatches(pattern) => self.log_has_line(|l| pattern.is_match(l)),
+            ReadinessProbe::Port(port) => tokio::net::TcpStream::connect(("127.0.0.1", *port))
+                .await
+                .is_ok(),
+            ReadinessProbe::File(path) => path.exists(),
+        }
+    }
+
+    fn log_has_line<F: Fn(&str) -> bool>(&self, predicate: F) -> bool {
+        std::fs::read(&self.inner.log_path)
+            .map(|content| String::from_utf8_lossy(&content).lines().any(predicate))
+            .unwrap_or(false)
+    }
+
+    /// The error to report, if the process failed (and wasn't killed through
+    /// this handle).
+    fn failure(&self) -> Option<CliError> {
+        match &*self.inner.state.borrow() {
+            State::Running => None,
+            State::Exited(status) if status.success() => None,
+            State::Exited(_) if self.inner.killed.load(Ordering::SeqCst) => None,
+            State::Exited(status) => Some(BackgroundProcessExited::new(
+                *status,
+                &self.inner.name,
+                &self.inner.log_path.to_string_lossy(),
+                &self.tail_log(ERROR_LOG_LINES).unwrap_or_default(),
+            )),
+            State::Lost(details) => Some(BackgroundProcessLost::new(&self.inner.name, details)),
+        }
+    }
+
+    /// Called by the monitor task once the process exits.
+    fn handle_early_exit(&self) {
+        if self.inner.closing.load(Ordering::SeqCst) {
+            return;
+        }
+        let Some(e) = self.failure() else {
+            return;
+        };
+        let printer = Printer::new();
+        match self.inner.on_early_exit {
+            EarlyExitPolicy::Ignore | EarlyExitPolicy::Fail => {}
+            EarlyExitPolicy::Warn => printer.warn(e.message()),
+            EarlyExitPolicy::Abort => {
+                eprintln!("{e}");
+                if let Some(registry) = CleanupRegistry::current() {
+                    let _ = registry.run_all(&printer);
+                }
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// Returns an error if the process failed and its policy is to fail the
+    /// script.
+    pub(crate) fn check(&self) -> Result<(), CliError> {
+        match (self.inner.on_early_exit, self.failure()) {
+            (EarlyExitPolicy::Fail | EarlyExitPolicy::Abort, Some(e)) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) async fn resolve(&self, printer: &Printer) -> Result<(), CliError> {
+        self.inner.closing.store(true, Ordering::SeqCst);
+        if self.is_running() {
+            match self.inner.on_close {
+                OnClose::Wait => {
+                    printer.info(&format!(
+                        "Waiting for background process '{}' to finish...",
+                        self.inner.name
+                    ));
+                    self.wait().await?;
+                }
+                OnClose::Kill => {
+                    self.kill().await?;
+                }
+            }
+        }
+        let failure = self.failure();
+        if failure.is_none() && self.inner.owns_log {
+            // Left pending, so it runs with the other cleanup actions.
+            let _ = register_cleanup(CleanupAction::Remove {
+                path: self.inner.log_path.clone(),
+            });
+        }
+        match (self.inner.on_early_exit, failure) {
+            (EarlyExitPolicy::Ignore, _) | (_, None) => Ok(()),
+            (EarlyExitPolicy::Warn, Some(e)) => {
+                printer.warn(e.message());
+                Ok(())
+            }
+            (EarlyExitPolicy::Fail | EarlyExitPolicy::Abort, Some(e)) => Err(e),
+        }
+    }
+}
+
+fn name_and_log_path(
+    program: &str,
+    options: &BackgroundOptions<'_>,
+) -> Result<(String, PathBuf), CliError> {
+    let name = options.name.map(String::from).unwrap_or_else(|| {
+        Path::new(program)
+            .file_name()
+            .map_or(program.to_string(), |n| n.to_string_lossy().to_string())
+    });
+    let log_path = match &options.log_file {
+        Some(path) => path.clone(),
+        None => {
+            let dir = std::env::temp_dir().join("background-logs");
+            mkdir_p(&dir)?;
+            dir.join(format!("{}-{}.log", name, uuid::Uuid::new_v4()))
+        }
+    };
+    Ok((name, log_path))
+}
+
+async fn pump_output<R: AsyncRead + Unpin>(mut reader: R, log: Arc<Mutex<File>>, echo: bool) {
+    let mut buffer = [0; 4096];
+    loop {
+        match reader.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                let _ = log.lock().unwrap().write_all(&buffer[..n]);
+                if echo {
+                    eprint!("{}", String::from_utf8_lossy(&buffer[..n]));
+                    let _ = io::stderr().flush();
+                }
+            }
+        }
+    }
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    #[tokio::test]
+    async fn readiness_log_tail_and_kill() {
+        let process = BackgroundProcess::spawn(
+            sh("echo starting; echo listening on 8080; sleep 30"),
+            "sh",
+            BackgroundOptions {
+                name: Some("server"),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        process
+            .wait_ready(
+                &ReadinessProbe::LogMatches(Regex::new(r"listening on \d+").unwrap()),
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap();
+        assert!(process.is_running());
+        assert_eq!(process.tail_log(1).unwrap(), "listening on 8080");
+
+        process.kill().await.unwrap();
+        assert!(!process.is_running());
+        assert!(process.check().is_ok());
+    }
+
+    #[tokio::test]
+    async fn failure_is_reported_with_log_output() {
+        let process = BackgroundProcess::spawn(
+            sh("echo 'bind: address already in use' >&2; exit 3"),
+            "sh",
+            BackgroundOptions {
+                stream_errors: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let e = process
+            .wait_ready(&ReadinessProbe::Port(1), Duration::from_secs(10))
+            .await
+            .unwrap_err();
+        assert!(e.message().contains("address already in use"));
+        assert!(process.check().is_err());
+    }
+}
@@ -1,15 +1,15 @@
 use std::{
-    fs,
-    io::{self, Read as _, Write as _},
-    path::Path,
+    fmt, fs,
+    path::{Path, PathBuf},
     process::ExitStatus,
+    sync::Arc,
 };
 
-use tokio::io::AsyncReadExt as _;
+use tokio::io::AsyncRead;
 
-use crate::{define_cli_error, CliError, IOError};
+use crate::{define_cli_error, CliError, CriticalError, IOError};
 
-use super::Printer;
+use super::{BackgroundOptions, BackgroundProcess, CommandRunner, LocalRunner, Printer};
 
 define_cli_error!(TtyExecuteError, "Failed to execute command.");
 define_cli_error!(
@@ -17,11 +17,6 @@ define_cli_error!(
     "[{exit_status}] Command failed.\n{output}",
     { exit_status: ExitStatus, output: &str }
 );
-define_cli_error!(
-    TtyBackgroundCommandFailed,
-    "[{exit_status}] Background command failed.",
-    { exit_status: ExitStatus }
-);
 define_cli_error!(
     TtyRequiredCommandMissing,
     "Required command not found: {command}.",
@@ -46,16 +41,62 @@ pub enum IOMode {
 pub struct ExecuteOptions<'a> {
     pub dir: Option<&'a Path>,
     pub env: Option<Vec<(String, String)>>,
+    /// Input for the command. If not set, stdin is inherited in Attach mode,
+    /// and empty otherwise.
+    pub stdin: Option<StdinSource<'a>>,
+    /// Limits applied to the command (and inherited by its children), like
+    /// running 'ulimit' before it in a shell. Ignored on non-Unix platforms.
+    pub rlimits: Vec<ResourceLimit>,
+}
+
+pub enum StdinSource<'a> {
+    Bytes(Vec<u8>),
+    /// Only supported by the async API.
+    Stream(Box<dyn AsyncRead + Send + Unpin + 'a>),
+    /// Connected directly to the command, without going through this process.
+    File(PathBuf),
+}
+
+impl fmt::Debug for StdinSource<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StdinSource::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            StdinSource::Stream(_) => write!(f, "Stream"),
+            StdinSource::File(path) => f.debug_tuple("File").field(path).finish(),
+        }
+    }
+}
+
+/// Sets the soft limit, capped at the current hard limit (which can't be
+/// raised without root).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceLimit {
+    /// Max number of open file descriptors ('ulimit -n').
+    OpenFiles(u64),
+    /// Max number of processes for the user ('ulimit -u').
+    Processes(u64),
+    /// Max stack size in bytes ('ulimit -s', which is in KiB).
+    StackSize(u64),
+    /// Max core dump size in bytes ('ulimit -c', which is in blocks).
+    CoreFileSize(u64),
 }
 
 #[derive(Debug)]
 pub struct Executor {
-    background_processes: Vec<tokio::process::Child>,
+    runner: Arc<dyn CommandRunner>,
+    background_processes: Vec<BackgroundProcess>,
 }
 
 impl Executor {
     pub fn new() -> Self {
+        Self::with_runner(LocalRunner)
+    }
+
+    /// Runs all commands through 'runner' instead of as local processes (ex.
+    /// a `ScriptedRunner` in tests).
+    pub fn with_runner<R: CommandRunner + 'static>(runner: R) -> Self {
         Executor {
+            runner: Arc::new(runner),
             background_processes: Vec::new(),
         }
     }
@@ -65,28 +106,7 @@ impl Executor {
         if program.is_empty() {
             return false;
         }
-
-        #[cfg(windows)]
-        {
-            tokio::process::Command::new("cmd")
-                .args(["/C", "where", "/Q"])
-                .arg(program)
-                .status()
-                .await
-                .map(|status| status.success())
-                .unwrap_or(false)
-        }
-
-        #[cfg(not(windows))]
-        {
-            tokio::process::Command::new("sh")
-                .args(["-c", "command -v -- \"$1\" >/dev/null 2>&1", "sh"])
-                .arg(program)
-                .status()
-                .await
-                .map(|status| status.success())
-                .unwrap_or(false)
-        }
+        self.runner.has_command(program).await
     }
 
     pub async fn require_command(&self, program: &str) -> Result<(), CliError> {
@@ -116,81 +136,44 @@ impl Executor {
         io_mode: IOMode,
         options: ExecuteOptions<'_>,
     ) -> Result<String, CliError> {
-        let abs_dir = match options.dir {
-            Some(p) => fs::canonicalize(p).map_err(|e| IOError::with_debug(&e))?,
-            None => std::env::current_dir().map_err(|e| IOError::with_debug(&e))?,
-        };
-        let mut child = tokio::process::Command::new(command)
-            .args(args)
-            .current_dir(abs_dir)
-            .envs(options.env.unwrap_or_default())
-            .stdin(match io_mode {
-                IOMode::Attach => std::process::Stdio::inherit(),
-                IOMode::StreamOutput | IOMode::Silent | IOMode::Mute => std::process::Stdio::null(),
-            })
-            .stdout(match io_mode {
-                IOMode::Attach => std::process::Stdio::inherit(),
-                IOMode::StreamOutput | IOMode::Silent | IOMode::Mute => {
-                    std::process::Stdio::piped()
-                }
-            })
-            .stderr(match io_mode {
-                IOMode::Attach => std::process::Stdio::inherit(),
-                IOMode::StreamOutput | IOMode::Silent | IOMode::Mute => {
-                    std::process::Stdio::piped()
-                }
-            })
-            .spawn()
-            .map_err(|e| TtyExecuteError::with_debug(&e))?;
-
-        let mut collected_output = String::new();
-
-        if io_mode != IOMode::Attach {
-            if let Some(mut stdout) = child.stdout.take() {
-                let mut buffer = [0; 1024];
-                loop {
-                    match stdout.read(&mut buffer).await {
-                        Ok(0) => break, // EOF reached
-                        Ok(n) => {
-                            let output = String::from_utf8_lossy(&buffer[..n]);
-                            if io_mode == IOMode::StreamOutput {
-                                print!("{}", output);
-                                io::stdout().flush().unwrap();
-                            }
-                            collected_output.push_str(&output);
-                        }
-                        Err(_) => break,
-                    }
-                }
-            }
+        self.execute_pipeline(&[(command, args)], io_mode, options)
+            .await
+    }
 
-            if let Some(mut stderr) = child.stderr.take() {
-                let mut buffer = [0; 1024];
-                loop {
-                    match stderr.read(&mut buffer).await {
-                        Ok(0) => break, // EOF reached
-                        Ok(n) => {
-                            let output = String::from_utf8_lossy(&buffer[..n]);
-                            if io_mode != IOMode::Mute {
-                                eprint!("{}", output);
-                                io::stderr().flush().unwrap();
-                            }
-                            collected_output.push_str(&output);
-                        }
-                        Err(_) => break,
-                    }
-                }
-            }
+    /// Runs 'commands' connected like a shell pipeline ('cmd1 | cmd2'), without
+    /// going through a shell. 'options' apply to every command, except 'stdin',
+    /// which is passed to the first one. The output (and in Attach mode, the
+    /// terminal) belongs to the last command.
+    ///
+    /// Like with 'set -o pipefail', the pipeline fails if any command fails,
+    /// reporting the status of the last one that did.
+    #[track_caller]
+    pub async fn execute_pipeline(
+        &self,
+        commands: &[(&str, &[&str])],
+        io_mode: IOMode,
+        options: ExecuteOptions<'_>,
+    ) -> Result<String, CliError> {
+        if commands.is_empty() {
+            return Err(CriticalError::new("pipeline has no commands"));
         }
-
-        let status = child
-            .wait()
+        let abs_dir = self.absolute_dir(options.dir)?;
+        let result = self
+            .runner
+            .run(
+                commands,
+                io_mode,
+                ExecuteOptions {
+                    dir: abs_dir.as_deref().or(options.dir),
+                    ..options
+                },
+            )
             .await
             .map_err(|e| TtyExecuteError::with_debug(&e))?;
-        if status.success() {
-            Ok(collected_output.trim().to_string())
+        if result.status.success() {
+            Ok(result.output.trim().to_string())
         } else {
-            Err(TtyCommandFailed::new(status, &collected_output))
+            Err(TtyCommandFailed::new(result.status, &result.output))
         }
     }
 
@@ -212,124 +195,116 @@ impl Executor {
         io_mode: IOMode,
         options: ExecuteOptions<'_>,
     ) -> Result<String, CliError> {
-        let abs_dir = match options.dir {
-            Some(p) => fs::canonicalize(p).map_err(|e| IOError::with_debug(&e))?,
-            None => std::env::current_dir().map_err(|e| IOError::with_debug(&e))?,
-        };
-        let mut child = std::process::Command::new(command)
-            .args(args)
-            .current_dir(abs_dir)
-            .envs(options.env.unwrap_or_default())
-            .stdin(match io_mode {
-                IOMode::Attach => std::process::Stdio::inherit(),
-                IOMode::StreamOutput | IOMode::Silent | IOMode::Mute => std::process::Stdio::null(),
-            })
-            .stdout(match io_mode {
-                IOMode::Attach => std::process::Stdio::inherit(),
-                IOMode::StreamOutput | IOMode::Silent | IOMode::Mute => {
-                    std::process::Stdio::piped()
-                }
-            })
-            .stderr(match io_mode {
-                IOMode::Attach => std::process::Stdio::inherit(),
-                IOMode::StreamOutput | IOMode::Silent | IOMode::Mute => {
-                    std::process::Stdio::piped()
-                }
-            })
-            .spawn()
+        let abs_dir = self.absolute_dir(options.dir)?;
+        let result = self
+            .runner
+            .run_sync(
+                command,
+                args,
+                io_mode,
+                ExecuteOptions {
+                    dir: abs_dir.as_deref().or(options.dir),
+                    ..options
+                },
+            )
             .map_err(|e| TtyExecuteError::with_debug(&e))?;
-
-        let mut collected_output = String::new();
-
-        if io_mode != IOMode::Attach {
-            if let Some(mut stdout) = child.stdout.take() {
-                let mut buffer = [0; 1024];
-                loop {
-                    match stdout.read(&mut buffer) {
-                        Ok(0) => break, // EOF reached
-                        Ok(n) => {
-                            let output = String::from_utf8_lossy(&buffer[..n]);
-                            if io_mode == IOMode::StreamOutput {
-                                print!("{}", output);
-                                io::stdout().flush().unwrap();
-                            }
-                            collected_output.push_str(&output);
-                        }
-                        Err(_) => break,
-                    }
-                }
-            }
-
-            if let Some(mut stderr) = child.stderr.take() {
-                let mut buffer = [0; 1024];
-                loop {
-                    match stderr.read(&mut buffer) {
-                        Ok(0) => break, // EOF reached
-                        Ok(n) => {
-                            let output = String::from_utf8_lossy(&buffer[..n]);
-                            if io_mode != IOMode::Mute {
-                                eprint!("{}", output);
-                                io::stderr().flush().unwrap();
-                            }
-                            collected_output.push_str(&output);
-                        }
-                        Err(_) => break,
-                    }
-                }
-            }
-        }
-
-        let status = child.wait().map_err(|e| TtyExecuteError::with_debug(&e))?;
-        if status.success() {
-            Ok(collected_output.trim().to_string())
+        if result.status.success() {
+            Ok(result.output.trim().to_string())
         } else {
-            Err(TtyCommandFailed::new(status, &collected_output))
+            Err(TtyCommandFailed::new(result.status, &result.output))
         }
     }
 
+    /// Starts a process without waiting for it. Its output is written to a
+    /// log file (see `BackgroundProcess`), and it is resolved when `Tty`
+    /// closes.
     pub async fn execute_background(
         &mut self,
         command: &str,
         args: &[&str],
         dir: Option<&str>,
-    ) -> Result<(), CliError> {
-        let abs_dir = fs::canonicalize(dir.unwrap_or(".")).map_err(|e| IOError::with_debug(&e))?;
-        self.background_processes.push(
-            tokio::process::Command::new(command)
-                .args(args)
-                .current_dir(abs_dir)
-                .stdout(std::process::Stdio::null())
-                .spawn()
-                .map_err(|e| TtyExecuteError::with_debug(&e))?,
-        );
-        Ok(())
+    ) -> Result<BackgroundProcess, CliError> {
+        self.execute_background_with_options(
+            command,
+            args,
+            ExecuteOptions {
+                dir: dir.map(Path::new),
+                ..Default::default()
+            },
+            BackgroundOptions::default(),
+        )
+        .await
+    }
+
+    pub async fn execute_background_with_options(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        options: ExecuteOptions<'_>,
+        background_options: BackgroundOptions<'_>,
+    ) -> Result<BackgroundProcess, CliError> {
+        let abs_dir = self.absolute_dir(options.dir)?;
+        let process = self.runner.spawn_background(
+            command,
+            args,
+            ExecuteOptions {
+                dir: abs_dir.as_deref().or(options.dir),
+                ..options
+            },
+            background_options,
+        )?;
+        self.background_processes.push(process.clone());
+        Ok(process)
+    }
+
+    /// The most recently started background process with this name.
+    pub fn background_process(&self, name: &str) -> Option<&BackgroundProcess> {
+        self.background_processes
+            .iter()
+            .rev()
+            .find(|p| p.name() == name)
     }
 
+    /// Returns an error if a background process has already failed, and its
+    /// `EarlyExitPolicy` is to fail the script. Useful to fail fast between
+    /// long steps, rather than when `Tty` closes.
+    pub fn check_background_processes(&self) -> Result<(), CliError> {
+        self.background_processes
+            .iter()
+            .try_for_each(BackgroundProcess::check)
+    }
+
+    /// Waits for (or kills, depending on `OnClose`) all background processes.
+    /// All processes are resolved even if one fails, and the first failure is
+    /// returned.
     pub(crate) async fn resolve_background_processes(
         &mut self,
         printer: &Printer,
     ) -> Result<(), CliError> {
-        let processes = self.background_processes.drain(..).collect::<Vec<_>>();
-        for mut process in processes {
-            let current_result = process.try_wait();
-            match current_result {
-                Ok(Some(_)) => {}
-                Ok(None) => {
-                    printer.info("Waiting for background process to finish...");
-                }
-                Err(e) => printer.error(&e.to_string()),
-            }
-            match process.wait().await {
-                // Normally we should check 'status.success()', but it seems
-                // that sometimes background processes end because of a signal.
-                // For now, ignore this and only show an error if it returned
-                // with non-zero exit code.
-                Ok(status) if status.code().unwrap_or_default() == 0 => {}
-                Ok(status) => return Err(TtyBackgroundCommandFailed::new(status)),
-                Err(e) => return Err(TtyExecuteError::with_debug(&e)),
+        let mut result = Ok(());
+        for process in self.background_processes.drain(..) {
+            let process_result = process.resolve(printer).await;
+            if result.is_ok() {
+                result = process_result;
             }
         }
-        Ok(())
+        result
+    }
+
+    /// Local working directories are resolved to absolute paths. Remote
+    /// runners get the directory as is.
+    fn absolute_dir(&self, dir: Option<&Path>) -> Result<Option<PathBuf>, CliError> {
+        if !self.runner.is_local() {
+            return Ok(None);
+        }
+        match dir {
+            Some(p) => fs::canonicalize(p)
+                .map(Some)
+                .map_err(|e| IOError::with_debug(&e)),
+            None => std::env::current_dir()
+                .map(Some)
+                .map_err(|e| IOError::with_debug(&e)),
+        }
     }
 
     pub(crate) async fn sudo_is_cached(&self) -> bool {
@@ -344,3 +319,100 @@ impl Executor {
         Ok(())
     }
 }
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stdin_is_piped_through_a_pipeline() {
+        let ex = Executor::new();
+        let output = ex
+            .execute_pipeline(
+                &[("cat", &[]), ("grep", &["-v", "skip"]), ("wc", &["-l"])],
+                IOMode::Mute,
+                ExecuteOptions {
+                    stdin: Some(StdinSource::Bytes(b"one\nskip\ntwo\n".to_vec())),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(output, "2");
+
+        // Fails like 'set -o pipefail', even though the last command succeeds.
+        assert!(ex
+            .execute_pipeline(
+                &[("false", &[]), ("cat", &[])],
+                IOMode::Mute,
+                Default::default()
+            )
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn pipeline_stops_started_commands_if_a_later_one_fails_to_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("marker");
+        let script = format!("sleep 1; touch '{}'", marker.display());
+        let ex = Executor::new();
+        assert!(ex
+            .execute_pipeline(
+                &[("sh", &["-c", &script]), ("no-such-program-xyz", &[])],
+                IOMode::Mute,
+                Default::default()
+            )
+            .await
+            .is_err());
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+        assert!(!marker.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn rlimits_apply_to_the_command() {
+        let ex = Executor::new();
+        let output = ex
+            .execute_with_options(
+                "sh",
+                &["-c", "ulimit -Sn"],
+                IOMode::Mute,
+                ExecuteOptions {
+                    rlimits: vec![ResourceLimit::OpenFiles(256)],
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(output, "256");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn rlimits_are_capped_at_the_hard_limit() {
+        use nix::sys::resource::{getrlimit, Resource, RLIM_INFINITY};
+
+        let (_, hard) = getrlimit(Resource::RLIMIT_NOFILE).unwrap();
+        if hard == RLIM_INFINITY {
+            return;
+        }
+        let ex = Executor::new();
+        let output = ex
+            .execute_with_options(
+                "sh",
+                &["-c", "ulimit -Sn"],
+                IOMode::Mute,
+                ExecuteOptions {
+                    rlimits: vec![ResourceLimit::OpenFiles(hard + 1)],
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(output, hard.to_string());
+    }
+}
@@ -1,9 +1,21 @@
+mod background;
 mod executor;
+mod preflight;
 mod printer;
+mod remote_runner;
+mod runner;
+mod script;
+mod scripted_runner;
 mod tty;
 mod user_preferences;
 
+pub use background::*;
 pub use executor::*;
+pub use preflight::*;
 pub use printer::*;
+pub use remote_runner::*;
+pub use runner::*;
+pub use script::*;
+pub use scripted_runner::*;
 pub use tty::*;
 pub use user_preferences::*;
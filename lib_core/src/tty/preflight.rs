@@ -0,0 +1,334 @@
+use std::fmt;
+
+use regex::Regex;
+use semver::{Version, VersionReq};
+
+use crate::{define_cli_error, CliError};
+
+use super::{Executor, IOMode, Printer};
+
+define_cli_error!(
+    InvalidVersionConstraint,
+    "Invalid version constraint '{constraint}' for '{tool}'.",
+    { tool: &str, constraint: &str }
+);
+define_cli_error!(
+    ToolchainPreflightFailed,
+    "Required tools are missing or outdated:\n{problems}",
+    { problems: &str }
+);
+
+/// How to find the installed version of a tool: the first match of 'pattern'
+/// (or of its first capture group, if it has one) in the output of the
+/// command. Versions like '3.19' or 'v20.1.0' are accepted.
+#[derive(Debug, Clone)]
+pub struct VersionDetection {
+    pub program: String,
+    pub args: Vec<String>,
+    pub pattern: Regex,
+}
+
+impl VersionDetection {
+    pub fn new(program: &str, args: &[&str], pattern: &str) -> Self {
+        VersionDetection {
+            program: program.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            pattern: Regex::new(pattern).expect("version pattern should be valid"),
+        }
+    }
+
+    fn version_in(&self, output: &str) -> Option<Version> {
+        let captures = self.pattern.captures(output)?;
+        let matched = captures.get(1).or_else(|| captures.get(0))?.as_str();
+        parse_lenient_version(matched)
+    }
+}
+
+/// A tool that must be installed, optionally in a range of versions.
+///
+/// Tools like 'flutter', 'sam', 'cargo-lambda', 'adb' and 'docker' have
+/// built-in detection rules. For other tools, the first version-like string
+/// in the output of '<tool> --version' is used, unless set with
+/// `detect_with`.
+#[derive(Debug, Clone)]
+pub struct ToolRequirement {
+    pub name: String,
+    /// Semver constraint, like '>=3.19' or '^1.100'.
+    pub version: Option<String>,
+    /// Printed when the tool is missing or outdated (ex. how to install it).
+    pub install_hint: Option<String>,
+    pub detection: VersionDetection,
+}
+
+impl ToolRequirement {
+    pub fn new(name: &str) -> Self {
+        ToolRequirement {
+            name: name.to_string(),
+            version: None,
+            install_hint: None,
+            detection: known_detection(name).unwrap_or_else(|| {
+                VersionDetection::new(name, &["--version"], r"\d+\.\d+(?:\.\d+)?")
+            }),
+        }
+    }
+
+    pub fn version(mut self, constraint: &str) -> Self {
+        self.version = Some(constraint.to_string());
+        self
+    }
+
+    pub fn install_hint(mut self, hint: &str) -> Self {
+        self.install_hint = Some(hint.to_string());
+        self
+    }
+
+    pub fn detect_with(mut self, detection: VersionDetection) -> Self {
+        self.detection = detection;
+        self
+    }
+}
+
+fn known_detection(name: &str) -> Option<VersionDetection> {
+    Some(match name {
+        // Flutter 3.19.6 • channel stable • ...
+        "flutter" => VersionDetection::new("flutter", &["--version"], r"Flutter (\d+\.\d+\.\d+)"),
+        // SAM CLI, version 1.115.0
+        "sam" => VersionDetection::new("sam", &["--version"], r"version (\d+\.\d+\.\d+)"),
+        // cargo-lambda 1.2.1 (12f9b61 2024-04-05Z)
+        "cargo-lambda" => VersionDetection::new(
+            "cargo",
+            &["lambda", "--version"],
+            r"cargo-lambda (\d+\.\d+\.\d+)",
+        ),
+        // Android Debug Bridge version 1.0.41
+        // Version 34.0.5-10900879
+        //
+        // The second line is the platform-tools version, which is what
+        // usually matters.
+        "adb" => VersionDetection::new("adb", &["--version"], r"(?m)^Version (\d+\.\d+\.\d+)"),
+        // Docker version 24.0.7, build afdd53b
+        "docker" => VersionDetection::new(
+            "docker",
+            &["--version"],
+            r"Docker version (\d+\.\d+(?:\.\d+)?)",
+        ),
+        // git version 2.43.0
+        "git" => VersionDetection::new("git", &["--version"], r"git version (\d+\.\d+\.\d+)"),
+        // aws-cli/2.15.0 Python/3.11.6 ...
+        "aws" => VersionDetection::new("aws", &["--version"], r"aws-cli/(\d+\.\d+\.\d+)"),
+        // v20.11.0
+        "node" => VersionDetection::new("node", &["--version"], r"v(\d+\.\d+\.\d+)"),
+        // rustc 1.79.0 (129f3b996 2024-06-10)
+        "rustc" | "cargo" => {
+            VersionDetection::new(name, &["--version"], &format!(r"{} (\d+\.\d+\.\d+)", name))
+        }
+        _ => return None,
+    })
+}
+
+/// Fills in missing minor and patch components, and ignores a 'v' prefix.
+fn parse_lenient_version(s: &str) -> Option<Version> {
+    let s = s.trim().trim_start_matches('v');
+    let mut parts = s.splitn(3, '.').collect::<Vec<_>>();
+    while parts.len() < 3 {
+        parts.push("0");
+    }
+    Version::parse(&parts.join(".")).ok()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolStatus {
+    /// 'version' is None if there is no constraint, and the version could not
+    /// be detected.
+    Ok {
+        version: Option<Version>,
+    },
+    Missing,
+    Outdated {
+        version: Version,
+    },
+    /// The tool is installed, but its version could not be detected, so the
+    /// constraint could not be checked. Reported as a warning only.
+    UnknownVersion,
+}
+
+#[derive(Debug, Clone)]
+pub struct ToolCheck {
+    pub requirement: ToolRequirement,
+    pub status: ToolStatus,
+}
+
+impl ToolCheck {
+    pub fn is_problem(&self) -> bool {
+        matches!(
+            self.status,
+            ToolStatus::Missing | ToolStatus::Outdated { .. }
+        )
+    }
+}
+
+impl fmt::Display for ToolCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = &self.requirement.name;
+        let constraint = self.requirement.version.as_deref().unwrap_or("*");
+        match &self.status {
+            ToolStatus::Ok {
+                version: Some(version),
+            } => write!(f, "{} {}", name, version)?,
+            ToolStatus::Ok { version: None } => write!(f, "{}", name)?,
+            ToolStatus::Missing => write!(f, "{}: not installed (requires {})", name, constraint)?,
+            ToolStatus::Outdated { version } => {
+                write!(f, "{} {}: requires {}", name, version, constraint)?
+            }
+            ToolStatus::UnknownVersion => write!(
+                f,
+                "{}: could not detect version (requires {})",
+                name, constraint
+            )?,
+        }
+        match &self.requirement.install_hint {
+            Some(hint) if self.is_problem() => write!(f, "\n    {}", hint),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ToolchainReport {
+    pub checks: Vec<ToolCheck>,
+}
+
+impl ToolchainReport {
+    pub fn problems(&self) -> impl Iterator<Item = &ToolCheck> {
+        self.checks.iter().filter(|c| c.is_problem())
+    }
+
+    pub fn print(&self, pr: &Printer) {
+        for check in &self.checks {
+            match check.status {
+                ToolStatus::Ok { .. } => pr.info(&format!("✓ {}", check)),
+                ToolStatus::UnknownVersion => pr.warn(&format!("? {}", check)),
+                ToolStatus::Missing | ToolStatus::Outdated { .. } => {
+                    pr.error(&format!("✗ {}", check))
+                }
+            }
+        }
+    }
+
+    /// Fails with a single error listing every missing or outdated tool.
+    pub fn into_result(self) -> Result<(), CliError> {
+        let problems = self
+            .problems()
+            .map(|c| format!("  {}", c))
+            .collect::<Vec<_>>();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ToolchainPreflightFailed::new(&problems.join("\n")))
+        }
+    }
+}
+
+/// Checks that all 'tools' are installed and satisfy their version
+/// constraints. Only fails if a constraint is invalid; missing or outdated
+/// tools are reported in the result (see also `Tty::preflight`).
+pub async fn check_toolchain(
+    ex: &Executor,
+    tools: &[ToolRequirement],
+) -> Result<ToolchainReport, CliError> {
+    let mut checks = Vec::with_capacity(tools.len());
+    for tool in tools {
+        let constraint = tool
+            .version
+            .as_deref()
+            .map(|c| {
+                VersionReq::parse(c)
+                    .map_err(|e| InvalidVersionConstraint::with_debug(&tool.name, c, &e))
+            })
+            .transpose()?;
+        let status = if !ex.has_command(&tool.name).await {
+            ToolStatus::Missing
+        } else {
+            let version = detect_version(ex, &tool.detection).await;
+            match (constraint, version) {
+                (None, version) => ToolStatus::Ok { version },
+                (Some(_), None) => ToolStatus::UnknownVersion,
+                (Some(req), Some(version)) if req.matches(&version) => ToolStatus::Ok {
+                    version: Some(version),
+                },
+                (Some(_), Some(version)) => ToolStatus::Outdated { version },
+            }
+        };
+        checks.push(ToolCheck {
+            requirement: tool.clone(),
+            status,
+        });
+    }
+    Ok(ToolchainReport { checks })
+}
+
+async fn detect_version(ex: &Executor, detection: &VersionDetection) -> Option<Version> {
+    let args = detection
+        .args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    // Some tools exit with an error when printing their version, so the
+    // output is taken from the error message too.
+    let output = match ex.execute(&detection.program, &args, IOMode::Mute).await {
+        Ok(output) => output,
+        Err(e) => e.message().clone(),
+    };
+    detection.version_in(&output)
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::{Expectation, ScriptedRunner};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn problems_are_reported_together() {
+        let runner = ScriptedRunner::new();
+        runner
+            .expect(
+                Expectation::new("docker", &["--version"])
+                    .output("Docker version 20.10.5, build 55c4c88"),
+            )
+            .expect(
+                Expectation::new("adb", &["--version"])
+                    .output("Android Debug Bridge version 1.0.41\nVersion 34.0.5-10900879"),
+            );
+        let ex = Executor::with_runner(runner.clone());
+
+        let report = check_toolchain(
+            &ex,
+            &[
+                ToolRequirement::new("docker").version(">=24"),
+                ToolRequirement::new("adb").version(">=34"),
+                ToolRequirement::new("sam")
+                    .version(">=1.100")
+                    .install_hint("brew install aws-sam-cli"),
+            ],
+        )
+        .await
+        .unwrap();
+        runner.assert_done();
+        assert_eq!(
+            report.checks[1].status,
+            ToolStatus::Ok {
+                version: Some(Version::new(34, 0, 5))
+            }
+        );
+        let e = report.into_result().unwrap_err();
+        assert_eq!(
+            e.message(),
+            "Required tools are missing or outdated:\n  docker 20.10.5: requires >=24\n  \
+             sam: not installed (requires >=1.100)\n    brew install aws-sam-cli"
+        );
+    }
+}
@@ -2,6 +2,7 @@ use std::{
     future::Future,
     io::{StdoutLock, Write as _},
     pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
 };
 
 use colored::{ColoredString, Colorize as _};
@@ -12,6 +13,17 @@ use crate::{continue_after_enter, yes_no, CliError};
 #[derive(Debug, Clone)]
 pub struct Printer;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only warnings, errors and results.
+    Quiet,
+    Normal,
+    /// Also prints debug messages.
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
 #[derive(Debug)]
 pub struct StatusBarPrinter {
     out: StdoutLock<'static>,
@@ -22,6 +34,19 @@ impl Printer {
         Printer
     }
 
+    /// Applies to all printers (ex. set from '--quiet' or '--verbose').
+    pub fn set_verbosity(verbosity: Verbosity) {
+        VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+    }
+
+    pub fn verbosity() -> Verbosity {
+        match VERBOSITY.load(Ordering::Relaxed) {
+            0 => Verbosity::Quiet,
+            1 => Verbosity::Normal,
+            _ => Verbosity::Verbose,
+        }
+    }
+
     pub(crate) fn section_open(&self, title: &str) {
         println!("{}", title.bold());
     }
@@ -59,8 +84,16 @@ impl Printer {
         Box::pin(async move { f(StatusBarPrinter::new()).await })
     }
 
+    pub fn debug(&self, message: &str) {
+        if Self::verbosity() >= Verbosity::Verbose {
+            println!("{}", message.dimmed());
+        }
+    }
+
     pub fn info(&self, message: &str) {
-        println!("{}", message.dimmed());
+        if Self::verbosity() >= Verbosity::Normal {
+            println!("{}", message.dimmed());
+        }
     }
 
     pub fn important(&self, message: &str) {
@@ -0,0 +1,281 @@
+use std::{
+    fmt,
+    io::{self, IsTerminal as _},
+    path::Path,
+};
+
+use crate::CliError;
+
+use super::{
+    BackgroundOptions, BackgroundProcess, CommandOutput, CommandRunner, ExecuteOptions, IOMode,
+    LocalRunner, ResourceLimit, RunnerFuture,
+};
+
+/// How a command should be set up on the target.
+#[derive(Debug, Clone, Copy)]
+pub struct LaunchOptions<'a> {
+    /// Working directory on the target. If not set, the target's default is
+    /// used (ex. the home directory over SSH).
+    pub dir: Option<&'a Path>,
+    pub env: &'a [(String, String)],
+    pub rlimits: &'a [ResourceLimit],
+    /// Whether a terminal should be allocated for the command (only set in
+    /// Attach mode, when the local stdin is a terminal).
+    pub tty: bool,
+}
+
+/// Translates a command into a local command that runs it somewhere else,
+/// like 'ssh host -- cmd' or 'docker exec container cmd'.
+pub trait CommandLauncher: fmt::Debug + Send + Sync {
+    /// Returns the local program and its args.
+    fn launch(
+        &self,
+        program: &str,
+        args: &[&str],
+        options: &LaunchOptions<'_>,
+    ) -> (String, Vec<String>);
+}
+
+/// Runs commands on a remote host or in a container, by launching each one
+/// through a local `CommandLauncher` (see `SshTarget` in lib_networking and
+/// `DockerContainer` in lib_docker). IO modes, stdin and background processes
+/// work like they do locally, and failures are reported the same way, with the
+/// remote exit status.
+///
+/// Each command of a pipeline is launched separately, and connected to the
+/// next one locally.
+#[derive(Debug, Clone)]
+pub struct RemoteRunner<L> {
+    launcher: L,
+}
+
+impl<L: CommandLauncher> RemoteRunner<L> {
+    pub fn new(launcher: L) -> Self {
+        RemoteRunner { launcher }
+    }
+
+    pub fn launcher(&self) -> &L {
+        &self.launcher
+    }
+
+    fn launch_all(
+        &self,
+        commands: &[(&str, &[&str])],
+        io_mode: IOMode,
+        options: &mut ExecuteOptions<'_>,
+    ) -> Vec<(String, Vec<String>)> {
+        let env = options.env.take().unwrap_or_default();
+        let rlimits = std::mem::take(&mut options.rlimits);
+        let launch_options = LaunchOptions {
+            dir: options.dir.take(),
+            env: &env,
+            rlimits: &rlimits,
+            tty: io_mode == IOMode::Attach
+                && commands.len() == 1
+                && options.stdin.is_none()
+                && io::stdin().is_terminal(),
+        };
+        commands
+            .iter()
+            .map(|(program, args)| self.launcher.launch(program, args, &launch_options))
+            .collect()
+    }
+}
+
+impl<L: CommandLauncher> CommandRunner for RemoteRunner<L> {
+    fn is_local(&self) -> bool {
+        false
+    }
+
+    fn has_command<'a>(&'a self, program: &'a str) -> RunnerFuture<'a, bool> {
+        Box::pin(async move {
+            let args = ["-c", "command -v -- \"$1\" >/dev/null 2>&1", "sh", program];
+            self.run(&[("sh", &args)], IOMode::Mute, ExecuteOptions::default())
+                .await
+                .map(|output| output.status.success())
+                .unwrap_or(false)
+        })
+    }
+
+    fn run<'a>(
+        &'a self,
+        commands: &'a [(&'a str, &'a [&'a str])],
+        io_mode: IOMode,
+        mut options: ExecuteOptions<'a>,
+    ) -> RunnerFuture<'a, io::Result<CommandOutput>> {
+        Box::pin(async move {
+            let launched = self.launch_all(commands, io_mode, &mut options);
+            let args = launched
+                .iter()
+                .map(|(_, args)| args.iter().map(String::as_str).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            let pipeline = launched
+                .iter()
+                .zip(&args)
+                .map(|((program, _), args)| (program.as_str(), args.as_slice()))
+                .collect::<Vec<_>>();
+            LocalRunner.run(&pipeline, io_mode, options).await
+        })
+    }
+
+    fn run_sync(
+        &self,
+        command: &str,
+        args: &[&str],
+        io_mode: IOMode,
+        mut options: ExecuteOptions<'_>,
+    ) -> io::Result<CommandOutput> {
+        let (program, args) = self
+            .launch_all(&[(command, args)], io_mode, &mut options)
+            .remove(0);
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        LocalRunner.run_sync(&program, &args, io_mode, options)
+    }
+
+    fn spawn_background(
+        &self,
+        command: &str,
+        args: &[&str],
+        mut options: ExecuteOptions<'_>,
+        background_options: BackgroundOptions<'_>,
+    ) -> Result<BackgroundProcess, CliError> {
+        let (program, args) = self
+            .launch_all(&[(command, args)], IOMode::Silent, &mut options)
+            .remove(0);
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        LocalRunner.spawn_background(
+            &program,
+            &args,
+            options,
+            BackgroundOptions {
+                name: background_options.name.or(Some(command)),
+                ..background_options
+            },
+        )
+    }
+}
+
+/// Quotes 'word' for a POSIX shell, if needed.
+pub fn shell_quote(word: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-./=:@%+,".contains(c);
+    if !word.is_empty() && word.chars().all(is_safe) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+/// A shell command line that applies 'options' (except 'tty') and then runs
+/// the command, for targets that take a single command string (like SSH).
+///
+/// Limits are set with 'ulimit', in the units used by bash. Like for local
+/// commands, only the soft limit is set, capped at the current hard limit.
+pub fn shell_command_line(program: &str, args: &[&str], options: &LaunchOptions<'_>) -> String {
+    let mut steps = Vec::new();
+    if let Some(dir) = options.dir {
+        steps.push(format!("cd -- {}", shell_quote(&dir.to_string_lossy())));
+    }
+    for limit in options.rlimits {
+        steps.push(match *limit {
+            ResourceLimit::OpenFiles(n) => soft_ulimit("n", n),
+            ResourceLimit::Processes(n) => soft_ulimit("u", n),
+            ResourceLimit::StackSize(n) => soft_ulimit("s", n / 1024),
+            ResourceLimit::CoreFileSize(n) => soft_ulimit("c", n / 1024),
+        });
+    }
+
+    let mut command = vec!["exec".to_string()];
+    if !options.env.is_empty() {
+        command.push("env".to_string());
+        command.extend(
+            options
+                .env
+                .iter()
+                .map(|(k, v)| shell_quote(&format!("{}={}", k, v))),
+        );
+    }
+    command.push(shell_quote(program));
+    command.extend(args.iter().map(|a| shell_quote(a)));
+    steps.push(command.join(" "));
+    steps.join(" && ")
+}
+
+/// A plain 'ulimit -n N' sets the hard limit too, which fails if N is above
+/// it, and can't be raised again otherwise.
+fn soft_ulimit(flag: &str, n: u64) -> String {
+    format!(
+        r#"ulimit -S -{flag} "$(h=$(ulimit -H -{flag}); [ "$h" = unlimited ] || [ "$h" -ge {n} ] && echo {n} || echo "$h")""#
+    )
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::Executor;
+
+    use super::*;
+
+    /// Runs commands through a local shell, like SSH would on the remote host.
+    #[derive(Debug)]
+    struct ShellLauncher;
+
+    impl CommandLauncher for ShellLauncher {
+        fn launch(
+            &self,
+            program: &str,
+            args: &[&str],
+            options: &LaunchOptions<'_>,
+        ) -> (String, Vec<String>) {
+            let command_line = shell_command_line(program, args, options);
+            ("sh".to_string(), vec!["-c".to_string(), command_line])
+        }
+    }
+
+    #[tokio::test]
+    async fn remote_commands_behave_like_local_ones() {
+        let ex = Executor::with_runner(RemoteRunner::new(ShellLauncher));
+        assert!(ex.has_command("sh").await);
+        assert!(!ex.has_command("no-such-command").await);
+
+        let output = ex
+            .execute_pipeline(
+                &[
+                    ("sh", &["-c", "echo $GREETING; pwd"]),
+                    ("tr", &["a-z", "A-Z"]),
+                ],
+                IOMode::Mute,
+                ExecuteOptions {
+                    // Not resolved locally.
+                    dir: Some(Path::new("/")),
+                    env: Some(vec![("GREETING".to_string(), "hi there".to_string())]),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(output, "HI THERE\n/");
+
+        let e = ex
+            .execute("sh", &["-c", "echo oops; exit 3"], IOMode::Mute)
+            .await
+            .unwrap_err();
+        assert!(e.message().starts_with("[exit status: 3] Command failed."));
+    }
+
+    #[test]
+    fn command_line_applies_options() {
+        let env = [("GREETING".to_string(), "it's me".to_string())];
+        let options = LaunchOptions {
+            dir: Some(Path::new("/srv/my app")),
+            env: &env,
+            rlimits: &[ResourceLimit::OpenFiles(8192)],
+            tty: false,
+        };
+        assert_eq!(
+            shell_command_line("echo", &["$HOME", "a;b", ""], &options),
+            r#"cd -- '/srv/my app' && ulimit -S -n "$(h=$(ulimit -H -n); [ "$h" = unlimited ] || [ "$h" -ge 8192 ] && echo 8192 || echo "$h")" && exec env 'GREETING=it'\''s me' echo '$HOME' 'a;b' ''"#
+        );
+    }
+}
@@ -0,0 +1,422 @@
+use std::{
+    fmt, fs,
+    future::Future,
+    io::{self, Read as _, Write as _},
+    pin::Pin,
+    process::{ExitStatus, Stdio},
+};
+
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+use crate::CliError;
+
+#[cfg(unix)]
+use super::ResourceLimit;
+use super::{BackgroundOptions, BackgroundProcess, ExecuteOptions, IOMode, StdinSource};
+
+pub type RunnerFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Result of a command that ran to completion, whether or not it succeeded.
+#[derive(Debug, Clone)]
+pub struct CommandOutput {
+    pub status: ExitStatus,
+    /// Collected stdout followed by stderr (empty in Attach mode).
+    pub output: String,
+}
+
+/// Backend used by `Executor` to run commands. `LocalRunner` runs real
+/// processes, while `ScriptedRunner` returns canned results, for testing code
+/// that shells out without the tools installed.
+///
+/// `Executor` resolves 'options.dir' to an absolute path before calling the
+/// runner (unless the runner isn't local), and turns failures into `CliError`s.
+pub trait CommandRunner: fmt::Debug + Send + Sync {
+    /// Whether commands run on this machine. Otherwise, 'options.dir' is a
+    /// path on the target, and is passed through as is.
+    fn is_local(&self) -> bool {
+        true
+    }
+
+    fn has_command<'a>(&'a self, program: &'a str) -> RunnerFuture<'a, bool>;
+
+    /// Runs 'commands' as a pipeline (a single command being a pipeline of
+    /// one). The status is that of the last command that failed, if any.
+    fn run<'a>(
+        &'a self,
+        commands: &'a [(&'a str, &'a [&'a str])],
+        io_mode: IOMode,
+        options: ExecuteOptions<'a>,
+    ) -> RunnerFuture<'a, io::Result<CommandOutput>>;
+
+    fn run_sync(
+        &self,
+        command: &str,
+        args: &[&str],
+        io_mode: IOMode,
+        options: ExecuteOptions<'_>,
+    ) -> io::Result<CommandOutput>;
+
+    fn spawn_background(
+        &self,
+        command: &str,
+        args: &[&str],
+        options: ExecuteOptions<'_>,
+        background_options: BackgroundOptions<'_>,
+    ) -> Result<BackgroundProcess, CliError>;
+}
+
+/// Runs commands as local processes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalRunner;
+
+impl CommandRunner for LocalRunner {
+    fn has_command<'a>(&'a self, program: &'a str) -> RunnerFuture<'a, bool> {
+        Box::pin(async move {
+            #[cfg(windows)]
+            {
+                tokio::process::Command::new("cmd")
+                    .args(["/C", "where", "/Q"])
+                    .arg(program)
+                    .status()
+                    .await
+                    .map(|status| status.success())
+                    .unwrap_or(false)
+            }
+
+            #[cfg(not(windows))]
+            {
+                tokio::process::Command::new("sh")
+                    .args(["-c", "command -v -- \"$1\" >/dev/null 2>&1", "sh"])
+                    .arg(program)
+                    .status()
+                    .await
+                    .map(|status| status.success())
+                    .unwrap_or(false)
+            }
+        })
+    }
+
+    fn run<'a>(
+        &'a self,
+        commands: &'a [(&'a str, &'a [&'a str])],
+        io_mode: IOMode,
+        options: ExecuteOptions<'a>,
+    ) -> RunnerFuture<'a, io::Result<CommandOutput>> {
+        Box::pin(run_pipeline(commands, io_mode, options))
+    }
+
+    fn run_sync(
+        &self,
+        command: &str,
+        args: &[&str],
+        io_mode: IOMode,
+        options: ExecuteOptions<'_>,
+    ) -> io::Result<CommandOutput> {
+        let mut command = std::process::Command::new(command);
+        command
+            .args(args)
+            .envs(options.env.unwrap_or_default())
+            .stdin(stdin_stdio(options.stdin.as_ref(), io_mode)?)
+            .stdout(match io_mode {
+                IOMode::Attach => std::process::Stdio::inherit(),
+                IOMode::StreamOutput | IOMode::Silent | IOMode::Mute => {
+                    std::process::Stdio::piped()
+                }
+            })
+            .stderr(match io_mode {
+                IOMode::Attach => std::process::Stdio::inherit(),
+                IOMode::StreamOutput | IOMode::Silent | IOMode::Mute => {
+                    std::process::Stdio::piped()
+                }
+            });
+        if let Some(dir) = options.dir {
+            command.current_dir(dir);
+        }
+        #[cfg(unix)]
+        if !options.rlimits.is_empty() {
+            use std::os::unix::process::CommandExt as _;
+            // SAFETY: The hook only calls getrlimit and setrlimit, which are
+            // async-signal-safe.
+            unsafe {
+                command.pre_exec(rlimits_hook(options.rlimits.clone()));
+            }
+        }
+        if matches!(options.stdin, Some(StdinSource::Stream(_))) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "streamed stdin is only supported by the async API",
+            ));
+        }
+        let mut child = command.spawn()?;
+
+        // Written from a separate thread, since the command may not read its
+        // input before its output is read.
+        let writer = match (child.stdin.take(), options.stdin) {
+            (Some(mut pipe), Some(StdinSource::Bytes(bytes))) => {
+                Some(std::thread::spawn(move || pipe.write_all(&bytes)))
+            }
+            _ => None,
+        };
+
+        let mut collected_output = String::new();
+
+        if io_mode != IOMode::Attach {
+            if let Some(mut stdout) = child.stdout.take() {
+                let mut buffer = [0; 1024];
+                loop {
+                    match stdout.read(&mut buffer) {
+                        Ok(0) => break, // EOF reached
+                        Ok(n) => {
+                            let output = String::from_utf8_lossy(&buffer[..n]);
+                            if io_mode == IOMode::StreamOutput {
+                                print!("{}", output);
+                                io::stdout().flush().unwrap();
+                            }
+                            collected_output.push_str(&output);
+                        }
+                        Err(_) => break,
+                    }
+                }
+            }
+
+            if let Some(mut stderr) = child.stderr.take() {
+                let mut buffer = [0; 1024];
+                loop {
+                    match stderr.read(&mut buffer) {
+                        Ok(0) => break, // EOF reached
+                        Ok(n) => {
+                            let output = String::from_utf8_lossy(&buffer[..n]);
+                            if io_mode != IOMode::Mute {
+                                eprint!("{}", output);
+                                io::stderr().flush().unwrap();
+                            }
+                            collected_output.push_str(&output);
+                        }
+                        Err(_) => break,
+                    }
+                }
+            }
+        }
+
+        let status = child.wait()?;
+        if status.success() {
+            match writer.map(|w| w.join()) {
+                // The command exited without reading all of its input, which
+                // is fine if it succeeded.
+                Some(Ok(Err(e))) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+                Some(Err(_)) => return Err(io::Error::other("stdin writer thread panicked")),
+                _ => {}
+            }
+        }
+        Ok(CommandOutput {
+            status,
+            output: collected_output,
+        })
+    }
+
+    fn spawn_background(
+        &self,
+        command: &str,
+        args: &[&str],
+        options: ExecuteOptions<'_>,
+        background_options: BackgroundOptions<'_>,
+    ) -> Result<BackgroundProcess, CliError> {
+        let mut child = tokio::process::Command::new(command);
+        child.args(args).envs(options.env.unwrap_or_default());
+        if let Some(dir) = options.dir {
+            child.current_dir(dir);
+        }
+        BackgroundProcess::spawn(child, command, background_options)
+    }
+}
+
+async fn run_pipeline(
+    commands: &[(&str, &[&str])],
+    io_mode: IOMode,
+    options: ExecuteOptions<'_>,
+) -> io::Result<CommandOutput> {
+    if commands.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "pipeline has no commands",
+        ));
+    }
+    let env = options.env.unwrap_or_default();
+    let stdin_source = options.stdin;
+
+    let mut children = Vec::with_capacity(commands.len());
+    let mut previous_stdout: Option<Stdio> = None;
+    for (i, (program, args)) in commands.iter().enumerate() {
+        let is_last = i == commands.len() - 1;
+        let mut command = tokio::process::Command::new(program);
+        command
+            .args(*args)
+            .envs(env.iter().cloned())
+            .stdin(match previous_stdout.take() {
+                Some(stdout) => stdout,
+                None => stdin_stdio(stdin_source.as_ref(), io_mode)?,
+            })
+            .stdout(match (is_last, io_mode) {
+                (true, IOMode::Attach) => Stdio::inherit(),
+                _ => Stdio::piped(),
+            })
+            .stderr(match (is_last, io_mode) {
+                (_, IOMode::Attach) => Stdio::inherit(),
+                (true, IOMode::StreamOutput | IOMode::Silent | IOMode::Mute) => Stdio::piped(),
+                (false, IOMode::Mute) => Stdio::null(),
+                (false, IOMode::StreamOutput | IOMode::Silent) => Stdio::inherit(),
+            });
+        if let Some(dir) = options.dir {
+            command.current_dir(dir);
+        }
+        #[cfg(unix)]
+        if !options.rlimits.is_empty() {
+            // SAFETY: The hook only calls getrlimit and setrlimit, which are
+            // async-signal-safe.
+            unsafe {
+                command.pre_exec(rlimits_hook(options.rlimits.clone()));
+            }
+        }
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => return Err(kill_all(&mut children, e).await),
+        };
+        if !is_last {
+            if let Some(stdout) = child.stdout.take() {
+                match stdout.try_into() {
+                    Ok(stdout) => previous_stdout = Some(stdout),
+                    Err(e) => {
+                        children.push(child);
+                        return Err(kill_all(&mut children, e).await);
+                    }
+                }
+            }
+        }
+        children.push(child);
+    }
+
+    // Write stdin while reading the output, since either side can block once
+    // the pipe buffers are full.
+    let stdin_pipe = children[0].stdin.take();
+    let write_stdin = async move {
+        let (Some(mut pipe), Some(source)) = (stdin_pipe, stdin_source) else {
+            return Ok(());
+        };
+        // The pipe is closed when dropped, which signals EOF.
+        match source {
+            StdinSource::Bytes(bytes) => pipe.write_all(&bytes).await,
+            StdinSource::Stream(mut reader) => {
+                tokio::io::copy(&mut reader, &mut pipe).await.map(|_| ())
+            }
+            StdinSource::File(_) => Ok(()),
+        }
+    };
+    let last = children.last_mut().expect("pipeline is not empty");
+    let (written, output) = tokio::join!(write_stdin, collect_output(last, io_mode));
+
+    let mut failed_status = None;
+    let mut last_status = None;
+    for child in &mut children {
+        let status = child.wait().await?;
+        if !status.success() {
+            failed_status = Some(status);
+        }
+        last_status = Some(status);
+    }
+    let status = failed_status
+        .or(last_status)
+        .expect("pipeline is not empty");
+    match written {
+        // The command exited without reading all of its input, which is fine
+        // if it succeeded.
+        Err(e) if status.success() && e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+        _ => Ok(CommandOutput { status, output }),
+    }
+}
+
+/// Stops the commands already started when the pipeline can't be completed,
+/// so they don't keep running unattended. Returns 'error'.
+async fn kill_all(children: &mut [tokio::process::Child], error: io::Error) -> io::Error {
+    for child in children {
+        // Also waits for the process to exit.
+        let _ = child.kill().await;
+    }
+    error
+}
+
+fn stdin_stdio(source: Option<&StdinSource<'_>>, io_mode: IOMode) -> io::Result<Stdio> {
+    Ok(match source {
+        Some(StdinSource::File(path)) => Stdio::from(fs::File::open(path)?),
+        Some(StdinSource::Bytes(_) | StdinSource::Stream(_)) => Stdio::piped(),
+        None => match io_mode {
+            IOMode::Attach => Stdio::inherit(),
+            IOMode::StreamOutput | IOMode::Silent | IOMode::Mute => Stdio::null(),
+        },
+    })
+}
+
+async fn collect_output(child: &mut tokio::process::Child, io_mode: IOMode) -> String {
+    let mut collected_output = String::new();
+
+    if io_mode != IOMode::Attach {
+        if let Some(mut stdout) = child.stdout.take() {
+            let mut buffer = [0; 1024];
+            loop {
+                match stdout.read(&mut buffer).await {
+                    Ok(0) => break, // EOF reached
+                    Ok(n) => {
+                        let output = String::from_utf8_lossy(&buffer[..n]);
+                        if io_mode == IOMode::StreamOutput {
+                            print!("{}", output);
+                            io::stdout().flush().unwrap();
+                        }
+                        collected_output.push_str(&output);
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+
+        if let Some(mut stderr) = child.stderr.take() {
+            let mut buffer = [0; 1024];
+            loop {
+                match stderr.read(&mut buffer).await {
+                    Ok(0) => break, // EOF reached
+                    Ok(n) => {
+                        let output = String::from_utf8_lossy(&buffer[..n]);
+                        if io_mode != IOMode::Mute {
+                            eprint!("{}", output);
+                            io::stderr().flush().unwrap();
+                        }
+                        collected_output.push_str(&output);
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+
+    collected_output
+}
+
+/// Runs in the forked child before exec, so it must not allocate or lock.
+#[cfg(unix)]
+fn rlimits_hook(limits: Vec<ResourceLimit>) -> impl FnMut() -> io::Result<()> + Send + Sync {
+    use nix::sys::resource::{getrlimit, setrlimit, Resource};
+
+    move || {
+        for limit in &limits {
+            let (resource, value) = match *limit {
+                ResourceLimit::OpenFiles(n) => (Resource::RLIMIT_NOFILE, n),
+                ResourceLimit::Processes(n) => (Resource::RLIMIT_NPROC, n),
+                ResourceLimit::StackSize(n) => (Resource::RLIMIT_STACK, n),
+                ResourceLimit::CoreFileSize(n) => (Resource::RLIMIT_CORE, n),
+            };
+            // Raising the hard limit requires root, so the soft limit is
+            // capped at it rather than failing the spawn.
+            let (_, hard) = getrlimit(resource)?;
+            setrlimit(resource, hard.min(value as _), hard)?;
+        }
+        Ok(())
+    }
+}
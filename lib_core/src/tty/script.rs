@@ -0,0 +1,222 @@
+use std::{fmt, future::Future, io, process::Command};
+
+use clap::{Args, Parser};
+use clap_complete::Shell;
+use tokio::{select, signal};
+
+use crate::{set_assume_yes, CliError, CtrlC, DeploymentEnv};
+
+use super::{Printer, Tty, Verbosity};
+
+/// Flags shared by all scripts. Include them in the script's parser with
+/// `#[command(flatten)]`.
+#[derive(Debug, Clone, Default, Args)]
+pub struct GlobalArgs {
+    /// Print debug messages.
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+    /// Only print warnings, errors and results.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Print results as JSON, where supported.
+    #[arg(long, global = true)]
+    pub json: bool,
+    /// Show what would be done, without doing it.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// Answer yes to confirmation prompts. Typed confirmations (ex. before
+    /// targeting production) are still required.
+    #[arg(short, long, global = true)]
+    pub yes: bool,
+    #[arg(long, global = true, value_enum)]
+    pub env: Option<DeploymentEnv>,
+    /// Print shell completions, and exit.
+    #[arg(long, value_enum, hide = true)]
+    pub completions: Option<Shell>,
+    /// Print the man page, and exit.
+    #[arg(long, hide = true)]
+    pub man_page: bool,
+}
+
+impl GlobalArgs {
+    pub fn verbosity(&self) -> Verbosity {
+        if self.quiet {
+            Verbosity::Quiet
+        } else if self.verbose {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        }
+    }
+}
+
+/// Version printed by '--version'. Use `script_version!` to get the version of
+/// the calling crate.
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptVersion {
+    pub version: &'static str,
+    /// Set by calling `emit_git_hash` from the crate's build script.
+    pub git_hash: Option<&'static str>,
+}
+
+impl fmt::Display for ScriptVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.git_hash {
+            Some(hash) => write!(f, "{} ({})", self.version, hash),
+            None => write!(f, "{}", self.version),
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! script_version {
+    () => {
+        $crate::ScriptVersion {
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: option_env!("GIT_HASH"),
+        }
+    };
+}
+
+/// For build scripts: makes the current commit available to `script_version!`.
+/// Does nothing outside of a git repository.
+pub fn emit_git_hash() {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+    };
+    if let Some(hash) = git(&["rev-parse", "--short", "HEAD"]) {
+        println!("cargo:rustc-env=GIT_HASH={}", hash);
+    }
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        println!("cargo:rerun-if-changed={}/refs/heads", git_dir);
+    }
+}
+
+/// Entry point of a script built on `Tty`, run with `run_script`:
+///
+/// ```ignore
+/// #[derive(Parser)]
+/// struct Deploy {
+///     #[command(flatten)]
+///     global: GlobalArgs,
+///     #[command(subcommand)]
+///     command: Commands,
+/// }
+///
+/// impl Script for Deploy {
+///     const NAME: &'static str = "deploy";
+///     const VERSION: ScriptVersion = script_version!();
+///     const PREFERENCES_PATH: &'static str = "~/.config/scripts/preferences.yaml";
+///
+///     fn global_args(&self) -> &GlobalArgs {
+///         &self.global
+///     }
+///
+///     async fn run(self, tty: &mut Tty) -> Result<(), CliError> {
+///         ...
+///     }
+/// }
+///
+/// #[tokio::main]
+/// async fn main() {
+///     run_script::<Deploy>().await
+/// }
+/// ```
+pub trait Script: Parser {
+    /// Used for '--version', completions, and to look up user preferences.
+    const NAME: &'static str;
+    const VERSION: ScriptVersion;
+    const PREFERENCES_PATH: &'static str;
+
+    fn global_args(&self) -> &GlobalArgs;
+
+    fn run(self, tty: &mut Tty) -> impl Future<Output = Result<(), CliError>>;
+}
+
+/// Parses the arguments, handles the global flags, and runs the script with a
+/// new `Tty`, which is closed with the result (exiting with an error code if
+/// it failed). A subcommand separator is printed if a subcommand was given,
+/// and Ctrl-C ends the script with an error.
+pub async fn run_script<S: Script>() {
+    // Leaked once, since clap requires a static version string.
+    let version: &'static str = Box::leak(S::VERSION.to_string().into_boxed_str());
+    let mut command = S::command().name(S::NAME).version(version);
+    let matches = command.clone().get_matches();
+    let script = S::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let args = script.global_args().clone();
+
+    if let Some(shell) = args.completions {
+        clap_complete::generate(shell, &mut command, S::NAME, &mut io::stdout());
+        return;
+    }
+    if args.man_page {
+        if let Err(e) = clap_mangen::Man::new(command).render(&mut io::stdout()) {
+            eprintln!("Failed to render man page: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    Printer::set_verbosity(args.verbosity());
+    set_assume_yes(args.yes);
+    let mut tty = match Tty::new(S::PREFERENCES_PATH.into(), S::NAME) {
+        Ok(tty) => tty,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1)
+        }
+    };
+    tty.set_global_args(args);
+    if let Some(subcommand) = matches.subcommand_name() {
+        tty.subcommand_separator(subcommand);
+    }
+
+    let deferral = tty.cleanup().defer_interrupts();
+    let result = select! {
+        result = script.run(&mut tty) => result,
+        _ = signal::ctrl_c() => Err(CtrlC::new()),
+    };
+    tty.close(result).await;
+    drop(deferral);
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use clap::Subcommand;
+
+    use super::*;
+
+    #[derive(Debug, Parser)]
+    struct Deploy {
+        #[command(flatten)]
+        global: GlobalArgs,
+        #[command(subcommand)]
+        command: Commands,
+    }
+
+    #[derive(Debug, Subcommand)]
+    enum Commands {
+        Api,
+    }
+
+    #[test]
+    fn global_args_are_accepted_after_the_subcommand() {
+        let deploy =
+            Deploy::try_parse_from(["deploy", "api", "-qy", "--env", "production"]).unwrap();
+        assert!(matches!(deploy.command, Commands::Api));
+        assert_eq!(deploy.global.verbosity(), Verbosity::Quiet);
+        assert!(deploy.global.yes);
+        assert_eq!(deploy.global.env, Some(DeploymentEnv::Production));
+
+        assert!(Deploy::try_parse_from(["deploy", "api", "-q", "-v"]).is_err());
+    }
+}
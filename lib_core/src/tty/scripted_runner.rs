@@ -0,0 +1,424 @@
+use std::{
+    io,
+    path::PathBuf,
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+};
+
+use tokio::io::AsyncReadExt as _;
+
+use crate::CliError;
+
+use super::{
+    BackgroundOptions, BackgroundProcess, CommandOutput, CommandRunner, ExecuteOptions, IOMode,
+    RunnerFuture, StdinSource,
+};
+
+/// Exit code returned for commands that don't match any expectation, the same
+/// as a shell returns for a missing command.
+const UNEXPECTED_COMMAND_EXIT_CODE: i32 = 127;
+
+/// Fake `CommandRunner` that returns canned results, for testing code that
+/// shells out without having the tools installed. Clones share the same
+/// expectations and recorded calls, so keep one to make assertions after
+/// passing another to `Executor::with_runner`.
+///
+/// Each call uses the first registered expectation that matches and isn't
+/// used up, so the same command can be scripted to return different results
+/// on consecutive calls. Commands without a matching expectation fail with
+/// exit code 127, and make `assert_done` panic.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedRunner {
+    inner: Arc<Mutex<ScriptState>>,
+}
+
+#[derive(Debug, Default)]
+struct ScriptState {
+    expectations: Vec<(Expectation, usize)>,
+    available: Vec<String>,
+    calls: Vec<RecordedCall>,
+    unexpected: Vec<RecordedCall>,
+}
+
+#[derive(Debug, Clone)]
+enum ArgsMatcher {
+    Exact(Vec<String>),
+    Prefix(Vec<String>),
+    Any,
+}
+
+#[derive(Debug, Clone)]
+pub struct Expectation {
+    program: String,
+    args: ArgsMatcher,
+    output: String,
+    exit_code: i32,
+    repeated: bool,
+}
+
+impl Expectation {
+    /// Matches 'program' called with exactly 'args'.
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        Self::with_matcher(program, ArgsMatcher::Exact(to_strings(args)))
+    }
+
+    /// Matches 'program' called with args starting with 'prefix'.
+    pub fn with_args_prefix(program: &str, prefix: &[&str]) -> Self {
+        Self::with_matcher(program, ArgsMatcher::Prefix(to_strings(prefix)))
+    }
+
+    pub fn any_args(program: &str) -> Self {
+        Self::with_matcher(program, ArgsMatcher::Any)
+    }
+
+    fn with_matcher(program: &str, args: ArgsMatcher) -> Self {
+        Expectation {
+            program: program.to_string(),
+            args,
+            output: String::new(),
+            exit_code: 0,
+            repeated: false,
+        }
+    }
+
+    pub fn output(mut self, output: &str) -> Self {
+        self.output = output.to_string();
+        self
+    }
+
+    pub fn exit_code(mut self, exit_code: i32) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+
+    /// Can be matched any number of times, instead of once.
+    pub fn repeated(mut self) -> Self {
+        self.repeated = true;
+        self
+    }
+
+    fn matches(&self, program: &str, args: &[String]) -> bool {
+        self.program == program
+            && match &self.args {
+                ArgsMatcher::Exact(expected) => expected == args,
+                ArgsMatcher::Prefix(prefix) => args.starts_with(prefix),
+                ArgsMatcher::Any => true,
+            }
+    }
+
+    fn describe(&self) -> String {
+        match &self.args {
+            ArgsMatcher::Exact(args) => command_line(&self.program, args),
+            ArgsMatcher::Prefix(prefix) => format!("{} ...", command_line(&self.program, prefix)),
+            ArgsMatcher::Any => format!("{} ...", self.program),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedCall {
+    pub program: String,
+    pub args: Vec<String>,
+    pub dir: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    /// Input passed to the command, if any.
+    pub stdin: Option<Vec<u8>>,
+    pub io_mode: Option<IOMode>,
+    pub background: bool,
+}
+
+impl RecordedCall {
+    pub fn command_line(&self) -> String {
+        command_line(&self.program, &self.args)
+    }
+}
+
+impl ScriptedRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn expect(&self, expectation: Expectation) -> &Self {
+        self.inner
+            .lock()
+            .unwrap()
+            .expectations
+            .push((expectation, 0));
+        self
+    }
+
+    /// Makes `Executor::has_command` return true for these programs. Programs
+    /// with an expectation are always considered available.
+    pub fn with_commands(&self, programs: &[&str]) -> &Self {
+        self.inner
+            .lock()
+            .unwrap()
+            .available
+            .extend(to_strings(programs));
+        self
+    }
+
+    /// All calls so far, in order, including unexpected ones.
+    pub fn calls(&self) -> Vec<RecordedCall> {
+        self.inner.lock().unwrap().calls.clone()
+    }
+
+    #[track_caller]
+    pub fn assert_called(&self, program: &str, args: &[&str]) {
+        let expected = command_line(program, &to_strings(args));
+        let calls = self.calls();
+        assert!(
+            calls.iter().any(|c| c.command_line() == expected),
+            "Expected call '{}', but got:\n{}",
+            expected,
+            describe_calls(&calls)
+        );
+    }
+
+    #[track_caller]
+    pub fn assert_not_called(&self, program: &str) {
+        let calls = self.calls();
+        assert!(
+            !calls.iter().any(|c| c.program == program),
+            "Expected no calls to '{}', but got:\n{}",
+            program,
+            describe_calls(&calls)
+        );
+    }
+
+    /// Panics if there were unexpected calls, or if an expectation (other
+    /// than repeated ones) was never used.
+    #[track_caller]
+    pub fn assert_done(&self) {
+        let (unexpected, unused) = {
+            let state = self.inner.lock().unwrap();
+            let unused = state
+                .expectations
+                .iter()
+                .filter(|(e, uses)| !e.repeated && *uses == 0)
+                .map(|(e, _)| format!("  {}", e.describe()))
+                .collect::<Vec<_>>();
+            (state.unexpected.clone(), unused)
+        };
+        assert!(
+            unexpected.is_empty() && unused.is_empty(),
+            "Unexpected calls:\n{}\nUnused expectations:\n{}",
+            describe_calls(&unexpected),
+            unused.join("\n")
+        );
+    }
+
+    fn call(&self, call: RecordedCall) -> CommandOutput {
+        let mut state = self.inner.lock().unwrap();
+        let matched = state
+            .expectations
+            .iter_mut()
+            .find(|(e, uses)| (e.repeated || *uses == 0) && e.matches(&call.program, &call.args));
+        let output = match matched {
+            Some((expectation, uses)) => {
+                *uses += 1;
+                CommandOutput {
+                    status: exit_status_from_code(expectation.exit_code),
+                    output: expectation.output.clone(),
+                }
+            }
+            None => {
+                state.unexpected.push(call.clone());
+                CommandOutput {
+                    status: exit_status_from_code(UNEXPECTED_COMMAND_EXIT_CODE),
+                    output: format!("Unexpected command: {}", call.command_line()),
+                }
+            }
+        };
+        state.calls.push(call);
+        output
+    }
+
+    fn record(
+        &self,
+        program: &str,
+        args: &[&str],
+        io_mode: Option<IOMode>,
+        options: &ExecuteOptions<'_>,
+        stdin: Option<Vec<u8>>,
+        background: bool,
+    ) -> CommandOutput {
+        self.call(RecordedCall {
+            program: program.to_string(),
+            args: to_strings(args),
+            dir: options.dir.map(PathBuf::from),
+            env: options.env.clone().unwrap_or_default(),
+            stdin,
+            io_mode,
+            background,
+        })
+    }
+}
+
+impl CommandRunner for ScriptedRunner {
+    fn has_command<'a>(&'a self, program: &'a str) -> RunnerFuture<'a, bool> {
+        let state = self.inner.lock().unwrap();
+        let available = state.available.iter().any(|p| p == program)
+            || state.expectations.iter().any(|(e, _)| e.program == program);
+        Box::pin(async move { available })
+    }
+
+    fn run<'a>(
+        &'a self,
+        commands: &'a [(&'a str, &'a [&'a str])],
+        io_mode: IOMode,
+        mut options: ExecuteOptions<'a>,
+    ) -> RunnerFuture<'a, io::Result<CommandOutput>> {
+        Box::pin(async move {
+            let mut stdin = match options.stdin.take() {
+                Some(StdinSource::Stream(mut reader)) => {
+                    let mut bytes = Vec::new();
+                    reader.read_to_end(&mut bytes).await?;
+                    Some(bytes)
+                }
+                Some(source) => read_stdin(source)?,
+                None => None,
+            };
+            let mut result = None::<CommandOutput>;
+            for (program, args) in commands {
+                let output =
+                    self.record(program, args, Some(io_mode), &options, stdin.take(), false);
+                result = Some(match result {
+                    // Like 'set -o pipefail'.
+                    Some(previous) if !output.status.success() || previous.status.success() => {
+                        output
+                    }
+                    Some(previous) => CommandOutput {
+                        output: output.output,
+                        ..previous
+                    },
+                    None => output,
+                });
+            }
+            result.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "pipeline has no commands")
+            })
+        })
+    }
+
+    fn run_sync(
+        &self,
+        command: &str,
+        args: &[&str],
+        io_mode: IOMode,
+        mut options: ExecuteOptions<'_>,
+    ) -> io::Result<CommandOutput> {
+        let stdin = match options.stdin.take() {
+            Some(source) => read_stdin(source)?,
+            None => None,
+        };
+        Ok(self.record(command, args, Some(io_mode), &options, stdin, false))
+    }
+
+    fn spawn_background(
+        &self,
+        command: &str,
+        args: &[&str],
+        options: ExecuteOptions<'_>,
+        background_options: BackgroundOptions<'_>,
+    ) -> Result<BackgroundProcess, CliError> {
+        let output = self.record(command, args, None, &options, None, true);
+        BackgroundProcess::exited(command, background_options, &output.output, output.status)
+    }
+}
+
+fn read_stdin(source: StdinSource<'_>) -> io::Result<Option<Vec<u8>>> {
+    match source {
+        StdinSource::Bytes(bytes) => Ok(Some(bytes)),
+        StdinSource::File(path) => std::fs::read(path).map(Some),
+        StdinSource::Stream(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "streamed stdin is only supported by the async API",
+        )),
+    }
+}
+
+fn to_strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+fn command_line(program: &str, args: &[String]) -> String {
+    std::iter::once(program)
+        .chain(args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn describe_calls(calls: &[RecordedCall]) -> String {
+    match calls.is_empty() {
+        true => "  (none)".to_string(),
+        false => calls
+            .iter()
+            .map(|c| format!("  {}", c.command_line()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+pub(crate) fn exit_status_from_code(code: i32) -> ExitStatus {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt as _;
+        // Encoded like the status returned by waitpid.
+        ExitStatus::from_raw((code & 0xff) << 8)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::ExitStatusExt as _;
+        ExitStatus::from_raw(code as u32)
+    }
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::Executor;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn expectations_are_matched_in_order_and_recorded() {
+        let runner = ScriptedRunner::new();
+        runner
+            .expect(Expectation::new("adb", &["devices"]).exit_code(1))
+            .expect(Expectation::new("adb", &["devices"]).output("emulator-5554\tdevice\n"))
+            .expect(Expectation::with_args_prefix("wc", &["-l"]).output("2"));
+        let ex = Executor::with_runner(runner.clone());
+
+        assert!(ex
+            .execute("adb", &["devices"], IOMode::Silent)
+            .await
+            .is_err());
+        assert_eq!(
+            ex.execute("adb", &["devices"], IOMode::Silent)
+                .await
+                .unwrap(),
+            "emulator-5554\tdevice"
+        );
+        assert!(ex.has_command("wc").await && !ex.has_command("flutter").await);
+        let output = ex
+            .execute_pipeline(
+                &[("cat", &[]), ("wc", &["-l"])],
+                IOMode::Mute,
+                ExecuteOptions {
+                    stdin: Some(StdinSource::Bytes(b"a\nb\n".to_vec())),
+                    ..Default::default()
+                },
+            )
+            .await;
+        // 'cat' has no expectation, so the pipeline fails.
+        assert!(output.is_err());
+        assert_eq!(runner.calls()[2].stdin, Some(b"a\nb\n".to_vec()));
+        runner.assert_called("wc", &["-l"]);
+        runner.assert_not_called("flutter");
+        let unexpected = std::panic::catch_unwind(|| runner.assert_done());
+        assert!(unexpected.is_err());
+    }
+}
@@ -4,15 +4,17 @@ use std::pin::Pin;
 
 use tokio::{select, signal};
 
-use crate::{CliError, CtrlC};
+use crate::{CleanupRegistry, CliError, CtrlC};
 
-use super::{Executor, Printer, UserPreferences};
+use super::{check_toolchain, Executor, GlobalArgs, Printer, ToolRequirement, UserPreferences};
 
 pub struct Tty {
     start_time: std::time::Instant,
     printer: Printer,
     user_preferences: UserPreferences,
     executor: Executor,
+    cleanup: CleanupRegistry,
+    global_args: GlobalArgs,
 }
 
 impl Tty {
@@ -20,15 +22,38 @@ impl Tty {
         let printer = Printer::new();
         let user_preferences = UserPreferences::new(preferences_path, script_name)?;
         let executor = Executor::new();
+        let cleanup = CleanupRegistry::new(user_preferences.dir().map(|d| d.join("cleanup")));
+        cleanup.install_as_current();
+        #[cfg(unix)]
+        cleanup.spawn_signal_handler(printer.clone());
+        cleanup.recover_abandoned(&printer)?;
 
         Ok(Self {
             start_time: std::time::Instant::now(),
             printer,
             user_preferences,
             executor,
+            cleanup,
+            global_args: GlobalArgs::default(),
         })
     }
 
+    /// Cleanup actions registered here are run when the script exits, even if
+    /// it exits with an error or is terminated.
+    pub fn cleanup(&self) -> &CleanupRegistry {
+        &self.cleanup
+    }
+
+    /// Flags shared by all scripts (see `run_script`). All unset if the script
+    /// doesn't use `run_script`.
+    pub fn global_args(&self) -> &GlobalArgs {
+        &self.global_args
+    }
+
+    pub(crate) fn set_global_args(&mut self, global_args: GlobalArgs) {
+        self.global_args = global_args;
+    }
+
     pub fn subcommand_separator(&self, subcommand: &str) {
         self.printer.subcommand_separator(subcommand);
     }
@@ -47,6 +72,24 @@ impl Tty {
         Ok(())
     }
 
+    /// Checks required tools up front, and prints one report of all missing
+    /// or outdated ones, rather than failing halfway through the script.
+    pub async fn preflight(&self, tools: &[ToolRequirement]) -> Result<(), CliError> {
+        self.printer.section_open("Checking required tools...");
+        let result = match check_toolchain(&self.executor, tools).await {
+            Ok(report) => {
+                report.print(&self.printer);
+                report.into_result()
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => self.printer.section_close(),
+            Err(_) => self.printer.section_error(),
+        }
+        result
+    }
+
     pub fn with_user_preferences<F, R>(&mut self, f: F) -> R
     where
         F: FnOnce(&mut UserPreferences) -> R,
@@ -91,6 +134,7 @@ impl Tty {
         let local_printer = self.printer.clone();
         local_printer.section_open(name);
         Box::pin(async move {
+            let _deferral = self.cleanup.defer_interrupts();
             let result = select! {
                 result = f(&mut self.printer, &mut self.executor) => result,
                 _ = signal::ctrl_c() => {
@@ -111,11 +155,23 @@ impl Tty {
     }
 
     pub async fn close<T>(mut self, final_result: Result<T, CliError>) {
+        // Ctrl-C must not start the same cleanup concurrently.
+        let _deferral = self.cleanup.defer_interrupts();
+        // Unmount first, since filesystems may be served by background
+        // processes (ex. sshfs), which only exit after. The other cleanup
+        // actions wait for background processes, which may still use the
+        // files they clean up.
+        let unmounts = self.cleanup.run_unmounts(&self.printer);
         let cleanup = self
             .executor
             .resolve_background_processes(&self.printer)
             .await;
-        match final_result.and(cleanup) {
+        let registry_cleanup = self.cleanup.run_all(&self.printer);
+        match final_result
+            .and(unmounts)
+            .and(cleanup)
+            .and(registry_cleanup)
+        {
             Ok(()) => {
                 self.printer.success("SUCCESS");
                 self.printer
@@ -2,7 +2,7 @@ use std::{
     collections::HashMap,
     fs,
     io::{self, Write as _},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use notify_rust::Notification;
@@ -24,6 +24,8 @@ pub struct UserPreferences {
 struct PreferencesFileContent {
     #[serde(default)]
     env: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    editor: Option<String>,
     #[serde(default)]
     scripts: HashMap<String, HashMap<String, Value>>,
 }
@@ -97,14 +99,16 @@ impl UserPreferences {
                 }
             }
         }
+        self.save()
+    }
 
+    fn save(&self) -> Result<(), CliError> {
         if let Ok(yaml) = serde_yaml::to_string(&self.preferences) {
             if let Some(parent) = self.preferences_path.parent() {
                 mkdir_p(parent)?;
             }
             let _ = fs::write(&self.preferences_path, yaml);
         }
-
         Ok(())
     }
 
@@ -138,7 +142,25 @@ impl UserPreferences {
         }
     }
 
+    /// Directory containing the (redirect-resolved) preferences file. Also used
+    /// to store other per-user state, such as the cleanup journal.
+    pub fn dir(&self) -> Option<&Path> {
+        self.preferences_path.parent()
+    }
+
     pub fn env_overrides(&self) -> &HashMap<String, String> {
         &self.preferences.env
     }
+
+    /// Editor command configured by the user (ex. "nvim" or "code --wait").
+    /// Shared across all scripts, and takes priority over $VISUAL / $EDITOR.
+    pub fn editor(&self) -> Option<&str> {
+        self.preferences.editor.as_deref()
+    }
+
+    /// Passing None goes back to $VISUAL / $EDITOR.
+    pub fn set_editor(&mut self, command: Option<&str>) -> Result<(), CliError> {
+        self.preferences.editor = command.map(String::from);
+        self.save()
+    }
 }
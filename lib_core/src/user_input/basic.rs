@@ -1,4 +1,8 @@
-use std::{io::Write as _, time::Duration};
+use std::{
+    io::Write as _,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
 use tokio::{io::AsyncBufReadExt as _, time::timeout};
 
@@ -6,7 +10,18 @@ use crate::{define_cli_error, CliError, IOError};
 
 define_cli_error!(UserCancelled, "User cancelled operation.");
 
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+/// Makes `confirm` succeed without asking (ex. set from '--yes'). Other
+/// prompts, including `confirm_by_typing`, still ask.
+pub fn set_assume_yes(assume_yes: bool) {
+    ASSUME_YES.store(assume_yes, Ordering::Relaxed);
+}
+
 pub fn confirm() -> Result<(), CliError> {
+    if ASSUME_YES.load(Ordering::Relaxed) {
+        return Ok(());
+    }
     match inquire::Confirm::new("Are you sure?").prompt() {
         Ok(true) => Ok(()),
         Ok(false) | Err(_) => Err(UserCancelled::new()),
@@ -99,3 +114,16 @@ pub fn ask_optional(prompt: &'static str) -> Result<Option<String>, CliError> {
         .map_err(|e| UserCancelled::with_debug(&e))
         .map(|x| if x.is_empty() { None } else { Some(x) })
 }
+
+/// Asks the user to type 'expected' to continue (ex. the name of the target
+/// environment), for operations that are hard to undo.
+pub fn confirm_by_typing(prompt: &str, expected: &str) -> Result<(), CliError> {
+    let answer = inquire::Text::new(&format!("{} Type '{}' to continue:", prompt, expected))
+        .prompt()
+        .map_err(|e| UserCancelled::with_debug(&e))?;
+    if answer.trim() == expected {
+        Ok(())
+    } else {
+        Err(UserCancelled::new())
+    }
+}
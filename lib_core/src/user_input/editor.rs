@@ -0,0 +1,410 @@
+use std::{fmt, path::Path};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    define_cli_error, with_written_to_tmp_file_with_suffix, yes_no, CliError, Printer,
+    UserCancelled, UserPreferences,
+};
+
+define_cli_error!(EditorError, "Editor error: {details}.", { details: &str });
+define_cli_error!(
+    StructuredEditSerializationError,
+    "Failed to serialize value as {format} for editing.",
+    { format: &str }
+);
+
+const DEFAULT_EDITOR: &str = "vim";
+
+/// Editors we know the command line flags for. Anything else is launched
+/// as-is, with the file path as the last argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorKind {
+    Vim,
+    Neovim,
+    Helix,
+    Nano,
+    Emacs,
+    VsCode,
+    Sublime,
+    Other,
+}
+
+impl EditorKind {
+    fn from_program(program: &str) -> Self {
+        let name = Path::new(program)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match name.trim_end_matches(".exe") {
+            "vi" | "vim" | "gvim" | "mvim" => EditorKind::Vim,
+            "nvim" => EditorKind::Neovim,
+            "hx" | "helix" => EditorKind::Helix,
+            "nano" => EditorKind::Nano,
+            "emacs" | "emacsclient" => EditorKind::Emacs,
+            "code" | "code-insiders" | "codium" | "cursor" => EditorKind::VsCode,
+            "subl" => EditorKind::Sublime,
+            _ => EditorKind::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EditOptions<'a> {
+    /// Extension of the temporary file (ex. "yaml"), which most editors use
+    /// to pick syntax highlighting.
+    pub extension: Option<&'a str>,
+    /// Soft-wrap long lines, if the editor supports it.
+    pub line_wrap: bool,
+    /// Start in insert mode when there is no initial text, if the editor is
+    /// modal.
+    pub start_insert_mode_if_empty: bool,
+    /// Passed to the editor as-is, before the file path.
+    pub extra_args: Vec<&'a str>,
+}
+
+impl Default for EditOptions<'_> {
+    fn default() -> Self {
+        EditOptions {
+            extension: None,
+            line_wrap: true,
+            start_insert_mode_if_empty: true,
+            extra_args: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Editor {
+    program: String,
+    args: Vec<String>,
+    kind: EditorKind,
+}
+
+impl Editor {
+    /// Parses an editor command, as would be found in $EDITOR (ex. "nvim" or
+    /// "code --wait"). Returns None if the command is empty.
+    pub fn from_command(command: &str) -> Option<Self> {
+        let mut parts = split_command(command).into_iter();
+        let program = parts.next()?;
+        Some(Editor {
+            kind: EditorKind::from_program(&program),
+            program,
+            args: parts.collect(),
+        })
+    }
+
+    /// Picks the editor configured in the user preferences, falling back to
+    /// $VISUAL, $EDITOR, and finally vim.
+    pub fn detect(preferences: Option<&UserPreferences>) -> Self {
+        preferences
+            .and_then(|p| p.editor())
+            .and_then(Self::from_command)
+            .or_else(|| Self::from_env("VISUAL"))
+            .or_else(|| Self::from_env("EDITOR"))
+            .unwrap_or_else(Self::vim)
+    }
+
+    pub fn vim() -> Self {
+        Self::from_command(DEFAULT_EDITOR).expect("hardcoded editor command should be valid")
+    }
+
+    fn from_env(var: &str) -> Option<Self> {
+        std::env::var(var)
+            .ok()
+            .and_then(|command| Self::from_command(&command))
+    }
+
+    pub fn kind(&self) -> EditorKind {
+        self.kind
+    }
+
+    /// Opens 'text' in the editor and returns the edited text, or None if the
+    /// buffer was left empty.
+    pub fn edit(
+        &self,
+        text: Option<String>,
+        options: &EditOptions<'_>,
+    ) -> Result<Option<String>, CliError> {
+        let has_initial_text = text.is_some();
+        let suffix = options
+            .extension
+            .map(|ext| format!(".{}", ext.trim_start_matches('.')))
+            .unwrap_or_default();
+
+        let edited =
+            with_written_to_tmp_file_with_suffix(text.unwrap_or_default(), &suffix, |path| {
+                let status = std::process::Command::new(&self.program)
+                    .args(self.command_args(path, has_initial_text, options))
+                    .status()
+                    .map_err(|e| {
+                        EditorError::with_debug(&format!("failed to open '{}'", self.program), &e)
+                    })?;
+                if !status.success() {
+                    return Err(EditorError::new(&format!(
+                        "'{}' exited with an error",
+                        self.program
+                    )));
+                }
+                std::fs::read_to_string(path)
+                    .map_err(|e| EditorError::with_debug("failed to read from temporary file", &e))
+            })?;
+
+        Ok(match edited.trim() {
+            "" => None,
+            x => Some(x.to_string()),
+        })
+    }
+
+    /// Opens 'value' serialized in the given format, and parses the result
+    /// back. If parsing fails, the user is asked whether to fix the buffer
+    /// (the broken text is kept) or cancel.
+    pub fn edit_structured<T>(
+        &self,
+        pr: &Printer,
+        value: Option<&T>,
+        format: StructuredFormat,
+    ) -> Result<T, CliError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let mut text =
+            match value {
+                Some(value) => Some(format.serialize(value).map_err(|e| {
+                    StructuredEditSerializationError::with_debug(format.name(), &e)
+                })?),
+                None => None,
+            };
+        let options = EditOptions {
+            extension: Some(format.extension()),
+            ..Default::default()
+        };
+        loop {
+            let edited = self
+                .edit(text.take(), &options)?
+                .ok_or_else(UserCancelled::new)?;
+            match format.deserialize::<T>(&edited) {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    pr.error(&format!("Failed to parse {}: {}", format.name(), e));
+                    if !yes_no("Edit again?")? {
+                        return Err(UserCancelled::new());
+                    }
+                    text = Some(edited);
+                }
+            }
+        }
+    }
+
+    fn command_args(
+        &self,
+        path: &Path,
+        has_initial_text: bool,
+        options: &EditOptions<'_>,
+    ) -> Vec<String> {
+        let mut args = self.args.clone();
+        match self.kind {
+            EditorKind::Vim | EditorKind::Neovim => {
+                if options.start_insert_mode_if_empty && !has_initial_text {
+                    args.extend(["-c".to_string(), "startinsert".to_string()]);
+                }
+                if options.line_wrap {
+                    args.extend(
+                        [
+                            "+windo set wrap",
+                            "+set textwidth=0",
+                            "+set wrapmargin=0",
+                            "+set linebreak",
+                            "+noremap j gj",
+                            "+noremap k gk",
+                        ]
+                        .map(String::from),
+                    );
+                }
+            }
+            EditorKind::Nano => {
+                if options.line_wrap {
+                    args.push("--softwrap".to_string());
+                }
+            }
+            EditorKind::VsCode | EditorKind::Sublime => {
+                // GUI editors return immediately unless asked to wait for the
+                // file to be closed.
+                if !args.iter().any(|a| a == "--wait" || a == "-w") {
+                    args.push("--wait".to_string());
+                }
+            }
+            EditorKind::Helix | EditorKind::Emacs | EditorKind::Other => {}
+        }
+        args.extend(options.extra_args.iter().map(|a| a.to_string()));
+        args.push(path.to_string_lossy().to_string());
+        args
+    }
+}
+
+impl fmt::Display for Editor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructuredFormat {
+    Yaml,
+    Json,
+}
+
+impl StructuredFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            StructuredFormat::Yaml => "YAML",
+            StructuredFormat::Json => "JSON",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            StructuredFormat::Yaml => "yaml",
+            StructuredFormat::Json => "json",
+        }
+    }
+
+    fn serialize<T: Serialize>(&self, value: &T) -> Result<String, String> {
+        match self {
+            StructuredFormat::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string()),
+            StructuredFormat::Json => {
+                serde_json::to_string_pretty(value).map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self, text: &str) -> Result<T, String> {
+        match self {
+            StructuredFormat::Yaml => serde_yaml::from_str(text).map_err(|e| e.to_string()),
+            StructuredFormat::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Opens 'text' in the user's editor (see `Editor::detect`).
+pub fn edit(
+    preferences: &UserPreferences,
+    text: Option<String>,
+) -> Result<Option<String>, CliError> {
+    Editor::detect(Some(preferences)).edit(text, &EditOptions::default())
+}
+
+/// Similar to `edit`, but for structured data. See `Editor::edit_structured`.
+pub fn edit_structured<T>(
+    pr: &Printer,
+    preferences: &UserPreferences,
+    value: Option<&T>,
+    format: StructuredFormat,
+) -> Result<T, CliError>
+where
+    T: Serialize + DeserializeOwned,
+{
+    Editor::detect(Some(preferences)).edit_structured(pr, value, format)
+}
+
+/// Splits a command into words, supporting single and double quotes (ex. for
+/// paths containing spaces). Escape sequences are not supported.
+fn split_command(command: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    for c in command.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                in_word = true;
+            }
+            None if c.is_whitespace() => {
+                if in_word {
+                    parts.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            None => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        parts.push(current);
+    }
+    parts
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn editor_commands_are_split_into_program_and_args() {
+        let editor = Editor::from_command("'/Applications/My Editor/bin/code' --wait").unwrap();
+        assert_eq!(editor.program, "/Applications/My Editor/bin/code");
+        assert_eq!(editor.args, vec!["--wait"]);
+        assert_eq!(editor.kind(), EditorKind::VsCode);
+        assert!(Editor::from_command("   ").is_none());
+    }
+
+    #[test]
+    fn editor_preference_is_saved_and_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("preferences.yaml");
+        let mut preferences = UserPreferences::new(path.clone(), "test").unwrap();
+        preferences.set_editor(Some("hx")).unwrap();
+
+        let preferences = UserPreferences::new(path, "test").unwrap();
+        assert_eq!(preferences.editor(), Some("hx"));
+        assert_eq!(Editor::detect(Some(&preferences)).kind(), EditorKind::Helix);
+    }
+
+    #[test]
+    fn gui_editors_are_made_to_wait_exactly_once() {
+        let path = Path::new("/tmp/buffer.yaml");
+        let options = EditOptions::default();
+        assert_eq!(
+            Editor::from_command("code")
+                .unwrap()
+                .command_args(path, true, &options),
+            vec!["--wait", "/tmp/buffer.yaml"]
+        );
+        assert_eq!(
+            Editor::from_command("subl -w")
+                .unwrap()
+                .command_args(path, true, &options),
+            vec!["-w", "/tmp/buffer.yaml"]
+        );
+    }
+
+    #[test]
+    fn vim_starts_in_insert_mode_only_for_empty_buffers() {
+        let path = Path::new("/tmp/buffer");
+        let options = EditOptions {
+            line_wrap: false,
+            ..Default::default()
+        };
+        let editor = Editor::from_command("nvim").unwrap();
+        assert_eq!(
+            editor.command_args(path, false, &options),
+            vec!["-c", "startinsert", "/tmp/buffer"]
+        );
+        assert_eq!(
+            editor.command_args(path, true, &options),
+            vec!["/tmp/buffer"]
+        );
+    }
+}
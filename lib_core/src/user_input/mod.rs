@@ -1,7 +1,9 @@
 mod basic;
+mod editor;
 mod select;
 mod vim;
 
 pub use basic::*;
+pub use editor::*;
 pub use select::*;
 pub use vim::*;
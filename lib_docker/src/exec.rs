@@ -0,0 +1,108 @@
+use lib_core::{shell_command_line, CommandLauncher, LaunchOptions};
+
+/// Runs commands inside a running container with 'docker exec', when used
+/// with `RemoteRunner`:
+///
+/// `Executor::with_runner(RemoteRunner::new(DockerContainer::new("api")))`
+#[derive(Debug, Clone)]
+pub struct DockerContainer {
+    pub container: String,
+    /// User (or 'user:group') to run as. Defaults to the container's user.
+    pub user: Option<String>,
+}
+
+impl DockerContainer {
+    pub fn new(container: &str) -> Self {
+        DockerContainer {
+            container: container.to_string(),
+            user: None,
+        }
+    }
+}
+
+impl CommandLauncher for DockerContainer {
+    fn launch(
+        &self,
+        program: &str,
+        args: &[&str],
+        options: &LaunchOptions<'_>,
+    ) -> (String, Vec<String>) {
+        // Stdin is only forwarded with '-i'.
+        let mut docker_args = vec!["exec".to_string(), "-i".to_string()];
+        if options.tty {
+            docker_args.push("-t".to_string());
+        }
+        if let Some(user) = &self.user {
+            docker_args.extend(["-u".to_string(), user.clone()]);
+        }
+        if let Some(dir) = options.dir {
+            docker_args.extend(["-w".to_string(), dir.display().to_string()]);
+        }
+        for (k, v) in options.env {
+            docker_args.extend(["-e".to_string(), format!("{}={}", k, v)]);
+        }
+        docker_args.push(self.container.clone());
+
+        if options.rlimits.is_empty() {
+            docker_args.push(program.to_string());
+            docker_args.extend(args.iter().map(|a| a.to_string()));
+        } else {
+            // 'docker exec' can't set limits, so they are set by a shell
+            // before it runs the command.
+            let command_line = shell_command_line(
+                program,
+                args,
+                &LaunchOptions {
+                    dir: None,
+                    env: &[],
+                    ..*options
+                },
+            );
+            docker_args.extend(["sh".to_string(), "-c".to_string(), command_line]);
+        }
+        ("docker".to_string(), docker_args)
+    }
+}
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use lib_core::ResourceLimit;
+
+    use super::*;
+
+    #[test]
+    fn options_are_passed_to_docker_exec() {
+        let env = [("RUST_LOG".to_string(), "debug".to_string())];
+        let (program, args) = DockerContainer::new("api").launch(
+            "cargo",
+            &["test"],
+            &LaunchOptions {
+                dir: Some(Path::new("/app")),
+                env: &env,
+                rlimits: &[ResourceLimit::OpenFiles(4096)],
+                tty: false,
+            },
+        );
+        assert_eq!(program, "docker");
+        assert_eq!(
+            args,
+            [
+                "exec",
+                "-i",
+                "-w",
+                "/app",
+                "-e",
+                "RUST_LOG=debug",
+                "api",
+                "sh",
+                "-c",
+                "ulimit -n 4096 && exec cargo test"
+            ]
+        );
+    }
+}
@@ -1,7 +1,9 @@
 mod build;
+mod exec;
 mod push;
 
 pub use build::*;
+pub use exec::*;
 use lib_core::define_cli_error;
 pub use push::*;
 
@@ -280,3 +280,58 @@ fn set_avd_orientation(avd_name: &str, orientation: Orientation) -> Result<(), C
 
     Ok(())
 }
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use lib_core::{CliErrorTrait as _, Expectation, ScriptedRunner};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn existing_avd_is_not_recreated() {
+        let runner = ScriptedRunner::new();
+        runner.expect(
+            Expectation::new("avdmanager", &["list", "avd"]).output(
+                "Available Android Virtual Devices:\n    Name: pixel_7\n    Device: pixel_7",
+            ),
+        );
+        let ex = Executor::with_runner(runner.clone());
+
+        create_android_emulator_if_not_exists(
+            &Printer::new(),
+            &ex,
+            "pixel_7",
+            "system-images;android-34;google_apis;x86_64",
+            Orientation::Portrait,
+        )
+        .await
+        .unwrap();
+        runner.assert_not_called("sdkmanager");
+        runner.assert_done();
+    }
+
+    #[tokio::test]
+    async fn missing_system_image_is_reported() {
+        let image = "system-images;android-34;google_apis;x86_64";
+        let runner = ScriptedRunner::new();
+        runner
+            .expect(Expectation::new("avdmanager", &["list", "avd"]))
+            .expect(Expectation::new("sdkmanager", &[image]).exit_code(1));
+        let ex = Executor::with_runner(runner.clone());
+
+        let e = create_android_emulator_if_not_exists(
+            &Printer::new(),
+            &ex,
+            "pixel_7",
+            image,
+            Orientation::Portrait,
+        )
+        .await
+        .unwrap_err();
+        assert!(e.message().contains("is not installed"));
+        runner.assert_done();
+    }
+}
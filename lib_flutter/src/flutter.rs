@@ -437,3 +437,78 @@ fn extract_ios_bundle_name(dir: &Path) -> Result<String, CliError> {
         "could not find 'CFBundleName' key",
     ))
 }
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use lib_core::{Expectation, ScriptedRunner};
+
+    use super::*;
+
+    #[test]
+    fn builds_run_flutter_with_the_platform_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let apk = dir
+            .path()
+            .join("build/app/outputs/flutter-apk/app-prod-release.apk");
+        fs::create_dir_all(apk.parent().unwrap()).unwrap();
+        fs::write(&apk, b"").unwrap();
+        fs::create_dir_all(dir.path().join("build/web")).unwrap();
+
+        let runner = ScriptedRunner::new();
+        runner
+            .expect(Expectation::new(
+                "flutter",
+                &[
+                    "build",
+                    "apk",
+                    "--release",
+                    "--flavor",
+                    "prod",
+                    "--target-platform",
+                    "android-arm,android-arm64",
+                ],
+            ))
+            .expect(Expectation::new(
+                "flutter",
+                &["build", "web", "--base-href", "/app/", "--debug"],
+            ));
+        let ex = Executor::with_runner(runner.clone());
+        let pr = Printer::new();
+
+        let output = flutter_build(
+            &pr,
+            &ex,
+            dir.path(),
+            BuildFor::Android,
+            BuildType::Release,
+            Some(BuildOptions {
+                flavor: Some("prod"),
+                ..Default::default()
+            }),
+        )
+        .unwrap();
+        assert_eq!(output, fs::canonicalize(&apk).unwrap());
+        let output = flutter_build(
+            &pr,
+            &ex,
+            dir.path(),
+            BuildFor::Web {
+                base_href: "/app/".to_string(),
+            },
+            BuildType::Debug,
+            None,
+        )
+        .unwrap();
+        assert!(output.ends_with("build/web"));
+
+        runner.assert_done();
+        let project_dir = fs::canonicalize(dir.path()).unwrap();
+        assert!(runner
+            .calls()
+            .iter()
+            .all(|call| call.dir.as_deref() == Some(project_dir.as_path())));
+    }
+}
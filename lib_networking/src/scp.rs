@@ -327,3 +327,40 @@ pub async fn scp_delete_files<'a>(
 
     Ok(())
 }
+
+// Tests.
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use lib_core::{Expectation, ScriptedRunner};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn files_are_copied_in_a_single_scp_call() {
+        let runner = ScriptedRunner::new();
+        runner.expect(Expectation::with_args_prefix("scp", &["-P", "22"]));
+        let ex = Executor::with_runner(runner.clone());
+        let (a, b) = (PathBuf::from("a.txt"), PathBuf::from("b.txt"));
+
+        scp_upload_files(
+            &Printer::new(),
+            &ex,
+            "me",
+            "host",
+            None,
+            vec![&a, &b],
+            "/srv",
+        )
+        .await
+        .unwrap();
+        scp_upload_files(&Printer::new(), &ex, "me", "host", None, vec![], "/srv")
+            .await
+            .unwrap();
+        runner.assert_done();
+        let calls = runner.calls();
+        let args = &calls[0].args;
+        assert_eq!(args[args.len() - 3..], ["a.txt", "b.txt", "me@host:/srv"]);
+    }
+}
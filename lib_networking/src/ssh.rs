@@ -1,11 +1,14 @@
 use chrono::{Datelike, Local, NaiveDate, NaiveTime};
-use lib_core::{define_cli_error, CliError, CriticalError, Executor, IOMode, InvalidUTF8, Printer};
+use lib_core::{
+    define_cli_error, shell_command_line, track_mount, CliError, CommandLauncher, CriticalError,
+    Executor, IOMode, InvalidUTF8, LaunchOptions, Printer,
+};
 use nix::unistd;
 use openssh::{ForwardType, KnownHosts, Session, SessionBuilder};
-use std::fs;
 use std::os::unix::fs::MetadataExt as _;
 use std::path::PathBuf;
 use std::time::{Duration, Instant};
+use std::{fmt, fs};
 
 use crate::{kill_open_sockets_on_port, wait_until_socket_open};
 
@@ -192,24 +195,11 @@ pub async fn forward_port<'a>(
         )),
     }
 
-    let connect_opt = connect_options.unwrap_or_default();
-    let ssh_port = connect_opt.port_or_default().to_string();
-    let identity_file = connect_opt.identity_file_or_default();
-    let known_hosts_file = connect_opt.known_hosts_file_or_default();
-    let connect_timeout = connect_opt.connect_timeout_or_default();
-
     if force_close_existing {
         kill_open_sockets_on_port(pr, local_port)?;
     }
 
-    let session = SessionBuilder::default()
-        .known_hosts_check(KnownHosts::Add)
-        .keyfile(identity_file)
-        .user_known_hosts_file(known_hosts_file)
-        .connect_timeout(connect_timeout)
-        .connect(format!("ssh://{}@{}:{}", user, hostname, ssh_port))
-        .await
-        .map_err(|e| SshConnectionError::with_debug(&e))?;
+    let session = connect_session(user, hostname, connect_options).await?;
 
     session
         .request_port_forward(
@@ -309,6 +299,10 @@ pub async fn ssh_cache_identity(
 }
 
 /// Identity must be cached before calling this function.
+///
+/// Only returns stdout, regardless of the exit status. To run commands with
+/// an `Executor` instead (with IO modes, and failures reported as
+/// `TtyCommandFailed`), see `SshTarget`.
 pub async fn ssh_exec_command<'a>(
     user: &str,
     hostname: &str,
@@ -316,29 +310,102 @@ pub async fn ssh_exec_command<'a>(
     program: &str,
     args: &[&str],
 ) -> Result<String, CliError> {
+    let session = connect_session(user, hostname, connect_options).await?;
+
+    let out = session
+        .command(program)
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| SshConnectionError::with_debug(&e))?;
+
+    String::from_utf8(out.stdout).map_err(|e| InvalidUTF8::with_debug(&e))
+}
+
+async fn connect_session(
+    user: &str,
+    hostname: &str,
+    connect_options: Option<SshConnectOptions<'_>>,
+) -> Result<Session, CliError> {
     let connect_opt = connect_options.unwrap_or_default();
     let port = connect_opt.port_or_default().to_string();
     let identity_file = connect_opt.identity_file_or_default();
     let known_hosts_file = connect_opt.known_hosts_file_or_default();
     let connect_timeout = connect_opt.connect_timeout_or_default();
 
-    let session = SessionBuilder::default()
+    SessionBuilder::default()
         .known_hosts_check(KnownHosts::Add)
         .keyfile(identity_file)
         .user_known_hosts_file(known_hosts_file)
         .connect_timeout(connect_timeout)
         .connect(format!("ssh://{}@{}:{}", user, hostname, port))
         .await
-        .map_err(|e| SshConnectionError::with_debug(&e))?;
+        .map_err(|e| SshConnectionError::with_debug(&e))
+}
 
-    let out = session
-        .command(program)
-        .args(args)
-        .output()
-        .await
-        .map_err(|e| SshConnectionError::with_debug(&e))?;
+/// Runs commands on a remote host, when used with `RemoteRunner`:
+///
+/// `Executor::with_runner(RemoteRunner::new(SshTarget::connect(..).await?))`
+///
+/// Commands go through the local 'ssh' client, multiplexed over the control
+/// socket of a single connection, which stays open until the target is
+/// dropped. Identity must be cached before connecting.
+pub struct SshTarget {
+    session: Session,
+    destination: String,
+}
 
-    String::from_utf8(out.stdout).map_err(|e| InvalidUTF8::with_debug(&e))
+impl SshTarget {
+    pub async fn connect(
+        user: &str,
+        hostname: &str,
+        connect_options: Option<SshConnectOptions<'_>>,
+    ) -> Result<Self, CliError> {
+        Ok(SshTarget {
+            session: connect_session(user, hostname, connect_options).await?,
+            destination: format!("{}@{}", user, hostname),
+        })
+    }
+
+    pub async fn close(self) -> Result<(), CliError> {
+        self.session
+            .close()
+            .await
+            .map_err(|e| SshConnectionError::with_debug(&e))
+    }
+}
+
+impl fmt::Debug for SshTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SshTarget")
+            .field("destination", &self.destination)
+            .field("control_socket", &self.session.control_socket())
+            .finish()
+    }
+}
+
+impl CommandLauncher for SshTarget {
+    fn launch(
+        &self,
+        program: &str,
+        args: &[&str],
+        options: &LaunchOptions<'_>,
+    ) -> (String, Vec<String>) {
+        let ssh_args = vec![
+            "-S".to_string(),
+            self.session.control_socket().display().to_string(),
+            "-o".to_string(),
+            "ControlMaster=no".to_string(),
+            "-o".to_string(),
+            "BatchMode=yes".to_string(),
+            if options.tty { "-t" } else { "-T" }.to_string(),
+            "--".to_string(),
+            self.destination.clone(),
+            // The remote command is a single string, run by the user's shell.
+            shell_command_line(program, args, options),
+        ];
+        ("ssh".to_string(), ssh_args)
+    }
 }
 
 pub async fn ssh_attach<'a>(
@@ -448,6 +515,7 @@ pub async fn sshfs<'a>(
         args.extend_from_slice(&common_args);
         args.extend_from_slice(&["-o", "idmap=user", remote_path, local_path]);
         ex.execute_background("sshfs", &args, None).await?;
+        track_mount(local_path, false);
         Ok(())
     } else if sudo_fallback {
         // Run as root, but mount as local user.
@@ -467,6 +535,7 @@ pub async fn sshfs<'a>(
             local_path,
         ]);
         ex.execute_background("sudo", &args, None).await?;
+        track_mount(local_path, true);
         Ok(())
     } else {
         Err(SshfsPermissionError::new(local_path))
@@ -475,7 +544,9 @@ pub async fn sshfs<'a>(
 
 #[cfg(test)]
 mod tests {
-    use super::stderr_indicates_host_key_changed;
+    use lib_core::{Expectation, ScriptedRunner};
+
+    use super::*;
 
     #[test]
     fn host_key_changed_error_is_terminal() {
@@ -499,4 +570,68 @@ Host key verification failed.
             "localhost: Permission denied (publickey)."
         ));
     }
+
+    /// 'ssh-add -l' lists 'cached', and the identity file has 'fingerprint'.
+    fn agent_runner(cached: &str, fingerprint: &str) -> ScriptedRunner {
+        let runner = ScriptedRunner::new();
+        runner
+            .expect(
+                Expectation::new("ssh-agent", &["-s"])
+                    .output("SSH_AUTH_SOCK=/tmp/agent.sock; export SSH_AUTH_SOCK;"),
+            )
+            .expect(Expectation::new(
+                "sh",
+                &["-c", "SSH_AUTH_SOCK=/tmp/agent.sock; export SSH_AUTH_SOCK;"],
+            ))
+            .expect(Expectation::new("ssh-add", &["-l"]).output(cached))
+            .expect(
+                Expectation::new("ssh-keygen", &["-lf", "/home/dev/.ssh/id_ed25519"])
+                    .output(&format!("256 {} dev@laptop (ED25519)", fingerprint)),
+            );
+        runner
+    }
+
+    #[tokio::test]
+    async fn identity_is_added_with_the_ttl_if_not_cached() {
+        let runner = agent_runner("The agent has no identities.", "SHA256:abc");
+        runner.expect(Expectation::new(
+            "ssh-add",
+            &["-t", "3600", "/home/dev/.ssh/id_ed25519"],
+        ));
+        let ex = Executor::with_runner(runner.clone());
+
+        ssh_cache_identity(
+            &Printer::new(),
+            &ex,
+            &PathBuf::from("/home/dev/.ssh/id_ed25519"),
+            SshCacheTtl::For(Duration::from_secs(3600)),
+        )
+        .await
+        .unwrap();
+        runner.assert_done();
+    }
+
+    #[tokio::test]
+    async fn cached_identity_is_not_added_again() {
+        let runner = agent_runner("256 SHA256:abc dev@laptop (ED25519)", "SHA256:abc");
+        let ex = Executor::with_runner(runner.clone());
+
+        ssh_cache_identity(
+            &Printer::new(),
+            &ex,
+            &PathBuf::from("/home/dev/.ssh/id_ed25519"),
+            SshCacheTtl::For(Duration::from_secs(3600)),
+        )
+        .await
+        .unwrap();
+        runner.assert_done();
+        assert_eq!(
+            runner
+                .calls()
+                .iter()
+                .filter(|call| call.program == "ssh-add")
+                .count(),
+            1
+        );
+    }
 }